@@ -120,17 +120,23 @@ impl VoloGrpcBackend {
         }
     }
 
-    fn build_client_req(&self, _ty: pilota_build::ty::Ty, streaming: bool) -> FastStr {
+    fn build_client_req(
+        &self,
+        req_enum_name: &Symbol,
+        variant_name: &Symbol,
+        _ty: pilota_build::ty::Ty,
+        streaming: bool,
+    ) -> FastStr {
         if streaming {
-            "requests.into_streaming_request().map(|s| ::volo_grpc::codegen::StreamExt::map(s, |m| \
-             ::std::result::Result::Ok(m)))"
-                .to_string()
-                .into()
+            format!(
+                "requests.into_streaming_request().map(|s| \
+                 {req_enum_name}::{variant_name}(::std::boxed::Box::pin(::volo_grpc::codegen::StreamExt::map(s, \
+                 |m| ::std::result::Result::Ok(m))) as _))"
+            )
+            .into()
         } else {
-            "requests.into_request().map(|m| \
-             ::futures::stream::once(::futures::future::ready(::std::result::Result::Ok(m))))"
-                .to_string()
-                .into()
+            // the unary message is kept as is, so that the request can be retried
+            format!("requests.into_request().map({req_enum_name}::{variant_name})").into()
         }
     }
 
@@ -334,7 +340,12 @@ impl CodegenBackend for VoloGrpcBackend {
 
             let resp_ty = self.client_output_ty(output_ty.clone(), server_streaming);
 
-            let req = self.build_client_req(input_ty.clone(), client_streaming);
+            let req = self.build_client_req(
+                &req_enum_name_send.clone().into(),
+                &variant_name.clone().into(),
+                input_ty.clone(),
+                client_streaming,
+            );
 
             let resp = self.build_client_resp(&resp_enum_name_recv.clone().into(), &variant_name.clone().into(), output_ty.clone(), server_streaming);
 
@@ -344,7 +355,7 @@ impl CodegenBackend for VoloGrpcBackend {
                         &self,
                         requests: {req_ty},
                     ) -> {resp_ty} {{
-                        let req = {req}.map(|message| {req_enum_name_send}::{variant_name}(::std::boxed::Box::pin(message) as _));
+                        let req = {req};
                         let mut cx = self.0.make_cx("{path}");
 
                         let resp = ::volo::Service::call(&self.0, &mut cx, req).await?;
@@ -359,7 +370,7 @@ impl CodegenBackend for VoloGrpcBackend {
                         self,
                         requests: {req_ty},
                     ) -> {resp_ty} {{
-                        let req = {req}.map(|message| {req_enum_name_send}::{variant_name}(::std::boxed::Box::pin(message) as _));
+                        let req = {req};
                         let mut cx = self.0.make_cx("{path}");
 
                         let resp = ::volo::client::OneShotService::call(self.0, &mut cx, req).await?;
@@ -375,10 +386,28 @@ impl CodegenBackend for VoloGrpcBackend {
         let client_methods = client_methods.join("\n");
         let oneshot_client_methods = oneshot_client_methods.join("\n");
 
-        let req_enum_send_variants = crate::join_multi_strs!(
-            "\n",
-            |enum_variant_names, req_tys| -> "{enum_variant_names}(::volo_grpc::BoxStream<'static, ::std::result::Result<{req_tys}, ::volo_grpc::Status>>),"
-        );
+        let client_streamings = s
+            .methods
+            .iter()
+            .map(|method| {
+                self.cx()
+                    .node_contains_tag::<ClientStreaming>(method.def_id)
+            })
+            .collect::<Vec<_>>();
+
+        let req_enum_send_variants =
+            itertools::izip!(&enum_variant_names, &req_tys, &client_streamings)
+                .map(|(variant_name, req_ty, client_streaming)| {
+                    if *client_streaming {
+                        format!(
+                            "{variant_name}(::volo_grpc::BoxStream<'static, \
+                             ::std::result::Result<{req_ty}, ::volo_grpc::Status>>),"
+                        )
+                    } else {
+                        format!("{variant_name}({req_ty}),")
+                    }
+                })
+                .join("\n");
 
         let req_enum_recv_variants = crate::join_multi_strs!(
             "\n",
@@ -395,12 +424,29 @@ impl CodegenBackend for VoloGrpcBackend {
             |enum_variant_names, resp_tys| -> "{enum_variant_names}(::volo_grpc::RecvStream<{resp_tys}>),"
         );
 
-        let req_send_into_body = crate::join_multi_strs!(
-            "",
-            |enum_variant_names| -> "Self::{enum_variant_names}(s) => {{
-                ::volo_grpc::codec::encode::encode(s, compression_encoding)
-            }}," 
-        );
+        let req_send_into_body = itertools::izip!(&enum_variant_names, &client_streamings)
+            .map(|(variant_name, client_streaming)| {
+                if *client_streaming {
+                    format!("Self::{variant_name}(s) => {{
+                        ::volo_grpc::codec::encode::encode(s, compression_encoding)
+                    }},")
+                } else {
+                    format!("Self::{variant_name}(m) => {{
+                        ::volo_grpc::codec::encode::encode(::futures::stream::once(::futures::future::ready(::std::result::Result::Ok(m))), compression_encoding)
+                    }},")
+                }
+            })
+            .join("");
+
+        let req_send_try_clone = itertools::izip!(&enum_variant_names, &client_streamings)
+            .map(|(variant_name, client_streaming)| {
+                if *client_streaming {
+                    format!("Self::{variant_name}(_) => ::std::option::Option::None,")
+                } else {
+                    format!("Self::{variant_name}(m) => ::std::option::Option::Some(Self::{variant_name}(::std::clone::Clone::clone(m))),")
+                }
+            })
+            .join("");
 
         let req_recv_from_body = crate::join_multi_strs!(
             "",
@@ -434,6 +480,12 @@ impl CodegenBackend for VoloGrpcBackend {
                         {req_send_into_body}
                     }}
                 }}
+
+                fn try_clone(&self) -> ::std::option::Option<Self> {{
+                    match self {{
+                        {req_send_try_clone}
+                    }}
+                }}
             }}
 
             pub enum {req_enum_name_recv} {{
@@ -34,9 +34,9 @@
 //! ```
 
 use metainfo::{FastStrMap, TypeMap};
-use volo::net::Address;
+use volo::{loadbalance::retry::RetryPolicy, net::Address};
 
-use crate::context::Config;
+use crate::context::{Config, Context};
 
 #[derive(Debug, Default)]
 pub struct CallOpt {
@@ -53,6 +53,8 @@ pub struct CallOpt {
     pub caller_faststr_tags: FastStrMap,
     /// Sets the caller tags for the call.
     pub caller_tags: TypeMap,
+    /// Sets the retry policy for the call, which overrides the one of the client.
+    pub retry_policy: Option<RetryPolicy>,
 }
 
 impl CallOpt {
@@ -85,6 +87,9 @@ impl volo::client::Apply<crate::context::ClientContext> for CallOpt {
             callee.set_address(addr);
         }
         cx.rpc_info.config_mut().unwrap().merge(self.config);
+        if let Some(policy) = self.retry_policy {
+            cx.extensions_mut().insert(policy);
+        }
         Ok(())
     }
 }
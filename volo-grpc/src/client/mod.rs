@@ -22,7 +22,12 @@ use volo::{
     client::{MkClient, WithOptService},
     context::{Endpoint, Role, RpcInfo},
     discovery::{Discover, DummyDiscover},
-    loadbalance::{random::WeightedRandomBalance, MkLbLayer},
+    loadbalance::{
+        hedge::{HedgePolicy, Hedger},
+        random::WeightedRandomBalance,
+        retry::RetryPolicy,
+        MkLbLayer,
+    },
     net::Address,
     FastStr,
 };
@@ -39,6 +44,8 @@ use crate::{
 pub struct ClientBuilder<IL, OL, C, LB, T, U> {
     http2_config: Http2Config,
     rpc_config: Config,
+    #[cfg(feature = "tls")]
+    tls_connector: Option<volo::net::tls::TlsConnector>,
     callee_name: FastStr,
     caller_name: FastStr,
     // Maybe address use Arc avoid memory alloc.
@@ -65,6 +72,8 @@ impl<C, T, U>
         Self {
             http2_config: Default::default(),
             rpc_config: Default::default(),
+            #[cfg(feature = "tls")]
+            tls_connector: None,
             callee_name: FastStr::new(service_name),
             caller_name: "".into(),
             target: None,
@@ -77,14 +86,16 @@ impl<C, T, U>
     }
 }
 
-impl<IL, OL, C, LB, T, U, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>, T, U> {
+impl<IL, OL, C, LB, T, U, DISC, H> ClientBuilder<IL, OL, C, LbConfig<LB, DISC, H>, T, U> {
     pub fn load_balance<NLB>(
         self,
         load_balance: NLB,
-    ) -> ClientBuilder<IL, OL, C, LbConfig<NLB, DISC>, T, U> {
+    ) -> ClientBuilder<IL, OL, C, LbConfig<NLB, DISC, H>, T, U> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
+            #[cfg(feature = "tls")]
+            tls_connector: self.tls_connector,
             callee_name: self.callee_name,
             caller_name: self.caller_name,
             target: self.target,
@@ -99,10 +110,12 @@ impl<IL, OL, C, LB, T, U, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>, T,
     pub fn discover<NDISC>(
         self,
         discover: NDISC,
-    ) -> ClientBuilder<IL, OL, C, LbConfig<LB, NDISC>, T, U> {
+    ) -> ClientBuilder<IL, OL, C, LbConfig<LB, NDISC, H>, T, U> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
+            #[cfg(feature = "tls")]
+            tls_connector: self.tls_connector,
             callee_name: self.callee_name,
             caller_name: self.caller_name,
             target: self.target,
@@ -113,6 +126,39 @@ impl<IL, OL, C, LB, T, U, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>, T,
             _marker: PhantomData,
         }
     }
+
+    /// Sets the retry policy of the client.
+    ///
+    /// Only the unary requests are retried, and the policy can be overridden per method by
+    /// [`RetryPolicy::method`], or per call by [`CallOpt::retry_policy`].
+    pub fn retry_policy(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.mk_lb = self.mk_lb.retry_policy(policy);
+        self
+    }
+
+    /// Sets the hedge policy of the client.
+    ///
+    /// A backup unary request will be sent to another instance if the first one hasn't returned
+    /// within the delay of the policy, which should only be enabled for idempotent methods.
+    pub fn hedge(
+        self,
+        policy: HedgePolicy,
+    ) -> ClientBuilder<IL, OL, C, LbConfig<LB, DISC, Hedger>, T, U> {
+        ClientBuilder {
+            http2_config: self.http2_config,
+            rpc_config: self.rpc_config,
+            #[cfg(feature = "tls")]
+            tls_connector: self.tls_connector,
+            callee_name: self.callee_name,
+            caller_name: self.caller_name,
+            target: self.target,
+            inner_layer: self.inner_layer,
+            outer_layer: self.outer_layer,
+            mk_client: self.mk_client,
+            mk_lb: self.mk_lb.hedge(policy),
+            _marker: PhantomData,
+        }
+    }
 }
 
 impl<IL, OL, C, LB, T, U> ClientBuilder<IL, OL, C, LB, T, U> {
@@ -229,6 +275,15 @@ impl<IL, OL, C, LB, T, U> ClientBuilder<IL, OL, C, LB, T, U> {
         self
     }
 
+    /// Connects to the servers over TLS.
+    ///
+    /// The protocol `h2` is negotiated by ALPN if the connector doesn't specify any.
+    #[cfg(feature = "tls")]
+    pub fn tls_connector(mut self, connector: volo::net::tls::TlsConnector) -> Self {
+        self.tls_connector = Some(connector);
+        self
+    }
+
     /// Sets the timeout for the response.
     ///
     /// Default is no timeout.
@@ -275,6 +330,8 @@ impl<IL, OL, C, LB, T, U> ClientBuilder<IL, OL, C, LB, T, U> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
+            #[cfg(feature = "tls")]
+            tls_connector: self.tls_connector,
             callee_name: self.callee_name,
             caller_name: self.caller_name,
             target: self.target,
@@ -316,6 +373,8 @@ impl<IL, OL, C, LB, T, U> ClientBuilder<IL, OL, C, LB, T, U> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
+            #[cfg(feature = "tls")]
+            tls_connector: self.tls_connector,
             callee_name: self.callee_name,
             caller_name: self.caller_name,
             target: self.target,
@@ -347,6 +406,8 @@ impl<IL, OL, C, LB, T, U> ClientBuilder<IL, OL, C, LB, T, U> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
+            #[cfg(feature = "tls")]
+            tls_connector: self.tls_connector,
             callee_name: self.callee_name,
             caller_name: self.caller_name,
             target: self.target,
@@ -378,6 +439,8 @@ impl<IL, OL, C, LB, T, U> ClientBuilder<IL, OL, C, LB, T, U> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
+            #[cfg(feature = "tls")]
+            tls_connector: self.tls_connector,
             callee_name: self.callee_name,
             caller_name: self.caller_name,
             target: self.target,
@@ -426,8 +489,16 @@ where
 {
     /// Builds a new [`Client`].
     pub fn build(self) -> C::Target {
-        let transport =
-            MetaService::new(ClientTransport::new(&self.http2_config, &self.rpc_config));
+        #[cfg(not(feature = "tls"))]
+        let transport = ClientTransport::new(&self.http2_config, &self.rpc_config);
+        #[cfg(feature = "tls")]
+        let transport = match self.tls_connector {
+            Some(connector) => {
+                ClientTransport::new_with_tls(&self.http2_config, &self.rpc_config, connector)
+            }
+            None => ClientTransport::new(&self.http2_config, &self.rpc_config),
+        };
+        let transport = MetaService::new(transport);
         let transport = self.outer_layer.layer(BoxCloneService::new(
             self.mk_lb.make().layer(self.inner_layer.layer(transport)),
         ));
@@ -5,6 +5,7 @@ use volo::newtype_impl_context;
 
 use crate::codec::compression::CompressionEncoding;
 
+#[derive(Clone)]
 pub struct ClientCxInner;
 
 /// A context for client to pass information such as `RpcInfo` and `Config` between middleware
@@ -19,6 +20,13 @@ impl ClientContext {
     }
 }
 
+impl ForkContext for ClientContext {
+    #[inline]
+    fn fork(&self) -> Self {
+        Self(self.0.fork())
+    }
+}
+
 impl Default for ClientContext {
     fn default() -> Self {
         Self(RpcCx::new(RpcInfo::with_role(Role::Client), ClientCxInner))
@@ -5,16 +5,24 @@ use tracing::warn;
 use volo::{
     context::Context,
     discovery::Discover,
-    loadbalance::{error::LoadBalanceError, LoadBalance, MkLbLayer},
+    loadbalance::{
+        error::{LoadBalanceError, Retryable},
+        hedge::{Hedge, HedgePolicy, HedgedCall, Hedger, NoHedge},
+        retry::{RetryBudget, RetryPolicy},
+        CallGuard, LoadBalance, MkLbLayer,
+    },
+    net::Address,
     Layer, Unwrap,
 };
 
-use crate::Request;
+use crate::{Request, SendEntryMessage};
 
-#[derive(Clone, Default, Copy)]
-pub struct LoadBalanceLayer<D, LB> {
+#[derive(Clone, Default)]
+pub struct LoadBalanceLayer<D, LB, H = NoHedge> {
     discover: D,
     load_balance: LB,
+    retry_policy: Option<RetryPolicy>,
+    hedge: H,
 }
 
 impl<D, LB> LoadBalanceLayer<D, LB> {
@@ -22,26 +30,77 @@ impl<D, LB> LoadBalanceLayer<D, LB> {
         LoadBalanceLayer {
             discover,
             load_balance,
+            retry_policy: None,
+            hedge: NoHedge,
         }
     }
+
+    /// Enables the hedged requests, which send a backup request to another address if the first
+    /// one is slow. Only the unary requests are hedged.
+    pub fn hedge(self, policy: HedgePolicy) -> LoadBalanceLayer<D, LB, Hedger> {
+        self.with_hedge(Hedger::new(policy))
+    }
+}
+
+impl<D, LB, H> LoadBalanceLayer<D, LB, H> {
+    fn with_hedge<NH>(self, hedge: NH) -> LoadBalanceLayer<D, LB, NH> {
+        LoadBalanceLayer {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            retry_policy: self.retry_policy,
+            hedge,
+        }
+    }
+
+    /// Sets the retry policy, only the unary requests can be retried.
+    pub fn retry_policy(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.retry_policy = policy;
+        self
+    }
 }
 
-impl<D, LB, S> Layer<S> for LoadBalanceLayer<D, LB>
+impl<D, LB, S, H> Layer<S> for LoadBalanceLayer<D, LB, H>
 where
     D: Discover,
     LB: LoadBalance<D>,
 {
-    type Service = LoadBalanceService<D, LB, S>;
+    type Service = LoadBalanceService<D, LB, S, H>;
 
     fn layer(self, inner: S) -> Self::Service {
-        LoadBalanceService::new(self.discover, self.load_balance, inner)
+        LoadBalanceService::with_hedge(
+            self.discover,
+            self.load_balance,
+            inner,
+            self.retry_policy
+                .unwrap_or_else(|| RetryPolicy::with_retry_count(0)),
+            self.hedge,
+        )
     }
 }
-#[derive(Clone)]
-pub struct LoadBalanceService<D, LB, S> {
+pub struct LoadBalanceService<D, LB, S, H = NoHedge> {
     discover: D,
     load_balance: Arc<LB>,
     service: S,
+    retry_policy: RetryPolicy,
+    retry_budget: Option<Arc<RetryBudget>>,
+    hedge: Arc<H>,
+}
+
+impl<D, LB, S, H> Clone for LoadBalanceService<D, LB, S, H>
+where
+    D: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            discover: self.discover.clone(),
+            load_balance: self.load_balance.clone(),
+            service: self.service.clone(),
+            retry_policy: self.retry_policy.clone(),
+            retry_budget: self.retry_budget.clone(),
+            hedge: self.hedge.clone(),
+        }
+    }
 }
 
 impl<D, LB, S> LoadBalanceService<D, LB, S>
@@ -50,12 +109,57 @@ where
     LB: LoadBalance<D>,
 {
     pub fn new(discover: D, load_balance: LB, service: S) -> Self {
+        Self::with_retry_policy(
+            discover,
+            load_balance,
+            service,
+            RetryPolicy::with_retry_count(0),
+        )
+    }
+
+    pub fn with_retry_policy(
+        discover: D,
+        load_balance: LB,
+        service: S,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self::with_hedge(discover, load_balance, service, retry_policy, NoHedge)
+    }
+
+    /// Enables the hedged requests, which requires the context to be forkable.
+    pub fn hedge(self, policy: HedgePolicy) -> LoadBalanceService<D, LB, S, Hedger> {
+        LoadBalanceService {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            service: self.service,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            hedge: Arc::new(Hedger::new(policy)),
+        }
+    }
+}
+
+impl<D, LB, S, H> LoadBalanceService<D, LB, S, H>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    fn with_hedge(
+        discover: D,
+        load_balance: LB,
+        service: S,
+        retry_policy: RetryPolicy,
+        hedge: H,
+    ) -> Self {
         let lb = Arc::new(load_balance);
 
         let service = Self {
             discover,
             load_balance: lb.clone(),
             service,
+            retry_budget: retry_policy.make_budget().map(Arc::new),
+            retry_policy,
+            hedge: Arc::new(hedge),
         };
 
         if let Some(mut channel) = service.discover.watch(None) {
@@ -70,19 +174,41 @@ where
         }
         service
     }
+
+    /// Sends the request to the address, and reports the call to the load balancer.
+    async fn call_address<Cx, T>(
+        &self,
+        cx: &mut Cx,
+        req: Request<T>,
+        address: Address,
+    ) -> Result<S::Response, S::Error>
+    where
+        Cx: Context,
+        S: Service<Cx, Request<T>>,
+    {
+        if let Some(callee) = cx.rpc_info_mut().callee_mut() {
+            callee.address = Some(address.clone())
+        }
+        let guard = CallGuard::<D, LB>::new(&*self.load_balance, address);
+        let result = self.service.call(cx, req).await;
+        guard.finish(result.is_ok());
+        result
+    }
 }
 
-impl<Cx, T, D, LB, S> Service<Cx, Request<T>> for LoadBalanceService<D, LB, S>
+impl<Cx, T, D, LB, S, H> Service<Cx, Request<T>> for LoadBalanceService<D, LB, S, H>
 where
     <Cx as Context>::Config: Sync,
     Cx: 'static + Context + Send + Sync,
     D: Discover,
     LB: LoadBalance<D>,
+    H: Hedge<Cx>,
     S: Service<Cx, Request<T>> + 'static + Send + Sync,
     for<'cx> S::Future<'cx>: Send,
     LoadBalanceError: Into<S::Error>,
-    S::Error: Debug,
-    T: Send + 'static,
+    S::Response: Send,
+    S::Error: Debug + Retryable + Send + 'static,
+    T: SendEntryMessage + Send + 'static,
 {
     type Response = S::Response;
 
@@ -114,19 +240,66 @@ where
                 }
             };
 
-            if let Some(addr) = picker.next() {
-                if let Some(callee) = cx.rpc_info_mut().callee_mut() {
-                    callee.address = Some(addr.clone())
-                }
+            // the policy set by the `CallOpt` takes precedence over the one of the method
+            let policy = match cx.extensions().get::<RetryPolicy>() {
+                Some(policy) => policy.clone(),
+                None => self.retry_policy.for_method(cx.rpc_info().method()).clone(),
+            };
+            if let Some(budget) = &self.retry_budget {
+                budget.deposit();
+            }
 
-                return match self.service.call(cx, req).await {
-                    Ok(resp) => Ok(resp),
+            let mut hedge = self.hedge.fork(cx);
+
+            let mut req = req;
+            let mut call_count = 0;
+            while let Some(addr) = picker.next() {
+                call_count += 1;
+                // keeps a copy for the next attempt, the streaming requests can't be retried
+                let retry_req = if call_count < policy.attempts() {
+                    req.try_clone()
+                } else {
+                    None
+                };
+                // only the first attempt of the unary requests is hedged
+                let hedge = hedge
+                    .take()
+                    .and_then(|hedge| Some((hedge, req.try_clone()?)));
+                let result = match hedge {
+                    Some((hedge, backup_req)) => {
+                        HedgedCall::new(&*self.load_balance, &self.service, &*self.hedge)
+                            .retry_budget(self.retry_budget.as_deref())
+                            .call(cx, (req, backup_req), addr, &mut picker, hedge)
+                            .await
+                    }
+                    None => self.call_address(cx, req, addr).await,
+                };
+                let err = match result {
+                    Ok(resp) => return Ok(resp),
                     Err(err) => {
-                        warn!("[VOLO] call endpoint: {:?} error: {:?}", addr, err);
-                        Err(err)
+                        warn!("[VOLO] call rpcinfo: {:?}, error: {:?}", cx.rpc_info(), err);
+                        err
                     }
                 };
-            } else {
+                req = match retry_req {
+                    Some(retry_req) if policy.is_retryable(&err) => retry_req,
+                    _ => return Err(err),
+                };
+                if let Some(budget) = &self.retry_budget {
+                    if !budget.try_withdraw() {
+                        warn!(
+                            "[VOLO] retry budget exhausted, call info: {:?}",
+                            cx.rpc_info()
+                        );
+                        return Err(err);
+                    }
+                }
+                let backoff = policy.backoff_of(call_count);
+                if !backoff.is_zero() {
+                    tokio::time::sleep(backoff).await;
+                }
+            }
+            if call_count == 0 {
                 warn!("[VOLO] zero call count, call info: {:?}", cx.rpc_info());
             }
             Err(LoadBalanceError::Retry).map_err(|err| err.into())?
@@ -134,7 +307,7 @@ where
     }
 }
 
-impl<D, LB, S> Debug for LoadBalanceService<D, LB, S>
+impl<D, LB, S, H> Debug for LoadBalanceService<D, LB, S, H>
 where
     D: Debug,
     LB: Debug,
@@ -148,9 +321,11 @@ where
     }
 }
 
-pub struct LbConfig<L, DISC> {
+pub struct LbConfig<L, DISC, H = NoHedge> {
     load_balance: L,
     discover: DISC,
+    retry_policy: Option<RetryPolicy>,
+    hedge: H,
 }
 
 impl<L, DISC> LbConfig<L, DISC> {
@@ -158,28 +333,202 @@ impl<L, DISC> LbConfig<L, DISC> {
         LbConfig {
             load_balance,
             discover,
+            retry_policy: None,
+            hedge: NoHedge,
         }
     }
+}
 
-    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, DISC> {
+impl<L, DISC, H> LbConfig<L, DISC, H> {
+    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, DISC, H> {
         LbConfig {
             load_balance,
             discover: self.discover,
+            retry_policy: self.retry_policy,
+            hedge: self.hedge,
         }
     }
 
-    pub fn discover<NDISC>(self, discover: NDISC) -> LbConfig<L, NDISC> {
+    pub fn discover<NDISC>(self, discover: NDISC) -> LbConfig<L, NDISC, H> {
         LbConfig {
             load_balance: self.load_balance,
             discover,
+            retry_policy: self.retry_policy,
+            hedge: self.hedge,
+        }
+    }
+
+    /// Sets the retry policy of the client.
+    ///
+    /// Defaults to `None`, which means no retry.
+    pub fn retry_policy(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets the hedge policy of the client.
+    ///
+    /// If the first attempt of a unary request is slow, a backup request will be sent to another
+    /// instance and the first response is used.
+    pub fn hedge(self, policy: HedgePolicy) -> LbConfig<L, DISC, Hedger> {
+        LbConfig {
+            load_balance: self.load_balance,
+            discover: self.discover,
+            retry_policy: self.retry_policy,
+            hedge: Hedger::new(policy),
         }
     }
 }
 
-impl<LB, DISC> MkLbLayer for LbConfig<LB, DISC> {
-    type Layer = LoadBalanceLayer<DISC, LB>;
+impl<LB, DISC, H> MkLbLayer for LbConfig<LB, DISC, H> {
+    type Layer = LoadBalanceLayer<DISC, LB, H>;
 
     fn make(self) -> Self::Layer {
         LoadBalanceLayer::new(self.discover, self.load_balance)
+            .retry_policy(self.retry_policy)
+            .with_hedge(self.hedge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use bytes::Bytes;
+    use motore::{service::service_fn, Service};
+    use volo::{
+        context::{Context, Endpoint, Role, RpcInfo},
+        discovery::StaticDiscover,
+        loadbalance::{hedge::HedgePolicy, random::WeightedRandomBalance, retry::RetryPolicy},
+        net::Address,
+    };
+
+    use super::LoadBalanceService;
+    use crate::{
+        codec::compression::CompressionEncoding, context::ClientContext, Request, SendEntryMessage,
+        Status,
+    };
+
+    /// A message that can be retried only if it's unary.
+    struct TestMessage {
+        unary: bool,
+    }
+
+    impl SendEntryMessage for TestMessage {
+        fn into_body(
+            self,
+            _compression_config: Option<CompressionEncoding>,
+        ) -> crate::BoxStream<'static, Result<Bytes, Status>> {
+            Box::pin(futures::stream::empty())
+        }
+
+        fn try_clone(&self) -> Option<Self> {
+            self.unary.then_some(Self { unary: true })
+        }
+    }
+
+    fn new_context() -> ClientContext {
+        ClientContext::new(RpcInfo::new(
+            Role::Client,
+            "/test.Test/Get".into(),
+            Endpoint::new("caller".into()),
+            Endpoint::new("callee".into()),
+            Default::default(),
+        ))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_policy() {
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+            "127.0.0.3:8000".parse().unwrap(),
+        ]);
+        let lb = WeightedRandomBalance::with_discover(&discover);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = {
+            let calls = calls.clone();
+            service_fn(move |_: &mut ClientContext, _: Request<TestMessage>| {
+                let calls = calls.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    if calls < 2 {
+                        Err(Status::unavailable("unavailable"))
+                    } else {
+                        Ok(())
+                    }
+                }
+            })
+        };
+        let policy = RetryPolicy::new()
+            .max_attempts(3)
+            .backoff(Duration::from_millis(10), Duration::from_secs(1));
+        let service = LoadBalanceService::with_retry_policy(discover, lb, service, policy);
+
+        let mut cx = new_context();
+        let req = Request::new(TestMessage { unary: true });
+        assert!(service.call(&mut cx, req).await.is_ok());
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 3);
+
+        // the streaming request can't be retried
+        let mut cx = new_context();
+        let req = Request::new(TestMessage { unary: false });
+        assert!(service.call(&mut cx, req).await.is_err());
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 1);
+
+        // the policy of the call overrides the one of the client
+        let mut cx = new_context();
+        cx.extensions_mut()
+            .insert(RetryPolicy::new().max_attempts(2));
+        let req = Request::new(TestMessage { unary: true });
+        assert!(service.call(&mut cx, req).await.is_err());
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedge() {
+        let slow = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let fast = Address::Ip("127.0.0.2:8000".parse().unwrap());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+        ]);
+        let lb = WeightedRandomBalance::with_discover(&discover);
+        let service = {
+            let slow = slow.clone();
+            service_fn(move |cx: &mut ClientContext, _: Request<TestMessage>| {
+                let address = cx.rpc_info().callee().unwrap().address().unwrap();
+                let latency = if address == slow {
+                    Duration::from_secs(1)
+                } else {
+                    Duration::from_millis(10)
+                };
+                async move {
+                    tokio::time::sleep(latency).await;
+                    Ok::<_, Status>(address)
+                }
+            })
+        };
+        let service =
+            LoadBalanceService::with_retry_policy(discover, lb, service, RetryPolicy::new())
+                .hedge(HedgePolicy::new(Duration::from_millis(50)));
+
+        // whichever is picked first, the fast one wins
+        for _ in 0..10 {
+            let mut cx = new_context();
+            let start = tokio::time::Instant::now();
+            let req = Request::new(TestMessage { unary: true });
+            assert_eq!(service.call(&mut cx, req).await.unwrap(), fast);
+            assert_eq!(
+                cx.rpc_info().callee().unwrap().address(),
+                Some(fast.clone())
+            );
+            assert!(start.elapsed() < Duration::from_millis(100));
+        }
     }
 }
@@ -8,6 +8,17 @@ pub trait SendEntryMessage {
         self,
         compression_config: Option<CompressionEncoding>,
     ) -> crate::BoxStream<'static, Result<Bytes, crate::Status>>;
+
+    /// Clones the message if it can be sent more than once, such as the message of a unary
+    /// request, so that the request can be retried.
+    ///
+    /// Returns `None` by default, which means the message can only be sent once.
+    fn try_clone(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
 }
 
 pub trait RecvEntryMessage: Sized {
@@ -5,7 +5,7 @@ use std::fmt::Debug;
 use futures::prelude::*;
 use http::Extensions;
 
-use crate::metadata::MetadataMap;
+use crate::{message::SendEntryMessage, metadata::MetadataMap};
 
 #[derive(Debug)]
 pub struct Request<T> {
@@ -84,6 +84,20 @@ impl<T> Request<T> {
         &mut self.extensions
     }
 
+    /// Clones the request if the message can be sent more than once.
+    ///
+    /// The extensions are not cloned, since they can't be cloned.
+    pub(crate) fn try_clone(&self) -> Option<Self>
+    where
+        T: SendEntryMessage,
+    {
+        Some(Self {
+            metadata: self.metadata.clone(),
+            message: self.message.try_clone()?,
+            extensions: Extensions::new(),
+        })
+    }
+
     #[doc(hidden)]
     pub fn map<F, U>(self, f: F) -> Request<U>
     where
@@ -6,7 +6,7 @@ mod meta;
 mod router;
 mod service;
 
-use std::{fmt, io, time::Duration};
+use std::{fmt, io, sync::Arc, time::Duration};
 
 use motore::{
     layer::{Identity, Layer, Stack},
@@ -14,7 +14,13 @@ use motore::{
     BoxError,
 };
 pub use service::ServiceBuilder;
-use volo::{net::incoming::Incoming, spawn};
+#[cfg(target_family = "unix")]
+use volo::hotrestart::{HotRestartConfig, DEFAULT_HOT_RESTART};
+use volo::{
+    net::incoming::Incoming,
+    server::{ServerHandle, ServerState},
+    spawn,
+};
 
 pub use self::router::Router;
 use crate::{
@@ -36,6 +42,8 @@ pub struct Server<L> {
     layer: L,
     http2_config: Http2Config,
     router: Router,
+    #[cfg(target_family = "unix")]
+    hot_restart: Option<HotRestartConfig>,
 }
 
 impl Default for Server<Identity> {
@@ -51,6 +59,8 @@ impl Server<Identity> {
             layer: Identity::new(),
             http2_config: Http2Config::default(),
             router: Router::new(),
+            #[cfg(target_family = "unix")]
+            hot_restart: None,
         }
     }
 }
@@ -158,6 +168,20 @@ impl<L> Server<L> {
         self
     }
 
+    /// Enables the hot restart, so that a new process of the server takes over the listener of
+    /// the running one, which exits after draining its connections, without refusing any
+    /// connection.
+    ///
+    /// The servers of a process share the hot restart, so they should be given the same config,
+    /// whose listener number counts the listeners of all of them.
+    ///
+    /// Default is `None`, which means disabled.
+    #[cfg(target_family = "unix")]
+    pub fn hot_restart(mut self, config: Option<HotRestartConfig>) -> Self {
+        self.hot_restart = config;
+        self
+    }
+
     /// Adds a new inner layer to the server.
     ///
     /// The layer's `Service` should be `Send + Sync + Clone + 'static`.
@@ -174,6 +198,8 @@ impl<L> Server<L> {
             layer: Stack::new(layer, self.layer),
             http2_config: self.http2_config,
             router: self.router,
+            #[cfg(target_family = "unix")]
+            hot_restart: self.hot_restart,
         }
     }
 
@@ -193,6 +219,8 @@ impl<L> Server<L> {
             layer: Stack::new(self.layer, layer),
             http2_config: self.http2_config,
             router: self.router,
+            #[cfg(target_family = "unix")]
+            hot_restart: self.hot_restart,
         }
     }
 
@@ -210,6 +238,8 @@ impl<L> Server<L> {
             layer: self.layer,
             http2_config: self.http2_config,
             router: self.router.add_service(s),
+            #[cfg(target_family = "unix")]
+            hot_restart: self.hot_restart,
         }
     }
 
@@ -232,19 +262,75 @@ impl<L> Server<L> {
             + 'static,
         <L::Service as Service<ServerContext, Request<hyper::Body>>>::Error: Into<Status> + Send,
     {
+        self.serve(incoming, signal, Arc::new(ServerState::new()))
+            .await
+    }
+
+    /// Spawns the server in background, and returns the [`ServerHandle`] to wait for the server
+    /// to be ready and to stop it.
+    pub fn spawn<A>(self, incoming: A) -> ServerHandle
+    where
+        A: volo::net::MakeIncoming + Send + 'static,
+        L: Layer<Router> + Send + 'static,
+        L::Service: Service<ServerContext, Request<hyper::Body>, Response = Response<Body>>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <L::Service as Service<ServerContext, Request<hyper::Body>>>::Error: Into<Status> + Send,
+    {
+        ServerHandle::spawn(move |state, signal| self.serve(incoming, signal, state))
+    }
+
+    async fn serve<A, F>(
+        self,
+        incoming: A,
+        signal: F,
+        state: Arc<ServerState>,
+    ) -> Result<(), BoxError>
+    where
+        A: volo::net::MakeIncoming,
+        F: std::future::Future<Output = io::Result<()>>,
+        L: Layer<Router>,
+        L::Service: Service<ServerContext, Request<hyper::Body>, Response = Response<Body>>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <L::Service as Service<ServerContext, Request<hyper::Body>>>::Error: Into<Status> + Send,
+    {
+        #[cfg(target_family = "unix")]
+        let hot_restart = self.hot_restart.is_some();
+        #[cfg(target_family = "unix")]
+        if let Some(config) = &self.hot_restart {
+            DEFAULT_HOT_RESTART.initialize_with(config).await?;
+        }
+        // shuts down gracefully as well when the new process has taken over the listener
+        let terminated = async {
+            #[cfg(target_family = "unix")]
+            if hot_restart {
+                DEFAULT_HOT_RESTART.terminated().await;
+                tracing::info!("[VOLO] hot restarted by the new process");
+                return;
+            }
+            std::future::pending().await
+        };
+
         let mut incoming = incoming.make_incoming().await?;
         tracing::info!("[VOLO] server start at: {:?}", incoming);
+        state.set_ready(incoming.local_addr());
 
         let service = motore::builder::ServiceBuilder::new()
             .layer(self.layer)
             .service(self.router);
 
         tokio::pin!(signal);
+        tokio::pin!(terminated);
         let (tx, rx) = tokio::sync::watch::channel(());
 
         loop {
             tokio::select! {
-                _ = &mut signal => {
+                _ = futures::future::select(&mut signal, &mut terminated) => {
                     drop(rx);
                     tracing::info!("[VOLO] graceful shutdown");
                     let _ = tx.send(());
@@ -278,6 +364,8 @@ impl<L> Server<L> {
                         .http2_max_header_list_size(self.http2_config.max_header_list_size);
 
                     let mut watch = rx.clone();
+                    let state = state.clone();
+                    state.on_accept();
                     spawn(async move {
                         let mut http_conn = server.serve_connection(conn, service);
                         tokio::select! {
@@ -297,6 +385,7 @@ impl<L> Server<L> {
                                 }
                             },
                         }
+                        state.on_close();
                     });
                 },
             }
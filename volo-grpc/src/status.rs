@@ -7,7 +7,10 @@ use http::header::{HeaderMap, HeaderValue};
 use percent_encoding::{percent_decode, percent_encode, AsciiSet, CONTROLS};
 use tower::BoxError;
 use tracing::{debug, trace, warn};
-use volo::loadbalance::error::{LoadBalanceError, Retryable};
+use volo::{
+    limit::LimitError,
+    loadbalance::error::{LoadBalanceError, Retryable},
+};
 
 use crate::{body::Body, metadata::MetadataMap};
 
@@ -764,7 +767,22 @@ impl From<BoxError> for Status {
 
 impl From<LoadBalanceError> for Status {
     fn from(err: LoadBalanceError) -> Self {
-        Self::unknown(err.to_string())
+        match err {
+            LoadBalanceError::InstanceCircuitOpen(_) => Self::unavailable(err.to_string()),
+            // the open circuit of the service is not retryable, see the `Retryable` impl
+            LoadBalanceError::ServiceCircuitOpen(_) => {
+                let mut status = Self::unavailable(err.to_string());
+                status.source = Some(Arc::new(err));
+                status
+            }
+            _ => Self::unknown(err.to_string()),
+        }
+    }
+}
+
+impl From<LimitError> for Status {
+    fn from(err: LimitError) -> Self {
+        Self::resource_exhausted(err.to_string())
     }
 }
 
@@ -776,6 +794,13 @@ impl From<anyhow::Error> for Status {
 
 impl Retryable for Status {
     fn retryable(&self) -> bool {
+        if let Some(LoadBalanceError::ServiceCircuitOpen(_)) = self
+            .source
+            .as_deref()
+            .and_then(|err| err.downcast_ref::<LoadBalanceError>())
+        {
+            return false;
+        }
         matches!(
             self.code,
             Code::Internal | Code::Unavailable | Code::Cancelled | Code::ResourceExhausted
@@ -1035,4 +1060,16 @@ mod tests {
 
         assert_eq!(status.details(), DETAILS);
     }
+
+    #[test]
+    fn circuit_open() {
+        let status = Status::from(LoadBalanceError::InstanceCircuitOpen("instance".into()));
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(status.retryable());
+
+        // retrying other instances won't help if the circuit of the service is open
+        let status = Status::from(LoadBalanceError::ServiceCircuitOpen("service".into()));
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(!status.retryable());
+    }
 }
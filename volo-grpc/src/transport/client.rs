@@ -41,11 +41,25 @@ impl<U> ClientTransport<U> {
     /// Creates a new [`ClientTransport`] by setting the underlying connection
     /// with the given config.
     pub fn new(http2_config: &Http2Config, rpc_config: &Config) -> Self {
-        let config = volo::net::dial::Config::new(
-            rpc_config.connect_timeout,
-            rpc_config.read_timeout,
-            rpc_config.write_timeout,
-        );
+        let config = dial_config(rpc_config);
+        Self::with_connector(http2_config, Connector::new(Some(config)))
+    }
+
+    /// Creates a new [`ClientTransport`] which connects to the servers over TLS.
+    #[cfg(feature = "tls")]
+    pub fn new_with_tls(
+        http2_config: &Http2Config,
+        rpc_config: &Config,
+        tls_connector: volo::net::tls::TlsConnector,
+    ) -> Self {
+        let config = dial_config(rpc_config);
+        Self::with_connector(
+            http2_config,
+            Connector::new_with_tls(Some(config), tls_connector),
+        )
+    }
+
+    fn with_connector(http2_config: &Http2Config, connector: Connector) -> Self {
         let http = HyperClient::builder()
             .http2_only(!http2_config.accept_http1)
             .http2_initial_stream_window_size(http2_config.init_stream_window_size)
@@ -58,7 +72,7 @@ impl<U> ClientTransport<U> {
             .http2_max_concurrent_reset_streams(http2_config.max_concurrent_reset_streams)
             .http2_max_send_buf_size(http2_config.max_send_buf_size)
             .retry_canceled_requests(http2_config.retry_canceled_requests)
-            .build(Connector::new(Some(config)));
+            .build(connector);
 
         ClientTransport {
             http_client: http,
@@ -67,6 +81,14 @@ impl<U> ClientTransport<U> {
     }
 }
 
+fn dial_config(rpc_config: &Config) -> volo::net::dial::Config {
+    volo::net::dial::Config::new(
+        rpc_config.connect_timeout,
+        rpc_config.read_timeout,
+        rpc_config.write_timeout,
+    )
+}
+
 impl<T, U> Service<ClientContext, Request<T>> for ClientTransport<U>
 where
     T: crate::message::SendEntryMessage + Send + 'static,
@@ -176,7 +198,7 @@ where
     }
 }
 
-fn build_uri(addr: Address, path: &str) -> hyper::Uri {
+pub(super) fn build_uri(addr: Address, path: &str) -> hyper::Uri {
     match addr {
         Address::Ip(ip) => hyper::Uri::builder()
             .scheme(http::uri::Scheme::HTTP)
@@ -15,9 +15,18 @@ use volo::net::{
     dial::{Config, DefaultMakeTransport, MakeTransport},
     Address,
 };
+#[cfg(feature = "tls")]
+use volo::net::{
+    conn::ConnStream,
+    tls::{TlsConnector, TlsMakeTransport},
+};
 
 #[derive(Clone, Debug)]
-pub struct Connector(DefaultMakeTransport);
+pub struct Connector {
+    mt: DefaultMakeTransport,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsMakeTransport>,
+}
 
 impl Connector {
     pub fn new(cfg: Option<Config>) -> Self {
@@ -27,7 +36,32 @@ impl Connector {
             mt.set_read_timeout(cfg.read_timeout);
             mt.set_write_timeout(cfg.write_timeout);
         }
-        Self(mt)
+        Self {
+            mt,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Connects to the servers over TLS, the protocol `h2` is negotiated by ALPN if the
+    /// [`TlsConnector`] doesn't specify any.
+    #[cfg(feature = "tls")]
+    pub fn new_with_tls(cfg: Option<Config>, connector: TlsConnector) -> Self {
+        let connector = if connector.alpn_protocols().is_empty() {
+            connector.with_alpn_protocols(vec![b"h2".to_vec()])
+        } else {
+            connector
+        };
+        let mut mt = TlsMakeTransport::new(connector);
+        if let Some(cfg) = cfg {
+            mt.set_connect_timeout(cfg.connect_timeout);
+            mt.set_read_timeout(cfg.read_timeout);
+            mt.set_write_timeout(cfg.write_timeout);
+        }
+        Self {
+            tls: Some(mt),
+            ..Self::new(cfg)
+        }
     }
 }
 
@@ -49,7 +83,7 @@ impl tower::Service<hyper::Uri> for Connector {
     }
 
     fn call(&mut self, uri: hyper::Uri) -> Self::Future {
-        let mk_conn = self.0;
+        let mk_conn = self.clone();
         Box::pin(async move {
             let authority = uri.authority().expect("authority required").as_str();
             let target: Address = match uri.scheme_str() {
@@ -81,7 +115,11 @@ impl tower::Service<hyper::Uri> for Connector {
                 _ => unimplemented!(),
             };
 
-            Ok(ConnectionWrapper(mk_conn.make_connection(target).await?))
+            #[cfg(feature = "tls")]
+            if let Some(mt) = mk_conn.tls {
+                return Ok(ConnectionWrapper(mt.make_connection(target).await?));
+            }
+            Ok(ConnectionWrapper(mk_conn.mt.make_connection(target).await?))
         })
     }
 }
@@ -122,6 +160,12 @@ impl AsyncWrite for ConnectionWrapper {
 
 impl Connection for ConnectionWrapper {
     fn connected(&self) -> Connected {
+        #[cfg(feature = "tls")]
+        if let ConnStream::Tls(stream) = &self.0.stream {
+            if stream.get_ref().1.alpn_protocol() == Some(b"h2") {
+                return Connected::new().negotiated_h2();
+            }
+        }
         Connected::new()
     }
 }
@@ -0,0 +1,162 @@
+use std::future::Future;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::{
+    header::{CONTENT_TYPE, TE},
+    HeaderValue,
+};
+use hyper::Client as HyperClient;
+use pilota::prost::encoding::{decode_key, int32, skip_field, string, DecodeContext};
+use volo::{discovery::health::Probe, net::Address, FastStr};
+
+use super::{client::build_uri, connect::Connector};
+use crate::{Code, Status};
+
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+/// The `SERVING` status of `grpc.health.v1.HealthCheckResponse`.
+const SERVING: i32 = 1;
+
+/// [`HealthProbe`] checks the instances by the [gRPC health checking protocol], which can be
+/// used with [`volo::discovery::HealthCheckDiscover`].
+///
+/// [gRPC health checking protocol]: https://github.com/grpc/grpc/blob/master/doc/health-checking.md
+#[derive(Clone)]
+pub struct HealthProbe {
+    http_client: HyperClient<Connector>,
+    service: FastStr,
+}
+
+impl HealthProbe {
+    /// Creates a probe that checks the overall health of the server.
+    pub fn new() -> Self {
+        Self {
+            http_client: HyperClient::builder()
+                .http2_only(true)
+                .build(Connector::default()),
+            service: FastStr::empty(),
+        }
+    }
+
+    /// Sets the name of the service to check.
+    pub fn service(mut self, service: impl Into<FastStr>) -> Self {
+        self.service = service.into();
+        self
+    }
+
+    async fn check(&self, address: &Address) -> Result<bool, Status> {
+        let mut req = hyper::Request::new(hyper::Body::from(encode_request(&self.service)));
+        *req.version_mut() = http::Version::HTTP_2;
+        *req.method_mut() = http::Method::POST;
+        *req.uri_mut() = build_uri(address.clone(), HEALTH_CHECK_PATH);
+        req.headers_mut()
+            .insert(TE, HeaderValue::from_static("trailers"));
+        req.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+
+        let resp = self
+            .http_client
+            .request(req)
+            .await
+            .map_err(|err| Status::from_error(err.into()))?;
+        // a trailers-only response carries the status in the headers
+        if let Some(status) = Status::from_header_map(resp.headers()) {
+            if status.code() != Code::Ok {
+                return Err(status);
+            }
+        }
+        let body = hyper::body::to_bytes(resp.into_body())
+            .await
+            .map_err(|err| Status::from_error(err.into()))?;
+        Ok(decode_response(body) == Some(SERVING))
+    }
+}
+
+impl Default for HealthProbe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Probe for HealthProbe {
+    type ProbeFut<'a> = impl Future<Output = bool> + Send + 'a;
+
+    fn probe<'a>(&'a self, address: &'a Address) -> Self::ProbeFut<'a> {
+        async move {
+            match self.check(address).await {
+                Ok(serving) => serving,
+                Err(status) => {
+                    tracing::debug!("[VOLO] grpc health check {} error: {}", address, status);
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Encodes the length prefixed `HealthCheckRequest`.
+fn encode_request(service: &FastStr) -> Bytes {
+    let mut message = BytesMut::new();
+    if !service.is_empty() {
+        string::encode(1, &service.to_string(), &mut message);
+    }
+    let mut buf = BytesMut::with_capacity(5 + message.len());
+    // uncompressed
+    buf.put_u8(0);
+    buf.put_u32(message.len() as u32);
+    buf.put(message);
+    buf.freeze()
+}
+
+/// Decodes the status of the length prefixed `HealthCheckResponse`, `None` if it's invalid.
+fn decode_response(mut body: Bytes) -> Option<i32> {
+    if body.len() < 5 {
+        return None;
+    }
+    body.advance(1);
+    let len = body.get_u32() as usize;
+    if body.len() < len {
+        return None;
+    }
+    let mut message = body.split_to(len);
+    let mut status = 0;
+    while message.has_remaining() {
+        let (tag, wire_type) = decode_key(&mut message).ok()?;
+        if tag == 1 {
+            int32::merge(
+                wire_type,
+                &mut status,
+                &mut message,
+                DecodeContext::default(),
+            )
+        } else {
+            skip_field(wire_type, tag, &mut message, DecodeContext::default())
+        }
+        .ok()?;
+    }
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{decode_response, encode_request, SERVING};
+
+    #[test]
+    fn test_codec() {
+        assert_eq!(&encode_request(&"".into())[..], &[0, 0, 0, 0, 0]);
+        assert_eq!(
+            &encode_request(&"a".into())[..],
+            &[0, 0, 0, 0, 3, 0x0a, 1, b'a']
+        );
+        assert_eq!(
+            decode_response(Bytes::from_static(&[0, 0, 0, 0, 2, 0x08, 1])),
+            Some(SERVING)
+        );
+        assert_eq!(
+            decode_response(Bytes::from_static(&[0, 0, 0, 0, 0])),
+            Some(0)
+        );
+        assert_eq!(decode_response(Bytes::from_static(&[0, 0])), None);
+    }
+}
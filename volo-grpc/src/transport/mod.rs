@@ -2,5 +2,7 @@
 
 mod client;
 mod connect;
+mod health;
 
 pub use client::ClientTransport;
+pub use health::HealthProbe;
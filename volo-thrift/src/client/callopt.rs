@@ -34,7 +34,7 @@
 //! ```
 
 use metainfo::{FastStrMap, TypeMap};
-use volo::net::Address;
+use volo::{loadbalance::retry::RetryPolicy, net::Address};
 
 use crate::context::Config;
 
@@ -53,6 +53,8 @@ pub struct CallOpt {
     pub caller_faststr_tags: FastStrMap,
     /// Sets the caller tags for the call.
     pub caller_tags: TypeMap,
+    /// Sets the retry policy for the call, which overrides the one of the client.
+    pub retry_policy: Option<RetryPolicy>,
 }
 
 impl CallOpt {
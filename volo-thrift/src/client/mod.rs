@@ -22,7 +22,13 @@ use volo::{
     client::WithOptService,
     context::{Context, Endpoint, Role, RpcInfo},
     discovery::{Discover, DummyDiscover},
-    loadbalance::{random::WeightedRandomBalance, LbConfig, MkLbLayer},
+    loadbalance::{
+        hedge::{HedgePolicy, Hedger},
+        outlier::OutlierDetectionConfig,
+        random::WeightedRandomBalance,
+        retry::RetryPolicy,
+        LbConfig, MkLbLayer,
+    },
     net::{
         dial::{DefaultMakeTransport, MakeTransport},
         Address,
@@ -53,6 +59,7 @@ pub struct ClientBuilder<IL, OL, MkClient, Req, Resp, MkT, MkC, LB> {
     pool: Option<pool::Config>,
     callee_name: FastStr,
     caller_name: FastStr,
+    multiplexed_service: Option<FastStr>,
     address: Option<Address>, // maybe address use Arc avoid memory alloc
     inner_layer: IL,
     outer_layer: OL,
@@ -85,6 +92,7 @@ impl<C, Req, Resp>
             config: Default::default(),
             pool: None,
             caller_name: "".into(),
+            multiplexed_service: None,
             callee_name: FastStr::new(service_name),
             address: None,
             inner_layer: Identity::new(),
@@ -103,17 +111,19 @@ impl<C, Req, Resp>
     }
 }
 
-impl<IL, OL, C, Req, Resp, MkT, MkC, LB, DISC>
-    ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, DISC>>
+impl<IL, OL, C, Req, Resp, MkT, MkC, LB, DISC, H>
+    ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, DISC, H>>
 {
+    #[allow(clippy::type_complexity)]
     pub fn load_balance<NLB>(
         self,
         load_balance: NLB,
-    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<NLB, DISC>> {
+    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<NLB, DISC, H>> {
         ClientBuilder {
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: self.inner_layer,
@@ -131,14 +141,16 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB, DISC>
         }
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn discover<NDISC>(
         self,
         discover: NDISC,
-    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, NDISC>> {
+    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, NDISC, H>> {
         ClientBuilder {
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: self.inner_layer,
@@ -161,6 +173,54 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB, DISC>
         self.mk_lb = self.mk_lb.retry_count(count);
         self
     }
+
+    /// Sets the retry policy of the client, which takes precedence over the retry count.
+    ///
+    /// The policy can be overridden per method by [`RetryPolicy::method`], or per call by
+    /// [`CallOpt::retry_policy`].
+    pub fn retry_policy(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.mk_lb = self.mk_lb.retry_policy(policy);
+        self
+    }
+
+    /// Sets the outlier detection config of the client.
+    ///
+    /// The instances that keep failing will be ejected from load balancing for a while.
+    pub fn outlier_detection(mut self, config: Option<OutlierDetectionConfig>) -> Self {
+        self.mk_lb = self.mk_lb.outlier_detection(config);
+        self
+    }
+
+    /// Sets the hedge policy of the client.
+    ///
+    /// A backup request will be sent to another instance if the first one hasn't returned within
+    /// the delay of the policy, which should only be enabled for idempotent methods.
+    #[allow(clippy::type_complexity)]
+    pub fn hedge(
+        self,
+        policy: HedgePolicy,
+    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, DISC, Hedger>> {
+        ClientBuilder {
+            config: self.config,
+            pool: self.pool,
+            caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
+            callee_name: self.callee_name,
+            address: self.address,
+            inner_layer: self.inner_layer,
+            outer_layer: self.outer_layer,
+            mk_client: self.mk_client,
+            _marker: PhantomData,
+            make_transport: self.make_transport,
+            make_codec: self.make_codec,
+            mk_lb: self.mk_lb.hedge(policy),
+
+            disable_timeout_layer: self.disable_timeout_layer,
+
+            #[cfg(feature = "multiplex")]
+            multiplex: self.multiplex,
+        }
+    }
 }
 
 impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LB> {
@@ -206,6 +266,14 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         self
     }
 
+    /// Prefixes the method names sent to the server with `service:`, following the
+    /// `TMultiplexedProtocol` convention of Apache Thrift, to call one of the services
+    /// registered on a multiplexed server.
+    pub fn multiplexed_service(mut self, service_name: impl AsRef<str>) -> Self {
+        self.multiplexed_service = Some(FastStr::new(service_name));
+        self
+    }
+
     /// Disable the default timeout layer.
     #[doc(hidden)]
     pub fn disable_timeout_layer(mut self) -> Self {
@@ -221,6 +289,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: self.inner_layer,
@@ -254,6 +323,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: self.inner_layer,
@@ -281,6 +351,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: self.inner_layer,
@@ -298,6 +369,15 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         }
     }
 
+    /// Connects to the servers over TLS.
+    #[cfg(feature = "tls")]
+    pub fn tls_connector(
+        self,
+        connector: volo::net::tls::TlsConnector,
+    ) -> ClientBuilder<IL, OL, C, Req, Resp, volo::net::tls::TlsMakeTransport, MkC, LB> {
+        self.make_transport(volo::net::tls::TlsMakeTransport::new(connector))
+    }
+
     /// Sets the target address.
     ///
     /// If the address is set, the call will be sent to the address directly.
@@ -329,6 +409,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: Stack::new(layer, self.inner_layer),
@@ -367,6 +448,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: self.inner_layer,
@@ -405,6 +487,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: self.inner_layer,
@@ -430,6 +513,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
             config: self.config,
             pool: self.pool,
             caller_name: self.caller_name,
+            multiplexed_service: self.multiplexed_service,
             callee_name: self.callee_name,
             address: self.address,
             inner_layer: self.inner_layer,
@@ -461,6 +545,7 @@ where
         pingpong::Client<Resp, MkT, MkC>,
         crate::transport::multiplex::Client<Resp, MkT, MkC>,
     >,
+    multiplexed_service: Option<FastStr>,
 }
 
 impl<Req, Resp, MkT, MkC> Service<ClientContext, Req> for MessageService<Resp, MkT, MkC>
@@ -481,7 +566,11 @@ where
         's: 'cx,
     {
         async move {
-            let msg = ThriftMessage::mk_client_msg(cx, Ok(req))?;
+            let mut msg = ThriftMessage::mk_client_msg(cx, Ok(req))?;
+            if let Some(service) = &self.multiplexed_service {
+                // only the method name on the wire is prefixed, the layers see the plain one
+                msg.meta.method = format!("{service}:{}", msg.meta.method).into();
+            }
             let resp = self.inner.call(cx, msg).await;
             match resp {
                 Ok(Some(ThriftMessage { data: Ok(data), .. })) => Ok(Some(data)),
@@ -557,6 +646,7 @@ where
                     self.make_codec,
                 ))
             },
+            multiplexed_service: self.multiplexed_service,
         };
 
         let transport = if !self.disable_timeout_layer {
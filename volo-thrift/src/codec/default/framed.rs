@@ -153,6 +153,9 @@ pub fn is_framed(buf: &[u8]) -> bool {
     ||
     // compact
     buf[4] == 0x82
+    ||
+    // json
+    buf[4] == b'['
 }
 
 #[derive(Clone)]
@@ -0,0 +1,688 @@
+//! Thrift over HTTP, which is the `THttpClient` and `THttpServer` transports of Apache Thrift.
+//!
+//! Each call is sent as an HTTP/1.1 `POST` request, and the reply is the body of the response.
+//! The connections are kept alive and reused as the other transports.
+//!
+//! The server side detects the HTTP request by its `POST` method, so the server can serve the
+//! HTTP and the other transports on the same port.
+//!
+//! The requests other than `POST` are rejected by closing the connection.
+//!
+//! Note: `Expect: 100-continue` is not supported, since the codec can't write an interim response
+//! while reading the request, so the clients should not send it.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use linkedbytes::LinkedBytes;
+use pilota::thrift::{DecodeError, DecodeErrorKind, EncodeError, ProtocolErrorKind};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt};
+use tracing::trace;
+use volo::{context::Role, util::buf_reader::BufReader, FastStr};
+
+use super::{MakeZeroCopyCodec, ZeroCopyDecoder, ZeroCopyEncoder};
+use crate::{context::ThriftContext, EntryMessage, ThriftMessage};
+
+/// The content type used by the thrift HTTP transports of Apache Thrift.
+pub const DEFAULT_CONTENT_TYPE: &str = "application/x-thrift";
+
+/// Default limit of the body, which is the same as the max frame size of framed.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024; // 16MB
+
+/// The head must fit in the buffer of the reader.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+/// [`MakeHttpCodec`] implements [`MakeZeroCopyCodec`] to create [`HttpEncoder`] and
+/// [`HttpDecoder`].
+#[derive(Clone)]
+pub struct MakeHttpCodec<Inner: MakeZeroCopyCodec> {
+    inner: Inner,
+    path: FastStr,
+    content_type: FastStr,
+    max_body_size: usize,
+}
+
+impl<Inner: MakeZeroCopyCodec> MakeHttpCodec<Inner> {
+    #[inline]
+    pub fn new(inner: Inner) -> Self {
+        Self {
+            inner,
+            path: FastStr::from_static_str("/"),
+            content_type: FastStr::from_static_str(DEFAULT_CONTENT_TYPE),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Sets the path of the requests at client side, defaults to `/`.
+    #[inline]
+    pub fn with_path(mut self, path: impl Into<FastStr>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Sets the content type of the requests at client side, defaults to
+    /// [`DEFAULT_CONTENT_TYPE`].
+    ///
+    /// The server side replies with the content type of the request.
+    #[inline]
+    pub fn with_content_type(mut self, content_type: impl Into<FastStr>) -> Self {
+        self.content_type = content_type.into();
+        self
+    }
+
+    #[inline]
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+}
+
+impl<Inner: MakeZeroCopyCodec> MakeZeroCopyCodec for MakeHttpCodec<Inner> {
+    type Encoder = HttpEncoder<Inner::Encoder>;
+
+    type Decoder = HttpDecoder<Inner::Decoder>;
+
+    #[inline]
+    fn make_codec(&self) -> (Self::Encoder, Self::Decoder) {
+        let (encoder, decoder) = self.inner.make_codec();
+        (
+            HttpEncoder::new(
+                encoder,
+                self.path.clone(),
+                self.content_type.clone(),
+                self.max_body_size,
+            ),
+            HttpDecoder::new(decoder, self.max_body_size),
+        )
+    }
+}
+
+/// This is used to tell the encoder to encode the HTTP response at server side, and carries the
+/// content type of the request.
+pub struct HasHttp {
+    pub content_type: Option<FastStr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyLength {
+    Fixed(usize),
+    Chunked,
+}
+
+#[derive(Debug)]
+struct Head {
+    content_type: Option<FastStr>,
+    body_length: BodyLength,
+}
+
+#[derive(Clone)]
+pub struct HttpDecoder<D: ZeroCopyDecoder> {
+    inner: D,
+    max_body_size: usize,
+}
+
+impl<D: ZeroCopyDecoder> HttpDecoder<D> {
+    #[inline]
+    pub fn new(inner: D, max_body_size: usize) -> Self {
+        Self {
+            inner,
+            max_body_size,
+        }
+    }
+
+    #[inline]
+    fn check_body_size(&self, size: usize) -> Result<(), DecodeError> {
+        if size > self.max_body_size {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!(
+                    "http body size {size} exceeds max body size {}",
+                    self.max_body_size
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn read_body<R: AsyncRead + Unpin + Send + Sync>(
+        &self,
+        reader: &mut BufReader<R>,
+        body_length: BodyLength,
+    ) -> Result<Bytes, DecodeError> {
+        match body_length {
+            BodyLength::Fixed(size) => {
+                self.check_body_size(size)?;
+                let mut buffer = BytesMut::zeroed(size);
+                reader.read_exact(&mut buffer).await?;
+                Ok(buffer.freeze())
+            }
+            BodyLength::Chunked => {
+                let mut buffer = BytesMut::new();
+                loop {
+                    let line = read_line(reader).await?;
+                    // the chunk extensions after ';' are ignored
+                    let size = line.split(|b| *b == b';').next().unwrap_or_default();
+                    let size = std::str::from_utf8(size)
+                        .ok()
+                        .and_then(|s| usize::from_str_radix(s.trim(), 16).ok())
+                        .ok_or_else(|| {
+                            DecodeError::new(
+                                DecodeErrorKind::InvalidData,
+                                format!(
+                                    "invalid http chunk size {:?}",
+                                    String::from_utf8_lossy(&line)
+                                ),
+                            )
+                        })?;
+                    if size == 0 {
+                        // skip the trailers
+                        while !read_line(reader).await?.is_empty() {}
+                        return Ok(buffer.freeze());
+                    }
+                    self.check_body_size(buffer.len() + size)?;
+                    let start = buffer.len();
+                    buffer.resize(start + size, 0);
+                    reader.read_exact(&mut buffer[start..]).await?;
+                    if !read_line(reader).await?.is_empty() {
+                        return Err(DecodeError::new(
+                            DecodeErrorKind::InvalidData,
+                            "invalid http chunk end",
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<D> ZeroCopyDecoder for HttpDecoder<D>
+where
+    D: ZeroCopyDecoder,
+{
+    #[inline]
+    fn decode<Msg: Send + EntryMessage, Cx: ThriftContext>(
+        &mut self,
+        cx: &mut Cx,
+        bytes: &mut Bytes,
+    ) -> Result<Option<ThriftMessage<Msg>>, DecodeError> {
+        let role = cx.rpc_info().role();
+        if role == Role::Server && !is_http(bytes) {
+            return self.inner.decode(cx, bytes);
+        }
+        let (head_size, head) = parse_head(role, bytes)?.ok_or_else(|| {
+            DecodeError::new(DecodeErrorKind::InvalidData, "incomplete http head")
+        })?;
+        bytes.advance(head_size);
+        if role == Role::Server {
+            cx.extensions_mut().insert(HasHttp {
+                content_type: head.content_type,
+            });
+        }
+        let BodyLength::Fixed(size) = head.body_length else {
+            return Err(DecodeError::new(
+                DecodeErrorKind::NotImplemented,
+                "chunked http body is only supported as the outermost codec",
+            ));
+        };
+        self.check_body_size(size)?;
+        if bytes.len() < size {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                "incomplete http body",
+            ));
+        }
+        let mut body = bytes.split_to(size);
+        self.inner.decode(cx, &mut body)
+    }
+
+    #[inline]
+    async fn decode_async<
+        Msg: Send + EntryMessage,
+        Cx: ThriftContext,
+        R: AsyncRead + Unpin + Send + Sync,
+    >(
+        &mut self,
+        cx: &mut Cx,
+        reader: &mut BufReader<R>,
+    ) -> Result<Option<ThriftMessage<Msg>>, DecodeError> {
+        let role = cx.rpc_info().role();
+        if role == Role::Server {
+            // no HTTP, just forward to inner decoder
+            match reader.fill_buf_at_least(HTTP_DETECT_LENGTH).await {
+                Ok(buf) if is_http(buf) => {}
+                _ => return self.inner.decode_async(cx, reader).await,
+            }
+        }
+
+        // read the whole head, and call inner decode with the body
+        let mut len = 1;
+        let (head_size, head) = loop {
+            let buf = reader.fill_buf_at_least(len).await?;
+            if let Some(head) = parse_head(role, buf)? {
+                break head;
+            }
+            len = buf.len() + 1;
+            if len > MAX_HEAD_SIZE {
+                return Err(DecodeError::new(
+                    DecodeErrorKind::InvalidData,
+                    format!("http head exceeds {MAX_HEAD_SIZE} bytes"),
+                ));
+            }
+        };
+        trace!("[VOLO] decode http head: {:?}", head);
+        reader.consume(head_size);
+        // insert before reading the body, so the error reply is also an http response
+        if role == Role::Server {
+            cx.extensions_mut().insert(HasHttp {
+                content_type: head.content_type,
+            });
+        }
+
+        let mut body = self.read_body(reader, head.body_length).await?;
+        cx.stats_mut().set_read_size(head_size + body.len());
+        cx.stats_mut().record_read_end_at();
+
+        self.inner.decode(cx, &mut body)
+    }
+}
+
+/// `POST` and a space.
+pub const HTTP_DETECT_LENGTH: usize = 5;
+
+/// Detects the HTTP request of thrift, which is always `POST`.
+#[inline]
+pub fn is_http(buf: &[u8]) -> bool {
+    buf.starts_with(b"POST ")
+}
+
+/// Parses the request head at server side or the response head at client side, returns `None` if
+/// the head is incomplete.
+fn parse_head(role: Role, buf: &[u8]) -> Result<Option<(usize, Head)>, DecodeError> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let invalid = |e: httparse::Error| {
+        DecodeError::new(
+            DecodeErrorKind::InvalidData,
+            format!("invalid http head: {e}"),
+        )
+    };
+    let (size, headers) = match role {
+        Role::Server => {
+            let mut req = httparse::Request::new(&mut headers);
+            let httparse::Status::Complete(size) = req.parse(buf).map_err(invalid)? else {
+                return Ok(None);
+            };
+            if req.method != Some("POST") {
+                return Err(DecodeError::new(
+                    DecodeErrorKind::InvalidData,
+                    format!("http method {:?} is not allowed", req.method),
+                ));
+            }
+            (size, req.headers)
+        }
+        Role::Client => {
+            let mut resp = httparse::Response::new(&mut headers);
+            let httparse::Status::Complete(size) = resp.parse(buf).map_err(invalid)? else {
+                return Ok(None);
+            };
+            if resp.code != Some(200) {
+                return Err(DecodeError::new(
+                    DecodeErrorKind::InvalidData,
+                    format!(
+                        "http status {} {}",
+                        resp.code.unwrap_or_default(),
+                        resp.reason.unwrap_or_default()
+                    ),
+                ));
+            }
+            (size, resp.headers)
+        }
+    };
+
+    let mut head = Head {
+        content_type: None,
+        body_length: BodyLength::Fixed(0),
+    };
+    for header in headers.iter() {
+        if header.name.eq_ignore_ascii_case("content-length") {
+            let size = std::str::from_utf8(header.value)
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+                .ok_or_else(|| {
+                    DecodeError::new(
+                        DecodeErrorKind::InvalidData,
+                        format!(
+                            "invalid http content length {:?}",
+                            String::from_utf8_lossy(header.value)
+                        ),
+                    )
+                })?;
+            // the chunked transfer encoding overrides the content length
+            if head.body_length != BodyLength::Chunked {
+                head.body_length = BodyLength::Fixed(size);
+            }
+        } else if header.name.eq_ignore_ascii_case("transfer-encoding") {
+            if header
+                .value
+                .split(|b| *b == b',')
+                .any(|v| v.trim_ascii().eq_ignore_ascii_case(b"chunked"))
+            {
+                head.body_length = BodyLength::Chunked;
+            }
+        } else if header.name.eq_ignore_ascii_case("content-type") {
+            head.content_type = std::str::from_utf8(header.value)
+                .ok()
+                .map(|s| FastStr::new(s.trim()));
+        }
+    }
+    Ok(Some((size, head)))
+}
+
+/// Reads a line without the CRLF.
+async fn read_line<R: AsyncRead + Unpin + Send + Sync>(
+    reader: &mut BufReader<R>,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut line = Vec::new();
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                "unexpected end of http body",
+            ));
+        }
+        if let Some(i) = buf.iter().position(|b| *b == b'\n') {
+            line.extend_from_slice(&buf[..i]);
+            reader.consume(i + 1);
+            break;
+        }
+        line.extend_from_slice(buf);
+        let n = buf.len();
+        reader.consume(n);
+        if line.len() > MAX_HEAD_SIZE {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!("http line exceeds {MAX_HEAD_SIZE} bytes"),
+            ));
+        }
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(line)
+}
+
+#[derive(Clone)]
+pub struct HttpEncoder<E: ZeroCopyEncoder> {
+    inner: E,
+    path: FastStr,
+    content_type: FastStr,
+    max_body_size: usize,
+    head: Vec<u8>, // cache the head built in size
+}
+
+impl<E: ZeroCopyEncoder> HttpEncoder<E> {
+    #[inline]
+    pub fn new(inner: E, path: FastStr, content_type: FastStr, max_body_size: usize) -> Self {
+        Self {
+            inner,
+            path,
+            content_type,
+            max_body_size,
+            head: Vec::new(),
+        }
+    }
+}
+
+impl<E> ZeroCopyEncoder for HttpEncoder<E>
+where
+    E: ZeroCopyEncoder,
+{
+    #[inline]
+    fn encode<Msg: Send + EntryMessage, Cx: ThriftContext>(
+        &mut self,
+        cx: &mut Cx,
+        linked_bytes: &mut LinkedBytes,
+        msg: ThriftMessage<Msg>,
+    ) -> Result<(), EncodeError> {
+        // only encode http if role is client or server has detected http in decode
+        if cx.rpc_info().role() == Role::Client || cx.extensions().contains::<HasHttp>() {
+            linked_bytes.bytes_mut().put_slice(&self.head);
+        }
+        self.inner.encode(cx, linked_bytes, msg)
+    }
+
+    #[inline]
+    fn size<Msg: Send + EntryMessage, Cx: ThriftContext>(
+        &mut self,
+        cx: &mut Cx,
+        msg: &ThriftMessage<Msg>,
+    ) -> Result<(usize, usize), EncodeError> {
+        let (real_size, malloc_size) = self.inner.size(cx, msg)?;
+        self.head.clear();
+        match cx.rpc_info().role() {
+            Role::Client => {
+                // the host is the address of the callee if known, or the service name
+                let host = cx.rpc_info().callee().map(|callee| {
+                    callee
+                        .address()
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_else(|| callee.service_name().to_string())
+                });
+                self.head = format!(
+                    "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nAccept: \
+                     {}\r\nContent-Length: {}\r\n\r\n",
+                    self.path,
+                    host.unwrap_or_default(),
+                    self.content_type,
+                    self.content_type,
+                    real_size,
+                )
+                .into_bytes();
+            }
+            Role::Server => {
+                let Some(has_http) = cx.extensions().get::<HasHttp>() else {
+                    return Ok((real_size, malloc_size));
+                };
+                self.head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                    has_http
+                        .content_type
+                        .as_deref()
+                        .unwrap_or(&self.content_type),
+                    real_size,
+                )
+                .into_bytes();
+            }
+        }
+        if real_size > self.max_body_size {
+            return Err(EncodeError::new(
+                ProtocolErrorKind::SizeLimit,
+                format!(
+                    "http body size {real_size} exceeds max body size {}",
+                    self.max_body_size
+                ),
+            ));
+        }
+        Ok((real_size + self.head.len(), malloc_size + self.head.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use linkedbytes::LinkedBytes;
+    use pilota::thrift::{DecodeError, TMessageIdentifier, TMessageType};
+    use volo::{
+        context::{Context, Role, RpcInfo},
+        util::buf_reader::BufReader,
+    };
+
+    use super::{parse_head, BodyLength, HasHttp, MakeHttpCodec};
+    use crate::{
+        codec::default::{
+            framed::MakeFramedCodec, thrift::MakeThriftCodec, MakeZeroCopyCodec, ZeroCopyDecoder,
+            ZeroCopyEncoder,
+        },
+        context::{ClientContext, ServerContext, ThriftContext},
+        message_wrapper::MessageMeta,
+        multiplexed::MultiplexedRequest,
+        protocol::TBinaryProtocol,
+        EntryMessage, ThriftMessage,
+    };
+
+    fn message(msg_type: TMessageType) -> ThriftMessage<MultiplexedRequest> {
+        let msg_ident = TMessageIdentifier::new("echo".into(), msg_type, 1);
+        // an empty struct
+        let mut body = Bytes::from_static(&[0]);
+        let req =
+            MultiplexedRequest::decode(&mut TBinaryProtocol::new(&mut body, true), &msg_ident)
+                .unwrap();
+        ThriftMessage {
+            data: Ok(req),
+            meta: MessageMeta {
+                msg_type,
+                method: "echo".into(),
+                seq_id: 1,
+            },
+        }
+    }
+
+    fn encode<E: ZeroCopyEncoder, Cx: ThriftContext>(
+        encoder: &mut E,
+        cx: &mut Cx,
+        msg: ThriftMessage<MultiplexedRequest>,
+    ) -> Vec<u8> {
+        let (size, _) = encoder.size(cx, &msg).unwrap();
+        let mut linked_bytes = LinkedBytes::new();
+        encoder.encode(cx, &mut linked_bytes, msg).unwrap();
+        let mut buf = Vec::new();
+        linked_bytes.sync_write_all_vectored(&mut buf).unwrap();
+        assert_eq!(buf.len(), size);
+        buf
+    }
+
+    async fn decode<D: ZeroCopyDecoder, Cx: ThriftContext>(
+        decoder: &mut D,
+        cx: &mut Cx,
+        buf: &[u8],
+    ) -> Result<ThriftMessage<MultiplexedRequest>, DecodeError> {
+        let msg = decoder.decode_async(cx, &mut BufReader::new(buf)).await?;
+        Ok(msg.unwrap())
+    }
+
+    fn client_cx() -> ClientContext {
+        let mut cx = ClientContext::new(1, RpcInfo::with_role(Role::Client), TMessageType::Call);
+        cx.rpc_info.method = Some("echo".into());
+        cx
+    }
+
+    fn make_codec() -> MakeHttpCodec<MakeFramedCodec<MakeThriftCodec>> {
+        MakeHttpCodec::new(MakeFramedCodec::new(MakeThriftCodec::new())).with_path("/thrift")
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let (mut client_encoder, mut client_decoder) = make_codec().make_codec();
+        let (mut server_encoder, mut server_decoder) = make_codec().make_codec();
+
+        let mut client_cx = client_cx();
+        let req = encode(
+            &mut client_encoder,
+            &mut client_cx,
+            message(TMessageType::Call),
+        );
+        let head_end = req.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = std::str::from_utf8(&req[..head_end]).unwrap();
+        assert!(head.starts_with("POST /thrift HTTP/1.1\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", req.len() - head_end)));
+
+        let mut server_cx = ServerContext::default();
+        let msg = decode(&mut server_decoder, &mut server_cx, &req)
+            .await
+            .unwrap();
+        assert_eq!(msg.meta.method, "echo");
+        assert!(server_cx.extensions().contains::<HasHttp>());
+
+        let resp = encode(
+            &mut server_encoder,
+            &mut server_cx,
+            message(TMessageType::Reply),
+        );
+        assert!(resp.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        let msg = decode(&mut client_decoder, &mut client_cx, &resp)
+            .await
+            .unwrap();
+        assert_eq!(msg.meta.msg_type, TMessageType::Reply);
+        assert!(msg.data.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_chunked() {
+        let (mut encoder, _) = make_codec().make_codec();
+        let body = encode(
+            &mut encoder,
+            &mut ServerContext::default(),
+            message(TMessageType::Reply),
+        );
+        let (first, second) = body.split_at(3);
+        let mut resp = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        resp.extend_from_slice(format!("{:x};ext=1\r\n", first.len()).as_bytes());
+        resp.extend_from_slice(first);
+        resp.extend_from_slice(format!("\r\n{:X}\r\n", second.len()).as_bytes());
+        resp.extend_from_slice(second);
+        resp.extend_from_slice(b"\r\n0\r\nTrailer: a\r\n\r\n");
+
+        let (_, mut decoder) = make_codec().make_codec();
+        let msg = decode(&mut decoder, &mut client_cx(), &resp).await.unwrap();
+        assert_eq!(msg.meta.msg_type, TMessageType::Reply);
+        assert_eq!(msg.meta.method, "echo");
+
+        // the body exceeding the limit is rejected before read
+        let (_, mut decoder) = make_codec().with_max_body_size(3).make_codec();
+        assert!(decode(&mut decoder, &mut client_cx(), &resp).await.is_err());
+        // the chunked body can't be decoded from the frame of an outer codec
+        let (_, mut decoder) = make_codec().make_codec();
+        assert!(decoder
+            .decode::<MultiplexedRequest, _>(&mut client_cx(), &mut Bytes::from(resp))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_server_fall_through() {
+        // a framed request without http
+        let (mut encoder, _) = MakeFramedCodec::new(MakeThriftCodec::new()).make_codec();
+        let req = encode(&mut encoder, &mut client_cx(), message(TMessageType::Call));
+
+        let (mut encoder, mut decoder) = make_codec().make_codec();
+        let mut cx = ServerContext::default();
+        let msg = decode(&mut decoder, &mut cx, &req).await.unwrap();
+        assert_eq!(msg.meta.method, "echo");
+        assert!(!cx.extensions().contains::<HasHttp>());
+        // and the reply is not http either
+        let resp = encode(&mut encoder, &mut cx, message(TMessageType::Reply));
+        assert_eq!(
+            u32::from_be_bytes(resp[..4].try_into().unwrap()) as usize,
+            resp.len() - 4
+        );
+    }
+
+    #[test]
+    fn test_parse_head() {
+        let req = b"POST /thrift HTTP/1.1\r\nHost: a\r\ncontent-type: application/vnd.apache.thrift.json\r\nContent-Length: 12\r\n\r\n[1,";
+        let (size, head) = parse_head(Role::Server, req).unwrap().unwrap();
+        assert_eq!(size, req.len() - 3);
+        assert_eq!(head.body_length, BodyLength::Fixed(12));
+        assert_eq!(
+            head.content_type.as_deref(),
+            Some("application/vnd.apache.thrift.json")
+        );
+        assert!(parse_head(Role::Server, &req[..20]).unwrap().is_none());
+        assert!(parse_head(Role::Server, b"GET / HTTP/1.1\r\n\r\n").is_err());
+
+        let resp = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n";
+        let (_, head) = parse_head(Role::Client, resp).unwrap().unwrap();
+        assert_eq!(head.body_length, BodyLength::Chunked);
+        let err = parse_head(Role::Client, b"HTTP/1.1 502 Bad Gateway\r\n\r\n").unwrap_err();
+        assert!(err.to_string().contains("502 Bad Gateway"));
+    }
+}
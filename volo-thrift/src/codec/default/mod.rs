@@ -33,11 +33,15 @@ use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
 use tracing::{trace, warn};
 use volo::util::buf_reader::BufReader;
 
-use self::{framed::MakeFramedCodec, thrift::MakeThriftCodec, ttheader::MakeTTHeaderCodec};
+use self::{
+    framed::MakeFramedCodec, http::MakeHttpCodec, thrift::MakeThriftCodec,
+    ttheader::MakeTTHeaderCodec,
+};
 use super::{Decoder, Encoder, MakeCodec};
 use crate::{context::ThriftContext, EntryMessage, ThriftMessage};
 
 pub mod framed;
+pub mod http;
 pub mod thrift;
 pub mod ttheader;
 // mod mesh_header;
@@ -250,12 +254,29 @@ impl DefaultMakeCodec<MakeTTHeaderCodec<MakeFramedCodec<MakeThriftCodec>>> {
     }
 }
 
+impl<Inner: MakeZeroCopyCodec> DefaultMakeCodec<MakeTTHeaderCodec<Inner>> {
+    /// Sets the transforms of the TTHeader payload, such as compression.
+    ///
+    /// See [`MakeTTHeaderCodec::with_transforms`] for more details.
+    pub fn with_transforms(mut self, transforms: Vec<ttheader::Transform>) -> Self {
+        self.make_zero_copy_codec = self.make_zero_copy_codec.with_transforms(transforms);
+        self
+    }
+}
+
 impl DefaultMakeCodec<MakeThriftCodec> {
     pub fn buffered() -> Self {
         DefaultMakeCodec::new(thrift::MakeThriftCodec::default())
     }
 }
 
+impl DefaultMakeCodec<MakeHttpCodec<MakeThriftCodec>> {
+    /// Thrift over HTTP, see [`http`] for more details.
+    pub fn http() -> Self {
+        DefaultMakeCodec::new(http::MakeHttpCodec::new(thrift::MakeThriftCodec::default()))
+    }
+}
+
 impl<MkZC: MakeZeroCopyCodec> DefaultMakeCodec<MkZC> {
     /// `make_zero_copy_codec` should implement [`MakeZeroCopyCodec`], which will be used to create
     /// the inner [`ZeroCopyEncoder`] and [`ZeroCopyDecoder`].
@@ -6,13 +6,27 @@ use pilota::thrift::{
     DecodeError, DecodeErrorKind, EncodeError, ProtocolError, ProtocolErrorKind,
     TAsyncBinaryProtocol, TAsyncCompactProtocol, TLengthProtocol,
 };
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncBufReadExt, AsyncRead};
 use volo::util::buf_reader::BufReader;
 
-use super::{MakeZeroCopyCodec, ZeroCopyDecoder, ZeroCopyEncoder};
-use crate::{context::ThriftContext, EntryMessage, ThriftMessage};
+use super::{
+    framed::DEFAULT_MAX_FRAME_SIZE,
+    ttheader::{HasTTHeader, ProtocolId},
+    MakeZeroCopyCodec, ZeroCopyDecoder, ZeroCopyEncoder,
+};
+use crate::{
+    context::ThriftContext,
+    protocol::{
+        fbthrift_compact::{is_fbthrift_compact, TFBThriftCompactProtocol},
+        json::{MessageScanner, TJsonProtocol},
+    },
+    EntryMessage, ThriftMessage,
+};
 
 /// [`MakeThriftCodec`] implements [`MakeZeroCopyCodec`] to create [`ThriftCodec`].
+///
+/// The multiplexed protocol, which prefixes the method names with the service names, is handled
+/// by [`crate::multiplexed`] instead of the codec.
 #[derive(Debug, Clone, Copy)]
 pub struct MakeThriftCodec {
     protocol: Protocol,
@@ -26,20 +40,6 @@ impl MakeThriftCodec {
         }
     }
 
-    /// Whether to use thrift multiplex protocol.
-    ///
-    /// When the multiplexed protocol is used, the name contains the service name,
-    /// a colon : and the method name. The multiplexed protocol is not compatible
-    /// with other protocols.
-    ///
-    /// Spec: https://github.com/apache/thrift/blob/master/doc/specs/thrift-rpc.md
-    ///
-    /// This is unimplemented yet.
-    // pub fn with_multiplex(mut self, multiplex: bool) -> Self {
-    //     self.multiplex = multiplex;
-    //     self
-    // }
-
     /// The `protocol` only takes effect at client side. The server side will auto detect the
     /// protocol.
     pub fn with_protocol(mut self, protocol: Protocol) -> Self {
@@ -68,20 +68,35 @@ impl MakeZeroCopyCodec for MakeThriftCodec {
 }
 
 /// This is used to tell the encoder which protocol is used.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Protocol {
     Binary,
     ApacheCompact,
     FBThriftCompact,
+    Json,
+}
+
+impl From<Protocol> for ProtocolId {
+    fn from(protocol: Protocol) -> Self {
+        match protocol {
+            Protocol::Binary => ProtocolId::Binary,
+            Protocol::ApacheCompact => ProtocolId::Compact,
+            Protocol::FBThriftCompact => ProtocolId::CompactV2,
+            // there is no protocol id for json, the peer detects it from the payload
+            Protocol::Json => ProtocolId::Binary,
+        }
+    }
 }
 
 /// Use ZST to optimize performance(reduce a Box call).
 pub struct ProtocolBinary;
 pub struct ProtocolApacheCompact;
+pub struct ProtocolFBThriftCompact;
+pub struct ProtocolJson;
 
-/// 1-byte protocol id
+/// 1-byte protocol id and 1-byte version of the compact protocol
 /// https://github.com/apache/thrift/blob/master/doc/specs/thrift-rpc.md#compatibility
-pub const HEADER_DETECT_LENGTH: usize = 1;
+pub const HEADER_DETECT_LENGTH: usize = 2;
 
 #[derive(Debug, Clone, Copy)]
 pub struct ThriftCodec {
@@ -95,6 +110,22 @@ impl ThriftCodec {
     pub fn new(protocol: Protocol) -> Self {
         Self { protocol }
     }
+
+    /// Returns the protocol to encode with, the server side uses the protocol of the request.
+    #[inline]
+    fn encode_protocol<Cx: ThriftContext>(&self, cx: &Cx) -> Protocol {
+        if cx.extensions().contains::<ProtocolBinary>() {
+            Protocol::Binary
+        } else if cx.extensions().contains::<ProtocolApacheCompact>() {
+            Protocol::ApacheCompact
+        } else if cx.extensions().contains::<ProtocolFBThriftCompact>() {
+            Protocol::FBThriftCompact
+        } else if cx.extensions().contains::<ProtocolJson>() {
+            Protocol::Json
+        } else {
+            self.protocol
+        }
+    }
 }
 
 impl Default for ThriftCodec {
@@ -104,6 +135,27 @@ impl Default for ThriftCodec {
     }
 }
 
+/// Returns the protocol to decode with, which is carried by the TTHeader or detected from the
+/// payload.
+///
+/// The TTHeader of the compact protocols is trusted to tell the Apache and the FBThrift ones apart,
+/// but the binary one is not, since the old peers always write binary in the TTHeader.
+#[inline]
+fn decode_protocol<Cx: ThriftContext>(cx: &Cx, buf: &[u8]) -> Result<Protocol, DecodeError> {
+    if !cx.extensions().contains::<HasTTHeader>() {
+        return Ok(detect(buf)?);
+    }
+    match cx.extensions().get::<ProtocolId>() {
+        Some(ProtocolId::Compact) => Ok(Protocol::ApacheCompact),
+        Some(ProtocolId::CompactV2) => Ok(Protocol::FBThriftCompact),
+        Some(ProtocolId::Protobuf) => Err(DecodeError::new(
+            DecodeErrorKind::NotImplemented,
+            "protobuf protocol in ttheader is not supported",
+        )),
+        Some(ProtocolId::Binary) | None => Ok(detect(buf)?),
+    }
+}
+
 #[async_trait::async_trait]
 impl ZeroCopyDecoder for ThriftCodec {
     #[inline]
@@ -120,9 +172,7 @@ impl ZeroCopyDecoder for ThriftCodec {
             ));
         }
 
-        // detect protocol
-        // TODO: support using protocol from TTHeader
-        let protocol = detect(bytes)?;
+        let protocol = decode_protocol(cx, bytes)?;
         // TODO: do we need to check the response protocol at client side?
         match protocol {
             Protocol::Binary => {
@@ -132,6 +182,8 @@ impl ZeroCopyDecoder for ThriftCodec {
                 };
                 #[cfg(not(feature = "unsafe-codec"))]
                 let mut p = TBinaryProtocol::new(bytes, true);
+                // insert before decoding, so the error reply is encoded with the same protocol
+                cx.extensions_mut().insert(ProtocolBinary);
                 let msg = ThriftMessage::<Msg>::decode(&mut p, cx)?;
                 #[cfg(feature = "unsafe-codec")]
                 {
@@ -140,19 +192,27 @@ impl ZeroCopyDecoder for ThriftCodec {
                     let index = p.index();
                     p.buf().advance(index);
                 }
-                cx.extensions_mut().insert(ProtocolBinary);
                 Ok(Some(msg))
             }
             Protocol::ApacheCompact => {
                 let mut p = TCompactInputProtocol::new(bytes);
-                let msg = ThriftMessage::<Msg>::decode(&mut p, cx)?;
                 cx.extensions_mut().insert(ProtocolApacheCompact);
+                let msg = ThriftMessage::<Msg>::decode(&mut p, cx)?;
+                Ok(Some(msg))
+            }
+            Protocol::FBThriftCompact => {
+                let mut p = TFBThriftCompactProtocol(TCompactInputProtocol::new(bytes));
+                cx.extensions_mut().insert(ProtocolFBThriftCompact);
+                let msg = ThriftMessage::<Msg>::decode(&mut p, cx)?;
+                Ok(Some(msg))
+            }
+            Protocol::Json => {
+                cx.extensions_mut().insert(ProtocolJson);
+                check_json_supported::<Msg>()?;
+                let mut p = TJsonProtocol::new(bytes);
+                let msg = ThriftMessage::<Msg>::decode(&mut p, cx)?;
                 Ok(Some(msg))
             }
-            p => Err(pilota::thrift::error::DecodeError::new(
-                DecodeErrorKind::NotImplemented,
-                format!("protocol {p:?} is not supported"),
-            )),
         }
     }
 
@@ -176,29 +236,36 @@ impl ZeroCopyDecoder for ThriftCodec {
             ));
         };
 
-        // detect protocol
-        // TODO: support using protocol from TTHeader
-        let protocol = detect(buf).map_err(|e| {
+        let protocol = decode_protocol(cx, buf).inspect_err(|_| {
             cx.stats_mut().record_read_end_at();
-            e
         })?;
         // TODO: do we need to check the response protocol at client side?
         let res = match protocol {
             Protocol::Binary => {
                 let mut p = TAsyncBinaryProtocol::new(reader);
-                let msg = ThriftMessage::<Msg>::decode_async(&mut p, cx).await?;
+                // insert before decoding, so the error reply is encoded with the same protocol
                 cx.extensions_mut().insert(ProtocolBinary);
+                let msg = ThriftMessage::<Msg>::decode_async(&mut p, cx).await?;
                 Ok(Some(msg))
             }
             Protocol::ApacheCompact => {
                 let mut p = TAsyncCompactProtocol::new(reader);
-                let msg = ThriftMessage::<Msg>::decode_async(&mut p, cx).await?;
                 cx.extensions_mut().insert(ProtocolApacheCompact);
+                let msg = ThriftMessage::<Msg>::decode_async(&mut p, cx).await?;
                 Ok(Some(msg))
             }
+            Protocol::Json => {
+                let mut bytes = read_json_message(reader).await?;
+                cx.extensions_mut().insert(ProtocolJson);
+                check_json_supported::<Msg>()?;
+                let mut p = TJsonProtocol::new(&mut bytes);
+                let msg = ThriftMessage::<Msg>::decode(&mut p, cx)?;
+                Ok(Some(msg))
+            }
+            // the fbthrift compact protocol is only supported with a framed transport
             p => Err(pilota::thrift::error::DecodeError::new(
                 DecodeErrorKind::NotImplemented,
-                format!("protocol {p:?} is not supported"),
+                format!("protocol {p:?} is not supported without framed transport"),
             )),
         };
         cx.stats_mut().record_read_end_at();
@@ -206,14 +273,68 @@ impl ZeroCopyDecoder for ThriftCodec {
     }
 }
 
+#[inline]
+fn check_json_supported<Msg: EntryMessage>() -> Result<(), DecodeError> {
+    if Msg::JSON_SUPPORTED {
+        return Ok(());
+    }
+    Err(DecodeError::new(
+        DecodeErrorKind::NotImplemented,
+        format!(
+            "json protocol is not supported by {}",
+            std::any::type_name::<Msg>()
+        ),
+    ))
+}
+
+/// Reads a whole json message, since the json protocol has no async implementation.
+async fn read_json_message<R: AsyncRead + Unpin + Send>(
+    reader: &mut BufReader<R>,
+) -> Result<Bytes, DecodeError> {
+    let mut scanner = MessageScanner::default();
+    let mut message = Vec::new();
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                "unexpected end of json message",
+            ));
+        }
+        let (n, complete) = match scanner.scan(buf) {
+            Some(n) => (n, true),
+            None => (buf.len(), false),
+        };
+        message.extend_from_slice(&buf[..n]);
+        reader.consume(n);
+        if complete {
+            return Ok(message.into());
+        }
+        if message.len() > DEFAULT_MAX_FRAME_SIZE as usize {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!(
+                    "json message size exceeds the limit {}",
+                    DEFAULT_MAX_FRAME_SIZE
+                ),
+            ));
+        }
+    }
+}
+
 /// Detect protocol according to https://github.com/apache/thrift/blob/master/doc/specs/thrift-rpc.md#compatibility
 #[inline]
 pub fn detect(buf: &[u8]) -> Result<Protocol, ProtocolError> {
     if buf[0] == 0x80 || buf[0] == 0x00 {
         Ok(Protocol::Binary)
+    } else if is_fbthrift_compact(buf) {
+        // the version of the compact protocol is 1 for Apache and 2 for FBThrift
+        Ok(Protocol::FBThriftCompact)
     } else if buf[0] == 0x82 {
-        // TODO: how do we differ ApacheCompact and FBThriftCompact?
         Ok(Protocol::ApacheCompact)
+    } else if buf[0] == b'[' {
+        // the json message is an array
+        Ok(Protocol::Json)
     } else {
         Err(pilota::thrift::new_protocol_error(
             ProtocolErrorKind::BadVersion,
@@ -230,15 +351,7 @@ impl ZeroCopyEncoder for ThriftCodec {
         linked_bytes: &mut LinkedBytes,
         msg: ThriftMessage<Msg>,
     ) -> Result<(), EncodeError> {
-        // for the client side, the match expression will always be `&self.protocol`
-        // TODO: use the protocol in TTHeader?
-        let mut protocol = self.protocol;
-        if cx.extensions().contains::<ProtocolBinary>() {
-            protocol = Protocol::Binary;
-        } else if cx.extensions().contains::<ProtocolApacheCompact>() {
-            protocol = Protocol::ApacheCompact;
-        }
-        match protocol {
+        match self.encode_protocol(cx) {
             Protocol::Binary => {
                 #[cfg(feature = "unsafe-codec")]
                 let buf = unsafe {
@@ -275,10 +388,17 @@ impl ZeroCopyEncoder for ThriftCodec {
                 msg.encode(&mut p)?;
                 Ok(())
             }
-            p => Err(pilota::thrift::error::EncodeError::new(
-                ProtocolErrorKind::NotImplemented,
-                format!("protocol {p:?} is not supported"),
-            )),
+            Protocol::FBThriftCompact => {
+                let mut p =
+                    TFBThriftCompactProtocol(TCompactOutputProtocol::new(linked_bytes, true));
+                msg.encode(&mut p)?;
+                Ok(())
+            }
+            Protocol::Json => {
+                let mut p = TJsonProtocol::new(linked_bytes);
+                msg.encode(&mut p)?;
+                Ok(())
+            }
         }
     }
 
@@ -288,13 +408,11 @@ impl ZeroCopyEncoder for ThriftCodec {
         cx: &mut Cx,
         msg: &ThriftMessage<Msg>,
     ) -> Result<(usize, usize), EncodeError> {
-        // for the client side, the match expression will always be `&self.protocol`
-        // TODO: use the protocol in TTHeader?
-        let mut protocol = self.protocol;
-        if cx.extensions().contains::<ProtocolBinary>() {
-            protocol = Protocol::Binary;
-        } else if cx.extensions().contains::<ProtocolApacheCompact>() {
-            protocol = Protocol::ApacheCompact;
+        let protocol = self.encode_protocol(cx);
+        let protocol_id = ProtocolId::from(protocol);
+        if protocol_id != ProtocolId::Binary {
+            // tell the TTHeader encoder which protocol is used, binary is the default
+            cx.extensions_mut().insert(protocol_id);
         }
         match protocol {
             Protocol::Binary => {
@@ -309,10 +427,17 @@ impl ZeroCopyEncoder for ThriftCodec {
                 let malloc_size = real_size - p.zero_copy_len();
                 Ok((real_size, malloc_size))
             }
-            p => Err(pilota::thrift::error::EncodeError::new(
-                ProtocolErrorKind::NotImplemented,
-                format!("protocol {p:?} is not supported"),
-            )),
+            Protocol::FBThriftCompact => {
+                let mut p = TFBThriftCompactProtocol(TCompactOutputProtocol::new((), true));
+                let real_size = msg.size(&mut p);
+                let malloc_size = real_size - p.zero_copy_len();
+                Ok((real_size, malloc_size))
+            }
+            Protocol::Json => {
+                let mut p = TJsonProtocol::new(());
+                let real_size = msg.size(&mut p);
+                Ok((real_size, real_size))
+            }
         }
     }
 }
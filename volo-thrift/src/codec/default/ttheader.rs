@@ -5,10 +5,16 @@
 //! For more information, please visit https://www.cloudwego.io/docs/kitex/reference/transport_protocol_ttheader/
 
 use std::{
-    collections::HashMap, convert::TryFrom, default::Default, net::SocketAddr, time::Duration,
+    collections::HashMap,
+    convert::TryFrom,
+    default::Default,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    time::Duration,
 };
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use linkedbytes::LinkedBytes;
 use metainfo::{Backward, Forward};
 use num_enum::TryFromPrimitive;
@@ -33,11 +39,24 @@ use crate::{
 #[derive(Clone)]
 pub struct MakeTTHeaderCodec<Inner: MakeZeroCopyCodec> {
     inner: Inner,
+    transforms: Vec<Transform>,
 }
 
 impl<Inner: MakeZeroCopyCodec> MakeTTHeaderCodec<Inner> {
     pub fn new(inner: Inner) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            transforms: Vec::new(),
+        }
+    }
+
+    /// Sets the transforms applied to the payload in order when encoding.
+    ///
+    /// The server without transforms replies with the transforms of the request, and the
+    /// transforms advertised by the peer are always reverted when decoding.
+    pub fn with_transforms(mut self, transforms: Vec<Transform>) -> Self {
+        self.transforms = transforms;
+        self
     }
 }
 
@@ -48,13 +67,41 @@ impl<Inner: MakeZeroCopyCodec> MakeZeroCopyCodec for MakeTTHeaderCodec<Inner> {
 
     fn make_codec(&self) -> (Self::Encoder, Self::Decoder) {
         let (encoder, decoder) = self.inner.make_codec();
-        (TTHeaderEncoder::new(encoder), TTHeaderDecoder::new(decoder))
+        (
+            TTHeaderEncoder::new(encoder).with_transforms(self.transforms.clone()),
+            TTHeaderDecoder::new(decoder),
+        )
     }
 }
 
 /// This is used to tell the encoder to encode TTHeader at server side.
 pub struct HasTTHeader;
 
+/// The transforms of the payload advertised by the peer, which is used by the server to encode
+/// the response with the same transforms.
+struct PeerTransforms(Vec<Transform>);
+
+/// The transforms of the TTHeader payload, the ids are the same as [THeader][THeader].
+///
+/// The zstd transform requires the `zstd` feature.
+///
+/// [THeader]: https://github.com/apache/thrift/blob/master/doc/specs/HeaderFormat.md
+#[derive(TryFromPrimitive, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum Transform {
+    Zlib = 0x01,
+    Snappy = 0x03,
+    #[cfg(feature = "zstd")]
+    Zstd = 0x05,
+}
+
+#[cfg(not(feature = "zstd"))]
+const ZSTD_TRANSFORM_ID: u8 = 0x05;
+
+/// The limit of the payload size after reverting the transforms, to avoid decompression bombs.
+const MAX_UNTRANSFORMED_SIZE: usize = 64 * 1024 * 1024;
+
 #[derive(Clone)]
 pub struct TTHeaderDecoder<D: ZeroCopyDecoder> {
     inner: D,
@@ -86,11 +133,23 @@ where
         }
 
         if is_ttheader(&bytes[..HEADER_DETECT_LENGTH]) {
-            let _size = bytes.get_u32() as usize;
+            let size = bytes.get_u32() as usize;
+            if bytes.len() < size {
+                return Err(DecodeError::new(
+                    pilota::thrift::DecodeErrorKind::InvalidData,
+                    format!(
+                        "ttheader size {size} exceeds the remaining {} bytes",
+                        bytes.len()
+                    ),
+                ));
+            }
+            let mut frame = bytes.split_to(size);
             // decode ttheader
-            decode(cx, bytes)?;
+            decode(cx, &mut frame)?;
             // set has ttheader flag
             cx.extensions_mut().insert(HasTTHeader);
+            // decode inner
+            return self.inner.decode(cx, &mut frame);
         }
         // decode inner
         self.inner.decode(cx, bytes)
@@ -148,6 +207,7 @@ pub fn is_ttheader(buf: &[u8]) -> bool {
 pub struct TTHeaderEncoder<E: ZeroCopyEncoder> {
     inner: E,
     inner_size: usize, // used to cache the size
+    transforms: Vec<Transform>,
 }
 
 impl<E: ZeroCopyEncoder> TTHeaderEncoder<E> {
@@ -155,8 +215,25 @@ impl<E: ZeroCopyEncoder> TTHeaderEncoder<E> {
         Self {
             inner,
             inner_size: 0,
+            transforms: Vec::new(),
         }
     }
+
+    pub fn with_transforms(mut self, transforms: Vec<Transform>) -> Self {
+        self.transforms = transforms;
+        self
+    }
+
+    // the server replies with the transforms of the request if it has no transforms configured
+    fn transforms<Cx: ThriftContext>(&self, cx: &Cx) -> Vec<Transform> {
+        if !self.transforms.is_empty() || cx.rpc_info().role() == Role::Client {
+            return self.transforms.clone();
+        }
+        cx.extensions()
+            .get::<PeerTransforms>()
+            .map(|t| t.0.clone())
+            .unwrap_or_default()
+    }
 }
 
 impl<E> ZeroCopyEncoder for TTHeaderEncoder<E>
@@ -169,13 +246,30 @@ where
         linked_bytes: &mut LinkedBytes,
         msg: ThriftMessage<Msg>,
     ) -> Result<(), EncodeError> {
-        let dst = linked_bytes.bytes_mut();
         // only encode ttheader if role is client or server has detected ttheader in decode
-        if cx.rpc_info().role() == Role::Client || cx.extensions().contains::<HasTTHeader>() {
+        if cx.rpc_info().role() != Role::Client && !cx.extensions().contains::<HasTTHeader>() {
+            return self.inner.encode(cx, linked_bytes, msg);
+        }
+
+        let transforms = self.transforms(cx);
+        if transforms.is_empty() {
             // encode ttheader first
-            encode(cx, dst, self.inner_size)?;
+            encode(cx, linked_bytes.bytes_mut(), self.inner_size, &transforms)?;
+            return self.inner.encode(cx, linked_bytes, msg);
         }
-        self.inner.encode(cx, linked_bytes, msg)
+
+        // the payload must be encoded before the ttheader to know the transformed size
+        let mut payload = LinkedBytes::with_capacity(self.inner_size);
+        self.inner.encode(cx, &mut payload, msg)?;
+        let payload = transform(&mut payload, self.inner_size, &transforms)?;
+
+        let dst = linked_bytes.bytes_mut();
+        let start = dst.len();
+        encode(cx, dst, payload.len(), &transforms)?;
+        let header_size = dst.len() - start;
+        cx.stats_mut().set_write_size(header_size + payload.len());
+        linked_bytes.insert(payload);
+        Ok(())
     }
 
     fn size<Msg: Send + EntryMessage, Cx: ThriftContext>(
@@ -187,7 +281,13 @@ where
         self.inner_size = real_size;
         // only calc ttheader size if role is client or server has detected ttheader in decode
         if cx.rpc_info().role() == Role::Client || cx.extensions().contains::<HasTTHeader>() {
-            let size = encode_size(cx)?;
+            let transforms_num = self.transforms(cx).len();
+            let size = encode_size(cx, transforms_num)?;
+            if transforms_num > 0 {
+                // the size of the transformed payload is unknown until it's encoded, and the
+                // payload is allocated separately, so only the header is allocated here
+                return Ok((real_size + size, size));
+            }
             Ok((real_size + size, malloc_size + size))
         } else {
             Ok((real_size, malloc_size))
@@ -195,6 +295,91 @@ where
     }
 }
 
+// Applies the transforms to the encoded payload in order.
+fn transform(
+    payload: &mut LinkedBytes,
+    size: usize,
+    transforms: &[Transform],
+) -> Result<Bytes, EncodeError> {
+    let mut buf = Vec::with_capacity(size);
+    payload.sync_write_all_vectored(&mut buf)?;
+    for transform in transforms {
+        buf = match transform {
+            Transform::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&buf)?;
+                encoder.finish()?
+            }
+            Transform::Snappy => snap::raw::Encoder::new()
+                .compress_vec(&buf)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+            #[cfg(feature = "zstd")]
+            Transform::Zstd => zstd::bulk::compress(&buf, zstd::DEFAULT_COMPRESSION_LEVEL)?,
+        };
+    }
+    if buf.len() > u32::MAX as usize {
+        return Err(EncodeError::new(
+            ProtocolErrorKind::SizeLimit,
+            format!(
+                "ttheader transformed payload size {} overflows u32",
+                buf.len()
+            ),
+        ));
+    }
+    Ok(buf.into())
+}
+
+// Reverts the transforms of the payload in reverse order.
+fn untransform(payload: &Bytes, transforms: &[Transform]) -> io::Result<Bytes> {
+    let mut buf = payload.to_vec();
+    for transform in transforms.iter().rev() {
+        buf = match transform {
+            Transform::Zlib => {
+                let mut decompressed = Vec::new();
+                ZlibDecoder::new(&buf[..])
+                    .take(MAX_UNTRANSFORMED_SIZE as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+                if decompressed.len() > MAX_UNTRANSFORMED_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "zlib: decompressed length exceeds the limit",
+                    ));
+                }
+                decompressed
+            }
+            Transform::Snappy => {
+                // check the declared length before the buffer of it is allocated
+                let len = snap::raw::decompress_len(&buf)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if len > MAX_UNTRANSFORMED_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "snappy: decompressed length exceeds the limit",
+                    ));
+                }
+                snap::raw::Decoder::new()
+                    .decompress_vec(&buf)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            }
+            #[cfg(feature = "zstd")]
+            Transform::Zstd => {
+                let mut decompressed = Vec::new();
+                zstd::stream::read::Decoder::new(&buf[..])?
+                    .take(MAX_UNTRANSFORMED_SIZE as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+                if decompressed.len() > MAX_UNTRANSFORMED_SIZE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "zstd: decompressed length exceeds the limit",
+                    ));
+                }
+                decompressed
+            }
+        };
+    }
+    Ok(buf.into())
+}
+
 pub const TT_HEADER_MAGIC: u16 = 0x1000;
 
 mod info {
@@ -210,7 +395,7 @@ pub(crate) const HEADER_TRANS_REMOTE_ADDR: &str = "rip";
 // connection.
 pub(crate) const HEADER_CONNECTION_READY_TO_RESET: &str = "crrst";
 
-#[derive(TryFromPrimitive, Clone, Copy, Default)]
+#[derive(TryFromPrimitive, Debug, Clone, Copy, Default, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ProtocolId {
     #[default]
@@ -274,6 +459,7 @@ pub(crate) fn encode<Cx: ThriftContext>(
     cx: &mut Cx,
     dst: &mut BytesMut,
     size: usize,
+    transforms: &[Transform],
 ) -> Result<(), EncodeError> {
     metainfo::METAINFO.with(|metainfo| {
         let metainfo = metainfo.borrow_mut();
@@ -303,12 +489,8 @@ pub(crate) fn encode<Cx: ThriftContext>(
             .get::<ProtocolId>()
             .unwrap_or(&ProtocolId::Binary);
         dst.put_u8(*protocol_id as u8);
-        dst.put_u8(0); // TODO: item.transform_ids_num
-
-        // TODO: item.transform_ids
-        // if let Some(ids) = &item.transform_ids {
-        //     dst.put_slice(ids);
-        // }
+        dst.put_u8(transforms.len() as u8);
+        transforms.iter().for_each(|t| dst.put_u8(*t as u8));
 
         let role = cx.rpc_info().role();
 
@@ -499,7 +681,10 @@ pub(crate) fn encode<Cx: ThriftContext>(
 }
 
 // this must be with sync to the encode impl
-pub(crate) fn encode_size<Cx: ThriftContext>(cx: &mut Cx) -> Result<usize, EncodeError> {
+pub(crate) fn encode_size<Cx: ThriftContext>(
+    cx: &mut Cx,
+    transforms_num: usize,
+) -> Result<usize, EncodeError> {
     let thrift_cx = cx;
     Ok(metainfo::METAINFO.with(|metainfo| {
         let metainfo = metainfo.borrow_mut();
@@ -519,12 +704,10 @@ pub(crate) fn encode_size<Cx: ThriftContext>(cx: &mut Cx) -> Result<usize, Encod
 
         // protocol_id
         len += 1; // TODO: item.protocol_id as u8(0=Binary; 2=Compact)
-        len += 1; // TODO: item.transform_ids_num
-
-        // TODO: item.transform_ids
-        // if let Some(ids) = &item.transform_ids {
-        //     dst.put_slice(ids);
-        // }
+                  // transform_ids_num
+        len += 1;
+        // transform_ids
+        len += transforms_num;
 
         let role = thrift_cx.rpc_info().role();
 
@@ -684,10 +867,25 @@ pub(crate) fn decode<Cx: ThriftContext>(
             }
 
             let transform_ids_num = src.get_u8();
-            let mut _transform_ids = None;
-            if transform_ids_num > 0 {
-                let _transform_ids_inner = src.split_to(transform_ids_num as usize);
-                _transform_ids = Some(_transform_ids_inner);
+            let mut transforms = Vec::with_capacity(transform_ids_num as usize);
+            for _ in 0..transform_ids_num {
+                let transform_id = src.get_u8();
+                match Transform::try_from_primitive(transform_id) {
+                    Ok(transform) => transforms.push(transform),
+                    #[cfg(not(feature = "zstd"))]
+                    Err(_) if transform_id == ZSTD_TRANSFORM_ID => {
+                        return Err(DecodeError::new(
+                            pilota::thrift::DecodeErrorKind::InvalidData,
+                            "zstd transform in ttheader requires the `zstd` feature",
+                        ));
+                    }
+                    Err(_) => {
+                        return Err(DecodeError::new(
+                            pilota::thrift::DecodeErrorKind::InvalidData,
+                            format!("unsupported transform id: {transform_id} in ttheader"),
+                        ));
+                    }
+                }
             }
 
             #[allow(clippy::mutable_key_type)]
@@ -767,6 +965,16 @@ pub(crate) fn decode<Cx: ThriftContext>(
                 }
             }
 
+            if !transforms.is_empty() {
+                *src = untransform(src, &transforms).map_err(|e| {
+                    DecodeError::new(
+                        pilota::thrift::DecodeErrorKind::InvalidData,
+                        format!("failed to revert the transforms {transforms:?} in ttheader: {e}"),
+                    )
+                })?;
+                cx.extensions_mut().insert(PeerTransforms(transforms));
+            }
+
             let role = cx.rpc_info().role();
             match role {
                 Role::Client => {
@@ -859,3 +1067,40 @@ pub(crate) fn decode<Cx: ThriftContext>(
             Ok(())
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use linkedbytes::LinkedBytes;
+
+    use super::{transform, untransform, Transform};
+
+    #[test]
+    fn test_transforms() {
+        let payload = Bytes::from("volo thrift ttheader ".repeat(1000));
+        for transforms in [
+            vec![Transform::Zlib],
+            vec![Transform::Snappy],
+            vec![Transform::Snappy, Transform::Zlib],
+            #[cfg(feature = "zstd")]
+            vec![Transform::Zstd],
+            #[cfg(feature = "zstd")]
+            vec![Transform::Zstd, Transform::Snappy],
+        ] {
+            let mut linked_bytes = LinkedBytes::new();
+            linked_bytes.bytes_mut().extend_from_slice(&payload[..10]);
+            linked_bytes.insert(payload.slice(10..));
+            let transformed = transform(&mut linked_bytes, payload.len(), &transforms).unwrap();
+            assert!(transformed.len() < payload.len());
+            assert_eq!(untransform(&transformed, &transforms).unwrap(), payload);
+        }
+        assert!(untransform(&payload, &[Transform::Zlib]).is_err());
+        #[cfg(feature = "zstd")]
+        assert!(untransform(&payload, &[Transform::Zstd]).is_err());
+
+        // the declared length of the snappy payload exceeds the limit
+        let payload = Bytes::from_static(&[0x80, 0x80, 0x80, 0x21, 0x00]);
+        let err = untransform(&payload, &[Transform::Snappy]).unwrap_err();
+        assert!(err.to_string().contains("exceeds the limit"));
+    }
+}
@@ -4,7 +4,7 @@ use chrono::{DateTime, Local};
 use paste::paste;
 use pilota::thrift::TMessageIdentifier;
 use volo::{
-    context::{Role, RpcCx, RpcInfo},
+    context::{Context, ForkContext, Role, RpcCx, RpcInfo},
     newtype_impl_context,
 };
 
@@ -217,6 +217,13 @@ impl ClientContext {
     }
 }
 
+impl ForkContext for ClientContext {
+    #[inline]
+    fn fork(&self) -> Self {
+        Self(self.0.fork())
+    }
+}
+
 impl std::ops::Deref for ClientContext {
     type Target = RpcCx<ClientCxInner, Config>;
 
@@ -495,6 +502,9 @@ impl ::volo::client::Apply<ClientContext> for CallOpt {
             callee.set_address(addr);
         }
         cx.rpc_info.config_mut().unwrap().merge(self.config);
+        if let Some(policy) = self.retry_policy {
+            cx.extensions_mut().insert(policy);
+        }
         Ok(())
     }
 }
@@ -3,8 +3,12 @@ use std::fmt::{self, Display, Formatter};
 use pilota::thrift::{
     DecodeError, EncodeError, Error as PilotaError, Message, ProtocolError, TAsyncInputProtocol,
     TInputProtocol, TLengthProtocol, TOutputProtocol, TStructIdentifier, TType, TransportError,
+    TransportErrorKind,
+};
+use volo::{
+    limit::LimitError,
+    loadbalance::error::{LoadBalanceError, Retryable},
 };
-use volo::loadbalance::error::{LoadBalanceError, Retryable};
 
 use crate::AnyhowError;
 
@@ -133,7 +137,15 @@ impl From<ApplicationError> for Error {
 
 impl From<LoadBalanceError> for Error {
     fn from(err: LoadBalanceError) -> Self {
-        new_application_error(ApplicationErrorKind::INTERNAL_ERROR, err.to_string())
+        match err {
+            // an open circuit of the instance means it's unavailable, so treat it as a retryable
+            // transport error to try other instances, while the one of the service isn't retried
+            LoadBalanceError::InstanceCircuitOpen(_) => Error::Transport(TransportError::new(
+                TransportErrorKind::NotOpen,
+                err.to_string(),
+            )),
+            _ => new_application_error(ApplicationErrorKind::INTERNAL_ERROR, err.to_string()),
+        }
     }
 }
 
@@ -146,9 +158,19 @@ impl Retryable for Error {
     }
 }
 
+impl From<LimitError> for Error {
+    fn from(err: LimitError) -> Self {
+        new_application_error(ApplicationErrorKind::INTERNAL_ERROR, err.to_string())
+    }
+}
+
 impl From<AnyhowError> for Error {
     fn from(err: AnyhowError) -> Self {
-        new_application_error(ApplicationErrorKind::UNKNOWN, err.to_string())
+        // the limit layers of the server return the errors of the handlers
+        match err.downcast::<LimitError>() {
+            Ok(err) => err.into(),
+            Err(err) => new_application_error(ApplicationErrorKind::UNKNOWN, err.to_string()),
+        }
     }
 }
 
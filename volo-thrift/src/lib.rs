@@ -15,6 +15,7 @@ pub mod client;
 pub use client::Client;
 pub mod codec;
 pub mod context;
+pub mod multiplexed;
 pub mod server;
 pub use anyhow::Error as AnyhowError;
 pub use error::*;
@@ -8,6 +8,10 @@ use pilota::thrift::{
 
 #[async_trait::async_trait]
 pub trait EntryMessage: Sized + Send {
+    /// Whether the message can be decoded from the JSON protocol, the requests in the JSON
+    /// protocol are rejected before decoding otherwise.
+    const JSON_SUPPORTED: bool = true;
+
     fn encode<T: TOutputProtocol>(&self, protocol: &mut T) -> Result<(), EncodeError>;
 
     fn decode<T: TInputProtocol>(
@@ -28,6 +32,8 @@ impl<Message> EntryMessage for Arc<Message>
 where
     Message: EntryMessage + Sync,
 {
+    const JSON_SUPPORTED: bool = Message::JSON_SUPPORTED;
+
     #[inline]
     fn encode<T: TOutputProtocol>(&self, protocol: &mut T) -> Result<(), EncodeError> {
         (**self).encode(protocol)
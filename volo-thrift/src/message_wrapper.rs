@@ -107,6 +107,16 @@ where
                         + e.size(protocol)
                         + protocol.message_end_len()
                 }
+                // the same as `encode`, which replies the protocol error as an application error
+                crate::Error::Protocol(e) => {
+                    let e = ApplicationError::new(
+                        ApplicationErrorKind::PROTOCOL_ERROR,
+                        e.message.clone(),
+                    );
+                    protocol.message_begin_len(&ident)
+                        + e.size(protocol)
+                        + protocol.message_end_len()
+                }
                 _ => 0,
             },
         }
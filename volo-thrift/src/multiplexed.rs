@@ -0,0 +1,582 @@
+//! Serves multiple thrift services on one server, following the `TMultiplexedProtocol` convention
+//! of Apache Thrift, where the clients send the method names as `ServiceName:method`.
+//!
+//! ```ignore
+//! let service = MultiplexedService::new()
+//!     .register("Calculator", CalculatorServer::new(CalculatorImpl))
+//!     .register("Weather", WeatherServer::new(WeatherImpl))
+//!     // requests without service names, e.g. from the non-multiplexed clients
+//!     .register_default(CalculatorServer::new(CalculatorImpl));
+//!
+//! Server::new(service).run(addr).await
+//! ```
+//!
+//! The arguments and the results are transcoded through the binary protocol without the IDL, so
+//! the requests in the JSON protocol, which encodes the strings and the binaries differently, are
+//! rejected.
+
+use std::{future::Future, marker::PhantomData};
+
+use bytes::{Bytes, BytesMut};
+use fxhash::FxHashMap;
+use motore::{layer::Layer, service::BoxService, Service};
+use pilota::{
+    thrift::{
+        DecodeError, EncodeError, TAsyncInputProtocol, TInputProtocol, TLengthProtocol,
+        TMessageIdentifier, TMessageType, TOutputProtocol,
+    },
+    FastStr,
+};
+
+use crate::{
+    context::ServerContext,
+    protocol::{
+        transcode::{transcode_struct, transcode_struct_async, Length, Output},
+        TBinaryProtocol,
+    },
+    server::Server,
+    ApplicationErrorKind, EntryMessage,
+};
+
+/// The separator between the service name and the method name.
+pub const SEPARATOR: char = ':';
+
+/// A request to a [`MultiplexedService`], with the arguments kept in the binary protocol.
+#[derive(Debug, Clone)]
+pub struct MultiplexedRequest {
+    /// The service name, which is empty if the method name has no service name.
+    pub service: FastStr,
+    /// The method name without the service name.
+    pub method: FastStr,
+    body: Bytes,
+}
+
+/// A response of a [`MultiplexedService`], with the result kept in the binary protocol.
+#[derive(Debug, Clone)]
+pub struct MultiplexedResponse {
+    body: Bytes,
+}
+
+#[inline]
+fn split_name(name: &FastStr) -> (FastStr, FastStr) {
+    match name.find(SEPARATOR) {
+        Some(i) => (name.slice_ref(&name[..i]), name.slice_ref(&name[i + 1..])),
+        None => (FastStr::empty(), name.clone()),
+    }
+}
+
+#[inline]
+fn decode_body<T: TInputProtocol>(protocol: &mut T) -> Result<Bytes, DecodeError> {
+    let mut body = BytesMut::new();
+    transcode_struct(
+        protocol,
+        &mut Output(&mut TBinaryProtocol::new(&mut body, true)),
+    )?;
+    Ok(body.freeze())
+}
+
+#[inline]
+async fn decode_body_async<T: TAsyncInputProtocol>(protocol: &mut T) -> Result<Bytes, DecodeError> {
+    let mut body = BytesMut::new();
+    transcode_struct_async(
+        protocol,
+        &mut Output(&mut TBinaryProtocol::new(&mut body, true)),
+    )
+    .await?;
+    Ok(body.freeze())
+}
+
+#[inline]
+fn encode_body<T: TOutputProtocol>(body: &Bytes, protocol: &mut T) -> Result<(), EncodeError> {
+    transcode_struct(
+        &mut TBinaryProtocol::new(&mut body.clone(), true),
+        &mut Output(protocol),
+    )?;
+    Ok(())
+}
+
+#[inline]
+fn body_size<T: TLengthProtocol>(body: &Bytes, protocol: &mut T) -> usize {
+    let mut len = Length::new(protocol);
+    // the body is always valid, since it's transcoded from a decoded struct
+    match transcode_struct(&mut TBinaryProtocol::new(&mut body.clone(), true), &mut len) {
+        Ok(()) => len.len,
+        Err(_) => 0,
+    }
+}
+
+#[async_trait::async_trait]
+impl EntryMessage for MultiplexedRequest {
+    const JSON_SUPPORTED: bool = false;
+
+    #[inline]
+    fn encode<T: TOutputProtocol>(&self, protocol: &mut T) -> Result<(), EncodeError> {
+        encode_body(&self.body, protocol)
+    }
+
+    #[inline]
+    fn decode<T: TInputProtocol>(
+        protocol: &mut T,
+        msg_ident: &TMessageIdentifier,
+    ) -> Result<Self, DecodeError> {
+        let (service, method) = split_name(&msg_ident.name);
+        Ok(Self {
+            service,
+            method,
+            body: decode_body(protocol)?,
+        })
+    }
+
+    #[inline]
+    async fn decode_async<T: TAsyncInputProtocol>(
+        protocol: &mut T,
+        msg_ident: &TMessageIdentifier,
+    ) -> Result<Self, DecodeError> {
+        let (service, method) = split_name(&msg_ident.name);
+        Ok(Self {
+            service,
+            method,
+            body: decode_body_async(protocol).await?,
+        })
+    }
+
+    #[inline]
+    fn size<T: TLengthProtocol>(&self, protocol: &mut T) -> usize {
+        body_size(&self.body, protocol)
+    }
+}
+
+#[async_trait::async_trait]
+impl EntryMessage for MultiplexedResponse {
+    #[inline]
+    fn encode<T: TOutputProtocol>(&self, protocol: &mut T) -> Result<(), EncodeError> {
+        encode_body(&self.body, protocol)
+    }
+
+    #[inline]
+    fn decode<T: TInputProtocol>(
+        protocol: &mut T,
+        _msg_ident: &TMessageIdentifier,
+    ) -> Result<Self, DecodeError> {
+        Ok(Self {
+            body: decode_body(protocol)?,
+        })
+    }
+
+    #[inline]
+    async fn decode_async<T: TAsyncInputProtocol>(
+        protocol: &mut T,
+        _msg_ident: &TMessageIdentifier,
+    ) -> Result<Self, DecodeError> {
+        Ok(Self {
+            body: decode_body_async(protocol).await?,
+        })
+    }
+
+    #[inline]
+    fn size<T: TLengthProtocol>(&self, protocol: &mut T) -> usize {
+        body_size(&self.body, protocol)
+    }
+}
+
+type BoxMultiplexedService =
+    BoxService<ServerContext, MultiplexedRequest, MultiplexedResponse, crate::Error>;
+
+/// [`MultiplexedService`] dispatches the requests to the registered services by the service names
+/// in the method names.
+///
+/// The requests without service names are dispatched to the default service if there is one, and
+/// the requests to unknown services are replied with `UNKNOWN_METHOD` errors.
+#[derive(Default)]
+pub struct MultiplexedService {
+    services: FxHashMap<FastStr, BoxMultiplexedService>,
+    default: Option<BoxMultiplexedService>,
+}
+
+impl MultiplexedService {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers the service of the server under the name, with the layers of the server applied.
+    ///
+    /// The other options of the server, such as the codec, are ignored, since the requests are
+    /// decoded by the server running the [`MultiplexedService`].
+    pub fn register<S, L, Req, MkC, SP>(
+        mut self,
+        service_name: impl AsRef<str>,
+        server: Server<S, L, Req, MkC, SP>,
+    ) -> Self
+    where
+        L: Layer<S>,
+        L::Service: Service<ServerContext, Req> + Send + Sync + 'static,
+        for<'cx> <L::Service as Service<ServerContext, Req>>::Future<'cx>: Send,
+        <L::Service as Service<ServerContext, Req>>::Error: Into<crate::Error>,
+        <L::Service as Service<ServerContext, Req>>::Response: EntryMessage,
+        Req: EntryMessage + 'static,
+    {
+        self.services
+            .insert(FastStr::new(service_name), box_service(server));
+        self
+    }
+
+    /// Registers the service of the server for the requests without service names.
+    pub fn register_default<S, L, Req, MkC, SP>(
+        mut self,
+        server: Server<S, L, Req, MkC, SP>,
+    ) -> Self
+    where
+        L: Layer<S>,
+        L::Service: Service<ServerContext, Req> + Send + Sync + 'static,
+        for<'cx> <L::Service as Service<ServerContext, Req>>::Future<'cx>: Send,
+        <L::Service as Service<ServerContext, Req>>::Error: Into<crate::Error>,
+        <L::Service as Service<ServerContext, Req>>::Response: EntryMessage,
+        Req: EntryMessage + 'static,
+    {
+        self.default = Some(box_service(server));
+        self
+    }
+}
+
+fn box_service<S, L, Req, MkC, SP>(server: Server<S, L, Req, MkC, SP>) -> BoxMultiplexedService
+where
+    L: Layer<S>,
+    L::Service: Service<ServerContext, Req> + Send + Sync + 'static,
+    for<'cx> <L::Service as Service<ServerContext, Req>>::Future<'cx>: Send,
+    <L::Service as Service<ServerContext, Req>>::Error: Into<crate::Error>,
+    <L::Service as Service<ServerContext, Req>>::Response: EntryMessage,
+    Req: EntryMessage + 'static,
+{
+    BoxService::new(Transcoded {
+        inner: server.into_service(),
+        _marker: PhantomData,
+    })
+}
+
+/// Decodes the request from and encodes the response to the binary bodies for the inner service.
+struct Transcoded<S, Req> {
+    inner: S,
+    _marker: PhantomData<fn(Req)>,
+}
+
+impl<S, Req> Service<ServerContext, MultiplexedRequest> for Transcoded<S, Req>
+where
+    S: Service<ServerContext, Req> + Send + Sync,
+    for<'cx> S::Future<'cx>: Send,
+    S::Error: Into<crate::Error>,
+    S::Response: EntryMessage,
+    Req: EntryMessage,
+{
+    type Response = MultiplexedResponse;
+
+    type Error = crate::Error;
+
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + Send + 'cx
+    where
+        Self: 'cx;
+
+    fn call<'cx, 's>(
+        &'s self,
+        cx: &'cx mut ServerContext,
+        req: MultiplexedRequest,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let msg_ident = TMessageIdentifier::new(
+                req.method,
+                cx.req_msg_type.unwrap_or(TMessageType::Call),
+                cx.seq_id.unwrap_or_default(),
+            );
+            let mut body = req.body;
+            let req = Req::decode(&mut TBinaryProtocol::new(&mut body, true), &msg_ident)?;
+            let resp = self.inner.call(cx, req).await.map_err(Into::into)?;
+            let mut body = BytesMut::new();
+            resp.encode(&mut TBinaryProtocol::new(&mut body, true))?;
+            Ok(MultiplexedResponse {
+                body: body.freeze(),
+            })
+        }
+    }
+}
+
+impl Service<ServerContext, MultiplexedRequest> for MultiplexedService {
+    type Response = MultiplexedResponse;
+
+    type Error = crate::Error;
+
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + Send + 'cx
+    where
+        Self: 'cx;
+
+    fn call<'cx, 's>(
+        &'s self,
+        cx: &'cx mut ServerContext,
+        req: MultiplexedRequest,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let service = if req.service.is_empty() {
+                self.default.as_ref()
+            } else {
+                self.services.get(&req.service)
+            };
+            let Some(service) = service else {
+                return Err(crate::new_application_error(
+                    ApplicationErrorKind::UNKNOWN_METHOD,
+                    format!("unknown service {}", req.service),
+                ));
+            };
+            // reply with the method name without the service name as Apache Thrift does, which is
+            // also what the layers of the registered service expect
+            cx.rpc_info.method = Some(req.method.clone());
+            service.call(cx, req).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, net::SocketAddr, time::Duration};
+
+    use bytes::{BufMut, Bytes, BytesMut};
+    use linkedbytes::LinkedBytes;
+    use motore::service::{service_fn, Service};
+    use pilota::thrift::{
+        compact::{TCompactInputProtocol, TCompactOutputProtocol},
+        TMessageIdentifier, TMessageType, TOutputProtocol, TStructIdentifier, TType,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::oneshot,
+    };
+    use volo::{client::MkClient, net::Address, FastStr};
+
+    use super::{MultiplexedRequest, MultiplexedService};
+    use crate::{
+        client::{Client, ClientBuilder},
+        context::ServerContext,
+        protocol::TBinaryProtocol,
+        server::Server,
+        EntryMessage,
+    };
+
+    /// Encodes a struct with the string as its first field in the binary protocol.
+    fn struct_body(s: &str) -> Bytes {
+        let mut body = BytesMut::new();
+        let mut protocol = TBinaryProtocol::new(&mut body, true);
+        protocol
+            .write_struct_begin(&TStructIdentifier { name: "result" })
+            .unwrap();
+        protocol.write_field_begin(TType::Binary, 0).unwrap();
+        protocol.write_string(s).unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+        body.freeze()
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    }
+
+    /// Replies with the tag, and the service name and the method name seen by the registered
+    /// service.
+    fn reply(tag: &str, req: MultiplexedRequest) -> Result<MultiplexedRequest, crate::Error> {
+        Ok(MultiplexedRequest {
+            service: FastStr::empty(),
+            method: req.method.clone(),
+            body: struct_body(&format!("{tag}/{}/{}", req.service, req.method)),
+        })
+    }
+
+    fn unused_addr() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    /// Runs a server with the `Calc` service and a default service until the sender is dropped.
+    async fn serve() -> (SocketAddr, oneshot::Sender<()>) {
+        let addr = unused_addr();
+        let service =
+            MultiplexedService::new()
+                .register(
+                    "Calc",
+                    Server::new(service_fn(
+                        |_: &mut ServerContext, req: MultiplexedRequest| async move {
+                            reply("calc", req)
+                        },
+                    )),
+                )
+                .register_default(Server::new(service_fn(
+                    |_: &mut ServerContext, req: MultiplexedRequest| async move {
+                        reply("default", req)
+                    },
+                )));
+        let (stop, stopped) = oneshot::channel::<()>();
+        tokio::spawn(
+            Server::new(service).run_with_shutdown(Address::from(addr), async move {
+                let _ = stopped.await;
+                Ok::<_, io::Error>(())
+            }),
+        );
+        for _ in 0..100 {
+            if TcpStream::connect(addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        (addr, stop)
+    }
+
+    /// Sends a framed binary call with an empty struct, and returns the message type, the method
+    /// name and the whole message of the reply.
+    async fn call(conn: &mut TcpStream, name: &str) -> (u8, String, Vec<u8>) {
+        let mut msg = BytesMut::new();
+        msg.put_u32(0x8001_0001);
+        msg.put_i32(name.len() as i32);
+        msg.put_slice(name.as_bytes());
+        msg.put_i32(1);
+        msg.put_u8(0);
+        conn.write_u32(msg.len() as u32).await.unwrap();
+        conn.write_all(&msg).await.unwrap();
+
+        let len = conn.read_u32().await.unwrap();
+        let mut msg = vec![0; len as usize];
+        conn.read_exact(&mut msg).await.unwrap();
+        let name_len = i32::from_be_bytes(msg[4..8].try_into().unwrap()) as usize;
+        let name = String::from_utf8(msg[8..8 + name_len].to_vec()).unwrap();
+        (msg[3], name, msg)
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_service() {
+        let (addr, _stop) = serve().await;
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+
+        // the reply carries the method name without the service name
+        let (msg_type, name, msg) = call(&mut conn, "Calc:add").await;
+        assert_eq!(msg_type, TMessageType::Reply as u8);
+        assert_eq!(name, "add");
+        assert!(contains(&msg, "calc//add"));
+
+        let (msg_type, name, msg) = call(&mut conn, "add").await;
+        assert_eq!(msg_type, TMessageType::Reply as u8);
+        assert_eq!(name, "add");
+        assert!(contains(&msg, "default//add"));
+
+        let (msg_type, _, msg) = call(&mut conn, "Weather:get").await;
+        assert_eq!(msg_type, TMessageType::Exception as u8);
+        assert!(contains(&msg, "unknown service Weather"));
+        // the application exception type of UNKNOWN_METHOD
+        assert!(msg.ends_with(&[TType::I32 as u8, 0, 2, 0, 0, 0, 1, 0]));
+
+        // the connection is still usable
+        let (msg_type, _, _) = call(&mut conn, "Calc:sub").await;
+        assert_eq!(msg_type, TMessageType::Reply as u8);
+    }
+
+    #[tokio::test]
+    async fn test_reject_json() {
+        let (addr, _stop) = serve().await;
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+        conn.write_all(br#"[1,"Calc:add",1,1,{}]"#).await.unwrap();
+
+        let mut reply = Vec::new();
+        let _ = tokio::time::timeout(Duration::from_secs(1), conn.read_to_end(&mut reply)).await;
+        let reply = String::from_utf8_lossy(&reply);
+        assert!(reply.starts_with('['), "{reply}");
+        assert!(reply.contains("json protocol is not supported"), "{reply}");
+    }
+
+    struct MkTestClient;
+
+    impl<S> MkClient<Client<S>> for MkTestClient {
+        type Target = Client<S>;
+
+        fn mk_client(&self, service: Client<S>) -> Self::Target {
+            service
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_multiplexed_service() {
+        let (addr, _stop) = serve().await;
+        for (service, expected) in [(Some("Calc"), "calc//add"), (None, "default//add")] {
+            let mut builder =
+                ClientBuilder::<_, _, _, MultiplexedRequest, MultiplexedRequest, _, _, _>::new(
+                    "calc",
+                    MkTestClient,
+                )
+                .address(addr);
+            if let Some(service) = service {
+                builder = builder.multiplexed_service(service);
+            }
+            let client = builder.build();
+
+            let mut cx = client.make_cx("add", false);
+            let req = MultiplexedRequest {
+                service: FastStr::empty(),
+                method: FastStr::new("add"),
+                body: Bytes::from_static(&[0]),
+            };
+            let resp = client.call(&mut cx, req).await.unwrap().unwrap();
+            assert!(contains(&resp.body, expected));
+            // the layers see the method name without the service name
+            assert_eq!(cx.rpc_info.method.as_deref(), Some("add"));
+        }
+    }
+
+    #[test]
+    fn test_decode_request() {
+        let mut compact = LinkedBytes::new();
+        let mut protocol = TCompactOutputProtocol::new(&mut compact, true);
+        protocol
+            .write_struct_begin(&TStructIdentifier { name: "args" })
+            .unwrap();
+        protocol.write_field_begin(TType::Binary, 1).unwrap();
+        protocol.write_string("volo").unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+
+        let mut bytes = Bytes::copy_from_slice(compact.bytes());
+        let msg_ident = TMessageIdentifier::new("Hello:hello".into(), TMessageType::Call, 1);
+        let req =
+            MultiplexedRequest::decode(&mut TCompactInputProtocol::new(&mut bytes), &msg_ident)
+                .unwrap();
+        assert_eq!(req.service, "Hello");
+        assert_eq!(req.method, "hello");
+        assert_eq!(
+            req.size(&mut TCompactOutputProtocol::new((), true)),
+            compact.bytes().len()
+        );
+
+        let mut expected = BytesMut::new();
+        let mut protocol = TBinaryProtocol::new(&mut expected, true);
+        protocol
+            .write_struct_begin(&TStructIdentifier { name: "args" })
+            .unwrap();
+        protocol.write_field_begin(TType::Binary, 1).unwrap();
+        protocol.write_string("volo").unwrap();
+        protocol.write_field_end().unwrap();
+        protocol.write_field_stop().unwrap();
+        protocol.write_struct_end().unwrap();
+        assert_eq!(req.body, expected);
+
+        let msg_ident = TMessageIdentifier::new("hello".into(), TMessageType::Call, 1);
+        let req = MultiplexedRequest::decode(
+            &mut TBinaryProtocol::new(&mut expected.freeze(), true),
+            &msg_ident,
+        )
+        .unwrap();
+        assert!(req.service.is_empty());
+        assert_eq!(req.method, "hello");
+    }
+}
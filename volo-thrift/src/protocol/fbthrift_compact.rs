@@ -0,0 +1,245 @@
+//! The compact protocol of [FBThrift][FBThrift], which is the same as the Apache Thrift compact
+//! protocol except that the version in the message header is 2 and doubles are big-endian.
+//!
+//! [FBThrift]: https://github.com/facebook/fbthrift/blob/main/thrift/lib/cpp2/protocol/CompactProtocol.h
+
+use bytes::{Buf, BufMut, Bytes};
+use pilota::{
+    thrift::{
+        DecodeError, DecodeErrorKind, EncodeError, TFieldIdentifier, TInputProtocol,
+        TLengthProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier, TMessageType,
+        TOutputProtocol, TSetIdentifier, TStructIdentifier, TType,
+    },
+    FastStr,
+};
+
+const COMPACT_PROTOCOL_ID: u8 = 0x82;
+const COMPACT_VERSION: u8 = 2;
+const COMPACT_VERSION_MASK: u8 = 0x1f;
+const COMPACT_TYPE_SHIFT_AMOUNT: u8 = 5;
+
+/// Returns whether the compact message header is of FBThrift, the version is in the low 5 bits of
+/// the second byte.
+#[inline]
+pub fn is_fbthrift_compact(header: &[u8]) -> bool {
+    header.len() >= 2
+        && header[0] == COMPACT_PROTOCOL_ID
+        && header[1] & COMPACT_VERSION_MASK == COMPACT_VERSION
+}
+
+/// [`TFBThriftCompactProtocol`] wraps the compact protocol of pilota, and only overrides the
+/// message header and doubles.
+pub struct TFBThriftCompactProtocol<P>(pub P);
+
+macro_rules! delegate {
+    ($($name:ident(&mut self $(, $arg:ident: $t:ty)*) -> $ret:ty;)*) => {
+        $(
+            #[inline]
+            fn $name(&mut self $(, $arg: $t)*) -> $ret {
+                self.0.$name($($arg),*)
+            }
+        )*
+    };
+}
+
+impl<P: TLengthProtocol> TLengthProtocol for TFBThriftCompactProtocol<P> {
+    delegate! {
+        message_begin_len(&mut self, identifier: &TMessageIdentifier) -> usize;
+        message_end_len(&mut self) -> usize;
+        struct_begin_len(&mut self, identifier: &TStructIdentifier) -> usize;
+        struct_end_len(&mut self) -> usize;
+        field_begin_len(&mut self, field_type: TType, id: Option<i16>) -> usize;
+        field_end_len(&mut self) -> usize;
+        field_stop_len(&mut self) -> usize;
+        bool_len(&mut self, b: bool) -> usize;
+        bytes_len(&mut self, b: &[u8]) -> usize;
+        bytes_vec_len(&mut self, b: &[u8]) -> usize;
+        byte_len(&mut self, b: u8) -> usize;
+        uuid_len(&mut self, u: [u8; 16]) -> usize;
+        i8_len(&mut self, i: i8) -> usize;
+        i16_len(&mut self, i: i16) -> usize;
+        i32_len(&mut self, i: i32) -> usize;
+        i64_len(&mut self, i: i64) -> usize;
+        double_len(&mut self, d: f64) -> usize;
+        string_len(&mut self, s: &str) -> usize;
+        faststr_len(&mut self, s: &FastStr) -> usize;
+        list_begin_len(&mut self, identifier: TListIdentifier) -> usize;
+        list_end_len(&mut self) -> usize;
+        set_begin_len(&mut self, identifier: TSetIdentifier) -> usize;
+        set_end_len(&mut self) -> usize;
+        map_begin_len(&mut self, identifier: TMapIdentifier) -> usize;
+        map_end_len(&mut self) -> usize;
+        zero_copy_len(&mut self) -> usize;
+    }
+
+    #[inline]
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+}
+
+impl<P: TOutputProtocol> TOutputProtocol for TFBThriftCompactProtocol<P> {
+    type BufMut = P::BufMut;
+
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> Result<(), EncodeError> {
+        self.0.write_byte(COMPACT_PROTOCOL_ID)?;
+        self.0.write_byte(
+            COMPACT_VERSION | ((identifier.message_type as u8) << COMPACT_TYPE_SHIFT_AMOUNT),
+        )?;
+        // the sequence number is written as u32 without zigzag encoding
+        let mut seq_id = identifier.sequence_number as u32;
+        while seq_id >= 0x80 {
+            self.0.write_byte(seq_id as u8 | 0x80)?;
+            seq_id >>= 7;
+        }
+        self.0.write_byte(seq_id as u8)?;
+        self.0.write_faststr(identifier.name.clone())
+    }
+
+    #[inline]
+    fn write_double(&mut self, d: f64) -> Result<(), EncodeError> {
+        self.0.buf_mut().put_f64(d);
+        Ok(())
+    }
+
+    delegate! {
+        write_message_end(&mut self) -> Result<(), EncodeError>;
+        write_struct_begin(&mut self, identifier: &TStructIdentifier) -> Result<(), EncodeError>;
+        write_struct_end(&mut self) -> Result<(), EncodeError>;
+        write_field_begin(&mut self, field_type: TType, id: i16) -> Result<(), EncodeError>;
+        write_field_end(&mut self) -> Result<(), EncodeError>;
+        write_field_stop(&mut self) -> Result<(), EncodeError>;
+        write_bool(&mut self, b: bool) -> Result<(), EncodeError>;
+        write_bytes(&mut self, b: Bytes) -> Result<(), EncodeError>;
+        write_bytes_without_len(&mut self, b: Bytes) -> Result<(), EncodeError>;
+        write_uuid(&mut self, u: [u8; 16]) -> Result<(), EncodeError>;
+        write_bytes_vec(&mut self, b: &[u8]) -> Result<(), EncodeError>;
+        write_byte(&mut self, b: u8) -> Result<(), EncodeError>;
+        write_i8(&mut self, i: i8) -> Result<(), EncodeError>;
+        write_i16(&mut self, i: i16) -> Result<(), EncodeError>;
+        write_i32(&mut self, i: i32) -> Result<(), EncodeError>;
+        write_i64(&mut self, i: i64) -> Result<(), EncodeError>;
+        write_string(&mut self, s: &str) -> Result<(), EncodeError>;
+        write_faststr(&mut self, s: FastStr) -> Result<(), EncodeError>;
+        write_list_begin(&mut self, identifier: TListIdentifier) -> Result<(), EncodeError>;
+        write_list_end(&mut self) -> Result<(), EncodeError>;
+        write_set_begin(&mut self, identifier: TSetIdentifier) -> Result<(), EncodeError>;
+        write_set_end(&mut self) -> Result<(), EncodeError>;
+        write_map_begin(&mut self, identifier: TMapIdentifier) -> Result<(), EncodeError>;
+        write_map_end(&mut self) -> Result<(), EncodeError>;
+        flush(&mut self) -> Result<(), EncodeError>;
+        buf_mut(&mut self) -> &mut Self::BufMut;
+    }
+}
+
+impl<P: TInputProtocol + Send> TInputProtocol for TFBThriftCompactProtocol<P> {
+    type Buf = P::Buf;
+
+    fn read_message_begin(&mut self) -> Result<TMessageIdentifier, DecodeError> {
+        let header = [self.0.read_byte()?, self.0.read_byte()?];
+        if !is_fbthrift_compact(&header) {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!("invalid fbthrift compact protocol header {header:?}"),
+            ));
+        }
+        let message_type = header[1] >> COMPACT_TYPE_SHIFT_AMOUNT;
+        let message_type = TMessageType::try_from(message_type).map_err(|_| {
+            DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!("invalid message type {message_type}"),
+            )
+        })?;
+
+        let mut seq_id = 0u32;
+        for shift in (0..35).step_by(7) {
+            let b = self.0.read_byte()?;
+            seq_id |= ((b & 0x7f) as u32) << shift;
+            if b & 0x80 == 0 {
+                let name = self.0.read_faststr()?;
+                return Ok(TMessageIdentifier::new(name, message_type, seq_id as i32));
+            }
+        }
+        Err(DecodeError::new(
+            DecodeErrorKind::InvalidData,
+            "invalid sequence number in fbthrift compact protocol",
+        ))
+    }
+
+    #[inline]
+    fn read_double(&mut self) -> Result<f64, DecodeError> {
+        let buf = self.0.buf();
+        if buf.remaining() < 8 {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                "not enough bytes to read double",
+            ));
+        }
+        Ok(buf.get_f64())
+    }
+
+    delegate! {
+        read_message_end(&mut self) -> Result<(), DecodeError>;
+        read_struct_begin(&mut self) -> Result<Option<TStructIdentifier>, DecodeError>;
+        read_struct_end(&mut self) -> Result<(), DecodeError>;
+        read_field_begin(&mut self) -> Result<TFieldIdentifier, DecodeError>;
+        read_field_end(&mut self) -> Result<(), DecodeError>;
+        read_bool(&mut self) -> Result<bool, DecodeError>;
+        read_bytes(&mut self) -> Result<Bytes, DecodeError>;
+        read_uuid(&mut self) -> Result<[u8; 16], DecodeError>;
+        read_i8(&mut self) -> Result<i8, DecodeError>;
+        read_i16(&mut self) -> Result<i16, DecodeError>;
+        read_i32(&mut self) -> Result<i32, DecodeError>;
+        read_i64(&mut self) -> Result<i64, DecodeError>;
+        read_string(&mut self) -> Result<String, DecodeError>;
+        read_faststr(&mut self) -> Result<FastStr, DecodeError>;
+        read_list_begin(&mut self) -> Result<TListIdentifier, DecodeError>;
+        read_list_end(&mut self) -> Result<(), DecodeError>;
+        read_set_begin(&mut self) -> Result<TSetIdentifier, DecodeError>;
+        read_set_end(&mut self) -> Result<(), DecodeError>;
+        read_map_begin(&mut self) -> Result<TMapIdentifier, DecodeError>;
+        read_map_end(&mut self) -> Result<(), DecodeError>;
+        read_byte(&mut self) -> Result<u8, DecodeError>;
+        read_bytes_vec(&mut self) -> Result<Vec<u8>, DecodeError>;
+        get_bytes(&mut self, ptr: Option<*const u8>, len: usize) -> Result<Bytes, DecodeError>;
+        buf(&mut self) -> &mut Self::Buf;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use linkedbytes::LinkedBytes;
+    use pilota::thrift::{
+        compact::{TCompactInputProtocol, TCompactOutputProtocol},
+        TInputProtocol, TLengthProtocol, TMessageIdentifier, TMessageType, TOutputProtocol,
+    };
+
+    use super::{is_fbthrift_compact, TFBThriftCompactProtocol};
+
+    #[test]
+    fn test_roundtrip() {
+        let ident = TMessageIdentifier::new("hello".into(), TMessageType::Reply, 300);
+        let mut buf = LinkedBytes::new();
+        let mut p = TFBThriftCompactProtocol(TCompactOutputProtocol::new(&mut buf, false));
+        p.write_message_begin(&ident).unwrap();
+        p.write_double(1.5).unwrap();
+        p.write_i32(-7).unwrap();
+        let len = TFBThriftCompactProtocol(TCompactOutputProtocol::new((), false))
+            .message_begin_len(&ident);
+
+        let mut bytes = Bytes::copy_from_slice(buf.bytes());
+        assert_eq!(bytes.len(), len + 8 + 1);
+        assert!(is_fbthrift_compact(&bytes));
+        // doubles are big-endian
+        assert_eq!(&bytes[len..len + 8], &1.5f64.to_be_bytes());
+
+        let mut p = TFBThriftCompactProtocol(TCompactInputProtocol::new(&mut bytes));
+        let decoded = p.read_message_begin().unwrap();
+        assert_eq!(decoded.name, "hello");
+        assert_eq!(decoded.message_type, TMessageType::Reply);
+        assert_eq!(decoded.sequence_number, 300);
+        assert_eq!(p.read_double().unwrap(), 1.5);
+        assert_eq!(p.read_i32().unwrap(), -7);
+    }
+}
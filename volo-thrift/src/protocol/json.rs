@@ -0,0 +1,1444 @@
+//! The JSON protocol of [Apache Thrift][TJSONProtocol].
+//!
+//! The keys of the JSON objects are always strings, so the numbers written as the keys of a map
+//! or the ids of the fields are quoted. Binaries are encoded in base64 without padding, and the
+//! padding is accepted when reading.
+//!
+//! Unlike the binary protocols, the raw bytes of the unknown fields can't be kept, so the unknown
+//! fields are dropped.
+//!
+//! [TJSONProtocol]: https://github.com/apache/thrift/blob/master/lib/java/src/main/java/org/apache/thrift/protocol/TJSONProtocol.java
+
+use bytes::{Buf, BufMut, Bytes};
+use linkedbytes::LinkedBytes;
+use pilota::{
+    thrift::{
+        DecodeError, DecodeErrorKind, EncodeError, ProtocolErrorKind, TFieldIdentifier,
+        TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+        TMessageType, TOutputProtocol, TSetIdentifier, TStructIdentifier, TType,
+    },
+    FastStr,
+};
+
+const VERSION: i64 = 1;
+
+#[derive(Debug, Clone, Copy, Default)]
+enum Context {
+    #[default]
+    Base,
+    List {
+        first: bool,
+    },
+    // the keys and the values of an object, `colon` is true when the next one is a key
+    Pair {
+        first: bool,
+        colon: bool,
+    },
+}
+
+impl Context {
+    /// Returns the separator before the next value, and moves to the next value.
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        match self {
+            Context::Base => None,
+            Context::List { first } => (!std::mem::take(first)).then_some(b','),
+            Context::Pair { first, colon } => {
+                if std::mem::take(first) {
+                    *colon = true;
+                    return None;
+                }
+                let sep = if *colon { b':' } else { b',' };
+                *colon = !*colon;
+                Some(sep)
+            }
+        }
+    }
+
+    /// Whether the current value is a key of an object, which must be a quoted string.
+    #[inline]
+    fn is_key(&self) -> bool {
+        matches!(self, Context::Pair { colon: true, .. })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Contexts {
+    current: Context,
+    stack: Vec<Context>,
+}
+
+impl Contexts {
+    #[inline]
+    fn push(&mut self, context: Context) {
+        self.stack
+            .push(std::mem::replace(&mut self.current, context));
+    }
+
+    #[inline]
+    fn pop(&mut self) {
+        self.current = self.stack.pop().unwrap_or_default();
+    }
+}
+
+#[inline]
+fn type_name(ttype: TType) -> Option<&'static str> {
+    Some(match ttype {
+        TType::Bool => "tf",
+        TType::I8 => "i8",
+        TType::I16 => "i16",
+        TType::I32 => "i32",
+        TType::I64 => "i64",
+        TType::Double => "dbl",
+        TType::Struct => "rec",
+        TType::Binary => "str",
+        TType::Map => "map",
+        TType::Set => "set",
+        TType::List => "lst",
+        TType::Uuid => "uid",
+        _ => return None,
+    })
+}
+
+#[inline]
+fn type_of(name: &[u8]) -> Result<TType, DecodeError> {
+    Ok(match name {
+        b"tf" => TType::Bool,
+        b"i8" => TType::I8,
+        b"i16" => TType::I16,
+        b"i32" => TType::I32,
+        b"i64" => TType::I64,
+        b"dbl" => TType::Double,
+        b"rec" => TType::Struct,
+        b"str" => TType::Binary,
+        b"map" => TType::Map,
+        b"set" => TType::Set,
+        b"lst" => TType::List,
+        b"uid" => TType::Uuid,
+        _ => {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!("unknown type name {:?}", String::from_utf8_lossy(name)),
+            ))
+        }
+    })
+}
+
+#[inline]
+fn escaped_len(b: u8) -> usize {
+    match b {
+        b'"' | b'\\' | 0x08 | 0x0c | b'\n' | b'\r' | b'\t' => 2,
+        0x00..=0x1f => 6,
+        _ => 1,
+    }
+}
+
+#[inline]
+fn base64_len(len: usize) -> usize {
+    (len * 4).div_ceil(3)
+}
+
+#[inline]
+fn is_special_double(d: f64) -> bool {
+    d.is_nan() || d.is_infinite()
+}
+
+#[inline]
+fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        "NaN".to_string()
+    } else if d.is_infinite() {
+        if d > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else {
+        format!("{d:?}")
+    }
+}
+
+#[inline]
+fn format_uuid(u: [u8; 16]) -> String {
+    let mut s = String::with_capacity(36);
+    for (i, b) in u.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            s.push('-');
+        }
+        s.push_str(&format!("{b:02x}"));
+    }
+    s
+}
+
+/// [`TJsonProtocol`] implements the JSON protocol, it writes to [`LinkedBytes`] and reads from
+/// [`Bytes`].
+///
+/// The lengths are calculated in the same order as the values are written, so the length
+/// protocol keeps its own contexts.
+pub struct TJsonProtocol<T> {
+    trans: T,
+    contexts: Contexts,
+    len_contexts: Contexts,
+}
+
+impl<T> TJsonProtocol<T> {
+    pub fn new(trans: T) -> Self {
+        Self {
+            trans,
+            contexts: Contexts::default(),
+            len_contexts: Contexts::default(),
+        }
+    }
+
+    // returns the length of the separator, and whether the value is a key
+    #[inline]
+    fn sep_len(&mut self) -> (usize, bool) {
+        let sep = self.len_contexts.current.next().map_or(0, |_| 1);
+        (sep, self.len_contexts.current.is_key())
+    }
+
+    #[inline]
+    fn json_integer_len(&mut self, i: i64) -> usize {
+        let (sep, is_key) = self.sep_len();
+        let quotes = if is_key { 2 } else { 0 };
+        sep + quotes + i.to_string().len()
+    }
+
+    #[inline]
+    fn json_double_len(&mut self, d: f64) -> usize {
+        let (sep, is_key) = self.sep_len();
+        let quotes = if is_key || is_special_double(d) { 2 } else { 0 };
+        sep + quotes + format_double(d).len()
+    }
+
+    #[inline]
+    fn json_string_len(&mut self, s: &[u8]) -> usize {
+        let (sep, _) = self.sep_len();
+        sep + 2 + s.iter().map(|b| escaped_len(*b)).sum::<usize>()
+    }
+
+    #[inline]
+    fn json_base64_len(&mut self, b: &[u8]) -> usize {
+        let (sep, _) = self.sep_len();
+        sep + 2 + base64_len(b.len())
+    }
+
+    #[inline]
+    fn json_object_start_len(&mut self) -> usize {
+        let (sep, _) = self.sep_len();
+        self.len_contexts.push(Context::Pair {
+            first: true,
+            colon: true,
+        });
+        sep + 1
+    }
+
+    #[inline]
+    fn json_object_end_len(&mut self) -> usize {
+        self.len_contexts.pop();
+        1
+    }
+
+    #[inline]
+    fn json_array_start_len(&mut self) -> usize {
+        let (sep, _) = self.sep_len();
+        self.len_contexts.push(Context::List { first: true });
+        sep + 1
+    }
+
+    #[inline]
+    fn json_array_end_len(&mut self) -> usize {
+        self.len_contexts.pop();
+        1
+    }
+
+    #[inline]
+    fn type_name_len(&mut self, ttype: TType) -> usize {
+        self.json_string_len(type_name(ttype).unwrap_or_default().as_bytes())
+    }
+}
+
+impl<T> TLengthProtocol for TJsonProtocol<T> {
+    #[inline]
+    fn message_begin_len(&mut self, identifier: &TMessageIdentifier) -> usize {
+        self.json_array_start_len()
+            + self.json_integer_len(VERSION)
+            + self.json_string_len(identifier.name.as_bytes())
+            + self.json_integer_len(identifier.message_type as i64)
+            + self.json_integer_len(identifier.sequence_number as i64)
+    }
+
+    #[inline]
+    fn message_end_len(&mut self) -> usize {
+        self.json_array_end_len()
+    }
+
+    #[inline]
+    fn struct_begin_len(&mut self, _identifier: &TStructIdentifier) -> usize {
+        self.json_object_start_len()
+    }
+
+    #[inline]
+    fn struct_end_len(&mut self) -> usize {
+        self.json_object_end_len()
+    }
+
+    #[inline]
+    fn field_begin_len(&mut self, field_type: TType, id: Option<i16>) -> usize {
+        self.json_integer_len(id.unwrap_or_default() as i64)
+            + self.json_object_start_len()
+            + self.type_name_len(field_type)
+    }
+
+    #[inline]
+    fn field_end_len(&mut self) -> usize {
+        self.json_object_end_len()
+    }
+
+    #[inline]
+    fn field_stop_len(&mut self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn bool_len(&mut self, b: bool) -> usize {
+        self.json_integer_len(b as i64)
+    }
+
+    #[inline]
+    fn bytes_len(&mut self, b: &[u8]) -> usize {
+        self.json_base64_len(b)
+    }
+
+    #[inline]
+    fn bytes_vec_len(&mut self, b: &[u8]) -> usize {
+        self.json_base64_len(b)
+    }
+
+    #[inline]
+    fn byte_len(&mut self, b: u8) -> usize {
+        self.json_integer_len(b as i8 as i64)
+    }
+
+    #[inline]
+    fn uuid_len(&mut self, u: [u8; 16]) -> usize {
+        self.json_string_len(format_uuid(u).as_bytes())
+    }
+
+    #[inline]
+    fn i8_len(&mut self, i: i8) -> usize {
+        self.json_integer_len(i as i64)
+    }
+
+    #[inline]
+    fn i16_len(&mut self, i: i16) -> usize {
+        self.json_integer_len(i as i64)
+    }
+
+    #[inline]
+    fn i32_len(&mut self, i: i32) -> usize {
+        self.json_integer_len(i as i64)
+    }
+
+    #[inline]
+    fn i64_len(&mut self, i: i64) -> usize {
+        self.json_integer_len(i)
+    }
+
+    #[inline]
+    fn double_len(&mut self, d: f64) -> usize {
+        self.json_double_len(d)
+    }
+
+    #[inline]
+    fn string_len(&mut self, s: &str) -> usize {
+        self.json_string_len(s.as_bytes())
+    }
+
+    #[inline]
+    fn faststr_len(&mut self, s: &FastStr) -> usize {
+        self.json_string_len(s.as_bytes())
+    }
+
+    #[inline]
+    fn list_begin_len(&mut self, identifier: TListIdentifier) -> usize {
+        self.json_array_start_len()
+            + self.type_name_len(identifier.element_type)
+            + self.json_integer_len(identifier.size as i64)
+    }
+
+    #[inline]
+    fn list_end_len(&mut self) -> usize {
+        self.json_array_end_len()
+    }
+
+    #[inline]
+    fn set_begin_len(&mut self, identifier: TSetIdentifier) -> usize {
+        self.json_array_start_len()
+            + self.type_name_len(identifier.element_type)
+            + self.json_integer_len(identifier.size as i64)
+    }
+
+    #[inline]
+    fn set_end_len(&mut self) -> usize {
+        self.json_array_end_len()
+    }
+
+    #[inline]
+    fn map_begin_len(&mut self, identifier: TMapIdentifier) -> usize {
+        self.json_array_start_len()
+            + self.type_name_len(identifier.key_type)
+            + self.type_name_len(identifier.value_type)
+            + self.json_integer_len(identifier.size as i64)
+            + self.json_object_start_len()
+    }
+
+    #[inline]
+    fn map_end_len(&mut self) -> usize {
+        self.json_object_end_len() + self.json_array_end_len()
+    }
+}
+
+impl TJsonProtocol<&mut LinkedBytes> {
+    // writes the separator, and returns whether the value is a key
+    #[inline]
+    fn write_sep(&mut self) -> bool {
+        if let Some(sep) = self.contexts.current.next() {
+            self.trans.bytes_mut().put_u8(sep);
+        }
+        self.contexts.current.is_key()
+    }
+
+    #[inline]
+    fn write_json_integer(&mut self, i: i64) {
+        let is_key = self.write_sep();
+        let dst = self.trans.bytes_mut();
+        if is_key {
+            dst.put_u8(b'"');
+        }
+        dst.put_slice(i.to_string().as_bytes());
+        if is_key {
+            dst.put_u8(b'"');
+        }
+    }
+
+    #[inline]
+    fn write_json_double(&mut self, d: f64) {
+        let quoted = self.write_sep() || is_special_double(d);
+        let dst = self.trans.bytes_mut();
+        if quoted {
+            dst.put_u8(b'"');
+        }
+        dst.put_slice(format_double(d).as_bytes());
+        if quoted {
+            dst.put_u8(b'"');
+        }
+    }
+
+    fn write_json_string(&mut self, s: &[u8]) {
+        self.write_sep();
+        let dst = self.trans.bytes_mut();
+        dst.reserve(s.len() + 2);
+        dst.put_u8(b'"');
+        for b in s {
+            match b {
+                b'"' => dst.put_slice(b"\\\""),
+                b'\\' => dst.put_slice(b"\\\\"),
+                0x08 => dst.put_slice(b"\\b"),
+                0x0c => dst.put_slice(b"\\f"),
+                b'\n' => dst.put_slice(b"\\n"),
+                b'\r' => dst.put_slice(b"\\r"),
+                b'\t' => dst.put_slice(b"\\t"),
+                0x00..=0x1f => dst.put_slice(format!("\\u{b:04x}").as_bytes()),
+                _ => dst.put_u8(*b),
+            }
+        }
+        dst.put_u8(b'"');
+    }
+
+    #[inline]
+    fn write_json_base64(&mut self, b: &[u8]) {
+        self.write_sep();
+        let dst = self.trans.bytes_mut();
+        dst.put_u8(b'"');
+        dst.put_slice(base64::encode_config(b, base64::STANDARD_NO_PAD).as_bytes());
+        dst.put_u8(b'"');
+    }
+
+    #[inline]
+    fn write_json_object_start(&mut self) {
+        self.write_sep();
+        self.trans.bytes_mut().put_u8(b'{');
+        self.contexts.push(Context::Pair {
+            first: true,
+            colon: true,
+        });
+    }
+
+    #[inline]
+    fn write_json_object_end(&mut self) {
+        self.contexts.pop();
+        self.trans.bytes_mut().put_u8(b'}');
+    }
+
+    #[inline]
+    fn write_json_array_start(&mut self) {
+        self.write_sep();
+        self.trans.bytes_mut().put_u8(b'[');
+        self.contexts.push(Context::List { first: true });
+    }
+
+    #[inline]
+    fn write_json_array_end(&mut self) {
+        self.contexts.pop();
+        self.trans.bytes_mut().put_u8(b']');
+    }
+
+    #[inline]
+    fn write_type_name(&mut self, ttype: TType) -> Result<(), EncodeError> {
+        let name = type_name(ttype).ok_or_else(|| {
+            EncodeError::new(
+                ProtocolErrorKind::InvalidData,
+                format!("invalid ttype {ttype:?}"),
+            )
+        })?;
+        self.write_json_string(name.as_bytes());
+        Ok(())
+    }
+}
+
+impl TOutputProtocol for TJsonProtocol<&mut LinkedBytes> {
+    type BufMut = LinkedBytes;
+
+    #[inline]
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> Result<(), EncodeError> {
+        self.write_json_array_start();
+        self.write_json_integer(VERSION);
+        self.write_json_string(identifier.name.as_bytes());
+        self.write_json_integer(identifier.message_type as i64);
+        self.write_json_integer(identifier.sequence_number as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_message_end(&mut self) -> Result<(), EncodeError> {
+        self.write_json_array_end();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_struct_begin(&mut self, _identifier: &TStructIdentifier) -> Result<(), EncodeError> {
+        self.write_json_object_start();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_struct_end(&mut self) -> Result<(), EncodeError> {
+        self.write_json_object_end();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_field_begin(&mut self, field_type: TType, id: i16) -> Result<(), EncodeError> {
+        self.write_json_integer(id as i64);
+        self.write_json_object_start();
+        self.write_type_name(field_type)
+    }
+
+    #[inline]
+    fn write_field_end(&mut self) -> Result<(), EncodeError> {
+        self.write_json_object_end();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_field_stop(&mut self) -> Result<(), EncodeError> {
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bool(&mut self, b: bool) -> Result<(), EncodeError> {
+        self.write_json_integer(b as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, b: Bytes) -> Result<(), EncodeError> {
+        self.write_json_base64(&b);
+        Ok(())
+    }
+
+    /// This is only used to write the raw bytes of the unknown fields, which are dropped in the
+    /// JSON protocol.
+    #[inline]
+    fn write_bytes_without_len(&mut self, _b: Bytes) -> Result<(), EncodeError> {
+        Ok(())
+    }
+
+    #[inline]
+    fn write_uuid(&mut self, u: [u8; 16]) -> Result<(), EncodeError> {
+        self.write_json_string(format_uuid(u).as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes_vec(&mut self, b: &[u8]) -> Result<(), EncodeError> {
+        self.write_json_base64(b);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_byte(&mut self, b: u8) -> Result<(), EncodeError> {
+        self.write_json_integer(b as i8 as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i8(&mut self, i: i8) -> Result<(), EncodeError> {
+        self.write_json_integer(i as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i16(&mut self, i: i16) -> Result<(), EncodeError> {
+        self.write_json_integer(i as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) -> Result<(), EncodeError> {
+        self.write_json_integer(i as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) -> Result<(), EncodeError> {
+        self.write_json_integer(i);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_double(&mut self, d: f64) -> Result<(), EncodeError> {
+        self.write_json_double(d);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_string(&mut self, s: &str) -> Result<(), EncodeError> {
+        self.write_json_string(s.as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_faststr(&mut self, s: FastStr) -> Result<(), EncodeError> {
+        self.write_json_string(s.as_bytes());
+        Ok(())
+    }
+
+    #[inline]
+    fn write_list_begin(&mut self, identifier: TListIdentifier) -> Result<(), EncodeError> {
+        self.write_json_array_start();
+        self.write_type_name(identifier.element_type)?;
+        self.write_json_integer(identifier.size as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_list_end(&mut self) -> Result<(), EncodeError> {
+        self.write_json_array_end();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_set_begin(&mut self, identifier: TSetIdentifier) -> Result<(), EncodeError> {
+        self.write_json_array_start();
+        self.write_type_name(identifier.element_type)?;
+        self.write_json_integer(identifier.size as i64);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_set_end(&mut self) -> Result<(), EncodeError> {
+        self.write_json_array_end();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_map_begin(&mut self, identifier: TMapIdentifier) -> Result<(), EncodeError> {
+        self.write_json_array_start();
+        self.write_type_name(identifier.key_type)?;
+        self.write_type_name(identifier.value_type)?;
+        self.write_json_integer(identifier.size as i64);
+        self.write_json_object_start();
+        Ok(())
+    }
+
+    #[inline]
+    fn write_map_end(&mut self) -> Result<(), EncodeError> {
+        self.write_json_object_end();
+        self.write_json_array_end();
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<(), EncodeError> {
+        Ok(())
+    }
+
+    #[inline]
+    fn buf_mut(&mut self) -> &mut Self::BufMut {
+        self.trans
+    }
+}
+
+#[inline]
+fn eof() -> DecodeError {
+    DecodeError::new(DecodeErrorKind::InvalidData, "unexpected end of json")
+}
+
+impl TJsonProtocol<&mut Bytes> {
+    #[inline]
+    fn skip_whitespace(&mut self) {
+        let n = self
+            .trans
+            .iter()
+            .take_while(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
+            .count();
+        self.trans.advance(n);
+    }
+
+    #[inline]
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.trans.first().copied()
+    }
+
+    #[inline]
+    fn read_syntax_char(&mut self, ch: u8) -> Result<(), DecodeError> {
+        match self.peek() {
+            Some(b) if b == ch => {
+                self.trans.advance(1);
+                Ok(())
+            }
+            Some(b) => Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!("expected '{}' but found '{}'", ch as char, b as char),
+            )),
+            None => Err(eof()),
+        }
+    }
+
+    // reads the separator, and returns whether the value is a key
+    #[inline]
+    fn read_sep(&mut self) -> Result<bool, DecodeError> {
+        if let Some(sep) = self.contexts.current.next() {
+            self.read_syntax_char(sep)?;
+        }
+        Ok(self.contexts.current.is_key())
+    }
+
+    #[inline]
+    fn read_numeric_chars(&mut self) -> Result<&str, DecodeError> {
+        self.skip_whitespace();
+        let n = self
+            .trans
+            .iter()
+            .take_while(|b| matches!(b, b'0'..=b'9' | b'+' | b'-' | b'.' | b'e' | b'E'))
+            .count();
+        // the numeric chars are always valid utf-8
+        std::str::from_utf8(&self.trans[..n])
+            .map_err(|e| DecodeError::new(DecodeErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn read_json_integer(&mut self) -> Result<i64, DecodeError> {
+        let is_key = self.read_sep()?;
+        if is_key {
+            self.read_syntax_char(b'"')?;
+        }
+        let s = self.read_numeric_chars()?;
+        let (n, i) = (
+            s.len(),
+            s.parse::<i64>().map_err(|e| {
+                DecodeError::new(
+                    DecodeErrorKind::InvalidData,
+                    format!("invalid integer {s:?}: {e}"),
+                )
+            })?,
+        );
+        self.trans.advance(n);
+        if is_key {
+            self.read_syntax_char(b'"')?;
+        }
+        Ok(i)
+    }
+
+    fn read_json_double(&mut self) -> Result<f64, DecodeError> {
+        let is_key = self.read_sep()?;
+        if self.peek() == Some(b'"') {
+            let s = self.read_json_string(true)?;
+            let d = std::str::from_utf8(&s)
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .ok_or_else(|| {
+                    DecodeError::new(
+                        DecodeErrorKind::InvalidData,
+                        format!("invalid double {:?}", String::from_utf8_lossy(&s)),
+                    )
+                })?;
+            if !is_key && !is_special_double(d) {
+                return Err(DecodeError::new(
+                    DecodeErrorKind::InvalidData,
+                    "numeric double is quoted",
+                ));
+            }
+            return Ok(d);
+        }
+        if is_key {
+            return Err(DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                "double as a key is not quoted",
+            ));
+        }
+        let s = self.read_numeric_chars()?;
+        let (n, d) = (
+            s.len(),
+            s.parse::<f64>().map_err(|e| {
+                DecodeError::new(
+                    DecodeErrorKind::InvalidData,
+                    format!("invalid double {s:?}: {e}"),
+                )
+            })?,
+        );
+        self.trans.advance(n);
+        Ok(d)
+    }
+
+    #[inline]
+    fn read_hex4(&mut self) -> Result<u16, DecodeError> {
+        if self.trans.len() < 4 {
+            return Err(eof());
+        }
+        let hex = self.trans.split_to(4);
+        std::str::from_utf8(&hex)
+            .ok()
+            .and_then(|s| u16::from_str_radix(s, 16).ok())
+            .ok_or_else(|| {
+                DecodeError::new(
+                    DecodeErrorKind::InvalidData,
+                    format!("invalid unicode escape {:?}", String::from_utf8_lossy(&hex)),
+                )
+            })
+    }
+
+    fn read_json_string(&mut self, skip_context: bool) -> Result<Bytes, DecodeError> {
+        if !skip_context {
+            self.read_sep()?;
+        }
+        self.read_syntax_char(b'"')?;
+
+        // the string without escapes can be returned without copy
+        match self.trans.iter().position(|b| *b == b'"' || *b == b'\\') {
+            Some(i) if self.trans[i] == b'"' => {
+                let s = self.trans.split_to(i);
+                self.trans.advance(1);
+                return Ok(s);
+            }
+            Some(_) => {}
+            None => return Err(eof()),
+        }
+
+        let mut s = Vec::new();
+        loop {
+            if !self.trans.has_remaining() {
+                return Err(eof());
+            }
+            match self.trans.get_u8() {
+                b'"' => break,
+                b'\\' => {
+                    if !self.trans.has_remaining() {
+                        return Err(eof());
+                    }
+                    match self.trans.get_u8() {
+                        b @ (b'"' | b'\\' | b'/') => s.push(b),
+                        b'b' => s.push(0x08),
+                        b'f' => s.push(0x0c),
+                        b'n' => s.push(b'\n'),
+                        b'r' => s.push(b'\r'),
+                        b't' => s.push(b'\t'),
+                        b'u' => {
+                            let mut code = self.read_hex4()? as u32;
+                            if (0xd800..0xdc00).contains(&code) {
+                                // the high surrogate must be followed by a low surrogate
+                                if !self.trans.starts_with(b"\\u") {
+                                    return Err(DecodeError::new(
+                                        DecodeErrorKind::InvalidData,
+                                        "expected low surrogate",
+                                    ));
+                                }
+                                self.trans.advance(2);
+                                let low = self.read_hex4()? as u32;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(DecodeError::new(
+                                        DecodeErrorKind::InvalidData,
+                                        "expected low surrogate",
+                                    ));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            let ch = char::from_u32(code).ok_or_else(|| {
+                                DecodeError::new(
+                                    DecodeErrorKind::InvalidData,
+                                    format!("invalid unicode code point {code:#x}"),
+                                )
+                            })?;
+                            s.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        b => {
+                            return Err(DecodeError::new(
+                                DecodeErrorKind::InvalidData,
+                                format!("invalid escape char '{}'", b as char),
+                            ))
+                        }
+                    }
+                }
+                b => s.push(b),
+            }
+        }
+        Ok(s.into())
+    }
+
+    #[inline]
+    fn read_json_utf8(&mut self) -> Result<FastStr, DecodeError> {
+        let s = self.read_json_string(false)?;
+        std::str::from_utf8(&s)
+            .map_err(|e| DecodeError::new(DecodeErrorKind::InvalidData, e.to_string()))?;
+        // SAFETY: the string is checked above
+        Ok(unsafe { FastStr::from_bytes_unchecked(s) })
+    }
+
+    #[inline]
+    fn read_json_base64(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let s = self.read_json_string(false)?;
+        let s = s
+            .strip_suffix(b"==")
+            .or_else(|| s.strip_suffix(b"="))
+            .unwrap_or(&s);
+        base64::decode_config(s, base64::STANDARD_NO_PAD)
+            .map_err(|e| DecodeError::new(DecodeErrorKind::InvalidData, e.to_string()))
+    }
+
+    #[inline]
+    fn read_json_object_start(&mut self) -> Result<(), DecodeError> {
+        self.read_sep()?;
+        self.read_syntax_char(b'{')?;
+        self.contexts.push(Context::Pair {
+            first: true,
+            colon: true,
+        });
+        Ok(())
+    }
+
+    #[inline]
+    fn read_json_object_end(&mut self) -> Result<(), DecodeError> {
+        self.read_syntax_char(b'}')?;
+        self.contexts.pop();
+        Ok(())
+    }
+
+    #[inline]
+    fn read_json_array_start(&mut self) -> Result<(), DecodeError> {
+        self.read_sep()?;
+        self.read_syntax_char(b'[')?;
+        self.contexts.push(Context::List { first: true });
+        Ok(())
+    }
+
+    #[inline]
+    fn read_json_array_end(&mut self) -> Result<(), DecodeError> {
+        self.read_syntax_char(b']')?;
+        self.contexts.pop();
+        Ok(())
+    }
+
+    #[inline]
+    fn read_type(&mut self) -> Result<TType, DecodeError> {
+        type_of(&self.read_json_string(false)?)
+    }
+
+    #[inline]
+    fn read_size(&mut self) -> Result<usize, DecodeError> {
+        let size = self.read_json_integer()?;
+        usize::try_from(size).map_err(|_| {
+            DecodeError::new(
+                DecodeErrorKind::NegativeSize,
+                format!("negative size {size}"),
+            )
+        })
+    }
+
+    #[inline]
+    fn read_integer_as<I: TryFrom<i64>>(&mut self) -> Result<I, DecodeError> {
+        let i = self.read_json_integer()?;
+        I::try_from(i).map_err(|_| {
+            DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!("integer {i} out of range"),
+            )
+        })
+    }
+
+    fn skip_value(&mut self, field_type: TType, depth: i8) -> Result<(), DecodeError> {
+        if depth == 0 {
+            return Err(DecodeError::new(
+                DecodeErrorKind::DepthLimit,
+                format!("cannot parse past {field_type:?}"),
+            ));
+        }
+        match field_type {
+            TType::Bool | TType::I8 | TType::I16 | TType::I32 | TType::I64 => {
+                self.read_json_integer()?;
+            }
+            TType::Double => {
+                self.read_json_double()?;
+            }
+            TType::Binary | TType::Uuid => {
+                self.read_json_string(false)?;
+            }
+            TType::Struct => {
+                self.read_struct_begin()?;
+                loop {
+                    let field_ident = self.read_field_begin()?;
+                    if field_ident.field_type == TType::Stop {
+                        break;
+                    }
+                    self.skip_value(field_ident.field_type, depth - 1)?;
+                    self.read_field_end()?;
+                }
+                self.read_struct_end()?;
+            }
+            TType::List | TType::Set => {
+                self.read_json_array_start()?;
+                let element_type = self.read_type()?;
+                let size = self.read_size()?;
+                for _ in 0..size {
+                    self.skip_value(element_type, depth - 1)?;
+                }
+                self.read_json_array_end()?;
+            }
+            TType::Map => {
+                let ident = self.read_map_begin()?;
+                for _ in 0..ident.size {
+                    self.skip_value(ident.key_type, depth - 1)?;
+                    self.skip_value(ident.value_type, depth - 1)?;
+                }
+                self.read_map_end()?;
+            }
+            u => {
+                return Err(DecodeError::new(
+                    DecodeErrorKind::DepthLimit,
+                    format!("cannot skip field type {u:?}"),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TInputProtocol for TJsonProtocol<&mut Bytes> {
+    type Buf = Bytes;
+
+    fn read_message_begin(&mut self) -> Result<TMessageIdentifier, DecodeError> {
+        self.read_json_array_start()?;
+        let version = self.read_json_integer()?;
+        if version != VERSION {
+            return Err(DecodeError::new(
+                DecodeErrorKind::BadVersion,
+                format!("unsupported json protocol version {version}"),
+            ));
+        }
+        let name = self.read_json_utf8()?;
+        let message_type = self.read_json_integer()?;
+        let message_type = u8::try_from(message_type)
+            .ok()
+            .and_then(|t| TMessageType::try_from(t).ok())
+            .ok_or_else(|| {
+                DecodeError::new(
+                    DecodeErrorKind::InvalidData,
+                    format!("invalid message type {message_type}"),
+                )
+            })?;
+        let sequence_number = self.read_integer_as::<i32>()?;
+        Ok(TMessageIdentifier::new(name, message_type, sequence_number))
+    }
+
+    #[inline]
+    fn read_message_end(&mut self) -> Result<(), DecodeError> {
+        self.read_json_array_end()
+    }
+
+    #[inline]
+    fn read_struct_begin(&mut self) -> Result<Option<TStructIdentifier>, DecodeError> {
+        self.read_json_object_start()?;
+        Ok(None)
+    }
+
+    #[inline]
+    fn read_struct_end(&mut self) -> Result<(), DecodeError> {
+        self.read_json_object_end()
+    }
+
+    #[inline]
+    fn read_field_begin(&mut self) -> Result<TFieldIdentifier, DecodeError> {
+        if self.peek() == Some(b'}') {
+            return Ok(TFieldIdentifier::new(None, TType::Stop, None));
+        }
+        let id = self.read_integer_as::<i16>()?;
+        self.read_json_object_start()?;
+        let field_type = self.read_type()?;
+        Ok(TFieldIdentifier::new(None, field_type, Some(id)))
+    }
+
+    #[inline]
+    fn read_field_end(&mut self) -> Result<(), DecodeError> {
+        self.read_json_object_end()
+    }
+
+    #[inline]
+    fn read_bool(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.read_json_integer()? != 0)
+    }
+
+    #[inline]
+    fn read_bytes(&mut self) -> Result<Bytes, DecodeError> {
+        Ok(self.read_json_base64()?.into())
+    }
+
+    fn read_uuid(&mut self) -> Result<[u8; 16], DecodeError> {
+        let s = self.read_json_string(false)?;
+        let hex = s
+            .iter()
+            .filter(|b| **b != b'-')
+            .copied()
+            .collect::<Vec<_>>();
+        let invalid = || {
+            DecodeError::new(
+                DecodeErrorKind::InvalidData,
+                format!("invalid uuid {:?}", String::from_utf8_lossy(&s)),
+            )
+        };
+        if hex.len() != 32 {
+            return Err(invalid());
+        }
+        let mut u = [0; 16];
+        for (i, b) in u.iter_mut().enumerate() {
+            *b = std::str::from_utf8(&hex[i * 2..i * 2 + 2])
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(invalid)?;
+        }
+        Ok(u)
+    }
+
+    #[inline]
+    fn read_i8(&mut self) -> Result<i8, DecodeError> {
+        self.read_integer_as()
+    }
+
+    #[inline]
+    fn read_i16(&mut self) -> Result<i16, DecodeError> {
+        self.read_integer_as()
+    }
+
+    #[inline]
+    fn read_i32(&mut self) -> Result<i32, DecodeError> {
+        self.read_integer_as()
+    }
+
+    #[inline]
+    fn read_i64(&mut self) -> Result<i64, DecodeError> {
+        self.read_json_integer()
+    }
+
+    #[inline]
+    fn read_double(&mut self) -> Result<f64, DecodeError> {
+        self.read_json_double()
+    }
+
+    #[inline]
+    fn read_string(&mut self) -> Result<String, DecodeError> {
+        Ok(self.read_json_utf8()?.into_string())
+    }
+
+    #[inline]
+    fn read_faststr(&mut self) -> Result<FastStr, DecodeError> {
+        self.read_json_utf8()
+    }
+
+    #[inline]
+    fn read_list_begin(&mut self) -> Result<TListIdentifier, DecodeError> {
+        self.read_json_array_start()?;
+        let element_type = self.read_type()?;
+        let size = self.read_size()?;
+        Ok(TListIdentifier { element_type, size })
+    }
+
+    #[inline]
+    fn read_list_end(&mut self) -> Result<(), DecodeError> {
+        self.read_json_array_end()
+    }
+
+    #[inline]
+    fn read_set_begin(&mut self) -> Result<TSetIdentifier, DecodeError> {
+        self.read_json_array_start()?;
+        let element_type = self.read_type()?;
+        let size = self.read_size()?;
+        Ok(TSetIdentifier { element_type, size })
+    }
+
+    #[inline]
+    fn read_set_end(&mut self) -> Result<(), DecodeError> {
+        self.read_json_array_end()
+    }
+
+    #[inline]
+    fn read_map_begin(&mut self) -> Result<TMapIdentifier, DecodeError> {
+        self.read_json_array_start()?;
+        let key_type = self.read_type()?;
+        let value_type = self.read_type()?;
+        let size = self.read_size()?;
+        self.read_json_object_start()?;
+        Ok(TMapIdentifier {
+            key_type,
+            value_type,
+            size,
+        })
+    }
+
+    #[inline]
+    fn read_map_end(&mut self) -> Result<(), DecodeError> {
+        self.read_json_object_end()?;
+        self.read_json_array_end()
+    }
+
+    /// Skips the value and returns the length of the skipped JSON text.
+    fn skip_till_depth(&mut self, field_type: TType, depth: i8) -> Result<usize, DecodeError> {
+        let remaining = self.trans.len();
+        self.skip_value(field_type, depth)?;
+        Ok(remaining - self.trans.len())
+    }
+
+    #[inline]
+    fn read_byte(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_i8()? as u8)
+    }
+
+    #[inline]
+    fn read_bytes_vec(&mut self) -> Result<Vec<u8>, DecodeError> {
+        self.read_json_base64()
+    }
+
+    /// This is only used to keep the raw bytes of the unknown fields, which are dropped in the
+    /// JSON protocol.
+    #[inline]
+    fn get_bytes(&mut self, _ptr: Option<*const u8>, _len: usize) -> Result<Bytes, DecodeError> {
+        Ok(Bytes::new())
+    }
+
+    #[inline]
+    fn buf(&mut self) -> &mut Self::Buf {
+        self.trans
+    }
+}
+
+/// [`MessageScanner`] finds the end of a JSON message in a stream, so the message can be read
+/// out before decoding.
+#[derive(Debug, Default)]
+pub struct MessageScanner {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl MessageScanner {
+    /// Scans the next part of the stream, and returns the length of the bytes till the end of the
+    /// message if the message is complete.
+    pub fn scan(&mut self, buf: &[u8]) -> Option<usize> {
+        for (i, b) in buf.iter().enumerate() {
+            if self.in_string {
+                match b {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match b {
+                b'"' => self.in_string = true,
+                b'[' | b'{' => self.depth += 1,
+                b']' | b'}' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        return Some(i + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use linkedbytes::LinkedBytes;
+    use pilota::thrift::{
+        TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+        TMessageType, TOutputProtocol, TStructIdentifier, TType,
+    };
+
+    use super::{MessageScanner, TJsonProtocol};
+
+    const STRUCT: TStructIdentifier = TStructIdentifier { name: "S" };
+
+    fn write<P: TOutputProtocol>(p: &mut P) {
+        let ident = TMessageIdentifier::new("echo".into(), TMessageType::Call, 7);
+        p.write_message_begin(&ident).unwrap();
+        p.write_struct_begin(&STRUCT).unwrap();
+        p.write_field_begin(TType::Binary, 1).unwrap();
+        p.write_string("a \"quoted\"\n\u{1}中文").unwrap();
+        p.write_field_end().unwrap();
+        p.write_field_begin(TType::Map, 2).unwrap();
+        p.write_map_begin(TMapIdentifier {
+            key_type: TType::I32,
+            value_type: TType::Double,
+            size: 2,
+        })
+        .unwrap();
+        p.write_i32(-1).unwrap();
+        p.write_double(f64::NAN).unwrap();
+        p.write_i32(2).unwrap();
+        p.write_double(0.5).unwrap();
+        p.write_map_end().unwrap();
+        p.write_field_end().unwrap();
+        p.write_field_begin(TType::List, 3).unwrap();
+        p.write_list_begin(TListIdentifier {
+            element_type: TType::Binary,
+            size: 1,
+        })
+        .unwrap();
+        p.write_bytes(Bytes::from_static(b"\x00\xffab")).unwrap();
+        p.write_list_end().unwrap();
+        p.write_field_end().unwrap();
+        p.write_field_begin(TType::Bool, 4).unwrap();
+        p.write_bool(true).unwrap();
+        p.write_field_end().unwrap();
+        p.write_field_stop().unwrap();
+        p.write_struct_end().unwrap();
+        p.write_message_end().unwrap();
+    }
+
+    fn len<P: TLengthProtocol>(p: &mut P) -> usize {
+        let ident = TMessageIdentifier::new("echo".into(), TMessageType::Call, 7);
+        p.message_begin_len(&ident)
+            + p.struct_begin_len(&STRUCT)
+            + p.field_begin_len(TType::Binary, Some(1))
+            + p.string_len("a \"quoted\"\n\u{1}中文")
+            + p.field_end_len()
+            + p.field_begin_len(TType::Map, Some(2))
+            + p.map_begin_len(TMapIdentifier {
+                key_type: TType::I32,
+                value_type: TType::Double,
+                size: 2,
+            })
+            + p.i32_len(-1)
+            + p.double_len(f64::NAN)
+            + p.i32_len(2)
+            + p.double_len(0.5)
+            + p.map_end_len()
+            + p.field_end_len()
+            + p.field_begin_len(TType::List, Some(3))
+            + p.list_begin_len(TListIdentifier {
+                element_type: TType::Binary,
+                size: 1,
+            })
+            + p.bytes_len(b"\x00\xffab")
+            + p.list_end_len()
+            + p.field_end_len()
+            + p.field_begin_len(TType::Bool, Some(4))
+            + p.bool_len(true)
+            + p.field_end_len()
+            + p.field_stop_len()
+            + p.struct_end_len()
+            + p.message_end_len()
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut buf = LinkedBytes::new();
+        write(&mut TJsonProtocol::new(&mut buf));
+        let json = std::str::from_utf8(buf.bytes()).unwrap();
+        assert_eq!(
+            json,
+            r#"[1,"echo",1,7,{"1":{"str":"a \"quoted\"\n\u0001中文"},"2":{"map":["i32","dbl",2,{"-1":"NaN","2":0.5}]},"3":{"lst":["str",1,"AP9hYg"]},"4":{"tf":1}}]"#
+        );
+        assert_eq!(len(&mut TJsonProtocol::new(())), json.len());
+    }
+
+    #[test]
+    fn test_decode() {
+        let json = r#" [1, "echo", 1, 7, {"1": {"str": "a \"quoted\"\n\u0001中😀"},
+            "2": {"map": ["i32", "dbl", 2, {"-1": "NaN", "2": 0.5}]},
+            "3": {"lst": ["str", 1, "AP9hYg=="]}, "5": {"rec": {"1": {"i64": 1}}}, "4": {"tf": 1}}] "#;
+        let mut scanner = MessageScanner::default();
+        assert_eq!(scanner.scan(&json.as_bytes()[..10]), None);
+        assert_eq!(
+            scanner.scan(&json.as_bytes()[10..]),
+            Some(json.trim_end().len() - 10)
+        );
+
+        let mut bytes = Bytes::from(json);
+        let mut p = TJsonProtocol::new(&mut bytes);
+        let ident = p.read_message_begin().unwrap();
+        assert_eq!(ident.name, "echo");
+        assert_eq!(ident.message_type, TMessageType::Call);
+        assert_eq!(ident.sequence_number, 7);
+        p.read_struct_begin().unwrap();
+
+        let field = p.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Binary, Some(1)));
+        assert_eq!(p.read_faststr().unwrap(), "a \"quoted\"\n\u{1}中😀");
+        p.read_field_end().unwrap();
+
+        let field = p.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Map, Some(2)));
+        let map = p.read_map_begin().unwrap();
+        assert_eq!(map.size, 2);
+        assert_eq!(p.read_i32().unwrap(), -1);
+        assert!(p.read_double().unwrap().is_nan());
+        assert_eq!(p.read_i32().unwrap(), 2);
+        assert_eq!(p.read_double().unwrap(), 0.5);
+        p.read_map_end().unwrap();
+        p.read_field_end().unwrap();
+
+        let field = p.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::List, Some(3)));
+        let list = p.read_list_begin().unwrap();
+        assert_eq!((list.element_type, list.size), (TType::Binary, 1));
+        assert_eq!(p.read_bytes().unwrap(), &b"\x00\xffab"[..]);
+        p.read_list_end().unwrap();
+        p.read_field_end().unwrap();
+
+        // unknown field
+        let field = p.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Struct, Some(5)));
+        p.skip(field.field_type).unwrap();
+        p.read_field_end().unwrap();
+
+        let field = p.read_field_begin().unwrap();
+        assert_eq!((field.field_type, field.id), (TType::Bool, Some(4)));
+        assert!(p.read_bool().unwrap());
+        p.read_field_end().unwrap();
+
+        assert_eq!(p.read_field_begin().unwrap().field_type, TType::Stop);
+        p.read_struct_end().unwrap();
+        p.read_message_end().unwrap();
+    }
+}
@@ -1,3 +1,7 @@
+pub mod fbthrift_compact;
+pub mod json;
+pub(crate) mod transcode;
+
 pub use binary::TBinaryProtocol;
 pub use pilota::thrift::{
     binary, TFieldIdentifier, TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier,
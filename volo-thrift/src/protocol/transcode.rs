@@ -0,0 +1,558 @@
+//! Transcodes thrift values between protocols without the IDL, by walking the values with their
+//! wire types as [`TInputProtocol::skip`] does.
+//!
+//! Note: The JSON protocol can't be transcoded correctly, since strings and binaries are both
+//! [`TType::Binary`] on the wire but are written differently in JSON.
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use pilota::thrift::{
+    DecodeError, DecodeErrorKind, EncodeError, ProtocolErrorKind, TAsyncInputProtocol,
+    TInputProtocol, TLengthProtocol, TListIdentifier, TMapIdentifier, TOutputProtocol,
+    TSetIdentifier, TStructIdentifier, TType,
+};
+
+const MAX_DEPTH: i8 = 64;
+
+const STRUCT: TStructIdentifier = TStructIdentifier { name: "" };
+
+#[derive(Debug)]
+pub enum TranscodeError {
+    Decode(DecodeError),
+    Encode(EncodeError),
+}
+
+impl From<DecodeError> for TranscodeError {
+    fn from(e: DecodeError) -> Self {
+        TranscodeError::Decode(e)
+    }
+}
+
+impl From<EncodeError> for TranscodeError {
+    fn from(e: EncodeError) -> Self {
+        TranscodeError::Encode(e)
+    }
+}
+
+impl From<TranscodeError> for DecodeError {
+    fn from(e: TranscodeError) -> Self {
+        match e {
+            TranscodeError::Decode(e) => e,
+            TranscodeError::Encode(e) => {
+                DecodeError::new(DecodeErrorKind::InvalidData, e.to_string())
+            }
+        }
+    }
+}
+
+impl From<TranscodeError> for EncodeError {
+    fn from(e: TranscodeError) -> Self {
+        match e {
+            TranscodeError::Decode(e) => {
+                EncodeError::new(ProtocolErrorKind::InvalidData, e.to_string())
+            }
+            TranscodeError::Encode(e) => e,
+        }
+    }
+}
+
+/// [`Sink`] receives the values read by the transcoder, which writes them to an output protocol
+/// by [`Output`] or counts their length by [`Length`].
+pub trait Sink {
+    fn struct_begin(&mut self) -> Result<(), EncodeError>;
+    fn struct_end(&mut self) -> Result<(), EncodeError>;
+    fn field_begin(&mut self, field_type: TType, id: i16) -> Result<(), EncodeError>;
+    fn field_end(&mut self) -> Result<(), EncodeError>;
+    fn field_stop(&mut self) -> Result<(), EncodeError>;
+    fn bool(&mut self, b: bool) -> Result<(), EncodeError>;
+    fn i8(&mut self, i: i8) -> Result<(), EncodeError>;
+    fn i16(&mut self, i: i16) -> Result<(), EncodeError>;
+    fn i32(&mut self, i: i32) -> Result<(), EncodeError>;
+    fn i64(&mut self, i: i64) -> Result<(), EncodeError>;
+    fn double(&mut self, d: f64) -> Result<(), EncodeError>;
+    fn binary(&mut self, b: Bytes) -> Result<(), EncodeError>;
+    fn uuid(&mut self, u: [u8; 16]) -> Result<(), EncodeError>;
+    fn list_begin(&mut self, identifier: TListIdentifier) -> Result<(), EncodeError>;
+    fn list_end(&mut self) -> Result<(), EncodeError>;
+    fn set_begin(&mut self, identifier: TSetIdentifier) -> Result<(), EncodeError>;
+    fn set_end(&mut self) -> Result<(), EncodeError>;
+    fn map_begin(&mut self, identifier: TMapIdentifier) -> Result<(), EncodeError>;
+    fn map_end(&mut self) -> Result<(), EncodeError>;
+}
+
+/// Writes the values to the output protocol.
+pub struct Output<'a, P>(pub &'a mut P);
+
+impl<P: TOutputProtocol> Sink for Output<'_, P> {
+    #[inline]
+    fn struct_begin(&mut self) -> Result<(), EncodeError> {
+        self.0.write_struct_begin(&STRUCT)
+    }
+
+    #[inline]
+    fn struct_end(&mut self) -> Result<(), EncodeError> {
+        self.0.write_struct_end()
+    }
+
+    #[inline]
+    fn field_begin(&mut self, field_type: TType, id: i16) -> Result<(), EncodeError> {
+        self.0.write_field_begin(field_type, id)
+    }
+
+    #[inline]
+    fn field_end(&mut self) -> Result<(), EncodeError> {
+        self.0.write_field_end()
+    }
+
+    #[inline]
+    fn field_stop(&mut self) -> Result<(), EncodeError> {
+        self.0.write_field_stop()
+    }
+
+    #[inline]
+    fn bool(&mut self, b: bool) -> Result<(), EncodeError> {
+        self.0.write_bool(b)
+    }
+
+    #[inline]
+    fn i8(&mut self, i: i8) -> Result<(), EncodeError> {
+        self.0.write_i8(i)
+    }
+
+    #[inline]
+    fn i16(&mut self, i: i16) -> Result<(), EncodeError> {
+        self.0.write_i16(i)
+    }
+
+    #[inline]
+    fn i32(&mut self, i: i32) -> Result<(), EncodeError> {
+        self.0.write_i32(i)
+    }
+
+    #[inline]
+    fn i64(&mut self, i: i64) -> Result<(), EncodeError> {
+        self.0.write_i64(i)
+    }
+
+    #[inline]
+    fn double(&mut self, d: f64) -> Result<(), EncodeError> {
+        self.0.write_double(d)
+    }
+
+    #[inline]
+    fn binary(&mut self, b: Bytes) -> Result<(), EncodeError> {
+        self.0.write_bytes(b)
+    }
+
+    #[inline]
+    fn uuid(&mut self, u: [u8; 16]) -> Result<(), EncodeError> {
+        self.0.write_uuid(u)
+    }
+
+    #[inline]
+    fn list_begin(&mut self, identifier: TListIdentifier) -> Result<(), EncodeError> {
+        self.0.write_list_begin(identifier)
+    }
+
+    #[inline]
+    fn list_end(&mut self) -> Result<(), EncodeError> {
+        self.0.write_list_end()
+    }
+
+    #[inline]
+    fn set_begin(&mut self, identifier: TSetIdentifier) -> Result<(), EncodeError> {
+        self.0.write_set_begin(identifier)
+    }
+
+    #[inline]
+    fn set_end(&mut self) -> Result<(), EncodeError> {
+        self.0.write_set_end()
+    }
+
+    #[inline]
+    fn map_begin(&mut self, identifier: TMapIdentifier) -> Result<(), EncodeError> {
+        self.0.write_map_begin(identifier)
+    }
+
+    #[inline]
+    fn map_end(&mut self) -> Result<(), EncodeError> {
+        self.0.write_map_end()
+    }
+}
+
+/// Counts the length of the values in the length protocol.
+pub struct Length<'a, P> {
+    protocol: &'a mut P,
+    pub len: usize,
+}
+
+impl<'a, P> Length<'a, P> {
+    #[inline]
+    pub fn new(protocol: &'a mut P) -> Self {
+        Self { protocol, len: 0 }
+    }
+}
+
+macro_rules! add_len {
+    ($self:ident.$f:ident($($arg:expr),*)) => {{
+        $self.len += $self.protocol.$f($($arg),*);
+        Ok(())
+    }};
+}
+
+impl<P: TLengthProtocol> Sink for Length<'_, P> {
+    #[inline]
+    fn struct_begin(&mut self) -> Result<(), EncodeError> {
+        add_len!(self.struct_begin_len(&STRUCT))
+    }
+
+    #[inline]
+    fn struct_end(&mut self) -> Result<(), EncodeError> {
+        add_len!(self.struct_end_len())
+    }
+
+    #[inline]
+    fn field_begin(&mut self, field_type: TType, id: i16) -> Result<(), EncodeError> {
+        add_len!(self.field_begin_len(field_type, Some(id)))
+    }
+
+    #[inline]
+    fn field_end(&mut self) -> Result<(), EncodeError> {
+        add_len!(self.field_end_len())
+    }
+
+    #[inline]
+    fn field_stop(&mut self) -> Result<(), EncodeError> {
+        add_len!(self.field_stop_len())
+    }
+
+    #[inline]
+    fn bool(&mut self, b: bool) -> Result<(), EncodeError> {
+        add_len!(self.bool_len(b))
+    }
+
+    #[inline]
+    fn i8(&mut self, i: i8) -> Result<(), EncodeError> {
+        add_len!(self.i8_len(i))
+    }
+
+    #[inline]
+    fn i16(&mut self, i: i16) -> Result<(), EncodeError> {
+        add_len!(self.i16_len(i))
+    }
+
+    #[inline]
+    fn i32(&mut self, i: i32) -> Result<(), EncodeError> {
+        add_len!(self.i32_len(i))
+    }
+
+    #[inline]
+    fn i64(&mut self, i: i64) -> Result<(), EncodeError> {
+        add_len!(self.i64_len(i))
+    }
+
+    #[inline]
+    fn double(&mut self, d: f64) -> Result<(), EncodeError> {
+        add_len!(self.double_len(d))
+    }
+
+    #[inline]
+    fn binary(&mut self, b: Bytes) -> Result<(), EncodeError> {
+        add_len!(self.bytes_len(&b))
+    }
+
+    #[inline]
+    fn uuid(&mut self, u: [u8; 16]) -> Result<(), EncodeError> {
+        add_len!(self.uuid_len(u))
+    }
+
+    #[inline]
+    fn list_begin(&mut self, identifier: TListIdentifier) -> Result<(), EncodeError> {
+        add_len!(self.list_begin_len(identifier))
+    }
+
+    #[inline]
+    fn list_end(&mut self) -> Result<(), EncodeError> {
+        add_len!(self.list_end_len())
+    }
+
+    #[inline]
+    fn set_begin(&mut self, identifier: TSetIdentifier) -> Result<(), EncodeError> {
+        add_len!(self.set_begin_len(identifier))
+    }
+
+    #[inline]
+    fn set_end(&mut self) -> Result<(), EncodeError> {
+        add_len!(self.set_end_len())
+    }
+
+    #[inline]
+    fn map_begin(&mut self, identifier: TMapIdentifier) -> Result<(), EncodeError> {
+        add_len!(self.map_begin_len(identifier))
+    }
+
+    #[inline]
+    fn map_end(&mut self) -> Result<(), EncodeError> {
+        add_len!(self.map_end_len())
+    }
+}
+
+#[inline]
+fn check_depth(field_type: TType, depth: i8) -> Result<(), DecodeError> {
+    if depth == 0 {
+        return Err(DecodeError::new(
+            DecodeErrorKind::DepthLimit,
+            format!("cannot parse past {field_type:?}"),
+        ));
+    }
+    Ok(())
+}
+
+#[inline]
+fn unsupported(field_type: TType) -> DecodeError {
+    DecodeError::new(
+        DecodeErrorKind::InvalidData,
+        format!("cannot transcode field type {field_type:?}"),
+    )
+}
+
+/// Transcodes a struct from the input protocol to the sink.
+#[inline]
+pub fn transcode_struct<I: TInputProtocol, S: Sink>(
+    input: &mut I,
+    sink: &mut S,
+) -> Result<(), TranscodeError> {
+    transcode(input, sink, TType::Struct, MAX_DEPTH)
+}
+
+fn transcode<I: TInputProtocol, S: Sink>(
+    input: &mut I,
+    sink: &mut S,
+    field_type: TType,
+    depth: i8,
+) -> Result<(), TranscodeError> {
+    check_depth(field_type, depth)?;
+    match field_type {
+        TType::Bool => sink.bool(input.read_bool()?)?,
+        TType::I8 => sink.i8(input.read_i8()?)?,
+        TType::I16 => sink.i16(input.read_i16()?)?,
+        TType::I32 => sink.i32(input.read_i32()?)?,
+        TType::I64 => sink.i64(input.read_i64()?)?,
+        TType::Double => sink.double(input.read_double()?)?,
+        TType::Binary => sink.binary(input.read_bytes()?)?,
+        TType::Uuid => sink.uuid(input.read_uuid()?)?,
+        TType::Struct => {
+            input.read_struct_begin()?;
+            sink.struct_begin()?;
+            loop {
+                let field_ident = input.read_field_begin()?;
+                if field_ident.field_type == TType::Stop {
+                    break;
+                }
+                sink.field_begin(field_ident.field_type, field_ident.id.unwrap_or_default())?;
+                transcode(input, sink, field_ident.field_type, depth - 1)?;
+                input.read_field_end()?;
+                sink.field_end()?;
+            }
+            sink.field_stop()?;
+            input.read_struct_end()?;
+            sink.struct_end()?;
+        }
+        TType::List => {
+            let list_ident = input.read_list_begin()?;
+            sink.list_begin(list_ident)?;
+            for _ in 0..list_ident.size {
+                transcode(input, sink, list_ident.element_type, depth - 1)?;
+            }
+            input.read_list_end()?;
+            sink.list_end()?;
+        }
+        TType::Set => {
+            let set_ident = input.read_set_begin()?;
+            sink.set_begin(set_ident)?;
+            for _ in 0..set_ident.size {
+                transcode(input, sink, set_ident.element_type, depth - 1)?;
+            }
+            input.read_set_end()?;
+            sink.set_end()?;
+        }
+        TType::Map => {
+            let map_ident = input.read_map_begin()?;
+            sink.map_begin(map_ident)?;
+            for _ in 0..map_ident.size {
+                transcode(input, sink, map_ident.key_type, depth - 1)?;
+                transcode(input, sink, map_ident.value_type, depth - 1)?;
+            }
+            input.read_map_end()?;
+            sink.map_end()?;
+        }
+        u => return Err(unsupported(u).into()),
+    }
+    Ok(())
+}
+
+/// Transcodes a struct from the async input protocol to the sink.
+#[inline]
+pub async fn transcode_struct_async<I: TAsyncInputProtocol, S: Sink + Send>(
+    input: &mut I,
+    sink: &mut S,
+) -> Result<(), TranscodeError> {
+    transcode_async(input, sink, TType::Struct, MAX_DEPTH).await
+}
+
+// the recursive async fn must be boxed
+fn transcode_async<'a, I: TAsyncInputProtocol, S: Sink + Send>(
+    input: &'a mut I,
+    sink: &'a mut S,
+    field_type: TType,
+    depth: i8,
+) -> BoxFuture<'a, Result<(), TranscodeError>> {
+    Box::pin(async move {
+        check_depth(field_type, depth)?;
+        match field_type {
+            TType::Bool => sink.bool(input.read_bool().await?)?,
+            TType::I8 => sink.i8(input.read_i8().await?)?,
+            TType::I16 => sink.i16(input.read_i16().await?)?,
+            TType::I32 => sink.i32(input.read_i32().await?)?,
+            TType::I64 => sink.i64(input.read_i64().await?)?,
+            TType::Double => sink.double(input.read_double().await?)?,
+            TType::Binary => sink.binary(input.read_bytes().await?)?,
+            TType::Uuid => sink.uuid(input.read_uuid().await?)?,
+            TType::Struct => {
+                input.read_struct_begin().await?;
+                sink.struct_begin()?;
+                loop {
+                    let field_ident = input.read_field_begin().await?;
+                    if field_ident.field_type == TType::Stop {
+                        break;
+                    }
+                    sink.field_begin(field_ident.field_type, field_ident.id.unwrap_or_default())?;
+                    transcode_async(&mut *input, &mut *sink, field_ident.field_type, depth - 1)
+                        .await?;
+                    input.read_field_end().await?;
+                    sink.field_end()?;
+                }
+                sink.field_stop()?;
+                input.read_struct_end().await?;
+                sink.struct_end()?;
+            }
+            TType::List => {
+                let list_ident = input.read_list_begin().await?;
+                sink.list_begin(list_ident)?;
+                for _ in 0..list_ident.size {
+                    transcode_async(&mut *input, &mut *sink, list_ident.element_type, depth - 1)
+                        .await?;
+                }
+                input.read_list_end().await?;
+                sink.list_end()?;
+            }
+            TType::Set => {
+                let set_ident = input.read_set_begin().await?;
+                sink.set_begin(set_ident)?;
+                for _ in 0..set_ident.size {
+                    transcode_async(&mut *input, &mut *sink, set_ident.element_type, depth - 1)
+                        .await?;
+                }
+                input.read_set_end().await?;
+                sink.set_end()?;
+            }
+            TType::Map => {
+                let map_ident = input.read_map_begin().await?;
+                sink.map_begin(map_ident)?;
+                for _ in 0..map_ident.size {
+                    transcode_async(&mut *input, &mut *sink, map_ident.key_type, depth - 1).await?;
+                    transcode_async(&mut *input, &mut *sink, map_ident.value_type, depth - 1)
+                        .await?;
+                }
+                input.read_map_end().await?;
+                sink.map_end()?;
+            }
+            u => return Err(unsupported(u).into()),
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use linkedbytes::LinkedBytes;
+    use pilota::thrift::{
+        binary::TBinaryProtocol,
+        compact::{TCompactInputProtocol, TCompactOutputProtocol},
+        TAsyncCompactProtocol, TMapIdentifier, TOutputProtocol, TStructIdentifier, TType,
+    };
+
+    use super::{transcode_struct, transcode_struct_async, Length, Output};
+
+    fn write<P: TOutputProtocol>(p: &mut P) {
+        p.write_struct_begin(&TStructIdentifier { name: "S" })
+            .unwrap();
+        p.write_field_begin(TType::Bool, 1).unwrap();
+        p.write_bool(true).unwrap();
+        p.write_field_end().unwrap();
+        p.write_field_begin(TType::Map, 2).unwrap();
+        p.write_map_begin(TMapIdentifier {
+            key_type: TType::Binary,
+            value_type: TType::I32,
+            size: 1,
+        })
+        .unwrap();
+        p.write_string("key").unwrap();
+        p.write_i32(-7).unwrap();
+        p.write_map_end().unwrap();
+        p.write_field_end().unwrap();
+        p.write_field_begin(TType::I64, 300).unwrap();
+        p.write_i64(-1).unwrap();
+        p.write_field_end().unwrap();
+        p.write_field_stop().unwrap();
+        p.write_struct_end().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transcode() {
+        let mut compact = LinkedBytes::new();
+        write(&mut TCompactOutputProtocol::new(&mut compact, true));
+        let mut expected = BytesMut::new();
+        write(&mut TBinaryProtocol::new(&mut expected, true));
+
+        // compact to binary
+        let mut bytes = Bytes::copy_from_slice(compact.bytes());
+        let mut binary = BytesMut::new();
+        transcode_struct(
+            &mut TCompactInputProtocol::new(&mut bytes),
+            &mut Output(&mut TBinaryProtocol::new(&mut binary, true)),
+        )
+        .unwrap();
+        assert_eq!(binary, expected);
+
+        // binary to compact, which is the same as the length
+        let mut bytes = binary.freeze();
+        let mut len_protocol = TCompactOutputProtocol::new((), true);
+        let mut len = Length::new(&mut len_protocol);
+        transcode_struct(
+            &mut TBinaryProtocol::new(&mut bytes.clone(), true),
+            &mut len,
+        )
+        .unwrap();
+        assert_eq!(len.len, compact.bytes().len());
+        let mut output = LinkedBytes::new();
+        transcode_struct(
+            &mut TBinaryProtocol::new(&mut bytes, true),
+            &mut Output(&mut TCompactOutputProtocol::new(&mut output, true)),
+        )
+        .unwrap();
+        assert_eq!(output.bytes(), compact.bytes());
+
+        // async compact to binary
+        let mut reader = &compact.bytes()[..];
+        let mut binary = BytesMut::new();
+        transcode_struct_async(
+            &mut TAsyncCompactProtocol::new(&mut reader),
+            &mut Output(&mut TBinaryProtocol::new(&mut binary, true)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(binary, expected);
+    }
+}
@@ -1,9 +1,12 @@
 use std::{
+    future::Future,
+    io,
     marker::PhantomData,
     sync::{atomic::Ordering, Arc},
     time::Duration,
 };
 
+use futures::FutureExt;
 use motore::{
     layer::{Identity, Layer, Stack},
     service::Service,
@@ -11,13 +14,18 @@ use motore::{
 };
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    sync::Notify,
+    sync::{watch, Notify},
 };
-use tracing::{info, trace};
-use volo::net::{
-    conn::{OwnedReadHalf, OwnedWriteHalf},
-    incoming::Incoming,
-    Address,
+use tracing::{info, trace, warn};
+#[cfg(target_family = "unix")]
+use volo::hotrestart::{HotRestartConfig, DEFAULT_HOT_RESTART};
+use volo::{
+    net::{
+        conn::{ConnGuard, OwnedReadHalf, OwnedWriteHalf},
+        incoming::Incoming,
+        Address,
+    },
+    server::{ServerHandle, ServerState},
 };
 
 use crate::{
@@ -41,6 +49,9 @@ pub struct Server<S, L, Req, MkC, SP> {
     stat_tracer: Vec<TraceFn>,
     #[cfg(feature = "multiplex")]
     multiplex: bool,
+    #[cfg(target_family = "unix")]
+    hot_restart: Option<HotRestartConfig>,
+    shutdown_config: ShutdownConfig,
     span_provider: SP,
     _marker: PhantomData<Req>,
 }
@@ -65,6 +76,9 @@ impl<S, Req>
             stat_tracer: Vec::new(),
             #[cfg(feature = "multiplex")]
             multiplex: false,
+            #[cfg(target_family = "unix")]
+            hot_restart: None,
+            shutdown_config: ShutdownConfig::default(),
             span_provider: DefaultProvider {},
             _marker: PhantomData,
         }
@@ -72,6 +86,14 @@ impl<S, Req>
 }
 
 impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
+    /// Returns the service wrapped by the layers, the codec and the other options are dropped.
+    pub(crate) fn into_service(self) -> L::Service
+    where
+        L: Layer<S>,
+    {
+        self.layer.layer(self.service)
+    }
+
     /// Adds a new inner layer to the server.
     ///
     /// The layer's `Service` should be `Send + Sync + Clone + 'static`.
@@ -91,6 +113,9 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             stat_tracer: self.stat_tracer,
             #[cfg(feature = "multiplex")]
             multiplex: self.multiplex,
+            #[cfg(target_family = "unix")]
+            hot_restart: self.hot_restart,
+            shutdown_config: self.shutdown_config,
             span_provider: self.span_provider,
             _marker: PhantomData,
         }
@@ -115,6 +140,9 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             stat_tracer: self.stat_tracer,
             #[cfg(feature = "multiplex")]
             multiplex: self.multiplex,
+            #[cfg(target_family = "unix")]
+            hot_restart: self.hot_restart,
+            shutdown_config: self.shutdown_config,
             span_provider: self.span_provider,
             _marker: PhantomData,
         }
@@ -143,12 +171,52 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             stat_tracer: self.stat_tracer,
             #[cfg(feature = "multiplex")]
             multiplex: self.multiplex,
+            #[cfg(target_family = "unix")]
+            hot_restart: self.hot_restart,
+            shutdown_config: self.shutdown_config,
             span_provider: self.span_provider,
             _marker: PhantomData,
         }
     }
 
+    /// Enables the hot restart, so that a new process of the server takes over the listener of
+    /// the running one, which exits after draining its connections, without refusing any
+    /// connection.
+    ///
+    /// The servers of a process share the hot restart, so they should be given the same config,
+    /// whose listener number counts the listeners of all of them.
+    ///
+    /// Default is `None`, which means disabled.
+    #[cfg(target_family = "unix")]
+    pub fn hot_restart(mut self, config: Option<HotRestartConfig>) -> Self {
+        self.hot_restart = config;
+        self
+    }
+
+    /// Sets the time to keep serving the requests after the shutdown starts, so that the
+    /// responses carry the mark telling the clients to close the connections.
+    ///
+    /// The wait is skipped when there is no connection.
+    ///
+    /// Default is 2 seconds.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_config.drain_timeout = timeout;
+        self
+    }
+
+    /// Sets the time to wait for the connections to be closed after draining, the connections
+    /// left open are force-closed then.
+    ///
+    /// Default is 28 seconds.
+    pub fn grace_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_config.grace_timeout = timeout;
+        self
+    }
+
     /// The main entry point for the server.
+    ///
+    /// The server shuts down gracefully when receiving `SIGINT`, `SIGHUP` or `SIGTERM`, or
+    /// `Ctrl-C` on windows. Use [`Server::run_with_shutdown`] to handle the signals by yourself.
     pub async fn run<MI: volo::net::incoming::MakeIncoming>(
         self,
         make_incoming: MI,
@@ -164,14 +232,112 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
         Req: EntryMessage + Send + 'static,
         S::Response: EntryMessage + Send + 'static + Sync,
         SP: SpanProvider,
+    {
+        self.run_with_shutdown(make_incoming, default_signal())
+            .await
+            .map(|_| ())
+    }
+
+    /// Runs server with a stop signal to control graceful shutdown, the signals of the process
+    /// are not handled by the server.
+    ///
+    /// An error returned by the signal fails the server.
+    pub async fn run_with_shutdown<MI, F>(
+        self,
+        make_incoming: MI,
+        signal: F,
+    ) -> Result<ShutdownReport, BoxError>
+    where
+        MI: volo::net::incoming::MakeIncoming,
+        F: Future<Output = io::Result<()>>,
+        L: Layer<S>,
+        MkC: MakeCodec<OwnedReadHalf, OwnedWriteHalf>,
+        L::Service: Service<ServerContext, Req, Response = S::Response> + Send + 'static + Sync,
+        <L::Service as Service<ServerContext, Req>>::Error: Into<crate::Error> + Send,
+        for<'cx> <L::Service as Service<ServerContext, Req>>::Future<'cx>: Send,
+        S: Service<ServerContext, Req> + Send + 'static,
+        S::Error: Into<crate::Error> + Send,
+        Req: EntryMessage + Send + 'static,
+        S::Response: EntryMessage + Send + 'static + Sync,
+        SP: SpanProvider,
+    {
+        self.serve(make_incoming, signal, Arc::new(ServerState::new()))
+            .await
+    }
+
+    /// Spawns the server in background, and returns the [`ServerHandle`] to wait for the server
+    /// to be ready and to stop it.
+    ///
+    /// The signals of the process are not handled by the server.
+    pub fn spawn<MI>(self, make_incoming: MI) -> ServerHandle
+    where
+        MI: volo::net::incoming::MakeIncoming + Send + 'static,
+        S: Send + 'static,
+        L: Send + 'static,
+        MkC: Send + 'static,
+        L: Layer<S>,
+        MkC: MakeCodec<OwnedReadHalf, OwnedWriteHalf>,
+        L::Service: Service<ServerContext, Req, Response = S::Response> + Send + 'static + Sync,
+        <L::Service as Service<ServerContext, Req>>::Error: Into<crate::Error> + Send,
+        for<'cx> <L::Service as Service<ServerContext, Req>>::Future<'cx>: Send,
+        S: Service<ServerContext, Req> + Send + 'static,
+        S::Error: Into<crate::Error> + Send,
+        Req: EntryMessage + Send + 'static,
+        S::Response: EntryMessage + Send + 'static + Sync,
+        SP: SpanProvider,
+    {
+        ServerHandle::spawn(move |state, signal| {
+            self.serve(make_incoming, signal, state)
+                .map(|res| res.map(|_| ()))
+        })
+    }
+
+    async fn serve<MI, F>(
+        self,
+        make_incoming: MI,
+        signal: F,
+        state: Arc<ServerState>,
+    ) -> Result<ShutdownReport, BoxError>
+    where
+        MI: volo::net::incoming::MakeIncoming,
+        F: Future<Output = io::Result<()>>,
+        L: Layer<S>,
+        MkC: MakeCodec<OwnedReadHalf, OwnedWriteHalf>,
+        L::Service: Service<ServerContext, Req, Response = S::Response> + Send + 'static + Sync,
+        <L::Service as Service<ServerContext, Req>>::Error: Into<crate::Error> + Send,
+        for<'cx> <L::Service as Service<ServerContext, Req>>::Future<'cx>: Send,
+        S: Service<ServerContext, Req> + Send + 'static,
+        S::Error: Into<crate::Error> + Send,
+        Req: EntryMessage + Send + 'static,
+        S::Response: EntryMessage + Send + 'static + Sync,
+        SP: SpanProvider,
     {
         // init server
         let service = Arc::new(self.layer.layer(self.service));
         // TODO(lyf1999): type annotation is needed here, figure out why
         let stat_tracer: Arc<[TraceFn]> = Arc::from(self.stat_tracer);
+        let shutdown_config = self.shutdown_config;
+
+        #[cfg(target_family = "unix")]
+        let hot_restart = self.hot_restart.is_some();
+        #[cfg(target_family = "unix")]
+        if let Some(config) = &self.hot_restart {
+            DEFAULT_HOT_RESTART.initialize_with(config).await?;
+        }
+        // shuts down gracefully as well when the new process has taken over the listener
+        let terminated = async {
+            #[cfg(target_family = "unix")]
+            if hot_restart {
+                DEFAULT_HOT_RESTART.terminated().await;
+                info!("[VOLO] hot restarted by the new process");
+                return;
+            }
+            std::future::pending().await
+        };
 
         let mut incoming = make_incoming.make_incoming().await?;
         info!("[VOLO] server start at: {:?}", incoming);
+        state.set_ready(incoming.local_addr());
 
         let conn_cnt = Arc::new(std::sync::atomic::AtomicUsize::new(0));
         let gconn_cnt = conn_cnt.clone();
@@ -182,9 +348,11 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
         );
         let (exit_notify_inner, exit_flag_inner, exit_mark_inner) =
             (exit_notify.clone(), exit_flag.clone(), exit_mark.clone());
+        let (force_close, force_close_rx) = watch::channel(());
+        let state_inner = state.clone();
 
         // spawn accept loop
-        let handler = tokio::spawn(async move {
+        let mut handler = tokio::spawn(async move {
             let exit_flag = exit_flag_inner.clone();
             loop {
                 if *exit_flag.read() {
@@ -194,24 +362,51 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
                     Ok(Some(conn)) => {
                         let peer_addr = conn.info.peer_addr;
                         trace!("[VOLO] accept connection from: {:?}", peer_addr);
+                        // the guard is released after the connection is handled
+                        let guard = conn.guard;
                         let (rh, wh) = conn.stream.into_split();
                         conn_cnt.fetch_add(1, Ordering::Relaxed);
 
                         #[cfg(feature = "multiplex")]
                         if self.multiplex {
-                            tokio::spawn(handle_conn_multiplex(
-                                rh,
-                                wh,
-                                service.clone(),
-                                self.make_codec.clone(),
-                                stat_tracer.clone(),
-                                exit_notify_inner.clone(),
-                                exit_mark_inner.clone(),
-                                conn_cnt.clone(),
-                                peer_addr,
-                            ));
+                            spawn_conn(
+                                handle_conn_multiplex(
+                                    rh,
+                                    wh,
+                                    service.clone(),
+                                    self.make_codec.clone(),
+                                    stat_tracer.clone(),
+                                    exit_notify_inner.clone(),
+                                    exit_mark_inner.clone(),
+                                    conn_cnt.clone(),
+                                    peer_addr,
+                                ),
+                                guard,
+                                force_close_rx.clone(),
+                                state_inner.clone(),
+                            );
                         } else {
-                            tokio::spawn(handle_conn(
+                            spawn_conn(
+                                handle_conn(
+                                    rh,
+                                    wh,
+                                    service.clone(),
+                                    self.make_codec.clone(),
+                                    stat_tracer.clone(),
+                                    exit_notify_inner.clone(),
+                                    exit_mark_inner.clone(),
+                                    conn_cnt.clone(),
+                                    peer_addr,
+                                    self.span_provider.clone(),
+                                ),
+                                guard,
+                                force_close_rx.clone(),
+                                state_inner.clone(),
+                            );
+                        }
+                        #[cfg(not(feature = "multiplex"))]
+                        spawn_conn(
+                            handle_conn(
                                 rh,
                                 wh,
                                 service.clone(),
@@ -222,21 +417,11 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
                                 conn_cnt.clone(),
                                 peer_addr,
                                 self.span_provider.clone(),
-                            ));
-                        }
-                        #[cfg(not(feature = "multiplex"))]
-                        tokio::spawn(handle_conn(
-                            rh,
-                            wh,
-                            service.clone(),
-                            self.make_codec.clone(),
-                            stat_tracer.clone(),
-                            exit_notify_inner.clone(),
-                            exit_mark_inner.clone(),
-                            conn_cnt.clone(),
-                            peer_addr,
-                            self.span_provider.clone(),
-                        ));
+                            ),
+                            guard,
+                            force_close_rx.clone(),
+                            state_inner.clone(),
+                        );
                     }
                     // no more incoming connections
                     Ok(None) => break Ok(()),
@@ -245,40 +430,19 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             }
         });
 
-        #[cfg(target_family = "unix")]
-        {
-            // graceful shutdown
-            let mut sigint =
-                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
-            let mut sighup =
-                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
-            let mut sigterm =
-                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
-
-            // graceful shutdown handler
-            tokio::select! {
-                _ = sigint.recv() => {}
-                _ = sighup.recv() => {}
-                _ = sigterm.recv() => {}
-                res = handler => {
-                    match res {
-                        Ok(res) => {
-                            match res {
-                                Ok(()) => {}
-                                Err(e) => return Err(Box::new(e))
-                            };
-                        }
-                        Err(e) => return Err(Box::new(e)),
-                    }
+        // graceful shutdown handler
+        tokio::pin!(signal);
+        tokio::pin!(terminated);
+        tokio::select! {
+            res = &mut signal => {
+                if let Err(e) = res {
+                    // the listener may be shared with a new process, so stop accepting anyway
+                    handler.abort();
+                    return Err(Box::new(e));
                 }
             }
-        }
-
-        // graceful shutdown handler for windows
-        #[cfg(target_family = "windows")]
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {}
-            res = handler => {
+            _ = &mut terminated => {}
+            res = &mut handler => {
                 match res {
                     Ok(res) => {
                         match res {
@@ -293,28 +457,42 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
 
         // received signal, graceful shutdown now
         info!("[VOLO] received signal, gracefully exiting now");
+        // stops accepting at once, the listener may be shared with a new process
+        handler.abort();
         *exit_flag.write() = true;
         exit_mark.store(true, Ordering::Relaxed);
 
         // Now we won't accept new connections.
         // And we want to send crrst reply to the peers in the short future.
         if gconn_cnt.load(Ordering::Relaxed) != 0 {
-            tokio::time::sleep(Duration::from_secs(2)).await;
+            tokio::time::sleep(shutdown_config.drain_timeout).await;
         }
         exit_notify.notify_waiters();
 
         // wait for all connections to be closed
-        for _ in 0..28 {
-            if gconn_cnt.load(Ordering::Relaxed) == 0 {
+        let deadline = tokio::time::Instant::now() + shutdown_config.grace_timeout;
+        while gconn_cnt.load(Ordering::Relaxed) != 0 {
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
                 break;
             }
             trace!(
                 "[VOLO] gracefully exiting, remaining connection count: {}",
                 gconn_cnt.load(Ordering::Relaxed)
             );
-            tokio::time::sleep(Duration::from_secs(1)).await;
+            tokio::time::sleep((deadline - now).min(Duration::from_millis(100))).await;
         }
-        Ok(())
+
+        let force_closed = gconn_cnt.load(Ordering::Relaxed);
+        if force_closed != 0 {
+            warn!(
+                "[VOLO] graceful shutdown timed out, force closing {} connections",
+                force_closed
+            );
+            let _ = force_close.send(());
+            state.on_force_close(force_closed);
+        }
+        Ok(ShutdownReport { force_closed })
     }
 
     #[cfg(feature = "multiplex")]
@@ -329,6 +507,9 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             make_codec: self.make_codec,
             stat_tracer: self.stat_tracer,
             multiplex,
+            #[cfg(target_family = "unix")]
+            hot_restart: self.hot_restart,
+            shutdown_config: self.shutdown_config,
             span_provider: self.span_provider,
             _marker: PhantomData,
         }
@@ -342,12 +523,89 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             stat_tracer: self.stat_tracer,
             #[cfg(feature = "multiplex")]
             multiplex: self.multiplex,
+            #[cfg(target_family = "unix")]
+            hot_restart: self.hot_restart,
+            shutdown_config: self.shutdown_config,
             span_provider: provider,
             _marker: PhantomData,
         }
     }
 }
 
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_GRACE_TIMEOUT: Duration = Duration::from_secs(28);
+
+#[derive(Debug, Clone, Copy)]
+struct ShutdownConfig {
+    drain_timeout: Duration,
+    grace_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            grace_timeout: DEFAULT_GRACE_TIMEOUT,
+        }
+    }
+}
+
+/// The result of the graceful shutdown of a [`Server`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownReport {
+    force_closed: usize,
+}
+
+impl ShutdownReport {
+    /// Returns the number of the connections still open after the grace timeout, which are
+    /// force-closed.
+    pub fn force_closed(&self) -> usize {
+        self.force_closed
+    }
+}
+
+/// Waits for the signals the server shuts down gracefully on by default.
+async fn default_signal() -> io::Result<()> {
+    #[cfg(target_family = "unix")]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sighup = signal(SignalKind::hangup())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = sigint.recv() => {}
+            _ = sighup.recv() => {}
+            _ = sigterm.recv() => {}
+        }
+        Ok(())
+    }
+
+    #[cfg(target_family = "windows")]
+    tokio::signal::ctrl_c().await
+}
+
+/// Spawns the task of the connection, which is dropped when the server force-closes the
+/// connections.
+fn spawn_conn<F>(
+    conn: F,
+    guard: Option<ConnGuard>,
+    mut force_close: watch::Receiver<()>,
+    state: Arc<ServerState>,
+) where
+    F: Future<Output = ()> + Send + 'static,
+{
+    state.on_accept();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = conn => {}
+            _ = force_close.changed() => {}
+        }
+        drop(guard);
+        state.on_close();
+    });
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn handle_conn<R, W, Req, Svc, Resp, MkC, SP>(
     rh: R,
@@ -430,3 +688,139 @@ async fn handle_conn_multiplex<R, W, Req, Svc, Resp, MkC>(
     .await;
     conn_cnt.fetch_sub(1, Ordering::Relaxed);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{io, net::SocketAddr, time::Duration};
+
+    use bytes::{BufMut, BytesMut};
+    use motore::service::service_fn;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::oneshot,
+    };
+    use volo::{
+        limit::{Quota, RateLimitConfig, RateLimitLayer, RateLimiter},
+        net::Address,
+    };
+
+    use super::Server;
+    use crate::{context::ServerContext, multiplexed::MultiplexedRequest};
+
+    fn unused_addr() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    async fn connect(addr: SocketAddr) -> TcpStream {
+        loop {
+            match TcpStream::connect(addr).await {
+                Ok(conn) => break conn,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    }
+
+    /// Encodes a framed binary call of the method with an empty struct.
+    fn framed_call(method: &str) -> BytesMut {
+        let mut msg = BytesMut::new();
+        msg.put_u32(0x8001_0001);
+        msg.put_i32(method.len() as i32);
+        msg.put_slice(method.as_bytes());
+        msg.put_i32(1);
+        msg.put_u8(0);
+
+        let mut buf = BytesMut::new();
+        buf.put_u32(msg.len() as u32);
+        buf.put_slice(&msg);
+        buf
+    }
+
+    /// Reads a framed binary message, and returns the message type and the whole message.
+    async fn read_framed(conn: &mut TcpStream) -> (u8, Vec<u8>) {
+        let len = conn.read_u32().await.unwrap();
+        let mut msg = vec![0; len as usize];
+        conn.read_exact(&mut msg).await.unwrap();
+        (msg[3], msg)
+    }
+
+    #[tokio::test]
+    async fn test_grace_timeout() {
+        let addr = unused_addr();
+        let service = service_fn(
+            |_: &mut ServerContext, req: MultiplexedRequest| async move {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok::<_, crate::Error>(req)
+            },
+        );
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            Server::new(service)
+                .drain_timeout(Duration::ZERO)
+                .grace_timeout(Duration::from_millis(100))
+                .run_with_shutdown(Address::from(addr), async move {
+                    let _ = stopped.await;
+                    Ok::<_, io::Error>(())
+                }),
+        );
+
+        let mut conn = connect(addr).await;
+        conn.write_all(&framed_call("echo")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // the request in flight holds the connection open past the grace timeout
+        stop.send(()).unwrap();
+        let report = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.force_closed(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let addr = unused_addr();
+        let service = service_fn(
+            |_: &mut ServerContext, req: MultiplexedRequest| async move { Ok::<_, crate::Error>(req) },
+        );
+        let limiter = RateLimiter::new(
+            RateLimitConfig::new().method("echo", Quota::per_second(0.0).burst(1)),
+        );
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            Server::new(service)
+                .layer(RateLimitLayer::new(limiter.clone()))
+                .run_with_shutdown(Address::from(addr), async move {
+                    let _ = stopped.await;
+                    Ok::<_, io::Error>(())
+                }),
+        );
+
+        let mut conn = connect(addr).await;
+        conn.write_all(&framed_call("echo")).await.unwrap();
+        let (msg_type, _) = read_framed(&mut conn).await;
+        assert_eq!(msg_type, 2);
+
+        // the quota of the method is exhausted, and the call gets an exception
+        conn.write_all(&framed_call("echo")).await.unwrap();
+        let (msg_type, msg) = read_framed(&mut conn).await;
+        assert_eq!(msg_type, 3);
+        assert!(String::from_utf8_lossy(&msg).contains("rate limited"));
+        assert_eq!(limiter.rejected(), 1);
+
+        // other methods are not limited
+        conn.write_all(&framed_call("other")).await.unwrap();
+        let (msg_type, _) = read_framed(&mut conn).await;
+        assert_eq!(msg_type, 2);
+
+        drop(conn);
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,847 @@
+//! Circuit breaker for volo clients.
+//!
+//! [`CircuitBreakerLayer`] keeps a circuit breaker for each callee service, and another one for
+//! each address of the callee service. When the failures of a callee reach the threshold, the
+//! circuit opens and the following requests fail fast with [`LoadBalanceError::CircuitOpen`]
+//! instead of waiting for the rpc timeout. After the cooling time, the circuit becomes half-open
+//! and lets a few requests through as probes, and closes again if all of them succeed.
+//!
+//! The layer should be added as an inner layer of the client, so that the address of the callee
+//! has been picked by the load balancer when the request arrives. An open circuit of an address
+//! produces a retryable error, so the load balancer will try the next address.
+
+use std::{
+    fmt::{self, Debug},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use motore::Service;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::{
+    context::Context,
+    loadbalance::{
+        error::{LoadBalanceError, Retryable},
+        hedge::HedgeToken,
+    },
+    net::Address,
+    FastStr, Layer,
+};
+
+const DEFAULT_FAILURE_RATE: f64 = 0.5;
+const DEFAULT_MIN_REQUESTS: usize = 200;
+const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+const DEFAULT_COOLING_TIME: Duration = Duration::from_secs(5);
+const DEFAULT_HALF_OPEN_REQUESTS: usize = 1;
+/// The closed breakers of the addresses without requests for this long are evicted, which is the
+/// same as resetting them.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// The minimal interval between the evictions of the idle breakers.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerConfig {
+    /// The failure rate in `[0, 1]` within a `window` that opens the circuit.
+    failure_rate: f64,
+    /// The minimum number of requests within a `window` to evaluate the `failure_rate`.
+    min_requests: usize,
+    /// The number of consecutive failures that opens the circuit, `0` means disabled.
+    consecutive_failures: usize,
+    /// The time window to calculate the failure rate.
+    window: Duration,
+    /// How long the circuit keeps open before it becomes half-open.
+    cooling_time: Duration,
+    /// The number of requests allowed to pass through when the circuit is half-open.
+    half_open_requests: usize,
+}
+
+impl CircuitBreakerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the failure rate that opens the circuit when there are at least `min_requests`
+    /// requests within the window.
+    ///
+    /// Defaults to 50% of 200 requests.
+    pub fn failure_rate(mut self, rate: f64, min_requests: usize) -> Self {
+        self.failure_rate = rate;
+        self.min_requests = min_requests;
+        self
+    }
+
+    /// Sets the number of consecutive failures that opens the circuit.
+    ///
+    /// Defaults to `0`, which means disabled.
+    pub fn consecutive_failures(mut self, n: usize) -> Self {
+        self.consecutive_failures = n;
+        self
+    }
+
+    /// Sets the time window to calculate the failure rate.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets how long the circuit keeps open before it becomes half-open.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn cooling_time(mut self, time: Duration) -> Self {
+        self.cooling_time = time;
+        self
+    }
+
+    /// Sets the number of probe requests when the circuit is half-open.
+    ///
+    /// Defaults to 1.
+    pub fn half_open_requests(mut self, n: usize) -> Self {
+        self.half_open_requests = n.max(1);
+        self
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_rate: DEFAULT_FAILURE_RATE,
+            min_requests: DEFAULT_MIN_REQUESTS,
+            consecutive_failures: 0,
+            window: DEFAULT_WINDOW,
+            cooling_time: DEFAULT_COOLING_TIME,
+            half_open_requests: DEFAULT_HALF_OPEN_REQUESTS,
+        }
+    }
+}
+
+/// The state of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { inflight: usize, successes: usize },
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: State,
+    window_start: Instant,
+    requests: usize,
+    failures: usize,
+    consecutive_failures: usize,
+    last_acquired: Instant,
+}
+
+impl Inner {
+    fn reset_counters(&mut self, now: Instant) {
+        self.window_start = now;
+        self.requests = 0;
+        self.failures = 0;
+        self.consecutive_failures = 0;
+    }
+}
+
+/// [`CircuitBreaker`] is the state machine of a single circuit.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// The name of the circuit used in logs.
+    name: String,
+    config: CircuitBreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: impl Into<String>, config: CircuitBreakerConfig) -> Self {
+        Self {
+            name: name.into(),
+            config,
+            inner: Mutex::new(Inner {
+                state: State::Closed,
+                window_start: Instant::now(),
+                requests: 0,
+                failures: 0,
+                consecutive_failures: 0,
+                last_acquired: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns the current state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        match self.inner.lock().unwrap().state {
+            State::Closed => CircuitState::Closed,
+            State::Open { until } if Instant::now() < until => CircuitState::Open,
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Returns whether a request is allowed to pass through.
+    ///
+    /// Every allowed request must be followed by a [`CircuitBreaker::record`].
+    pub fn try_acquire(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_acquired = Instant::now();
+        match &mut inner.state {
+            State::Closed => true,
+            State::Open { until } => {
+                if Instant::now() < *until {
+                    return false;
+                }
+                inner.state = State::HalfOpen {
+                    inflight: 1,
+                    successes: 0,
+                };
+                true
+            }
+            State::HalfOpen { inflight, .. } => {
+                if *inflight < self.config.half_open_requests {
+                    *inflight += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Records the result of an allowed request.
+    pub fn record(&self, success: bool) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        match &mut inner.state {
+            State::Closed => {
+                if now.duration_since(inner.window_start) >= self.config.window {
+                    inner.window_start = now;
+                    inner.requests = 0;
+                    inner.failures = 0;
+                }
+                inner.requests += 1;
+                if success {
+                    inner.consecutive_failures = 0;
+                    return;
+                }
+                inner.failures += 1;
+                inner.consecutive_failures += 1;
+
+                let too_many_consecutive_failures = self.config.consecutive_failures > 0
+                    && inner.consecutive_failures >= self.config.consecutive_failures;
+                let too_high_failure_rate = inner.requests >= self.config.min_requests
+                    && inner.failures as f64 >= self.config.failure_rate * inner.requests as f64;
+                if too_many_consecutive_failures || too_high_failure_rate {
+                    self.open(&mut inner, now);
+                }
+            }
+            State::HalfOpen {
+                inflight,
+                successes,
+            } => {
+                *inflight = inflight.saturating_sub(1);
+                if !success {
+                    self.open(&mut inner, now);
+                    return;
+                }
+                *successes += 1;
+                if *successes >= self.config.half_open_requests {
+                    info!("[VOLO] circuit breaker closes for {}", self.name);
+                    inner.state = State::Closed;
+                    inner.reset_counters(now);
+                }
+            }
+            // the request was allowed before the circuit opens
+            State::Open { .. } => {}
+        }
+    }
+
+    /// Releases an allowed request without recording the result, it's used when the request is
+    /// not sent at all.
+    pub fn release(&self) {
+        if let State::HalfOpen { inflight, .. } = &mut self.inner.lock().unwrap().state {
+            *inflight = inflight.saturating_sub(1);
+        }
+    }
+
+    /// Whether the circuit is closed and has no request for a while, so it can be dropped.
+    fn is_idle(&self, now: Instant) -> bool {
+        let inner = self.inner.lock().unwrap();
+        matches!(inner.state, State::Closed)
+            && now.duration_since(inner.last_acquired) >= IDLE_TIMEOUT
+    }
+
+    fn open(&self, inner: &mut Inner, now: Instant) {
+        warn!(
+            "[VOLO] circuit breaker opens for {} in {:?}",
+            self.name, self.config.cooling_time
+        );
+        inner.state = State::Open {
+            until: now + self.config.cooling_time,
+        };
+        inner.reset_counters(now);
+    }
+}
+
+/// [`ErrorClassifier`] decides whether an error should be counted as a failure of the callee.
+pub trait ErrorClassifier<E>: Send + Sync + 'static {
+    fn is_failure(&self, err: &E) -> bool;
+}
+
+/// [`RetryableClassifier`] counts the [`Retryable`] errors as failures, such as the transport
+/// errors, while the business errors are not counted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryableClassifier;
+
+impl<E> ErrorClassifier<E> for RetryableClassifier
+where
+    E: Retryable,
+{
+    fn is_failure(&self, err: &E) -> bool {
+        err.retryable()
+    }
+}
+
+impl<E, F> ErrorClassifier<E> for F
+where
+    F: Fn(&E) -> bool + Send + Sync + 'static,
+{
+    fn is_failure(&self, err: &E) -> bool {
+        self(err)
+    }
+}
+
+type Breakers<K> = DashMap<K, Arc<CircuitBreaker>>;
+
+/// [`Permit`] records the result to the circuit breaker when it's dropped.
+///
+/// The request is recorded as failed if the permit is dropped before it's finished, since a
+/// cancelled request is usually caused by a timeout, unless it's a cancelled hedged request.
+struct Permit {
+    breaker: Arc<CircuitBreaker>,
+    success: bool,
+    released: bool,
+    hedge_token: Option<HedgeToken>,
+}
+
+impl Permit {
+    fn acquire(breaker: Arc<CircuitBreaker>, hedge_token: Option<HedgeToken>) -> Option<Self> {
+        if breaker.try_acquire() {
+            Some(Self {
+                breaker,
+                success: false,
+                released: false,
+                hedge_token,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn finish(mut self, success: bool) {
+        self.success = success;
+    }
+
+    /// Drops the permit without recording the result.
+    fn release(mut self) {
+        self.released = true;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let cancelled = matches!(&self.hedge_token, Some(token) if token.is_cancelled());
+        if self.released || cancelled {
+            self.breaker.release();
+        } else {
+            self.breaker.record(self.success);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CircuitBreakerService<S, C> {
+    inner: S,
+    classifier: Arc<C>,
+    service_config: Option<CircuitBreakerConfig>,
+    instance_config: Option<CircuitBreakerConfig>,
+    service_breakers: Arc<Breakers<FastStr>>,
+    instance_breakers: Arc<Breakers<(FastStr, Address)>>,
+    last_sweep: Arc<Mutex<Option<Instant>>>,
+}
+
+impl<S, C> CircuitBreakerService<S, C> {
+    /// Returns the state of the circuit breaker of the callee service, `None` if there's no
+    /// request to the service yet.
+    pub fn service_state(&self, service_name: &FastStr) -> Option<CircuitState> {
+        self.service_breakers.get(service_name).map(|b| b.state())
+    }
+
+    /// Returns the state of the circuit breaker of the address of the callee service, `None` if
+    /// there's no request to the address yet.
+    pub fn instance_state(
+        &self,
+        service_name: &FastStr,
+        address: &Address,
+    ) -> Option<CircuitState> {
+        self.instance_breakers
+            .get(&(service_name.clone(), address.clone()))
+            .map(|b| b.state())
+    }
+
+    /// Evicts the idle breakers of the addresses, so the breakers of the addresses that are
+    /// removed by the discovery don't pile up.
+    fn sweep(&self) {
+        let now = Instant::now();
+        {
+            // someone else is sweeping
+            let Ok(mut last_sweep) = self.last_sweep.try_lock() else {
+                return;
+            };
+            match *last_sweep {
+                Some(last) if now.duration_since(last) < SWEEP_INTERVAL => return,
+                _ => *last_sweep = Some(now),
+            }
+        }
+        self.instance_breakers
+            .retain(|_, breaker| !breaker.is_idle(now));
+    }
+}
+
+impl<Cx, Req, S, C> Service<Cx, Req> for CircuitBreakerService<S, C>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+    for<'cx> S::Future<'cx>: Send,
+    S::Error: Send,
+    LoadBalanceError: Into<S::Error>,
+    C: ErrorClassifier<S::Error>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx>
+        = impl Future<Output = Result<Self::Response, Self::Error>> + Send + 'cx
+    where
+        Self: 'cx;
+
+    fn call<'cx, 's>(&'s self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let (service_name, address) = match cx.rpc_info().callee() {
+                Some(callee) => (callee.service_name(), callee.address()),
+                None => return self.inner.call(cx, req).await,
+            };
+            let hedge_token = cx.extensions().get::<HedgeToken>().cloned();
+
+            let service_permit = match self.service_config {
+                Some(config) => {
+                    let breaker = self
+                        .service_breakers
+                        .entry(service_name.clone())
+                        .or_insert_with(|| {
+                            Arc::new(CircuitBreaker::new(
+                                format!("service: {service_name}"),
+                                config,
+                            ))
+                        })
+                        .clone();
+                    match Permit::acquire(breaker, hedge_token.clone()) {
+                        Some(permit) => Some(permit),
+                        None => {
+                            return Err(LoadBalanceError::ServiceCircuitOpen(
+                                service_name.to_string(),
+                            )
+                            .into());
+                        }
+                    }
+                }
+                None => None,
+            };
+            let instance_permit = match (self.instance_config, address) {
+                (Some(config), Some(address)) => {
+                    self.sweep();
+                    let breaker = self
+                        .instance_breakers
+                        .entry((service_name.clone(), address.clone()))
+                        .or_insert_with(|| {
+                            Arc::new(CircuitBreaker::new(
+                                format!("service: {service_name}, address: {address}"),
+                                config,
+                            ))
+                        })
+                        .clone();
+                    match Permit::acquire(breaker, hedge_token.clone()) {
+                        Some(permit) => Some(permit),
+                        None => {
+                            // the request is not sent, so it's neither a success nor a failure
+                            // of the service
+                            if let Some(permit) = service_permit {
+                                permit.release();
+                            }
+                            return Err(LoadBalanceError::InstanceCircuitOpen(format!(
+                                "service: {service_name}, address: {address}"
+                            ))
+                            .into());
+                        }
+                    }
+                }
+                _ => None,
+            };
+
+            let resp = self.inner.call(cx, req).await;
+            let success = match &resp {
+                Ok(_) => true,
+                Err(err) => !self.classifier.is_failure(err),
+            };
+            if let Some(permit) = instance_permit {
+                permit.finish(success);
+            }
+            if let Some(permit) = service_permit {
+                permit.finish(success);
+            }
+            resp
+        }
+    }
+}
+
+impl<S, C> Debug for CircuitBreakerService<S, C>
+where
+    S: Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreakerService")
+            .field("inner", &self.inner)
+            .field("service_config", &self.service_config)
+            .field("instance_config", &self.instance_config)
+            .finish()
+    }
+}
+
+/// [`CircuitBreakerLayer`] adds circuit breakers for the callee service and each of its
+/// addresses.
+///
+/// Both of them are enabled with the default [`CircuitBreakerConfig`] and the
+/// [`RetryableClassifier`] by default.
+#[derive(Clone)]
+pub struct CircuitBreakerLayer<C = RetryableClassifier> {
+    classifier: C,
+    service_config: Option<CircuitBreakerConfig>,
+    instance_config: Option<CircuitBreakerConfig>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new() -> Self {
+        Self {
+            classifier: RetryableClassifier,
+            service_config: Some(CircuitBreakerConfig::default()),
+            instance_config: Some(CircuitBreakerConfig::default()),
+        }
+    }
+}
+
+impl Default for CircuitBreakerLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> CircuitBreakerLayer<C> {
+    /// Sets the config of the circuit breaker of the callee service, `None` means disabled.
+    pub fn service_config(mut self, config: Option<CircuitBreakerConfig>) -> Self {
+        self.service_config = config;
+        self
+    }
+
+    /// Sets the config of the circuit breakers of the addresses, `None` means disabled.
+    pub fn instance_config(mut self, config: Option<CircuitBreakerConfig>) -> Self {
+        self.instance_config = config;
+        self
+    }
+
+    /// Sets the classifier which decides whether an error is counted as a failure.
+    pub fn classifier<NC>(self, classifier: NC) -> CircuitBreakerLayer<NC> {
+        CircuitBreakerLayer {
+            classifier,
+            service_config: self.service_config,
+            instance_config: self.instance_config,
+        }
+    }
+}
+
+impl<S, C> Layer<S> for CircuitBreakerLayer<C> {
+    type Service = CircuitBreakerService<S, C>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        CircuitBreakerService {
+            inner,
+            classifier: Arc::new(self.classifier),
+            service_config: self.service_config,
+            instance_config: self.instance_config,
+            service_breakers: Arc::new(DashMap::new()),
+            instance_breakers: Arc::new(DashMap::new()),
+            last_sweep: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use motore::{service::service_fn, Service};
+
+    use super::{CircuitBreaker, CircuitBreakerConfig, CircuitBreakerLayer, CircuitState};
+    use crate::{
+        context::{Endpoint, Role, RpcCx, RpcInfo},
+        loadbalance::error::{LoadBalanceError, Retryable},
+        net::Address,
+        FastStr, Layer,
+    };
+
+    #[derive(Debug)]
+    enum TestError {
+        Transport,
+        Business,
+        CircuitOpen,
+    }
+
+    impl Retryable for TestError {
+        fn retryable(&self) -> bool {
+            matches!(self, TestError::Transport)
+        }
+    }
+
+    impl From<LoadBalanceError> for TestError {
+        fn from(err: LoadBalanceError) -> Self {
+            match err {
+                LoadBalanceError::ServiceCircuitOpen(_)
+                | LoadBalanceError::InstanceCircuitOpen(_) => TestError::CircuitOpen,
+                _ => TestError::Transport,
+            }
+        }
+    }
+
+    type TestContext = RpcCx<(), ()>;
+
+    async fn handle(_cx: &mut TestContext, request: &'static str) -> Result<(), TestError> {
+        match request {
+            "transport" => Err(TestError::Transport),
+            "business" => Err(TestError::Business),
+            _ => Ok(()),
+        }
+    }
+
+    fn new_context(address: &Address) -> TestContext {
+        let mut callee = Endpoint::new("test".into());
+        callee.set_address(address.clone());
+        RpcCx::new(
+            RpcInfo::new(
+                Role::Client,
+                "method".into(),
+                Endpoint::new("caller".into()),
+                callee,
+                (),
+            ),
+            (),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_state_machine() {
+        let breaker = CircuitBreaker::new(
+            "test",
+            CircuitBreakerConfig::new()
+                .consecutive_failures(3)
+                .cooling_time(Duration::from_secs(5))
+                .half_open_requests(2),
+        );
+
+        for _ in 0..3 {
+            assert!(breaker.try_acquire());
+            breaker.record(false);
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.try_acquire());
+
+        // only `half_open_requests` probes are allowed after the cooling time
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire());
+        assert!(breaker.try_acquire());
+        assert!(!breaker.try_acquire());
+
+        // a failed probe opens the circuit again
+        breaker.record(true);
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // the circuit closes when all the probes succeed
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(breaker.try_acquire());
+        assert!(breaker.try_acquire());
+        breaker.record(true);
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failure_rate() {
+        let breaker = CircuitBreaker::new(
+            "test",
+            CircuitBreakerConfig::new()
+                .failure_rate(0.5, 4)
+                .window(Duration::from_secs(10)),
+        );
+
+        breaker.record(false);
+        breaker.record(false);
+        breaker.record(true);
+        // the counters are reset when the window expires
+        tokio::time::advance(Duration::from_secs(10)).await;
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        breaker.record(true);
+        breaker.record(true);
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_layer() {
+        let config = CircuitBreakerConfig::new().consecutive_failures(2);
+        let service = CircuitBreakerLayer::new()
+            .service_config(None)
+            .instance_config(Some(config))
+            .layer(service_fn(handle));
+        let service_name = FastStr::from_static_str("test");
+        let (bad, good): (Address, Address) = (
+            Address::Ip("127.0.0.1:8000".parse().unwrap()),
+            Address::Ip("127.0.0.2:8000".parse().unwrap()),
+        );
+
+        // business errors are not counted as failures by default
+        for _ in 0..2 {
+            let mut cx = new_context(&bad);
+            assert!(matches!(
+                service.call(&mut cx, "business").await,
+                Err(TestError::Business)
+            ));
+        }
+        assert_eq!(
+            service.instance_state(&service_name, &bad),
+            Some(CircuitState::Closed)
+        );
+
+        for _ in 0..2 {
+            let mut cx = new_context(&bad);
+            assert!(service.call(&mut cx, "transport").await.is_err());
+        }
+        let mut cx = new_context(&bad);
+        assert!(matches!(
+            service.call(&mut cx, "ok").await,
+            Err(TestError::CircuitOpen)
+        ));
+
+        // other addresses are not affected
+        let mut cx = new_context(&good);
+        assert!(service.call(&mut cx, "ok").await.is_ok());
+        assert_eq!(service.service_state(&service_name), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rejected_by_instance() {
+        let service = CircuitBreakerLayer::new()
+            .service_config(Some(
+                CircuitBreakerConfig::new()
+                    .consecutive_failures(1)
+                    .cooling_time(Duration::from_secs(5))
+                    .half_open_requests(1),
+            ))
+            .instance_config(Some(
+                CircuitBreakerConfig::new()
+                    .consecutive_failures(1)
+                    .cooling_time(Duration::from_secs(60)),
+            ))
+            .layer(service_fn(handle));
+        let service_name = FastStr::from_static_str("test");
+        let (bad, good): (Address, Address) = (
+            Address::Ip("127.0.0.1:8000".parse().unwrap()),
+            Address::Ip("127.0.0.2:8000".parse().unwrap()),
+        );
+
+        let mut cx = new_context(&bad);
+        assert!(service.call(&mut cx, "transport").await.is_err());
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(
+            service.service_state(&service_name),
+            Some(CircuitState::HalfOpen)
+        );
+
+        // the request rejected by the address is not a probe of the service
+        let mut cx = new_context(&bad);
+        assert!(matches!(
+            service.call(&mut cx, "ok").await,
+            Err(TestError::CircuitOpen)
+        ));
+        assert_eq!(
+            service.service_state(&service_name),
+            Some(CircuitState::HalfOpen)
+        );
+
+        let mut cx = new_context(&good);
+        assert!(service.call(&mut cx, "ok").await.is_ok());
+        assert_eq!(
+            service.service_state(&service_name),
+            Some(CircuitState::Closed)
+        );
+    }
+    #[tokio::test(start_paused = true)]
+    async fn test_evict_idle_breakers() {
+        let service = CircuitBreakerLayer::new()
+            .service_config(None)
+            .instance_config(Some(
+                CircuitBreakerConfig::new()
+                    .consecutive_failures(1)
+                    .cooling_time(Duration::from_secs(120)),
+            ))
+            .layer(service_fn(handle));
+        let service_name = FastStr::from_static_str("test");
+        let (bad, good): (Address, Address) = (
+            Address::Ip("127.0.0.1:8000".parse().unwrap()),
+            Address::Ip("127.0.0.2:8000".parse().unwrap()),
+        );
+
+        let mut cx = new_context(&bad);
+        assert!(service.call(&mut cx, "transport").await.is_err());
+        let mut cx = new_context(&good);
+        assert!(service.call(&mut cx, "ok").await.is_ok());
+
+        // the idle closed breaker is evicted, while the open one is kept
+        tokio::time::advance(Duration::from_secs(60)).await;
+        let mut cx = new_context(&bad);
+        assert!(matches!(
+            service.call(&mut cx, "ok").await,
+            Err(TestError::CircuitOpen)
+        ));
+        assert_eq!(service.instance_state(&service_name, &good), None);
+        assert_eq!(
+            service.instance_state(&service_name, &bad),
+            Some(CircuitState::Open)
+        );
+    }
+}
@@ -99,6 +99,23 @@ where
     }
 }
 
+/// [`ForkContext`] creates a new context from the current one for another request of the same
+/// call, such as a hedged request.
+pub trait ForkContext: Context {
+    fn fork(&self) -> Self;
+}
+
+impl<I, Config> ForkContext for RpcCx<I, Config>
+where
+    I: Clone,
+    Config: Clone + Send + Debug,
+{
+    /// The extensions are not forked, since they can't be cloned.
+    fn fork(&self) -> Self {
+        Self::new(self.rpc_info.fork(), self.inner.clone())
+    }
+}
+
 impl<I, Config> std::ops::Deref for RpcCx<I, Config> {
     type Target = I;
 
@@ -228,6 +245,16 @@ impl Endpoint {
         self.address.clone()
     }
 
+    /// Creates a new endpoint with the same service name and address.
+    ///
+    /// The tags are not forked, since they can't be cloned.
+    #[inline]
+    pub fn fork(&self) -> Self {
+        let mut endpoint = Self::new(self.service_name.clone());
+        endpoint.address = self.address.clone();
+        endpoint
+    }
+
     /// Clear the information
     #[inline]
     pub fn clear(&mut self) {
@@ -332,6 +359,21 @@ impl<Config> RpcInfo<Config> {
         self.config.as_mut()
     }
 
+    /// Creates a new rpc info with the same role, method, endpoints and config.
+    #[inline]
+    pub fn fork(&self) -> Self
+    where
+        Config: Clone,
+    {
+        RpcInfo {
+            role: self.role,
+            caller: self.caller.as_ref().map(Endpoint::fork),
+            callee: self.callee.as_ref().map(Endpoint::fork),
+            method: self.method.clone(),
+            config: self.config.clone(),
+        }
+    }
+
     #[inline]
     pub fn clear(&mut self) {
         if let Some(ep) = self.caller_mut() {
@@ -0,0 +1,493 @@
+//! Generic combinators over [`Discover`].
+//!
+//! - [`FilterDiscover`] drops the instances that don't match a predicate.
+//! - [`MergeDiscover`] merges the instances of two discovers.
+//! - [`FallbackDiscover`] uses a static list of instances when the inner discover has none.
+//!
+//! The [`Change`]s from the inner discovers are mapped in a background task, so
+//! [`Discover::watch`] of the combinators must be called inside the runtime.
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use async_broadcast::{InactiveReceiver, Receiver, RecvError, Sender, TrySendError};
+use dashmap::DashMap;
+use futures::{future::Either, TryFutureExt};
+use tracing::warn;
+
+use super::{diff_instances, Change, Discover, Instance};
+use crate::{context::Endpoint, loadbalance::error::LoadBalanceError};
+
+const CHANNEL_CAPACITY: usize = 32;
+
+/// Maps the changes from `receiver` by `f`, which maps all the instances of a key.
+///
+/// The mapped instances of each key are kept in the task, so the `added`, `updated` and `removed`
+/// of the mapped changes are exact.
+fn map_watch<K, F>(mut receiver: Receiver<Change<K>>, f: F) -> Receiver<Change<K>>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    F: Fn(&[Arc<Instance>]) -> Vec<Arc<Instance>> + Send + 'static,
+{
+    let (mut sender, mapped) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+    sender.set_overflow(true);
+    tokio::spawn(async move {
+        let mut states: HashMap<K, Vec<Arc<Instance>>> = HashMap::new();
+        loop {
+            let change = match receiver.recv().await {
+                Ok(change) => change,
+                Err(RecvError::Overflowed(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+            let prev = match states.remove(&change.key) {
+                Some(prev) => prev,
+                None => f(&previous(&change)),
+            };
+            let next = f(&change.all);
+            states.insert(change.key.clone(), next.clone());
+            if let Some(change) = diff_instances(change.key, &prev, next) {
+                if let Err(TrySendError::Closed(_)) = sender.try_broadcast(change) {
+                    return;
+                }
+            }
+        }
+    });
+    mapped
+}
+
+/// Recovers the previous instances from the change.
+///
+/// The previous versions of the `updated` instances are unknown, so the current ones are used.
+pub(crate) fn previous<K>(change: &Change<K>) -> Vec<Arc<Instance>> {
+    let added = change
+        .added
+        .iter()
+        .map(|i| &i.address)
+        .collect::<HashSet<_>>();
+    change
+        .all
+        .iter()
+        .filter(|i| !added.contains(&i.address))
+        .chain(change.removed.iter())
+        .cloned()
+        .collect()
+}
+
+/// [`FilterDiscover`] only keeps the instances that match the predicate.
+///
+/// # Example
+///
+/// ```
+/// use volo::discovery::{FilterDiscover, StaticDiscover};
+///
+/// let discover = StaticDiscover::from(vec!["127.0.0.1:8080".parse().unwrap()]);
+/// // drops the canary instances
+/// let discover = FilterDiscover::new(discover, |instance| {
+///     instance.tags.get("env").map(|env| env.as_ref()) != Some("canary")
+/// });
+/// ```
+#[derive(Clone)]
+pub struct FilterDiscover<D, F> {
+    inner: D,
+    predicate: Arc<F>,
+}
+
+impl<D, F> FilterDiscover<D, F>
+where
+    F: Fn(&Instance) -> bool,
+{
+    pub fn new(inner: D, predicate: F) -> Self {
+        Self {
+            inner,
+            predicate: Arc::new(predicate),
+        }
+    }
+}
+
+impl<D, F> Discover for FilterDiscover<D, F>
+where
+    D: Discover,
+    F: Fn(&Instance) -> bool + Send + Sync + 'static,
+{
+    type Key = D::Key;
+    type Error = D::Error;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Self::DiscFut<'s> {
+        async move {
+            let instances = self.inner.discover(endpoint).await?;
+            Ok(filter(&instances, &*self.predicate))
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        self.inner.key(endpoint)
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        let predicate = self.predicate.clone();
+        self.inner
+            .watch(keys)
+            .map(|receiver| map_watch(receiver, move |instances| filter(instances, &*predicate)))
+    }
+}
+
+fn filter<F>(instances: &[Arc<Instance>], predicate: &F) -> Vec<Arc<Instance>>
+where
+    F: Fn(&Instance) -> bool,
+{
+    instances
+        .iter()
+        .filter(|instance| predicate(instance))
+        .cloned()
+        .collect()
+}
+
+/// [`FallbackDiscover`] uses the static instances when the inner discover returns no instances
+/// or fails.
+#[derive(Clone)]
+pub struct FallbackDiscover<D> {
+    inner: D,
+    fallback: Arc<Vec<Arc<Instance>>>,
+}
+
+impl<D> FallbackDiscover<D> {
+    pub fn new(inner: D, fallback: Vec<Arc<Instance>>) -> Self {
+        Self {
+            inner,
+            fallback: Arc::new(fallback),
+        }
+    }
+}
+
+impl<D> Discover for FallbackDiscover<D>
+where
+    D: Discover,
+{
+    type Key = D::Key;
+    type Error = LoadBalanceError;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Self::DiscFut<'s> {
+        async move {
+            match self.inner.discover(endpoint).await {
+                Ok(instances) if !instances.is_empty() => Ok(instances),
+                Ok(_) => Ok(self.fallback.to_vec()),
+                Err(err) => {
+                    let err = err.into();
+                    if self.fallback.is_empty() {
+                        return Err(err);
+                    }
+                    warn!(
+                        "[VOLO] discover {} error, use the fallback instances: {}",
+                        endpoint.service_name(),
+                        err
+                    );
+                    Ok(self.fallback.to_vec())
+                }
+            }
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        self.inner.key(endpoint)
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        let fallback = self.fallback.clone();
+        self.inner.watch(keys).map(|receiver| {
+            map_watch(receiver, move |instances| {
+                if instances.is_empty() {
+                    fallback.to_vec()
+                } else {
+                    instances.to_vec()
+                }
+            })
+        })
+    }
+}
+
+/// [`MergeDiscover`] returns the union of the instances of two discovers, and the instances with
+/// the same address are only returned once, in favor of the first discover.
+///
+/// Both discovers should return the same key for an endpoint.
+pub struct MergeDiscover<A, B>
+where
+    A: Discover,
+{
+    first: A,
+    second: B,
+    state: Arc<MergeState<A::Key>>,
+}
+
+type InstancePair = (Vec<Arc<Instance>>, Vec<Arc<Instance>>);
+
+struct MergeState<K> {
+    /// The latest instances of both discovers, only the discovered keys are watched.
+    instances: DashMap<K, InstancePair>,
+    watching: AtomicBool,
+    sender: Sender<Change<K>>,
+    receiver: InactiveReceiver<Change<K>>,
+}
+
+impl<A, B> MergeDiscover<A, B>
+where
+    A: Discover,
+    B: Discover<Key = A::Key>,
+{
+    pub fn new(first: A, second: B) -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+        sender.set_overflow(true);
+        Self {
+            first,
+            second,
+            state: Arc::new(MergeState {
+                instances: DashMap::new(),
+                watching: AtomicBool::new(false),
+                sender,
+                receiver: receiver.deactivate(),
+            }),
+        }
+    }
+}
+
+impl<A, B> Clone for MergeDiscover<A, B>
+where
+    A: Discover + Clone,
+    B: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            first: self.first.clone(),
+            second: self.second.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<K> MergeState<K>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+{
+    /// Applies the change from the first discover if `first`, or the second one.
+    fn update(&self, change: Change<K>, first: bool) {
+        let (prev, next) = match self.instances.get_mut(&change.key) {
+            Some(mut entry) => {
+                let prev = merge(&entry.0, &entry.1);
+                if first {
+                    entry.0 = change.all;
+                } else {
+                    entry.1 = change.all;
+                }
+                (prev, merge(&entry.0, &entry.1))
+            }
+            None => return,
+        };
+        if let Some(change) = diff_instances(change.key, &prev, next) {
+            let _ = self.sender.try_broadcast(change);
+        }
+    }
+}
+
+fn merge(first: &[Arc<Instance>], second: &[Arc<Instance>]) -> Vec<Arc<Instance>> {
+    let mut addresses = HashSet::with_capacity(first.len() + second.len());
+    first
+        .iter()
+        .chain(second.iter())
+        .filter(|instance| addresses.insert(instance.address.clone()))
+        .cloned()
+        .collect()
+}
+
+impl<A, B> Discover for MergeDiscover<A, B>
+where
+    A: Discover,
+    B: Discover<Key = A::Key>,
+{
+    type Key = A::Key;
+    type Error = LoadBalanceError;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Self::DiscFut<'s> {
+        async move {
+            let (first, second) = futures::future::try_join(
+                self.first.discover(endpoint).map_err(Into::into),
+                self.second.discover(endpoint).map_err(Into::into),
+            )
+            .await?;
+            let instances = merge(&first, &second);
+            self.state
+                .instances
+                .insert(self.key(endpoint), (first, second));
+            Ok(instances)
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        self.first.key(endpoint)
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        // the inner discovers are only watched once, with the keys of the first call
+        if self.state.watching.swap(true, Ordering::Relaxed) {
+            return Some(self.state.receiver.activate_cloned());
+        }
+        let first = self.first.watch(keys);
+        let second = self.second.watch(keys);
+        if first.is_none() && second.is_none() {
+            return None;
+        }
+        // the task exits when both of the inner channels are closed
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let mut first = first;
+            let mut second = second;
+            loop {
+                let recv = match (&mut first, &mut second) {
+                    (Some(f), Some(s)) => {
+                        match futures::future::select(Box::pin(f.recv()), Box::pin(s.recv())).await
+                        {
+                            Either::Left((recv, _)) => (recv, true),
+                            Either::Right((recv, _)) => (recv, false),
+                        }
+                    }
+                    (Some(f), None) => (f.recv().await, true),
+                    (None, Some(s)) => (s.recv().await, false),
+                    (None, None) => return,
+                };
+                match recv {
+                    (Ok(change), first) => state.update(change, first),
+                    (Err(RecvError::Overflowed(_)), _) => {}
+                    (Err(RecvError::Closed), true) => first = None,
+                    (Err(RecvError::Closed), false) => second = None,
+                }
+            }
+        });
+        Some(self.state.receiver.activate_cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::{FallbackDiscover, FilterDiscover, MergeDiscover};
+    use crate::{
+        context::Endpoint,
+        discovery::{Change, Discover, DummyDiscover, Instance, StaticDiscover},
+        net::Address,
+    };
+
+    fn instance(addr: &str, env: &str) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(addr.parse().unwrap()),
+            weight: 1,
+            tags: HashMap::from([("env".into(), env.to_string().into())]),
+        })
+    }
+
+    fn addresses(instances: &[Arc<Instance>]) -> Vec<Address> {
+        instances.iter().map(|i| i.address.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_filter_and_fallback() {
+        let endpoint = Endpoint::new("hello".into());
+        let discover = FilterDiscover::new(
+            StaticDiscover::new(vec![
+                instance("127.0.0.1:8000", "prod"),
+                instance("127.0.0.1:8001", "canary"),
+            ]),
+            |instance| instance.tags.get("env").unwrap() != "canary",
+        );
+        assert_eq!(
+            addresses(&discover.discover(&endpoint).await.unwrap()),
+            vec![Address::Ip("127.0.0.1:8000".parse().unwrap())]
+        );
+
+        let discover = FallbackDiscover::new(DummyDiscover, vec![instance("127.0.0.1:9000", "")]);
+        assert_eq!(
+            addresses(&discover.discover(&endpoint).await.unwrap()),
+            vec![Address::Ip("127.0.0.1:9000".parse().unwrap())]
+        );
+    }
+
+    #[derive(Clone)]
+    struct ChannelDiscover {
+        instances: Vec<Arc<Instance>>,
+        sender: async_broadcast::Sender<Change<()>>,
+    }
+
+    impl Discover for ChannelDiscover {
+        type Key = ();
+        type Error = std::convert::Infallible;
+        type DiscFut<'a> =
+            impl std::future::Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+        fn discover<'s>(&'s self, _: &'s Endpoint) -> Self::DiscFut<'s> {
+            async move { Ok(self.instances.clone()) }
+        }
+
+        fn key(&self, _: &Endpoint) {}
+
+        fn watch(&self, _: Option<&[()]>) -> Option<async_broadcast::Receiver<Change<()>>> {
+            Some(self.sender.new_receiver())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let endpoint = Endpoint::new("hello".into());
+        let (sender, _receiver) = async_broadcast::broadcast(8);
+        let canary = instance("127.0.0.1:8001", "canary");
+        let prod = instance("127.0.0.1:8000", "prod");
+        let inner = ChannelDiscover {
+            instances: vec![prod.clone(), canary.clone()],
+            sender: sender.clone(),
+        };
+
+        // the canary instance is removed, which is not visible through the filter
+        let filter = FilterDiscover::new(inner.clone(), |instance| {
+            instance.tags.get("env").unwrap() != "canary"
+        });
+        let mut filter_watcher = filter.watch(None).unwrap();
+        let merge = MergeDiscover::new(
+            inner,
+            StaticDiscover::new(vec![instance("127.0.0.1:8000", "other")]),
+        );
+        let _ = merge.discover(&endpoint).await.unwrap();
+        let mut merge_watcher = merge.watch(None).unwrap();
+
+        let new = instance("127.0.0.1:8002", "prod");
+        sender
+            .broadcast(Change {
+                key: (),
+                all: vec![prod.clone(), new.clone()],
+                added: vec![new.clone()],
+                updated: vec![],
+                removed: vec![canary.clone()],
+            })
+            .await
+            .unwrap();
+
+        let change = filter_watcher.recv().await.unwrap();
+        assert_eq!(addresses(&change.added), vec![new.address.clone()]);
+        assert!(change.removed.is_empty());
+        assert_eq!(
+            addresses(&change.all),
+            addresses(&[prod.clone(), new.clone()])
+        );
+
+        let change = merge_watcher.recv().await.unwrap();
+        assert_eq!(addresses(&change.added), vec![new.address.clone()]);
+        assert_eq!(addresses(&change.removed), addresses(&[canary]));
+        assert_eq!(change.all, vec![prod, new]);
+    }
+}
@@ -0,0 +1,377 @@
+//! DNS based service discovery.
+//!
+//! [`DnsDiscover`] resolves the target of an endpoint to its A/AAAA records, which makes it
+//! suitable for the headless services of Kubernetes. The records of each target are refreshed
+//! in the background, and the changes are sent to the load balancer through
+//! [`Discover::watch`].
+
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use dashmap::{mapref::entry::Entry, DashMap};
+use tracing::warn;
+
+use super::{diff_address, Change, Discover, Instance};
+use crate::{context::Endpoint, loadbalance::error::LoadBalanceError, net::Address, FastStr};
+
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// The TTL of the records is never shorter than this, to avoid flooding the DNS server.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const CHANNEL_CAPACITY: usize = 32;
+
+/// The result of a DNS lookup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsRecords {
+    pub ips: Vec<IpAddr>,
+    /// The TTL of the records, `None` if the resolver doesn't know it.
+    pub ttl: Option<Duration>,
+}
+
+/// [`Resolve`] looks up the A/AAAA records of a host.
+pub trait Resolve: Send + Sync + 'static {
+    type ResolveFut<'a>: Future<Output = io::Result<DnsRecords>> + Send + 'a
+    where
+        Self: 'a;
+
+    fn resolve<'a>(&'a self, host: &'a str) -> Self::ResolveFut<'a>;
+}
+
+/// [`GaiResolver`] resolves the hosts by the `getaddrinfo` of the system, which doesn't provide
+/// the TTL of the records.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GaiResolver;
+
+impl Resolve for GaiResolver {
+    type ResolveFut<'a> = impl Future<Output = io::Result<DnsRecords>> + Send + 'a;
+
+    fn resolve<'a>(&'a self, host: &'a str) -> Self::ResolveFut<'a> {
+        async move {
+            let mut ips = tokio::net::lookup_host((host, 0))
+                .await?
+                .map(|addr| addr.ip())
+                .collect::<Vec<_>>();
+            ips.dedup();
+            Ok(DnsRecords { ips, ttl: None })
+        }
+    }
+}
+
+struct Inner<R> {
+    resolver: R,
+    target: Option<FastStr>,
+    default_port: Option<u16>,
+    refresh_interval: Duration,
+    weight: u32,
+    cache: DashMap<FastStr, Vec<Arc<Instance>>>,
+    sender: Sender<Change<FastStr>>,
+    receiver: InactiveReceiver<Change<FastStr>>,
+}
+
+/// [`DnsDiscover`] resolves the `host:port` target to the instances.
+///
+/// The target is the service name of the endpoint by default, and can be set by
+/// [`DnsDiscover::target`] for all the endpoints.
+#[derive(Clone)]
+pub struct DnsDiscover<R = GaiResolver> {
+    inner: Arc<Inner<R>>,
+}
+
+impl DnsDiscover {
+    pub fn new() -> Self {
+        Self::with_resolver(GaiResolver)
+    }
+}
+
+impl Default for DnsDiscover {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R> DnsDiscover<R>
+where
+    R: Resolve,
+{
+    pub fn with_resolver(resolver: R) -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+        sender.set_overflow(true);
+        Self {
+            inner: Arc::new(Inner {
+                resolver,
+                target: None,
+                default_port: None,
+                refresh_interval: DEFAULT_REFRESH_INTERVAL,
+                weight: 1,
+                cache: DashMap::new(),
+                sender,
+                receiver: receiver.deactivate(),
+            }),
+        }
+    }
+
+    /// Sets the `host:port` to resolve for all the endpoints, rather than their service names.
+    pub fn target(mut self, target: impl Into<FastStr>) -> Self {
+        self.inner_mut().target = Some(target.into());
+        self
+    }
+
+    /// Sets the port used when the target doesn't contain one.
+    pub fn default_port(mut self, port: u16) -> Self {
+        self.inner_mut().default_port = Some(port);
+        self
+    }
+
+    /// Sets the refresh interval of the records, which is also the max TTL.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.inner_mut().refresh_interval = interval;
+        self
+    }
+
+    /// Sets the weight of the discovered instances.
+    ///
+    /// Defaults to 1.
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.inner_mut().weight = weight;
+        self
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner<R> {
+        Arc::get_mut(&mut self.inner).expect("DnsDiscover should be configured before cloned")
+    }
+}
+
+impl<R> Inner<R>
+where
+    R: Resolve,
+{
+    /// Resolves the target to the instances, and returns the TTL of the records.
+    async fn resolve(
+        &self,
+        target: &str,
+    ) -> Result<(Vec<Arc<Instance>>, Option<Duration>), LoadBalanceError> {
+        let (host, port) = split_host_port(target, self.default_port).ok_or_else(|| {
+            LoadBalanceError::Discover(format!("invalid dns target: {target}").into())
+        })?;
+        let records = self
+            .resolver
+            .resolve(host)
+            .await
+            .map_err(|err| LoadBalanceError::Discover(Box::new(err)))?;
+        let instances = records
+            .ips
+            .into_iter()
+            .map(|ip| {
+                Arc::new(Instance {
+                    address: Address::Ip(SocketAddr::new(ip, port)),
+                    weight: self.weight,
+                    tags: Default::default(),
+                })
+            })
+            .collect();
+        Ok((instances, records.ttl))
+    }
+
+    fn next_refresh(&self, ttl: Option<Duration>) -> Duration {
+        match ttl {
+            Some(ttl) => ttl.clamp(
+                MIN_REFRESH_INTERVAL,
+                self.refresh_interval.max(MIN_REFRESH_INTERVAL),
+            ),
+            None => self.refresh_interval,
+        }
+    }
+
+    /// Refreshes the records of the target periodically, until the discover is dropped.
+    async fn refresh(inner: Weak<Self>, target: FastStr, mut delay: Duration) {
+        loop {
+            tokio::time::sleep(delay).await;
+            let inner = match inner.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let next = match inner.resolve(&target).await {
+                Ok((next, ttl)) => {
+                    delay = inner.next_refresh(ttl);
+                    next
+                }
+                Err(err) => {
+                    // keep the previous instances
+                    warn!("[VOLO] dns discover refresh {} error: {}", target, err);
+                    delay = inner.refresh_interval;
+                    continue;
+                }
+            };
+            let prev = match inner.cache.get_mut(&target) {
+                Some(mut prev) => std::mem::replace(prev.value_mut(), next.clone()),
+                None => return,
+            };
+            let (change, changed) = diff_address(target.clone(), prev, next);
+            if changed {
+                let _ = inner.sender.try_broadcast(change);
+            }
+        }
+    }
+}
+
+impl<R> Discover for DnsDiscover<R>
+where
+    R: Resolve,
+{
+    type Key = FastStr;
+    type Error = LoadBalanceError;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Self::DiscFut<'s> {
+        async move {
+            let target = self.key(endpoint);
+            if let Some(instances) = self.inner.cache.get(&target) {
+                return Ok(instances.clone());
+            }
+            let (instances, ttl) = self.inner.resolve(&target).await?;
+            // only the first resolving of the target starts the refreshing task
+            if let Entry::Vacant(entry) = self.inner.cache.entry(target.clone()) {
+                entry.insert(instances.clone());
+                tokio::spawn(Inner::refresh(
+                    Arc::downgrade(&self.inner),
+                    target,
+                    self.inner.next_refresh(ttl),
+                ));
+            }
+            Ok(instances)
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        match &self.inner.target {
+            Some(target) => target.clone(),
+            None => endpoint.service_name(),
+        }
+    }
+
+    fn watch(&self, _keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        Some(self.inner.receiver.activate_cloned())
+    }
+}
+
+/// Splits the `host:port` target, the host of IPv6 can be wrapped in brackets like `[::1]:80`.
+fn split_host_port(target: &str, default_port: Option<u16>) -> Option<(&str, u16)> {
+    if let Some(rest) = target.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        return match rest.strip_prefix(':') {
+            Some(port) => Some((host, port.parse().ok()?)),
+            None if rest.is_empty() => Some((host, default_port?)),
+            None => None,
+        };
+    }
+    match target.rsplit_once(':') {
+        // a bare IPv6 address without port
+        Some((host, _)) if host.contains(':') => Some((target, default_port?)),
+        Some((host, port)) => Some((host, port.parse().ok()?)),
+        None => Some((target, default_port?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{split_host_port, DnsDiscover, DnsRecords, Resolve};
+    use crate::{context::Endpoint, discovery::Discover, net::Address};
+
+    #[derive(Clone, Default)]
+    struct StubResolver(Arc<Mutex<DnsRecords>>);
+
+    impl StubResolver {
+        fn set(&self, ips: &[&str], ttl: Option<Duration>) {
+            *self.0.lock().unwrap() = DnsRecords {
+                ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+                ttl,
+            };
+        }
+    }
+
+    impl Resolve for StubResolver {
+        type ResolveFut<'a> = impl Future<Output = io::Result<DnsRecords>> + Send + 'a;
+
+        fn resolve<'a>(&'a self, host: &'a str) -> Self::ResolveFut<'a> {
+            async move {
+                assert_eq!(host, "svc.local");
+                Ok(self.0.lock().unwrap().clone())
+            }
+        }
+    }
+
+    fn addresses(instances: &[Arc<crate::discovery::Instance>]) -> Vec<Address> {
+        instances.iter().map(|i| i.address.clone()).collect()
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("svc.local:80", None),
+            Some(("svc.local", 80))
+        );
+        assert_eq!(
+            split_host_port("svc.local", Some(80)),
+            Some(("svc.local", 80))
+        );
+        assert_eq!(split_host_port("svc.local", None), None);
+        assert_eq!(split_host_port("[::1]:80", None), Some(("::1", 80)));
+        assert_eq!(split_host_port("::1", Some(80)), Some(("::1", 80)));
+        assert_eq!(split_host_port("svc.local:http", None), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dns_discover() {
+        let resolver = StubResolver::default();
+        resolver.set(&["10.0.0.1", "fd00::1"], Some(Duration::from_secs(5)));
+        let discover =
+            DnsDiscover::with_resolver(resolver.clone()).refresh_interval(Duration::from_secs(60));
+        let mut watcher = discover.watch(None).unwrap();
+        let endpoint = Endpoint::new("svc.local:8888".into());
+
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(
+            addresses(&instances),
+            vec![
+                Address::Ip("10.0.0.1:8888".parse().unwrap()),
+                Address::Ip("[fd00::1]:8888".parse().unwrap()),
+            ]
+        );
+
+        // the records are refreshed after the TTL
+        resolver.set(&["10.0.0.1", "10.0.0.2"], None);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        let change = watcher.recv().await.unwrap();
+        assert_eq!(change.key, "svc.local:8888");
+        assert_eq!(
+            addresses(&change.added),
+            vec![Address::Ip("10.0.0.2:8888".parse().unwrap())]
+        );
+        assert_eq!(
+            addresses(&change.removed),
+            vec![Address::Ip("[fd00::1]:8888".parse().unwrap())]
+        );
+        assert_eq!(
+            addresses(&discover.discover(&endpoint).await.unwrap()),
+            addresses(&change.all)
+        );
+
+        // nothing is sent if the records are not changed
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(watcher.try_recv().is_err());
+    }
+}
@@ -0,0 +1,351 @@
+//! File based service discovery, which requires the `file-discover` feature.
+//!
+//! [`FileDiscover`] reads the instances of the services from a YAML or JSON file, which maps the
+//! service names to their instances:
+//!
+//! ```yaml
+//! hello:
+//!   - address: 127.0.0.1:8080
+//!     weight: 10
+//!     tags:
+//!       env: prod
+//!   - address: unix:/tmp/hello.sock
+//! ```
+//!
+//! The file is polled for modifications, and the changes are sent to the load balancer through
+//! [`Discover::watch`], so the instances can be updated without restarting the process.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock, Weak,
+    },
+    time::{Duration, SystemTime},
+};
+
+use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use serde::Deserialize;
+use tracing::warn;
+
+use super::{diff_instances, Change, Discover, Instance};
+use crate::{context::Endpoint, loadbalance::error::LoadBalanceError, net::Address, FastStr};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const CHANNEL_CAPACITY: usize = 32;
+
+type Services = HashMap<FastStr, Vec<Arc<Instance>>>;
+
+#[derive(Debug, Deserialize)]
+struct InstanceConfig {
+    address: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+struct Inner {
+    path: PathBuf,
+    poll_interval: Duration,
+    // the modified time and the length of the file when the services are loaded
+    modified: Option<(SystemTime, u64)>,
+    services: RwLock<Services>,
+    watching: AtomicBool,
+    sender: Sender<Change<FastStr>>,
+    receiver: InactiveReceiver<Change<FastStr>>,
+}
+
+/// [`FileDiscover`] discovers the instances of an endpoint by its service name from a file.
+///
+/// The services that are not in the file have no instances.
+#[derive(Clone)]
+pub struct FileDiscover {
+    inner: Arc<Inner>,
+}
+
+impl FileDiscover {
+    /// Loads the services from the file.
+    ///
+    /// The file is parsed as YAML, which is also compatible with JSON.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, LoadBalanceError> {
+        let path = path.into();
+        // read before loading, so a modification in between is reloaded by the watching task
+        let modified = modified(&path);
+        let services = load(&path)?;
+        let (mut sender, receiver) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+        sender.set_overflow(true);
+        Ok(Self {
+            inner: Arc::new(Inner {
+                path,
+                poll_interval: DEFAULT_POLL_INTERVAL,
+                modified,
+                services: RwLock::new(services),
+                watching: AtomicBool::new(false),
+                sender,
+                receiver: receiver.deactivate(),
+            }),
+        })
+    }
+
+    /// Sets the interval to check the file for modifications.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("FileDiscover should be configured before cloned")
+            .poll_interval = interval;
+        self
+    }
+
+    /// Starts the watching task if it's not started, which must be called inside the runtime.
+    fn ensure_watching(&self) {
+        if !self.inner.watching.swap(true, Ordering::Relaxed) {
+            tokio::spawn(Inner::watch(
+                Arc::downgrade(&self.inner),
+                self.inner.modified,
+            ));
+        }
+    }
+}
+
+impl Inner {
+    /// Reloads the file when it's modified, until the discover is dropped.
+    async fn watch(inner: Weak<Self>, mut modified: Option<(SystemTime, u64)>) {
+        let poll_interval = match inner.upgrade() {
+            Some(inner) => inner.poll_interval,
+            None => return,
+        };
+        let mut interval = tokio::time::interval(poll_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let inner = match inner.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let path = inner.path.clone();
+            let current = modified_async(&path).await;
+            if current.is_none() || current == modified {
+                continue;
+            }
+            modified = current;
+
+            let next = match tokio::fs::read(&path).await {
+                Ok(content) => parse(&content),
+                Err(err) => Err(LoadBalanceError::Discover(Box::new(err))),
+            };
+            match next {
+                Ok(next) => inner.update(next),
+                // keep the previous instances
+                Err(err) => warn!(
+                    "[VOLO] file discover reload {} error: {}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+    }
+
+    fn update(&self, next: Services) {
+        let prev = std::mem::replace(&mut *self.services.write().unwrap(), next.clone());
+        let mut changes = Vec::new();
+        for (key, instances) in next.iter() {
+            let prev = prev.get(key).map(Vec::as_slice).unwrap_or_default();
+            changes.extend(diff_instances(key.clone(), prev, instances.clone()));
+        }
+        for (key, instances) in prev.iter() {
+            // the services removed from the file
+            if !next.contains_key(key) {
+                changes.extend(diff_instances(key.clone(), instances, Vec::new()));
+            }
+        }
+
+        for change in changes {
+            let _ = self.sender.try_broadcast(change);
+        }
+    }
+}
+
+impl Discover for FileDiscover {
+    type Key = FastStr;
+    type Error = LoadBalanceError;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Self::DiscFut<'s> {
+        async move {
+            self.ensure_watching();
+            Ok(self
+                .inner
+                .services
+                .read()
+                .unwrap()
+                .get(&endpoint.service_name())
+                .cloned()
+                .unwrap_or_default())
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name()
+    }
+
+    fn watch(&self, _keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        self.ensure_watching();
+        Some(self.inner.receiver.activate_cloned())
+    }
+}
+
+fn load(path: &Path) -> Result<Services, LoadBalanceError> {
+    let content = std::fs::read(path).map_err(|err| LoadBalanceError::Discover(Box::new(err)))?;
+    parse(&content)
+}
+
+fn parse(content: &[u8]) -> Result<Services, LoadBalanceError> {
+    let config: Option<HashMap<String, Vec<InstanceConfig>>> =
+        serde_yaml::from_slice(content).map_err(|err| LoadBalanceError::Discover(Box::new(err)))?;
+    config
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(service, instances)| {
+            let instances = instances
+                .into_iter()
+                .map(|instance| {
+                    Ok(Arc::new(Instance {
+                        address: parse_address(&instance.address)?,
+                        weight: instance.weight,
+                        tags: instance
+                            .tags
+                            .into_iter()
+                            .map(|(k, v)| (Cow::Owned(k), Cow::Owned(v)))
+                            .collect(),
+                    }))
+                })
+                .collect::<Result<_, LoadBalanceError>>()?;
+            Ok((FastStr::new(service), instances))
+        })
+        .collect()
+}
+
+/// Parses the `ip:port` address, or the `unix:path` address on unix.
+fn parse_address(address: &str) -> Result<Address, LoadBalanceError> {
+    #[cfg(target_family = "unix")]
+    if let Some(path) = address.strip_prefix("unix:") {
+        return Ok(Address::Unix(Cow::Owned(PathBuf::from(path))));
+    }
+    address
+        .parse::<SocketAddr>()
+        .map(Address::Ip)
+        .map_err(|_| LoadBalanceError::Discover(format!("invalid address: {address}").into()))
+}
+
+/// Returns the modified time and the length of the file, which change when it's modified.
+fn modified(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+async fn modified_async(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use super::FileDiscover;
+    use crate::{context::Endpoint, discovery::Discover, net::Address};
+
+    #[tokio::test]
+    async fn test_file_discover() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(
+            b"hello:\n  - address: 127.0.0.1:8080\n    weight: 10\n    tags:\n      env: \
+              prod\n  - address: 127.0.0.1:8081\n",
+        )
+        .unwrap();
+        let discover = FileDiscover::new(file.path())
+            .unwrap()
+            .poll_interval(Duration::from_millis(10));
+        let mut watcher = discover.watch(None).unwrap();
+
+        let instances = discover
+            .discover(&Endpoint::new("hello".into()))
+            .await
+            .unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(
+            instances[0].address,
+            Address::Ip("127.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(instances[0].weight, 10);
+        assert_eq!(instances[0].tags.get("env").unwrap(), "prod");
+        assert_eq!(instances[1].weight, 1);
+        assert!(discover
+            .discover(&Endpoint::new("world".into()))
+            .await
+            .unwrap()
+            .is_empty());
+
+        // rewrite the file in json
+        std::fs::write(
+            file.path(),
+            r#"{"hello": [{"address": "127.0.0.1:8080", "weight": 20}, {"address": "127.0.0.1:8082"}]}"#,
+        )
+        .unwrap();
+        let change = tokio::time::timeout(Duration::from_secs(5), watcher.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.key, "hello");
+        assert_eq!(change.all.len(), 2);
+        assert_eq!(
+            change.added[0].address,
+            Address::Ip("127.0.0.1:8082".parse().unwrap())
+        );
+        assert_eq!(change.updated[0].weight, 20);
+        assert_eq!(
+            change.removed[0].address,
+            Address::Ip("127.0.0.1:8081".parse().unwrap())
+        );
+        assert_eq!(
+            discover
+                .discover(&Endpoint::new("hello".into()))
+                .await
+                .unwrap(),
+            change.all
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modified_before_watching() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"hello:\n  - address: 127.0.0.1:8080\n")
+            .unwrap();
+        let discover = FileDiscover::new(file.path())
+            .unwrap()
+            .poll_interval(Duration::from_millis(10));
+
+        // modified after loaded but before the watching task is started
+        std::fs::write(
+            file.path(),
+            "hello:\n  - address: 127.0.0.1:8080\n  - address: 127.0.0.1:8081\n",
+        )
+        .unwrap();
+        let mut watcher = discover.watch(None).unwrap();
+        let change = tokio::time::timeout(Duration::from_secs(5), watcher.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.all.len(), 2);
+    }
+}
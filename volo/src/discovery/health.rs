@@ -0,0 +1,508 @@
+//! Active health checking of the discovered instances.
+//!
+//! [`HealthCheckDiscover`] wraps a [`Discover`] and probes the instances of each discovered key
+//! periodically in the background. The unhealthy instances are removed from the discovery result,
+//! and the changes of the health are sent to the load balancer through [`Discover::watch`] as
+//! synthetic [`Change`]s, so that the instances that are still registered but not serving are
+//! not picked.
+//!
+//! The probe is pluggable by [`Probe`]: [`TcpProbe`] checks whether a connection can be
+//! established, and any async function can be used as a probe by [`probe_fn`], such as calling a
+//! ping method of a thrift service.
+//!
+//! If all the instances of a key are unhealthy, all of them are returned, since it's more likely
+//! that the probe is broken than all the instances are down.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use async_broadcast::{InactiveReceiver, Receiver, RecvError, Sender};
+use dashmap::DashMap;
+use tracing::{info, warn};
+
+use super::{diff_instances, Change, Discover, Instance};
+use crate::{
+    context::Endpoint,
+    net::{
+        dial::{DefaultMakeTransport, MakeTransport},
+        Address,
+    },
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_UNHEALTHY_THRESHOLD: usize = 2;
+const DEFAULT_HEALTHY_THRESHOLD: usize = 1;
+const CHANNEL_CAPACITY: usize = 32;
+
+/// [`Probe`] checks whether an instance is healthy.
+pub trait Probe: Send + Sync + 'static {
+    type ProbeFut<'a>: Future<Output = bool> + Send + 'a
+    where
+        Self: 'a;
+
+    fn probe<'a>(&'a self, address: &'a Address) -> Self::ProbeFut<'a>;
+}
+
+/// [`TcpProbe`] treats an instance as healthy if a connection to it can be established.
+#[derive(Debug, Clone, Default)]
+pub struct TcpProbe<MT = DefaultMakeTransport> {
+    make_transport: MT,
+}
+
+impl TcpProbe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<MT> TcpProbe<MT> {
+    pub fn with_make_transport(make_transport: MT) -> Self {
+        Self { make_transport }
+    }
+}
+
+impl<MT> Probe for TcpProbe<MT>
+where
+    MT: MakeTransport,
+{
+    type ProbeFut<'a> = impl Future<Output = bool> + Send + 'a;
+
+    fn probe<'a>(&'a self, address: &'a Address) -> Self::ProbeFut<'a> {
+        async move {
+            self.make_transport
+                .make_transport(address.clone())
+                .await
+                .is_ok()
+        }
+    }
+}
+
+/// [`ProbeFn`] is a [`Probe`] created by [`probe_fn`].
+#[derive(Clone, Copy)]
+pub struct ProbeFn<F> {
+    f: F,
+}
+
+/// Returns a [`Probe`] that calls the async function with the address of the instance.
+///
+/// # Example
+///
+/// ```
+/// use volo::discovery::health::probe_fn;
+///
+/// let probe = probe_fn(|address| async move {
+///     // call the ping method of the instance at `address`
+///     true
+/// });
+/// ```
+pub fn probe_fn<F, Fut>(f: F) -> ProbeFn<F>
+where
+    F: Fn(Address) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    ProbeFn { f }
+}
+
+impl<F, Fut> Probe for ProbeFn<F>
+where
+    F: Fn(Address) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    type ProbeFut<'a> = Fut;
+
+    fn probe<'a>(&'a self, address: &'a Address) -> Self::ProbeFut<'a> {
+        (self.f)(address.clone())
+    }
+}
+
+impl<F> fmt::Debug for ProbeFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProbeFn").finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HealthCheckConfig {
+    interval: Duration,
+    timeout: Duration,
+    unhealthy_threshold: usize,
+    healthy_threshold: usize,
+}
+
+impl HealthCheckConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the interval between the probes of an instance.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the timeout of a probe, a probe that times out is failed.
+    ///
+    /// Defaults to 1 second.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the number of consecutive failed probes after which a healthy instance is unhealthy.
+    ///
+    /// Defaults to 2.
+    pub fn unhealthy_threshold(mut self, n: usize) -> Self {
+        self.unhealthy_threshold = n.max(1);
+        self
+    }
+
+    /// Sets the number of consecutive successful probes after which an unhealthy instance is
+    /// healthy again.
+    ///
+    /// Defaults to 1.
+    pub fn healthy_threshold(mut self, n: usize) -> Self {
+        self.healthy_threshold = n.max(1);
+        self
+    }
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+            unhealthy_threshold: DEFAULT_UNHEALTHY_THRESHOLD,
+            healthy_threshold: DEFAULT_HEALTHY_THRESHOLD,
+        }
+    }
+}
+
+/// The health of an instance, the new instances are healthy until they fail the probes.
+#[derive(Debug, Clone, Copy)]
+struct Health {
+    healthy: bool,
+    /// The number of consecutive probes with the opposite result of `healthy`.
+    count: usize,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            healthy: true,
+            count: 0,
+        }
+    }
+}
+
+/// The instances of a key and their health.
+#[derive(Default)]
+struct Instances {
+    all: Vec<Arc<Instance>>,
+    health: HashMap<Address, Health>,
+}
+
+impl Instances {
+    fn new(all: Vec<Arc<Instance>>) -> Self {
+        let mut instances = Self::default();
+        instances.update(all);
+        instances
+    }
+
+    /// Updates the instances and keeps the health of the existing ones.
+    fn update(&mut self, all: Vec<Arc<Instance>>) {
+        let mut health = HashMap::with_capacity(all.len());
+        for instance in &all {
+            let prev = self.health.remove(&instance.address).unwrap_or_default();
+            health.insert(instance.address.clone(), prev);
+        }
+        self.all = all;
+        self.health = health;
+    }
+
+    fn healthy(&self) -> Vec<Arc<Instance>> {
+        let healthy = self
+            .all
+            .iter()
+            .filter(|instance| self.health.get(&instance.address).is_none_or(|h| h.healthy))
+            .cloned()
+            .collect::<Vec<_>>();
+        // all the instances are returned if none of them is healthy
+        if healthy.is_empty() {
+            self.all.clone()
+        } else {
+            healthy
+        }
+    }
+}
+
+struct Inner<K, P> {
+    probe: P,
+    config: HealthCheckConfig,
+    instances: DashMap<K, Instances>,
+    watching: AtomicBool,
+    sender: Sender<Change<K>>,
+    receiver: InactiveReceiver<Change<K>>,
+}
+
+/// [`HealthCheckDiscover`] only returns the healthy instances of the inner discover, see the
+/// [module level documentation](self) for more details.
+pub struct HealthCheckDiscover<D, P = TcpProbe>
+where
+    D: Discover,
+{
+    discover: D,
+    inner: Arc<Inner<D::Key, P>>,
+}
+
+impl<D, P> HealthCheckDiscover<D, P>
+where
+    D: Discover,
+    P: Probe,
+{
+    pub fn new(discover: D, probe: P, config: HealthCheckConfig) -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+        sender.set_overflow(true);
+        Self {
+            discover,
+            inner: Arc::new(Inner {
+                probe,
+                config,
+                instances: DashMap::new(),
+                watching: AtomicBool::new(false),
+                sender,
+                receiver: receiver.deactivate(),
+            }),
+        }
+    }
+}
+
+impl<D, P> Clone for HealthCheckDiscover<D, P>
+where
+    D: Discover + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            discover: self.discover.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, P> Inner<K, P>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    P: Probe,
+{
+    /// Updates the instances of the key by `f`, and sends the change of the healthy instances.
+    fn update<F>(&self, key: &K, f: F)
+    where
+        F: FnOnce(&mut Instances),
+    {
+        let change = match self.instances.get_mut(key) {
+            Some(mut instances) => {
+                let prev = instances.healthy();
+                f(&mut instances);
+                diff_instances(key.clone(), &prev, instances.healthy())
+            }
+            None => return,
+        };
+        if let Some(change) = change {
+            let _ = self.sender.try_broadcast(change);
+        }
+    }
+
+    /// Probes the instances of the key periodically, until the discover is dropped.
+    async fn check(inner: Weak<Self>, key: K) {
+        loop {
+            let interval = match inner.upgrade() {
+                Some(inner) => inner.config.interval,
+                None => return,
+            };
+            tokio::time::sleep(interval).await;
+            let inner = match inner.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let addresses = match inner.instances.get(&key) {
+                Some(instances) => instances
+                    .all
+                    .iter()
+                    .map(|instance| instance.address.clone())
+                    .collect::<Vec<_>>(),
+                None => return,
+            };
+            let results = futures::future::join_all(addresses.iter().map(|address| async {
+                tokio::time::timeout(inner.config.timeout, inner.probe.probe(address))
+                    .await
+                    .unwrap_or(false)
+            }))
+            .await;
+
+            let config = inner.config;
+            inner.update(&key, |instances| {
+                for (address, success) in addresses.iter().zip(results) {
+                    if let Some(health) = instances.health.get_mut(address) {
+                        health.observe(address, success, &config);
+                    }
+                }
+            });
+        }
+    }
+}
+
+impl Health {
+    fn observe(&mut self, address: &Address, success: bool, config: &HealthCheckConfig) {
+        if success == self.healthy {
+            self.count = 0;
+            return;
+        }
+        self.count += 1;
+        let threshold = if self.healthy {
+            config.unhealthy_threshold
+        } else {
+            config.healthy_threshold
+        };
+        if self.count >= threshold {
+            self.healthy = success;
+            self.count = 0;
+            if success {
+                info!("[VOLO] health check: {} is healthy", address);
+            } else {
+                warn!("[VOLO] health check: {} is unhealthy", address);
+            }
+        }
+    }
+}
+
+impl<D, P> Discover for HealthCheckDiscover<D, P>
+where
+    D: Discover,
+    P: Probe,
+{
+    type Key = D::Key;
+    type Error = D::Error;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Self::DiscFut<'s> {
+        async move {
+            let key = self.discover.key(endpoint);
+            if let Some(instances) = self.inner.instances.get(&key) {
+                return Ok(instances.healthy());
+            }
+            let instances = self.discover.discover(endpoint).await?;
+            // only the first discovery of the key starts the checking task
+            let entry = self.inner.instances.entry(key.clone());
+            if let dashmap::mapref::entry::Entry::Vacant(entry) = entry {
+                entry.insert(Instances::new(instances.clone()));
+                tokio::spawn(Inner::check(Arc::downgrade(&self.inner), key));
+            }
+            Ok(instances)
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        self.discover.key(endpoint)
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        // the changes of the inner discover are forwarded once, with the keys of the first call
+        if !self.inner.watching.swap(true, Ordering::Relaxed) {
+            if let Some(mut receiver) = self.discover.watch(keys) {
+                let inner = Arc::downgrade(&self.inner);
+                tokio::spawn(async move {
+                    loop {
+                        let change = match receiver.recv().await {
+                            Ok(change) => change,
+                            Err(RecvError::Overflowed(_)) => continue,
+                            Err(RecvError::Closed) => return,
+                        };
+                        let inner = match inner.upgrade() {
+                            Some(inner) => inner,
+                            None => return,
+                        };
+                        inner.update(&change.key, |instances| instances.update(change.all));
+                    }
+                });
+            }
+        }
+        Some(self.inner.receiver.activate_cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{probe_fn, HealthCheckConfig, HealthCheckDiscover};
+    use crate::{
+        context::Endpoint,
+        discovery::{Discover, StaticDiscover},
+        net::Address,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_health_check() {
+        let a: Address = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let b: Address = Address::Ip("127.0.0.1:8001".parse().unwrap());
+        let unhealthy = Arc::new(Mutex::new(HashSet::new()));
+        let probe = {
+            let unhealthy = unhealthy.clone();
+            probe_fn(move |address| {
+                let healthy = !unhealthy.lock().unwrap().contains(&address);
+                async move { healthy }
+            })
+        };
+        let discover = HealthCheckDiscover::new(
+            StaticDiscover::from(vec![
+                "127.0.0.1:8000".parse().unwrap(),
+                "127.0.0.1:8001".parse().unwrap(),
+            ]),
+            probe,
+            HealthCheckConfig::new()
+                .interval(Duration::from_secs(1))
+                .unhealthy_threshold(2),
+        );
+        let mut watcher = discover.watch(None).unwrap();
+        let endpoint = Endpoint::new("hello".into());
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 2);
+
+        // the instance is unhealthy after 2 failed probes
+        unhealthy.lock().unwrap().insert(b.clone());
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 2);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let change = watcher.recv().await.unwrap();
+        assert_eq!(change.removed[0].address, b);
+        assert_eq!(change.all.len(), 1);
+        assert_eq!(discover.discover(&endpoint).await.unwrap()[0].address, a);
+
+        // all the instances are returned if none of them is healthy
+        unhealthy.lock().unwrap().insert(a.clone());
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let change = watcher.recv().await.unwrap();
+        assert_eq!(change.added[0].address, b);
+        assert_eq!(change.all.len(), 2);
+
+        // and it's healthy again after a successful probe
+        unhealthy.lock().unwrap().clear();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 2);
+        assert!(watcher.try_recv().is_err());
+    }
+}
@@ -15,8 +15,21 @@ use std::{
 
 use async_broadcast::Receiver;
 
+#[cfg(feature = "file-discover")]
+pub use self::file::FileDiscover;
+pub use self::{
+    combinator::{FallbackDiscover, FilterDiscover, MergeDiscover},
+    dns::DnsDiscover,
+    health::HealthCheckDiscover,
+};
 use crate::{context::Endpoint, loadbalance::error::LoadBalanceError, net::Address};
 
+pub mod combinator;
+pub mod dns;
+#[cfg(feature = "file-discover")]
+pub mod file;
+pub mod health;
+
 /// [`Instance`] contains information of an instance from the target service.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Instance {
@@ -117,6 +130,51 @@ where
     )
 }
 
+/// [`diff_instances`] compares prev and next by the address like [`diff_address`], and also
+/// returns the instances whose weight or tags are changed as `updated`.
+///
+/// Returns `None` if there's no diff between prev and next.
+pub fn diff_instances<K>(
+    key: K,
+    prev: &[Arc<Instance>],
+    next: Vec<Arc<Instance>>,
+) -> Option<Change<K>> {
+    let prev_map = prev
+        .iter()
+        .map(|i| (&i.address, i))
+        .collect::<HashMap<_, _>>();
+    let next_map = next
+        .iter()
+        .map(|i| (&i.address, i))
+        .collect::<HashMap<_, _>>();
+
+    let mut added = Vec::new();
+    let mut updated = Vec::new();
+    for i in &next {
+        match prev_map.get(&i.address) {
+            None => added.push(i.clone()),
+            Some(prev) if prev != &i => updated.push(i.clone()),
+            Some(_) => {}
+        }
+    }
+    let removed = prev
+        .iter()
+        .filter(|i| !next_map.contains_key(&i.address))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if added.is_empty() && updated.is_empty() && removed.is_empty() {
+        return None;
+    }
+    Some(Change {
+        key,
+        all: next,
+        added,
+        updated,
+        removed,
+    })
+}
+
 /// [`StaticDiscover`] is a simple implementation of [`Discover`] that returns a static list of
 /// instances.
 #[derive(Clone)]
@@ -6,7 +6,7 @@ use std::{
     os::fd::{AsRawFd, RawFd},
     path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicI32, Ordering},
+        atomic::{AtomicBool, AtomicI32, Ordering},
         Arc, Mutex as StdMutex, OnceLock,
     },
     time::Duration,
@@ -26,7 +26,7 @@ use nix::{
 use tokio::{
     io::{self, Interest},
     net::UnixDatagram,
-    sync::Mutex,
+    sync::{Mutex, Notify},
 };
 
 const HOT_RESTART_PARENT_ADDR: &'static str = "volo_hot_restart_parent.sock";
@@ -36,6 +36,33 @@ lazy_static! {
     pub static ref DEFAULT_HOT_RESTART: HotRestart = HotRestart::new();
 }
 
+/// [`HotRestartConfig`] configures the hot restart of the servers in a process.
+#[derive(Debug, Clone)]
+pub struct HotRestartConfig {
+    sock_dir: PathBuf,
+    listener_num: i32,
+}
+
+impl HotRestartConfig {
+    /// Creates the config that passes the listeners through the unix sockets in `sock_dir`, which
+    /// must be the same for the running process and the new one.
+    pub fn new(sock_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            sock_dir: sock_dir.into(),
+            listener_num: 1,
+        }
+    }
+
+    /// Sets the number of the listeners of all the servers in the process, the running process
+    /// exits after the new one has taken over all of them.
+    ///
+    /// Defaults to 1.
+    pub fn listener_num(mut self, n: i32) -> Self {
+        self.listener_num = n.max(1);
+        self
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 #[repr(u8)]
 enum HotRestartState {
@@ -101,6 +128,29 @@ pub struct HotRestart {
     parent_sock_path: OnceLock<PathBuf>,
     child_sock_path: OnceLock<PathBuf>,
     domain_sock: Arc<Mutex<Option<UnixDatagram>>>,
+    terminate: Arc<Terminate>,
+}
+
+/// How the parent exits when the child takes over the listeners.
+#[derive(Default)]
+struct Terminate {
+    // set when a server waits for the termination and drains the connections itself, otherwise
+    // the parent is killed by `SIGTERM`
+    graceful: AtomicBool,
+    // sticky, so that the servers waiting after the termination are still notified
+    terminated: AtomicBool,
+    notify: Notify,
+}
+
+impl Terminate {
+    fn terminate(&self) {
+        if self.graceful.load(Ordering::Acquire) {
+            self.terminated.store(true, Ordering::Release);
+            self.notify.notify_waiters();
+        } else {
+            signal::kill(getpid(), signal::SIGTERM).unwrap();
+        }
+    }
 }
 
 impl HotRestart {
@@ -113,9 +163,15 @@ impl HotRestart {
             parent_sock_path: OnceLock::new(),
             child_sock_path: OnceLock::new(),
             domain_sock: Arc::new(Mutex::new(None)),
+            terminate: Arc::new(Terminate::default()),
         }
     }
 
+    /// Initializes with the config, which is a no-op if it has been initialized.
+    pub async fn initialize_with(&self, config: &HotRestartConfig) -> io::Result<()> {
+        self.initialize(&config.sock_dir, config.listener_num).await
+    }
+
     pub async fn initialize(
         &self,
         sock_dir_path: &Path,
@@ -173,6 +229,7 @@ impl HotRestart {
             domain_sock,
             self.child_sock_path.get().unwrap().clone(),
             fds,
+            self.terminate.clone(),
         ));
 
         Ok(())
@@ -182,6 +239,7 @@ impl HotRestart {
         parent_sock: UnixDatagram,
         child_sock_path: PathBuf,
         fds: Arc<StdMutex<HashMap<String, RawFd>>>,
+        terminate: Arc<Terminate>,
     ) -> io::Result<()> {
         tracing::info!("hot_restart parent_handle");
         loop {
@@ -201,7 +259,7 @@ impl HotRestart {
                 Ok(HotRestartMessage::TerminateParentRequest) => {
                     tracing::info!("hot_restart parent terminate");
                     parent_sock.shutdown(std::net::Shutdown::Both)?;
-                    signal::kill(getpid(), signal::SIGTERM).unwrap();
+                    terminate.terminate();
                     break;
                 }
                 Ok(_) => {
@@ -325,6 +383,26 @@ impl HotRestart {
         Ok(())
     }
 
+    /// Waits until the child has taken over all the listeners, and the current process should
+    /// exit after draining the connections.
+    ///
+    /// Once it's called, the process is no longer killed by `SIGTERM` when the child takes over,
+    /// which is how the servers shut down gracefully with the hot restart enabled.
+    ///
+    /// All the servers waiting on it are notified, including the ones that start waiting after
+    /// the termination.
+    pub async fn terminated(&self) {
+        self.terminate.graceful.store(true, Ordering::Release);
+        let notified = self.terminate.notify.notified();
+        tokio::pin!(notified);
+        // register before checking the flag, so a concurrent termination is not missed
+        notified.as_mut().enable();
+        if self.terminate.terminated.load(Ordering::Acquire) {
+            return;
+        }
+        notified.await;
+    }
+
     pub fn register_listener_fd(&self, addr: String, raw_fd: RawFd) {
         tracing::info!("hot_restart register_listener_fd: {}, {}", addr, raw_fd);
         let mut listener_fds = self.listener_fds.lock().unwrap();
@@ -385,6 +463,7 @@ impl HotRestart {
                     let parent_sock_buf = self.parent_sock_path.get().unwrap().clone();
                     let child_sock_buf = self.child_sock_path.get().unwrap().clone();
                     let fds = self.listener_fds.clone();
+                    let terminate = self.terminate.clone();
                     tokio::spawn(async move {
                         let mut interval = tokio::time::interval(Duration::from_millis(5));
 
@@ -395,8 +474,13 @@ impl HotRestart {
                                 continue;
                             };
                             tracing::info!("hot_restart child->parent");
-                            Self::parent_handle(domain_sock, child_sock_buf.clone(), fds.clone())
-                                .await?;
+                            Self::parent_handle(
+                                domain_sock,
+                                child_sock_buf.clone(),
+                                fds.clone(),
+                                terminate.clone(),
+                            )
+                            .await?;
                             break;
                         }
 
@@ -417,3 +501,50 @@ impl HotRestart {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        os::fd::{AsRawFd, FromRawFd},
+    };
+
+    use super::HotRestart;
+
+    #[tokio::test]
+    async fn test_graceful_terminate() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let parent = HotRestart::new();
+        parent.initialize(dir.path(), 1).await.unwrap();
+        parent.register_listener_fd(addr.clone(), listener.as_raw_fd());
+        let terminated = parent.terminated();
+        tokio::pin!(terminated);
+        // the parent is no longer killed by SIGTERM once it waits for the termination
+        assert!(futures::poll!(&mut terminated).is_pending());
+        // every server of the parent drains, not only the first one
+        let other = parent.terminated();
+        tokio::pin!(other);
+        assert!(futures::poll!(&mut other).is_pending());
+
+        let child = HotRestart::new();
+        child.initialize(dir.path(), 1).await.unwrap();
+        let fd = child.dup_parent_listener_sock(addr).await.unwrap().unwrap();
+        let inherited = unsafe { TcpListener::from_raw_fd(fd) };
+        assert_eq!(
+            inherited.local_addr().unwrap(),
+            listener.local_addr().unwrap()
+        );
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), terminated)
+            .await
+            .unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), other)
+            .await
+            .unwrap();
+        // a server waiting after the termination returns immediately
+        assert!(futures::poll!(Box::pin(parent.terminated())).is_ready());
+    }
+}
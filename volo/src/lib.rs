@@ -8,10 +8,13 @@ pub use async_trait::async_trait;
 pub use motore::{layer, layer::Layer, service, Service};
 pub use tokio::main;
 
+pub mod circuit_breaker;
 pub mod context;
 pub mod discovery;
+pub mod limit;
 pub mod loadbalance;
 pub mod net;
+pub mod server;
 pub mod util;
 pub use hack::Unwrap;
 #[cfg(target_family = "unix")]
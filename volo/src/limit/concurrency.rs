@@ -0,0 +1,325 @@
+//! Concurrency limit of the requests.
+//!
+//! [`ConcurrencyLimitLayer`] rejects the requests when the number of the inflight requests
+//! reaches the limit, which is either fixed or adjusted by [`AimdLimit`] with the observed
+//! latency.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use volo::limit::{AimdLimit, ConcurrencyLimitLayer};
+//!
+//! let layer = ConcurrencyLimitLayer::aimd(
+//!     AimdLimit::new(100, Duration::from_millis(200)).max_limit(1000),
+//! );
+//! let limiter = layer.limiter();
+//! assert_eq!(limiter.limit(), 100);
+//! // server.layer(layer)
+//! ```
+
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use motore::Service;
+use tokio::time::Instant;
+
+use super::LimitError;
+use crate::Layer;
+
+const DEFAULT_MIN_LIMIT: usize = 1;
+const DEFAULT_MAX_LIMIT: usize = 10000;
+const DEFAULT_BACKOFF_RATIO: f64 = 0.9;
+
+/// [`AimdLimit`] adjusts the limit by additive increase and multiplicative decrease.
+///
+/// The limit increases by 1 when a request finishes within the latency threshold while at least
+/// half of the limit is in use, and decreases by the backoff ratio when a request is slower than
+/// the threshold or is cancelled, which usually means it's timed out. The limit decreases at most
+/// once within a latency threshold, so that a burst of slow requests doesn't drop the limit to
+/// the minimum at once.
+#[derive(Debug, Clone, Copy)]
+pub struct AimdLimit {
+    initial_limit: usize,
+    min_limit: usize,
+    max_limit: usize,
+    latency_threshold: Duration,
+    backoff_ratio: f64,
+}
+
+impl AimdLimit {
+    pub fn new(initial_limit: usize, latency_threshold: Duration) -> Self {
+        Self {
+            initial_limit,
+            min_limit: DEFAULT_MIN_LIMIT,
+            max_limit: DEFAULT_MAX_LIMIT,
+            latency_threshold,
+            backoff_ratio: DEFAULT_BACKOFF_RATIO,
+        }
+    }
+
+    /// Defaults to 1.
+    pub fn min_limit(mut self, limit: usize) -> Self {
+        self.min_limit = limit.max(1);
+        self
+    }
+
+    /// Defaults to 10000.
+    pub fn max_limit(mut self, limit: usize) -> Self {
+        self.max_limit = limit;
+        self
+    }
+
+    /// Sets the ratio in `(0, 1)` the limit is multiplied by when it decreases.
+    ///
+    /// Defaults to 0.9.
+    pub fn backoff_ratio(mut self, ratio: f64) -> Self {
+        self.backoff_ratio = ratio;
+        self
+    }
+}
+
+/// [`ConcurrencyLimiter`] is shared by all the clones of a [`ConcurrencyLimitLayer`].
+pub struct ConcurrencyLimiter {
+    limit: AtomicUsize,
+    inflight: AtomicUsize,
+    rejected: AtomicU64,
+    aimd: Option<AimdLimit>,
+    last_backoff: Mutex<Option<Instant>>,
+}
+
+impl ConcurrencyLimiter {
+    fn new(limit: usize, aimd: Option<AimdLimit>) -> Self {
+        Self {
+            limit: AtomicUsize::new(limit),
+            inflight: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            aimd,
+            last_backoff: Mutex::new(None),
+        }
+    }
+
+    /// Returns the current limit.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the inflight requests.
+    pub fn inflight(&self) -> usize {
+        self.inflight.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the rejected requests.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    fn try_acquire(self: &Arc<Self>) -> Option<Permit> {
+        let acquired = self
+            .inflight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |inflight| {
+                (inflight < self.limit()).then_some(inflight + 1)
+            })
+            .is_ok();
+        if !acquired {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(Permit {
+            limiter: self.clone(),
+            start: Instant::now(),
+            finished: false,
+        })
+    }
+
+    fn on_complete(&self, inflight: usize, latency: Duration, finished: bool) {
+        let aimd = match &self.aimd {
+            Some(aimd) => aimd,
+            None => return,
+        };
+        if finished && latency <= aimd.latency_threshold {
+            if inflight * 2 >= self.limit() {
+                let _ = self
+                    .limit
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |limit| {
+                        (limit < aimd.max_limit).then_some(limit + 1)
+                    });
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        let mut last_backoff = self.last_backoff.lock().unwrap();
+        if last_backoff.is_some_and(|last| now.duration_since(last) < aimd.latency_threshold) {
+            return;
+        }
+        *last_backoff = Some(now);
+        let _ = self
+            .limit
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |limit| {
+                Some(((limit as f64 * aimd.backoff_ratio) as usize).max(aimd.min_limit))
+            });
+    }
+}
+
+impl fmt::Debug for ConcurrencyLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimiter")
+            .field("limit", &self.limit())
+            .field("inflight", &self.inflight())
+            .field("rejected", &self.rejected())
+            .field("aimd", &self.aimd)
+            .finish()
+    }
+}
+
+/// [`Permit`] is released when it's dropped, and a request dropped before it's finished is
+/// regarded as cancelled.
+struct Permit {
+    limiter: Arc<ConcurrencyLimiter>,
+    start: Instant,
+    finished: bool,
+}
+
+impl Permit {
+    fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let inflight = self.limiter.inflight.fetch_sub(1, Ordering::AcqRel);
+        self.limiter
+            .on_complete(inflight, self.start.elapsed(), self.finished);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimitLayer {
+    limiter: Arc<ConcurrencyLimiter>,
+}
+
+impl ConcurrencyLimitLayer {
+    /// Limits the inflight requests to a fixed number.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limiter: Arc::new(ConcurrencyLimiter::new(limit, None)),
+        }
+    }
+
+    /// Limits the inflight requests to a number adjusted by the observed latency.
+    pub fn aimd(aimd: AimdLimit) -> Self {
+        let limit = aimd.initial_limit.clamp(aimd.min_limit, aimd.max_limit);
+        Self {
+            limiter: Arc::new(ConcurrencyLimiter::new(limit, Some(aimd))),
+        }
+    }
+
+    pub fn limiter(&self) -> Arc<ConcurrencyLimiter> {
+        self.limiter.clone()
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimitService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        ConcurrencyLimitService {
+            inner,
+            limiter: self.limiter,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimitService<S> {
+    inner: S,
+    limiter: Arc<ConcurrencyLimiter>,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for ConcurrencyLimitService<S>
+where
+    Cx: Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+    for<'cx> S::Future<'cx>: Send,
+    LimitError: Into<S::Error>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + Send + 'cx
+    where
+        Self: 'cx;
+
+    fn call<'cx, 's>(&'s self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let permit = match self.limiter.try_acquire() {
+                Some(permit) => permit,
+                None => return Err(LimitError::Overloaded(self.limiter.limit()).into()),
+            };
+            let resp = self.inner.call(cx, req).await;
+            permit.finish();
+            resp
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{AimdLimit, ConcurrencyLimitLayer};
+
+    #[tokio::test]
+    async fn test_fixed_limit() {
+        let limiter = ConcurrencyLimitLayer::new(2).limiter();
+        let first = limiter.try_acquire().unwrap();
+        let _second = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.rejected(), 1);
+
+        first.finish();
+        assert_eq!(limiter.inflight(), 1);
+        assert!(limiter.try_acquire().is_some());
+        assert_eq!(limiter.limit(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_aimd_limit() {
+        let threshold = Duration::from_millis(100);
+        let limiter =
+            ConcurrencyLimitLayer::aimd(AimdLimit::new(10, threshold).min_limit(5).max_limit(11))
+                .limiter();
+
+        // increases when the limit is utilized
+        let permits = (0..6)
+            .map(|_| limiter.try_acquire().unwrap())
+            .collect::<Vec<_>>();
+        permits.into_iter().for_each(|permit| permit.finish());
+        assert_eq!(limiter.limit(), 11);
+
+        // decreases once within the threshold when the requests are cancelled
+        drop(limiter.try_acquire().unwrap());
+        drop(limiter.try_acquire().unwrap());
+        assert_eq!(limiter.limit(), 9);
+
+        // or slow
+        tokio::time::advance(threshold).await;
+        let permit = limiter.try_acquire().unwrap();
+        tokio::time::advance(threshold * 2).await;
+        permit.finish();
+        assert_eq!(limiter.limit(), 8);
+    }
+}
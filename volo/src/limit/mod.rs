@@ -0,0 +1,23 @@
+//! Request level limits for both clients and servers.
+//!
+//! The layers reject the requests exceeding the limits with a [`LimitError`], which is converted
+//! to the error of the protocol, e.g. a thrift `ApplicationError` or a gRPC `RESOURCE_EXHAUSTED`
+//! status, so that the overloaded servers shed the load early instead of queueing the requests
+//! until they time out.
+
+pub mod concurrency;
+pub mod rate;
+
+pub use concurrency::{
+    AimdLimit, ConcurrencyLimitLayer, ConcurrencyLimitService, ConcurrencyLimiter,
+};
+pub use rate::{Quota, RateLimitConfig, RateLimitLayer, RateLimitService, RateLimiter};
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[non_exhaustive]
+pub enum LimitError {
+    #[error("overloaded: reached the concurrency limit {0}")]
+    Overloaded(usize),
+    #[error("rate limited: exceeded the quota of {0}")]
+    RateLimited(String),
+}
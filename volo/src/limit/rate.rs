@@ -0,0 +1,451 @@
+//! QPS limit of the requests by token buckets.
+//!
+//! [`RateLimitLayer`] limits the requests globally, per method and per caller service name, and
+//! a request is rejected if any of the limits applied to it is exceeded. The [`RateLimiter`] is
+//! shared, so the configuration can be updated at runtime.
+//!
+//! ```
+//! use volo::limit::{Quota, RateLimitConfig, RateLimitLayer, RateLimiter};
+//!
+//! let limiter = RateLimiter::new(
+//!     RateLimitConfig::new()
+//!         .global(Quota::per_second(10000.0))
+//!         .method("GetItem", Quota::per_second(1000.0).burst(100))
+//!         .default_caller(Quota::per_second(100.0)),
+//! );
+//! let layer = RateLimitLayer::new(limiter.clone());
+//! // server.layer(layer)
+//!
+//! // updates the quotas later
+//! limiter.update(RateLimitConfig::new().default_caller(Quota::per_second(200.0)));
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use motore::Service;
+use tokio::time::Instant;
+
+use super::LimitError;
+use crate::{context::Context, FastStr, Layer};
+
+/// The number of requests allowed per second, and in a burst.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    per_second: f64,
+    burst: u32,
+}
+
+impl Quota {
+    /// The burst defaults to the requests per second, at least 1.
+    pub fn per_second(per_second: f64) -> Self {
+        Self {
+            per_second,
+            burst: (per_second as u32).max(1),
+        }
+    }
+
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+/// The quotas of a [`RateLimiter`], the limits without a quota are not enforced.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    global: Option<Quota>,
+    methods: HashMap<FastStr, Quota>,
+    default_method: Option<Quota>,
+    callers: HashMap<FastStr, Quota>,
+    default_caller: Option<Quota>,
+}
+
+impl RateLimitConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the quota of all the requests.
+    pub fn global(mut self, quota: Quota) -> Self {
+        self.global = Some(quota);
+        self
+    }
+
+    /// Sets the quota of the method.
+    pub fn method(mut self, method: impl Into<FastStr>, quota: Quota) -> Self {
+        self.methods.insert(method.into(), quota);
+        self
+    }
+
+    /// Sets the quota of each method without its own quota.
+    pub fn default_method(mut self, quota: Quota) -> Self {
+        self.default_method = Some(quota);
+        self
+    }
+
+    /// Sets the quota of the caller service.
+    pub fn caller(mut self, caller: impl Into<FastStr>, quota: Quota) -> Self {
+        self.callers.insert(caller.into(), quota);
+        self
+    }
+
+    /// Sets the quota of each caller service without its own quota.
+    pub fn default_caller(mut self, quota: Quota) -> Self {
+        self.default_caller = Some(quota);
+        self
+    }
+
+    fn method_quota(&self, method: &FastStr) -> Option<Quota> {
+        self.methods.get(method).copied().or(self.default_method)
+    }
+
+    fn caller_quota(&self, caller: &FastStr) -> Option<Quota> {
+        self.callers.get(caller).copied().or(self.default_caller)
+    }
+}
+
+/// The buckets not used for this long are evicted even if they are not full.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// The minimal interval between the evictions of the idle buckets.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A token bucket refilled at `rate` tokens per second, up to `burst` tokens.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+    /// Unlike `last`, it's not updated by the refills when checking whether the bucket is idle.
+    last_used: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+            last_used: Instant::now(),
+        }
+    }
+
+    /// Changes the rate and the burst, the tokens are kept.
+    fn reset(&mut self, rate: f64, burst: u32) {
+        self.refill();
+        self.rate = rate;
+        self.burst = f64::from(burst.max(1));
+        self.tokens = self.tokens.min(self.burst);
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+    }
+
+    pub(crate) fn try_acquire(&mut self) -> bool {
+        self.refill();
+        self.last_used = self.last;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Gives back the token of a request that is not sent.
+    fn release(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(self.burst);
+    }
+
+    /// Whether the bucket can be dropped, a full bucket is the same as a new one.
+    fn is_idle(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last_used) >= IDLE_TIMEOUT {
+            return true;
+        }
+        self.refill();
+        self.tokens >= self.burst
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Global,
+    Method(FastStr),
+    Caller(FastStr),
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Global => write!(f, "global"),
+            Key::Method(method) => write!(f, "method: {method}"),
+            Key::Caller(caller) => write!(f, "caller: {caller}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    quota: Quota,
+    bucket: TokenBucket,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    config: RwLock<Arc<RateLimitConfig>>,
+    buckets: DashMap<Key, Bucket>,
+    last_sweep: Mutex<Option<Instant>>,
+    rejected: AtomicU64,
+}
+
+/// [`RateLimiter`] keeps the token buckets of the limits, and is cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config: RwLock::new(Arc::new(config)),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Replaces the quotas, the tokens of the existing limits are kept.
+    pub fn update(&self, config: RateLimitConfig) {
+        let config = Arc::new(config);
+        *self.inner.config.write().unwrap() = config.clone();
+        // the limits without a quota are no longer enforced
+        self.inner.buckets.retain(|key, _| match key {
+            Key::Global => config.global.is_some(),
+            Key::Method(method) => config.method_quota(method).is_some(),
+            Key::Caller(caller) => config.caller_quota(caller).is_some(),
+        });
+    }
+
+    pub fn config(&self) -> Arc<RateLimitConfig> {
+        self.inner.config.read().unwrap().clone()
+    }
+
+    /// Returns the number of the rejected requests.
+    pub fn rejected(&self) -> u64 {
+        self.inner.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Evicts the idle buckets, so the buckets of the callers with the default quota don't pile
+    /// up.
+    fn sweep(&self) {
+        let now = Instant::now();
+        {
+            // someone else is sweeping
+            let Ok(mut last_sweep) = self.inner.last_sweep.try_lock() else {
+                return;
+            };
+            match *last_sweep {
+                Some(last) if now.duration_since(last) < SWEEP_INTERVAL => return,
+                _ => *last_sweep = Some(now),
+            }
+        }
+        self.inner
+            .buckets
+            .retain(|_, bucket| !bucket.bucket.is_idle(now));
+    }
+
+    /// Takes a token from each limit applied to the request, or returns the exceeded limit.
+    fn try_acquire(&self, method: Option<&FastStr>, caller: Option<&FastStr>) -> Result<(), Key> {
+        self.sweep();
+        let config = self.config();
+        let limits = [
+            config.global.map(|quota| (Key::Global, quota)),
+            method.and_then(|method| {
+                config
+                    .method_quota(method)
+                    .map(|quota| (Key::Method(method.clone()), quota))
+            }),
+            caller.and_then(|caller| {
+                config
+                    .caller_quota(caller)
+                    .map(|quota| (Key::Caller(caller.clone()), quota))
+            }),
+        ];
+
+        let mut acquired = Vec::with_capacity(limits.len());
+        for (key, quota) in limits.into_iter().flatten() {
+            let ok = {
+                let mut bucket = self
+                    .inner
+                    .buckets
+                    .entry(key.clone())
+                    .or_insert_with(|| Bucket {
+                        quota,
+                        bucket: TokenBucket::new(quota.per_second, quota.burst),
+                    });
+                if bucket.quota != quota {
+                    bucket.quota = quota;
+                    bucket.bucket.reset(quota.per_second, quota.burst);
+                }
+                bucket.bucket.try_acquire()
+            };
+            if !ok {
+                for key in acquired {
+                    if let Some(mut bucket) = self.inner.buckets.get_mut(&key) {
+                        bucket.bucket.release();
+                    }
+                }
+                self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(key);
+            }
+            acquired.push(key);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    limiter: RateLimiter,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: RateLimiter) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: RateLimiter,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for RateLimitService<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+    for<'cx> S::Future<'cx>: Send,
+    LimitError: Into<S::Error>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx>
+        = impl Future<Output = Result<Self::Response, Self::Error>> + Send + 'cx
+    where
+        Self: 'cx;
+
+    fn call<'cx, 's>(&'s self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let rpc_info = cx.rpc_info();
+            let caller = rpc_info.caller().map(|caller| caller.service_name());
+            if let Err(key) = self.limiter.try_acquire(rpc_info.method(), caller.as_ref()) {
+                return Err(LimitError::RateLimited(key.to_string()).into());
+            }
+            self.inner.call(cx, req).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Quota, RateLimitConfig, RateLimiter};
+    use crate::FastStr;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limit() {
+        let (get, set) = (FastStr::new("get"), FastStr::new("set"));
+        let (a, b) = (FastStr::new("a"), FastStr::new("b"));
+        let limiter = RateLimiter::new(
+            RateLimitConfig::new()
+                .global(Quota::per_second(3.0))
+                .method("set", Quota::per_second(1.0))
+                .default_caller(Quota::per_second(2.0)),
+        );
+
+        assert!(limiter.try_acquire(Some(&set), Some(&a)).is_ok());
+        // the method limit is exceeded, and the global token is given back
+        assert!(limiter.try_acquire(Some(&set), Some(&b)).is_err());
+        assert!(limiter.try_acquire(Some(&get), Some(&a)).is_ok());
+        // the caller limit is exceeded
+        assert!(limiter.try_acquire(Some(&get), Some(&a)).is_err());
+        assert!(limiter.try_acquire(Some(&get), Some(&b)).is_ok());
+        // the global limit is exceeded
+        assert!(limiter.try_acquire(Some(&get), Some(&b)).is_err());
+        assert_eq!(limiter.rejected(), 3);
+
+        tokio::time::advance(std::time::Duration::from_secs(1)).await;
+        assert!(limiter.try_acquire(Some(&set), None).is_ok());
+
+        // the updated quotas take effect at once
+        limiter.update(RateLimitConfig::new().method("set", Quota::per_second(0.0)));
+        assert!(limiter.try_acquire(Some(&get), Some(&a)).is_ok());
+        assert!(limiter.try_acquire(Some(&set), Some(&a)).is_err());
+        // the buckets of the removed quotas are dropped
+        assert_eq!(limiter.inner.buckets.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_evict_idle_buckets() {
+        let limiter = RateLimiter::new(
+            RateLimitConfig::new()
+                .caller("a", Quota::per_second(0.0))
+                .default_caller(Quota::per_second(1.0).burst(2)),
+        );
+        let a = FastStr::new("a");
+        assert!(limiter.try_acquire(None, Some(&a)).is_ok());
+        for i in 0..100 {
+            let caller = FastStr::new(format!("caller-{i}"));
+            assert!(limiter.try_acquire(None, Some(&caller)).is_ok());
+        }
+        assert_eq!(limiter.inner.buckets.len(), 101);
+
+        // the refilled buckets are evicted, but the exhausted one is kept until it's idle
+        tokio::time::advance(std::time::Duration::from_secs(10)).await;
+        assert!(limiter.try_acquire(None, None).is_ok());
+        assert_eq!(limiter.inner.buckets.len(), 1);
+        assert!(limiter.try_acquire(None, Some(&a)).is_err());
+
+        // the sweeps in between don't keep it alive
+        for _ in 0..5 {
+            tokio::time::advance(std::time::Duration::from_secs(10)).await;
+            assert!(limiter.try_acquire(None, None).is_ok());
+            assert_eq!(limiter.inner.buckets.len(), 1);
+        }
+        tokio::time::advance(std::time::Duration::from_secs(10)).await;
+        assert!(limiter.try_acquire(None, None).is_ok());
+        assert!(limiter.inner.buckets.is_empty());
+    }
+}
@@ -9,6 +9,12 @@ pub enum LoadBalanceError {
     Discover(#[from] BoxError),
     #[error("missing 'request_hash' for consistent hash load balancer")]
     MissRequestHash,
+    /// The circuit breaker of the callee service is open, retrying other instances won't help.
+    #[error("circuit breaker is open for service: {0}")]
+    ServiceCircuitOpen(String),
+    /// The circuit breaker of the instance is open, the request can be retried on others.
+    #[error("circuit breaker is open for {0}")]
+    InstanceCircuitOpen(String),
 }
 
 pub trait Retryable {
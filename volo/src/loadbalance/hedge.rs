@@ -0,0 +1,411 @@
+//! Hedged requests for the load balance service.
+//!
+//! If the first attempt of a request hasn't returned within the hedge delay, a backup request is
+//! sent to the next address yielded by the load balancer, and whichever succeeds first is
+//! returned while the other one is cancelled. This cuts the tail latency caused by a single slow
+//! instance, at the cost of some extra requests, so it should only be enabled for idempotent
+//! methods.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::{
+    future::{select, Either},
+    pin_mut,
+};
+use motore::Service;
+use tokio::time::Instant;
+
+use super::{
+    error::Retryable,
+    outlier::{finish_outlier, OutlierDetector, OutlierGuard},
+    retry::RetryBudget,
+    CallGuard, LoadBalance,
+};
+use crate::{
+    context::{Context, ForkContext},
+    discovery::Discover,
+    net::Address,
+    FastStr,
+};
+
+/// The number of latency samples to calculate the percentile.
+const LATENCY_SAMPLES: usize = 1000;
+/// The minimum number of latency samples before the percentile is used.
+const MIN_LATENCY_SAMPLES: usize = 100;
+/// The percentile is recalculated every `RECALCULATE_INTERVAL` samples.
+const RECALCULATE_INTERVAL: usize = 100;
+
+/// [`HedgePolicy`] configures when the requests are hedged.
+///
+/// The backup request is sent with a context forked by [`ForkContext::fork`], which doesn't carry
+/// the extensions of the original context, so the layers below the load balance service
+/// shouldn't rely on the extensions inserted by the outer layers for hedged methods.
+#[derive(Debug, Clone)]
+pub struct HedgePolicy {
+    /// The fixed delay, which is also used before there are enough samples for the percentile.
+    delay: Duration,
+    /// The latency percentile in `(0, 1)` used as the delay.
+    percentile: Option<f64>,
+    /// The methods to hedge, `None` means all the methods.
+    methods: Option<Arc<HashSet<FastStr>>>,
+}
+
+impl HedgePolicy {
+    /// Creates a policy that sends the backup request after a fixed `delay`.
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            percentile: None,
+            methods: None,
+        }
+    }
+
+    /// Uses the latency percentile of the recent successful requests as the delay, such as `0.95`
+    /// for the p95 latency.
+    ///
+    /// The fixed delay is used until there are enough samples.
+    pub fn percentile(mut self, percentile: Option<f64>) -> Self {
+        if let Some(p) = percentile {
+            assert!(
+                p > 0.0 && p < 1.0,
+                "percentile of HedgePolicy must be in (0, 1)"
+            );
+        }
+        self.percentile = percentile;
+        self
+    }
+
+    /// Only hedges the specified methods, which should be idempotent.
+    ///
+    /// All the methods are hedged by default.
+    pub fn methods<I, M>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = M>,
+        M: Into<FastStr>,
+    {
+        self.methods = Some(Arc::new(methods.into_iter().map(Into::into).collect()));
+        self
+    }
+}
+
+/// [`Hedge`] decides whether a request should be hedged, and forks the context for the backup
+/// request.
+pub trait Hedge<Cx>: Send + Sync + 'static {
+    /// Returns the hedge delay and the context of the backup request, `None` means the request
+    /// shouldn't be hedged.
+    fn fork(&self, cx: &Cx) -> Option<(Duration, Cx)>;
+
+    /// Records the latency of a successful request.
+    fn observe(&self, latency: Duration);
+}
+
+/// [`NoHedge`] never hedges the requests, so the context doesn't need to be forkable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoHedge;
+
+impl<Cx> Hedge<Cx> for NoHedge {
+    #[inline]
+    fn fork(&self, _cx: &Cx) -> Option<(Duration, Cx)> {
+        None
+    }
+
+    #[inline]
+    fn observe(&self, _latency: Duration) {}
+}
+
+/// [`Hedger`] tracks the latencies and decides the hedge delay of the requests.
+#[derive(Debug)]
+pub struct Hedger {
+    policy: HedgePolicy,
+    latencies: Mutex<Latencies>,
+}
+
+#[derive(Debug, Default)]
+struct Latencies {
+    samples: VecDeque<Duration>,
+    since_calculated: usize,
+    percentile: Option<Duration>,
+}
+
+impl Hedger {
+    pub fn new(policy: HedgePolicy) -> Self {
+        Self {
+            policy,
+            latencies: Mutex::new(Latencies::default()),
+        }
+    }
+
+    /// Returns the hedge delay of the method, `None` means the method shouldn't be hedged.
+    pub(super) fn delay(&self, method: Option<&FastStr>) -> Option<Duration> {
+        if let Some(methods) = &self.policy.methods {
+            if !method.is_some_and(|m| methods.contains(m)) {
+                return None;
+            }
+        }
+        if self.policy.percentile.is_some() {
+            if let Some(delay) = self.latencies.lock().unwrap().percentile {
+                return Some(delay);
+            }
+        }
+        Some(self.policy.delay)
+    }
+
+    /// Records the latency of a successful request.
+    fn record(&self, latency: Duration) {
+        let percentile = match self.policy.percentile {
+            Some(percentile) => percentile,
+            None => return,
+        };
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.samples.len() == LATENCY_SAMPLES {
+            latencies.samples.pop_front();
+        }
+        latencies.samples.push_back(latency);
+        latencies.since_calculated += 1;
+
+        if latencies.samples.len() >= MIN_LATENCY_SAMPLES
+            && (latencies.percentile.is_none()
+                || latencies.since_calculated >= RECALCULATE_INTERVAL)
+        {
+            let mut sorted = latencies.samples.iter().copied().collect::<Vec<_>>();
+            sorted.sort_unstable();
+            let offset = ((sorted.len() as f64 * percentile) as usize).min(sorted.len() - 1);
+            latencies.percentile = Some(sorted[offset]);
+            latencies.since_calculated = 0;
+        }
+    }
+}
+
+impl<Cx> Hedge<Cx> for Hedger
+where
+    Cx: ForkContext,
+{
+    fn fork(&self, cx: &Cx) -> Option<(Duration, Cx)> {
+        self.delay(cx.rpc_info().method())
+            .map(|delay| (delay, cx.fork()))
+    }
+
+    fn observe(&self, latency: Duration) {
+        self.record(latency);
+    }
+}
+
+/// [`HedgeToken`] is inserted into the context of the requests that are hedged, and is
+/// cancelled before the losing request is dropped.
+///
+/// The layers that treat dropped requests as failures can check the token to tell a cancelled
+/// hedged request from a timeout.
+#[derive(Debug, Clone, Default)]
+pub struct HedgeToken(Arc<AtomicBool>);
+
+impl HedgeToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// [`HedgedCall`] sends a hedged request through the inner service of a load balance service,
+/// which is shared by the load balance services of the different protocols.
+pub struct HedgedCall<'a, D, LB, S, H> {
+    load_balance: &'a LB,
+    service: &'a S,
+    hedge: &'a H,
+    retry_budget: Option<&'a RetryBudget>,
+    outlier_detector: Option<&'a OutlierDetector>,
+    _marker: PhantomData<fn(D)>,
+}
+
+impl<'a, D, LB, S, H> HedgedCall<'a, D, LB, S, H>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    pub fn new(load_balance: &'a LB, service: &'a S, hedge: &'a H) -> Self {
+        Self {
+            load_balance,
+            service,
+            hedge,
+            retry_budget: None,
+            outlier_detector: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Withdraws from the retry budget for the backup request, the request isn't hedged if the
+    /// budget is exhausted.
+    pub fn retry_budget(mut self, budget: Option<&'a RetryBudget>) -> Self {
+        self.retry_budget = budget;
+        self
+    }
+
+    /// Reports the results of the requests to the outlier detector.
+    pub(super) fn outlier_detector(mut self, detector: Option<&'a OutlierDetector>) -> Self {
+        self.outlier_detector = detector;
+        self
+    }
+
+    fn outlier_guard(&self, address: &Address) -> Option<OutlierGuard<'a>> {
+        self.outlier_detector
+            .map(|detector| OutlierGuard::new(detector, address.clone()))
+    }
+
+    /// Sends the request to the address, and reports the call to the load balancer.
+    async fn call_address<Cx, Req>(
+        &self,
+        cx: &mut Cx,
+        req: Req,
+        address: Address,
+    ) -> Result<S::Response, S::Error>
+    where
+        Cx: Context,
+        S: Service<Cx, Req>,
+    {
+        if let Some(callee) = cx.rpc_info_mut().callee_mut() {
+            callee.address = Some(address.clone())
+        }
+        let guard = CallGuard::<D, LB>::new(self.load_balance, address);
+        let result = self.service.call(cx, req).await;
+        guard.finish(result.is_ok());
+        result
+    }
+
+    /// Sends the request to the address, and the backup request with `backup_cx` to the next
+    /// address of the picker if the first one hasn't returned within `delay`.
+    ///
+    /// The first successful response is returned and the other request is cancelled. If both of
+    /// them fail, the error of the later one is returned.
+    pub async fn call<Cx, Req, I>(
+        self,
+        cx: &mut Cx,
+        (req, backup_req): (Req, Req),
+        address: Address,
+        picker: &mut I,
+        (delay, mut backup_cx): (Duration, Cx),
+    ) -> Result<S::Response, S::Error>
+    where
+        Cx: Context,
+        H: Hedge<Cx>,
+        S: Service<Cx, Req>,
+        S::Error: Retryable,
+        I: Iterator<Item = Address>,
+    {
+        let (token, backup_token) = (HedgeToken::default(), HedgeToken::default());
+        cx.extensions_mut().insert(token.clone());
+        backup_cx.extensions_mut().insert(backup_token.clone());
+
+        let (result, backup_address) = {
+            let start = Instant::now();
+            let outlier_guard = self.outlier_guard(&address);
+            let primary = self.call_address(cx, req, address);
+            pin_mut!(primary);
+            let sleep = tokio::time::sleep(delay);
+            pin_mut!(sleep);
+
+            if let Either::Left((result, _)) = select(primary.as_mut(), sleep).await {
+                if result.is_ok() {
+                    self.hedge.observe(start.elapsed());
+                }
+                finish_outlier(outlier_guard, &result);
+                return result;
+            }
+            // the backup request is a retry in effect, so it's limited by the retry budget too
+            let backup_address = if self.retry_budget.is_none_or(RetryBudget::try_withdraw) {
+                picker.next()
+            } else {
+                None
+            };
+            let backup_address = match backup_address {
+                Some(address) => address,
+                None => {
+                    let result = primary.await;
+                    finish_outlier(outlier_guard, &result);
+                    return result;
+                }
+            };
+
+            let backup_outlier_guard = self.outlier_guard(&backup_address);
+            let backup = self.call_address(&mut backup_cx, backup_req, backup_address.clone());
+            pin_mut!(backup);
+            match select(primary, backup).await {
+                Either::Left((result, backup)) => {
+                    finish_outlier(outlier_guard, &result);
+                    if result.is_ok() {
+                        self.hedge.observe(start.elapsed());
+                        backup_token.cancel();
+                        if let Some(guard) = backup_outlier_guard {
+                            guard.cancel();
+                        }
+                        (result, None)
+                    } else {
+                        let result = backup.await;
+                        finish_outlier(backup_outlier_guard, &result);
+                        (result, Some(backup_address))
+                    }
+                }
+                Either::Right((result, primary)) => {
+                    finish_outlier(backup_outlier_guard, &result);
+                    if result.is_ok() {
+                        token.cancel();
+                        if let Some(guard) = outlier_guard {
+                            guard.cancel();
+                        }
+                        (result, Some(backup_address))
+                    } else {
+                        let result = primary.await;
+                        finish_outlier(outlier_guard, &result);
+                        (result, None)
+                    }
+                }
+            }
+        };
+        // the response comes from the backup request
+        if let Some(address) = backup_address {
+            if let Some(callee) = cx.rpc_info_mut().callee_mut() {
+                callee.address = Some(address);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{HedgePolicy, Hedger};
+
+    #[test]
+    fn test_delay() {
+        let hedger = Hedger::new(
+            HedgePolicy::new(Duration::from_millis(50))
+                .percentile(Some(0.9))
+                .methods(["get"]),
+        );
+        assert_eq!(hedger.delay(Some(&"set".into())), None);
+        assert_eq!(hedger.delay(None), None);
+        assert_eq!(
+            hedger.delay(Some(&"get".into())),
+            Some(Duration::from_millis(50))
+        );
+
+        for i in 0..100 {
+            hedger.record(Duration::from_millis(i));
+        }
+        assert_eq!(
+            hedger.delay(Some(&"get".into())),
+            Some(Duration::from_millis(90))
+        );
+    }
+}
@@ -3,15 +3,49 @@ use std::{fmt::Debug, future::Future, sync::Arc};
 use motore::Service;
 use tracing::warn;
 
-use super::error::{LoadBalanceError, Retryable};
-use crate::{context::Context, discovery::Discover, loadbalance::LoadBalance, Layer, Unwrap};
+use super::{
+    error::{LoadBalanceError, Retryable},
+    hedge::{Hedge, HedgePolicy, HedgedCall, Hedger, NoHedge},
+    locality::CallerLocality,
+    outlier::{
+        finish_outlier, OutlierDetectionConfig, OutlierDetector, OutlierFilter, OutlierGuard,
+    },
+    retry::{RetryBudget, RetryPolicy},
+};
+use crate::{
+    context::Context,
+    discovery::Discover,
+    loadbalance::{CallGuard, LoadBalance},
+    net::Address,
+    Layer, Unwrap,
+};
 
-#[derive(Clone)]
-pub struct LoadBalanceService<D, LB, S> {
+pub struct LoadBalanceService<D, LB, S, H = NoHedge> {
     discover: D,
     load_balance: Arc<LB>,
     service: S,
-    retry: usize,
+    retry_policy: RetryPolicy,
+    retry_budget: Option<Arc<RetryBudget>>,
+    outlier_detector: Option<Arc<OutlierDetector>>,
+    hedge: Arc<H>,
+}
+
+impl<D, LB, S, H> Clone for LoadBalanceService<D, LB, S, H>
+where
+    D: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            discover: self.discover.clone(),
+            load_balance: self.load_balance.clone(),
+            service: self.service.clone(),
+            retry_policy: self.retry_policy.clone(),
+            retry_budget: self.retry_budget.clone(),
+            outlier_detector: self.outlier_detector.clone(),
+            hedge: self.hedge.clone(),
+        }
+    }
 }
 
 impl<D, LB, S> LoadBalanceService<D, LB, S>
@@ -20,20 +54,84 @@ where
     LB: LoadBalance<D>,
 {
     pub fn new(discover: D, load_balance: LB, service: S, retry: usize) -> Self {
+        Self::with_options(
+            discover,
+            load_balance,
+            service,
+            RetryPolicy::with_retry_count(retry),
+            None,
+        )
+    }
+
+    pub fn with_options(
+        discover: D,
+        load_balance: LB,
+        service: S,
+        retry_policy: RetryPolicy,
+        outlier_detection: Option<OutlierDetectionConfig>,
+    ) -> Self {
+        Self::with_hedge(
+            discover,
+            load_balance,
+            service,
+            retry_policy,
+            outlier_detection,
+            NoHedge,
+        )
+    }
+
+    /// Enables the hedged requests, which requires the context to be forkable.
+    pub fn hedge(self, policy: HedgePolicy) -> LoadBalanceService<D, LB, S, Hedger> {
+        LoadBalanceService {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            service: self.service,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            outlier_detector: self.outlier_detector,
+            hedge: Arc::new(Hedger::new(policy)),
+        }
+    }
+}
+
+impl<D, LB, S, H> LoadBalanceService<D, LB, S, H>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    fn with_hedge(
+        discover: D,
+        load_balance: LB,
+        service: S,
+        retry_policy: RetryPolicy,
+        outlier_detection: Option<OutlierDetectionConfig>,
+        hedge: H,
+    ) -> Self {
         let lb = Arc::new(load_balance);
+        let outlier_detector = outlier_detection.map(|cfg| Arc::new(OutlierDetector::new(cfg)));
 
         let service = Self {
             discover,
             load_balance: lb.clone(),
             service,
-            retry,
+            retry_budget: retry_policy.make_budget().map(Arc::new),
+            retry_policy,
+            outlier_detector: outlier_detector.clone(),
+            hedge: Arc::new(hedge),
         };
 
         if let Some(mut channel) = service.discover.watch(None) {
             tokio::spawn(async move {
                 loop {
                     match channel.recv().await {
-                        Ok(recv) => lb.rebalance(recv),
+                        Ok(recv) => {
+                            if let Some(detector) = &outlier_detector {
+                                for instance in recv.removed.iter() {
+                                    detector.remove(&instance.address);
+                                }
+                            }
+                            lb.rebalance(recv)
+                        }
                         Err(err) => warn!("[VOLO] discovering subscription error: {:?}", err),
                     }
                 }
@@ -41,16 +139,44 @@ where
         }
         service
     }
+
+    fn outlier_guard(&self, address: &Address) -> Option<OutlierGuard<'_>> {
+        self.outlier_detector
+            .as_deref()
+            .map(|detector| OutlierGuard::new(detector, address.clone()))
+    }
+
+    /// Sends the request to the address, and reports the call to the load balancer.
+    async fn call_address<Cx, Req>(
+        &self,
+        cx: &mut Cx,
+        req: Req,
+        address: Address,
+    ) -> Result<S::Response, S::Error>
+    where
+        Cx: Context,
+        S: Service<Cx, Req>,
+    {
+        if let Some(callee) = cx.rpc_info_mut().callee_mut() {
+            callee.address = Some(address.clone())
+        }
+        let guard = CallGuard::<D, LB>::new(&*self.load_balance, address);
+        let result = self.service.call(cx, req).await;
+        guard.finish(result.is_ok());
+        result
+    }
 }
 
-impl<Cx, Req, D, LB, S> Service<Cx, Req> for LoadBalanceService<D, LB, S>
+impl<Cx, Req, D, LB, S, H> Service<Cx, Req> for LoadBalanceService<D, LB, S, H>
 where
     Cx: 'static + Context + Send + Sync,
     D: Discover,
     LB: LoadBalance<D>,
+    H: Hedge<Cx>,
     S: Service<Cx, Req> + 'static + Send + Sync,
     LoadBalanceError: Into<S::Error>,
-    S::Error: Debug + Retryable,
+    S::Response: Send,
+    S::Error: Debug + Retryable + Send + 'static,
     Req: Clone + Send + Sync + 'static,
     for<'cx> S::Future<'cx>: Send,
 {
@@ -71,6 +197,13 @@ where
             "must set callee endpoint before load balance service"
         );
         async move {
+            // the load balancer can only see the callee, so the locality of the caller is copied
+            if let Some(locality) = cx.rpc_info().caller().and_then(CallerLocality::from_caller) {
+                cx.rpc_info_mut()
+                    .callee_mut()
+                    .volo_unwrap()
+                    .insert(locality);
+            }
             let callee = cx.rpc_info().callee().volo_unwrap();
 
             let picker = match &callee.address {
@@ -83,22 +216,66 @@ where
                     return self.service.call(cx, req).await;
                 }
             };
+            // the policy set by the `CallOpt` takes precedence over the one of the method
+            let policy = match cx.extensions().get::<RetryPolicy>() {
+                Some(policy) => policy.clone(),
+                None => self.retry_policy.for_method(cx.rpc_info().method()).clone(),
+            };
+            if let Some(budget) = &self.retry_budget {
+                budget.deposit();
+            }
+
+            let mut hedge = self.hedge.fork(cx);
+
+            let mut picker = OutlierFilter::new(picker, self.outlier_detector.as_deref());
             let mut call_count = 0;
-            for (addr, _) in picker.zip(0..self.retry + 1) {
-                call_count += 1;
-                if let Some(callee) = cx.rpc_info_mut().callee_mut() {
-                    callee.address = Some(addr.clone())
+            while call_count < policy.attempts() {
+                let addr = match picker.next() {
+                    Some(addr) => addr,
+                    None => break,
+                };
+                if call_count > 0 {
+                    let backoff = policy.backoff_of(call_count);
+                    if !backoff.is_zero() {
+                        tokio::time::sleep(backoff).await;
+                    }
                 }
+                call_count += 1;
 
-                match self.service.call(cx, req.clone()).await {
-                    Ok(resp) => {
-                        return Ok(resp);
+                // only the first attempt is hedged
+                let result = match hedge.take() {
+                    Some(hedge) => {
+                        HedgedCall::new(&*self.load_balance, &self.service, &*self.hedge)
+                            .retry_budget(self.retry_budget.as_deref())
+                            .outlier_detector(self.outlier_detector.as_deref())
+                            .call(cx, (req.clone(), req.clone()), addr, &mut picker, hedge)
+                            .await
                     }
+                    _ => {
+                        let outlier_guard = self.outlier_guard(&addr);
+                        let result = self.call_address(cx, req.clone(), addr).await;
+                        finish_outlier(outlier_guard, &result);
+                        result
+                    }
+                };
+                match result {
+                    Ok(resp) => return Ok(resp),
                     Err(err) => {
                         warn!("[VOLO] call rpcinfo: {:?}, error: {:?}", cx.rpc_info(), err);
-                        if !err.retryable() {
+                        if !policy.is_retryable(&err) {
                             return Err(err);
                         }
+                        if call_count < policy.attempts() {
+                            if let Some(budget) = &self.retry_budget {
+                                if !budget.try_withdraw() {
+                                    warn!(
+                                        "[VOLO] retry budget exhausted, call rpcinfo: {:?}",
+                                        cx.rpc_info()
+                                    );
+                                    return Err(err);
+                                }
+                            }
+                        }
                     }
                 }
             }
@@ -110,7 +287,7 @@ where
     }
 }
 
-impl<D, LB, S> Debug for LoadBalanceService<D, LB, S>
+impl<D, LB, S, H> Debug for LoadBalanceService<D, LB, S, H>
 where
     D: Debug,
     LB: Debug,
@@ -124,11 +301,14 @@ where
     }
 }
 
-#[derive(Clone, Default, Copy)]
-pub struct LoadBalanceLayer<D, LB> {
+#[derive(Clone, Default)]
+pub struct LoadBalanceLayer<D, LB, H = NoHedge> {
     discover: D,
     load_balance: LB,
     retry_count: usize,
+    retry_policy: Option<RetryPolicy>,
+    outlier_detection: Option<OutlierDetectionConfig>,
+    hedge: H,
 }
 
 impl<D, LB> LoadBalanceLayer<D, LB> {
@@ -137,30 +317,93 @@ impl<D, LB> LoadBalanceLayer<D, LB> {
             discover,
             load_balance,
             retry_count,
+            retry_policy: None,
+            outlier_detection: None,
+            hedge: NoHedge,
         }
     }
+
+    /// Enables the hedged requests, which send a backup request to another address if the first
+    /// one is slow.
+    ///
+    /// The context of the requests must implement [`ForkContext`](crate::context::ForkContext).
+    pub fn hedge(self, policy: HedgePolicy) -> LoadBalanceLayer<D, LB, Hedger> {
+        self.with_hedge(Hedger::new(policy))
+    }
+}
+
+impl<D, LB, H> LoadBalanceLayer<D, LB, H> {
+    pub(super) fn with_hedge<NH>(self, hedge: NH) -> LoadBalanceLayer<D, LB, NH> {
+        LoadBalanceLayer {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            retry_count: self.retry_count,
+            retry_policy: self.retry_policy,
+            outlier_detection: self.outlier_detection,
+            hedge,
+        }
+    }
+
+    /// Sets the retry policy, which takes precedence over the retry count.
+    pub fn retry_policy(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Enables the outlier detection, which ejects the addresses that keep failing.
+    pub fn outlier_detection(mut self, config: Option<OutlierDetectionConfig>) -> Self {
+        self.outlier_detection = config;
+        self
+    }
 }
 
-impl<D, LB, S> Layer<S> for LoadBalanceLayer<D, LB>
+impl<D, LB, S, H> Layer<S> for LoadBalanceLayer<D, LB, H>
 where
     D: Discover,
     LB: LoadBalance<D>,
 {
-    type Service = LoadBalanceService<D, LB, S>;
+    type Service = LoadBalanceService<D, LB, S, H>;
 
     fn layer(self, inner: S) -> Self::Service {
-        LoadBalanceService::new(self.discover, self.load_balance, inner, self.retry_count)
+        let retry_policy = self
+            .retry_policy
+            .unwrap_or_else(|| RetryPolicy::with_retry_count(self.retry_count));
+        LoadBalanceService::with_hedge(
+            self.discover,
+            self.load_balance,
+            inner,
+            retry_policy,
+            self.outlier_detection,
+            self.hedge,
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::convert::Infallible;
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
-    use motore::service::service_fn;
+    use motore::{service::service_fn, Service};
 
     use super::LoadBalanceService;
-    use crate::{discovery::StaticDiscover, loadbalance::random::WeightedRandomBalance};
+    use crate::{
+        context::{Context, Endpoint, Role, RpcCx, RpcInfo},
+        discovery::StaticDiscover,
+        loadbalance::{
+            error::{LoadBalanceError, Retryable},
+            hedge::HedgePolicy,
+            random::WeightedRandomBalance,
+            retry::RetryPolicy,
+        },
+        net::Address,
+    };
 
     #[derive(Debug)]
     struct MotoreContext;
@@ -178,4 +421,143 @@ mod tests {
 
         LoadBalanceService::new(discover, lb, service, 1);
     }
+
+    #[derive(Debug)]
+    struct TestError;
+
+    impl Retryable for TestError {
+        fn retryable(&self) -> bool {
+            true
+        }
+    }
+
+    impl From<LoadBalanceError> for TestError {
+        fn from(_: LoadBalanceError) -> Self {
+            TestError
+        }
+    }
+
+    type TestContext = RpcCx<(), ()>;
+
+    fn new_context(method: &'static str) -> TestContext {
+        RpcCx::new(
+            RpcInfo::new(
+                Role::Client,
+                method.into(),
+                Endpoint::new("caller".into()),
+                Endpoint::new("callee".into()),
+                (),
+            ),
+            (),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_policy() {
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+            "127.0.0.3:8000".parse().unwrap(),
+        ]);
+        let lb = WeightedRandomBalance::with_discover(&discover);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = {
+            let calls = calls.clone();
+            service_fn(move |_: &mut TestContext, _: ()| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                async { Err::<(), _>(TestError) }
+            })
+        };
+        let policy = RetryPolicy::new()
+            .max_attempts(3)
+            .backoff(Duration::from_millis(10), Duration::from_secs(1))
+            .method("create", RetryPolicy::new().max_attempts(1));
+        let service = LoadBalanceService::with_options(discover, lb, service, policy, None);
+
+        let mut cx = new_context("get");
+        assert!(service.call(&mut cx, ()).await.is_err());
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 3);
+
+        // the non-idempotent method is not retried
+        let mut cx = new_context("create");
+        assert!(service.call(&mut cx, ()).await.is_err());
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 1);
+
+        // the policy of the call overrides the others
+        let mut cx = new_context("create");
+        cx.extensions_mut()
+            .insert(RetryPolicy::new().max_attempts(2));
+        assert!(service.call(&mut cx, ()).await.is_err());
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedge() {
+        let slow = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let fast = Address::Ip("127.0.0.2:8000".parse().unwrap());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+        ]);
+        let lb = WeightedRandomBalance::with_discover(&discover);
+        let service = {
+            let slow = slow.clone();
+            service_fn(move |cx: &mut TestContext, _: ()| {
+                let address = cx.rpc_info().callee().unwrap().address().unwrap();
+                let latency = if address == slow {
+                    Duration::from_secs(1)
+                } else {
+                    Duration::from_millis(10)
+                };
+                async move {
+                    tokio::time::sleep(latency).await;
+                    Ok::<_, TestError>(address)
+                }
+            })
+        };
+        let service =
+            LoadBalanceService::with_options(discover, lb, service, RetryPolicy::new(), None)
+                .hedge(HedgePolicy::new(Duration::from_millis(50)));
+
+        // whichever is picked first, the fast one wins
+        for _ in 0..10 {
+            let mut cx = new_context("get");
+            let start = tokio::time::Instant::now();
+            assert_eq!(service.call(&mut cx, ()).await.unwrap(), fast);
+            assert_eq!(
+                cx.rpc_info().callee().unwrap().address(),
+                Some(fast.clone())
+            );
+            assert!(start.elapsed() < Duration::from_millis(100));
+        }
+    }
+    #[tokio::test(start_paused = true)]
+    async fn test_hedge_budget() {
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+        ]);
+        let lb = WeightedRandomBalance::with_discover(&discover);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = {
+            let calls = calls.clone();
+            service_fn(move |_: &mut TestContext, _: ()| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                async {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    Ok::<_, TestError>(())
+                }
+            })
+        };
+        let policy = RetryPolicy::new().budget(Some(0.0));
+        let service = LoadBalanceService::with_options(discover, lb, service, policy, None)
+            .hedge(HedgePolicy::new(Duration::from_millis(50)));
+
+        // the initial budget allows 10 backup requests, then the requests are no longer hedged
+        for _ in 0..20 {
+            let mut cx = new_context("get");
+            assert!(service.call(&mut cx, ()).await.is_ok());
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 30);
+    }
 }
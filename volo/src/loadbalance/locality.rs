@@ -0,0 +1,372 @@
+//! Locality aware load balancing.
+//!
+//! [`LocalityBalance`] wraps a load balancer and prefers the instances in the same zone as the
+//! caller, then the instances in the same region, and the other instances at last. The locality
+//! of the caller is set by the [`Zone`] and [`Region`] faststr tags of the caller [`Endpoint`],
+//! and the locality of an instance is set by its `zone` and `region` tags.
+//!
+//! When the ratio of the healthy instances in the local zone drops below the threshold, part of
+//! the requests spill over to the other zones in proportion. An instance is unhealthy after
+//! several consecutive failed calls, and becomes healthy again after a successful one.
+
+use std::{collections::HashMap, future::Future, iter::Fuse, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use rand::Rng;
+
+use super::{error::LoadBalanceError, LoadBalance};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+    FastStr,
+};
+
+/// The tag of [`Instance`] for its zone.
+pub const ZONE_TAG: &str = "zone";
+/// The tag of [`Instance`] for its region.
+pub const REGION_TAG: &str = "region";
+
+const DEFAULT_SPILLOVER_THRESHOLD: f64 = 0.7;
+const DEFAULT_UNHEALTHY_FAILURES: usize = 3;
+
+/// The faststr tag of the caller [`Endpoint`] for its zone.
+pub struct Zone;
+
+/// The faststr tag of the caller [`Endpoint`] for its region.
+pub struct Region;
+
+/// The locality of the caller, which is copied to the tags of the callee [`Endpoint`] by the load
+/// balance service, since the load balancer can only see the callee.
+#[derive(Debug, Clone, Default)]
+pub(super) struct CallerLocality {
+    zone: Option<FastStr>,
+    region: Option<FastStr>,
+}
+
+impl CallerLocality {
+    pub(super) fn from_caller(caller: &Endpoint) -> Option<Self> {
+        let zone = caller.get_faststr::<Zone>().cloned();
+        let region = caller.get_faststr::<Region>().cloned();
+        if zone.is_none() && region.is_none() {
+            return None;
+        }
+        Some(Self { zone, region })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Zone,
+    Region,
+    Remote,
+}
+
+/// The localities of the instances of a key.
+#[derive(Debug, Default)]
+struct Localities {
+    instances: HashMap<Address, Arc<Instance>>,
+}
+
+impl Localities {
+    fn level(&self, address: &Address, caller: &CallerLocality) -> Level {
+        let instance = match self.instances.get(address) {
+            Some(instance) => instance,
+            None => return Level::Remote,
+        };
+        let same = |tag: &str, value: &Option<FastStr>| {
+            value.as_ref().is_some_and(|value| {
+                instance
+                    .tags
+                    .get(tag)
+                    .is_some_and(|tag| tag.as_ref() == value.as_str())
+            })
+        };
+        if same(ZONE_TAG, &caller.zone)
+            && (caller.region.is_none() || same(REGION_TAG, &caller.region))
+        {
+            return Level::Zone;
+        }
+        if same(REGION_TAG, &caller.region) {
+            return Level::Region;
+        }
+        Level::Remote
+    }
+}
+
+/// [`LocalityBalance`] prefers the instances in the same locality as the caller, and picks the
+/// instances of each locality by the inner load balancer.
+pub struct LocalityBalance<LB, K> {
+    inner: LB,
+    spillover_threshold: f64,
+    unhealthy_failures: usize,
+    localities: DashMap<K, Arc<Localities>>,
+    failures: DashMap<Address, usize>,
+}
+
+impl<LB, K> LocalityBalance<LB, K>
+where
+    K: std::hash::Hash + Eq,
+{
+    pub fn new(inner: LB) -> Self {
+        Self {
+            inner,
+            spillover_threshold: DEFAULT_SPILLOVER_THRESHOLD,
+            unhealthy_failures: DEFAULT_UNHEALTHY_FAILURES,
+            localities: DashMap::new(),
+            failures: DashMap::new(),
+        }
+    }
+
+    /// Sets the ratio of the healthy instances in the local zone in `[0, 1]`, below which the
+    /// requests spill over to the other zones.
+    ///
+    /// With a healthy ratio `r` below the threshold `t`, about `1 - r / t` of the requests are not
+    /// preferred to the local zone. Defaults to 0.7.
+    pub fn spillover_threshold(mut self, threshold: f64) -> Self {
+        self.spillover_threshold = threshold;
+        self
+    }
+
+    /// Sets the number of consecutive failed calls after which an instance is unhealthy.
+    ///
+    /// Defaults to 3.
+    pub fn unhealthy_failures(mut self, failures: usize) -> Self {
+        self.unhealthy_failures = failures.max(1);
+        self
+    }
+
+    fn is_healthy(&self, address: &Address) -> bool {
+        self.failures
+            .get(address)
+            .is_none_or(|failures| *failures < self.unhealthy_failures)
+    }
+
+    /// Returns whether the local zone is preferred for a request.
+    fn prefer_zone(&self, localities: &Localities, caller: &CallerLocality) -> bool {
+        let (mut total, mut healthy) = (0, 0);
+        for address in localities.instances.keys() {
+            if localities.level(address, caller) == Level::Zone {
+                total += 1;
+                if self.is_healthy(address) {
+                    healthy += 1;
+                }
+            }
+        }
+        if total == 0 {
+            return false;
+        }
+        let ratio = healthy as f64 / total as f64;
+        if ratio >= self.spillover_threshold {
+            return true;
+        }
+        rand::thread_rng().gen_bool(ratio / self.spillover_threshold)
+    }
+}
+
+/// [`LocalityPicker`] yields the addresses of the inner picker by the locality, the addresses of
+/// the less preferred localities are buffered until the inner picker is exhausted.
+pub struct LocalityPicker<I> {
+    /// Some pickers don't expect to be polled again after they're exhausted.
+    inner: Fuse<I>,
+    localities: Option<(Arc<Localities>, CallerLocality)>,
+    prefer_zone: bool,
+    /// The buffered addresses of the region and the remote localities.
+    buffered: [Vec<Address>; 2],
+}
+
+impl<I> Iterator for LocalityPicker<I>
+where
+    I: Iterator<Item = Address>,
+{
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (localities, caller) = match &self.localities {
+            Some(localities) => localities,
+            None => return self.inner.next(),
+        };
+        for address in self.inner.by_ref() {
+            let level = match localities.level(&address, caller) {
+                Level::Zone if !self.prefer_zone => Level::Region,
+                level => level,
+            };
+            match level {
+                Level::Zone => return Some(address),
+                Level::Region => self.buffered[0].push(address),
+                Level::Remote => self.buffered[1].push(address),
+            }
+        }
+        // the buffers are yielded in order, which is fine for the few retries
+        for buffered in &mut self.buffered {
+            if !buffered.is_empty() {
+                return Some(buffered.remove(0));
+            }
+        }
+        None
+    }
+}
+
+impl<D, LB> LoadBalance<D> for LocalityBalance<LB, D::Key>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    type InstanceIter = LocalityPicker<LB::InstanceIter>;
+
+    type GetFut<'future> = impl Future<Output = Result<Self::InstanceIter, LoadBalanceError>> + Send + 'future
+    where
+        Self: 'future;
+
+    fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Self::GetFut<'future>
+    where
+        Self: 'future,
+    {
+        async move {
+            let inner = self.inner.get_picker(endpoint, discover).await?.fuse();
+            let caller = match endpoint.get::<CallerLocality>() {
+                Some(caller) => caller.clone(),
+                None => {
+                    return Ok(LocalityPicker {
+                        inner,
+                        localities: None,
+                        prefer_zone: false,
+                        buffered: Default::default(),
+                    });
+                }
+            };
+            let key = discover.key(endpoint);
+            let localities = match self.localities.get(&key) {
+                Some(localities) => localities.clone(),
+                None => {
+                    let instances = discover.discover(endpoint).await.map_err(Into::into)?;
+                    let localities = Arc::new(Localities {
+                        instances: instances
+                            .into_iter()
+                            .map(|instance| (instance.address.clone(), instance))
+                            .collect(),
+                    });
+                    self.localities
+                        .entry(key)
+                        .or_insert(localities)
+                        .value()
+                        .clone()
+                }
+            };
+            let prefer_zone = self.prefer_zone(&localities, &caller);
+            Ok(LocalityPicker {
+                inner,
+                localities: Some((localities, caller)),
+                prefer_zone,
+                buffered: Default::default(),
+            })
+        }
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        for instance in &changes.removed {
+            self.failures.remove(&instance.address);
+        }
+        if let Some(mut localities) = self.localities.get_mut(&changes.key) {
+            *localities = Arc::new(Localities {
+                instances: changes
+                    .all
+                    .iter()
+                    .map(|instance| (instance.address.clone(), instance.clone()))
+                    .collect(),
+            });
+        }
+        self.inner.rebalance(changes);
+    }
+
+    fn on_call_start(&self, address: &Address) {
+        self.inner.on_call_start(address);
+    }
+
+    fn on_call_end(&self, address: &Address, elapsed: Duration, success: bool) {
+        if success {
+            self.failures.remove(address);
+        } else {
+            *self.failures.entry(address.clone()).or_default() += 1;
+        }
+        self.inner.on_call_end(address, elapsed, success);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use super::{CallerLocality, LocalityBalance, Region, Zone};
+    use crate::{
+        context::Endpoint,
+        discovery::{Instance, StaticDiscover},
+        loadbalance::{random::WeightedRandomBalance, LoadBalance},
+        net::Address,
+    };
+
+    fn instance(port: u16, region: &'static str, zone: &'static str) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(([127, 0, 0, 1], port).into()),
+            weight: 1,
+            tags: HashMap::from([
+                ("region".into(), region.into()),
+                ("zone".into(), zone.into()),
+            ]),
+        })
+    }
+
+    fn port(address: &Address) -> u16 {
+        match address {
+            Address::Ip(addr) => addr.port(),
+            #[cfg(target_family = "unix")]
+            Address::Unix(_) => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_locality() {
+        let discover = StaticDiscover::new(vec![
+            instance(1, "cn", "a"),
+            instance(2, "cn", "b"),
+            instance(3, "us", "a"),
+            instance(4, "cn", "a"),
+        ]);
+        let lb = LocalityBalance::new(WeightedRandomBalance::new()).spillover_threshold(1.0);
+        let mut caller = Endpoint::new("caller".into());
+        caller.insert_faststr::<Region>("cn".into());
+        caller.insert_faststr::<Zone>("a".into());
+        let mut callee = Endpoint::new("callee".into());
+        callee.insert(CallerLocality::from_caller(&caller).unwrap());
+
+        for _ in 0..10 {
+            let picker = lb.get_picker(&callee, &discover).await.unwrap();
+            let ports = picker.map(|a| port(&a)).collect::<Vec<_>>();
+            assert_eq!(ports.len(), 4);
+            assert!(matches!(ports[..], [1, 4, 2, 3] | [4, 1, 2, 3]));
+        }
+
+        // 1 of the 2 instances in the zone is unhealthy, some requests spill over to the region
+        let address = Address::Ip(([127, 0, 0, 1], 1).into());
+        for _ in 0..3 {
+            LoadBalance::<StaticDiscover>::on_call_end(&lb, &address, Duration::ZERO, false);
+        }
+        let mut spilled = false;
+        for _ in 0..100 {
+            let mut picker = lb.get_picker(&callee, &discover).await.unwrap();
+            spilled |= port(&picker.next().unwrap()) == 2;
+        }
+        assert!(spilled);
+
+        // the requests without the locality of the caller are not affected
+        let picker = lb
+            .get_picker(&Endpoint::new("callee".into()), &discover)
+            .await
+            .unwrap();
+        assert_eq!(picker.count(), 4);
+    }
+}
@@ -1,11 +1,28 @@
 pub mod consistent_hash;
 pub mod error;
+pub mod hedge;
 mod layer;
+pub mod locality;
+pub mod outlier;
+pub mod p2c;
 pub mod random;
+pub mod retry;
+pub mod round_robin;
+pub mod route;
+
+use std::{
+    future::Future,
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
 
-use std::future::Future;
-
-use self::{error::LoadBalanceError, layer::LoadBalanceLayer};
+use self::{
+    error::LoadBalanceError,
+    hedge::{HedgePolicy, Hedger, NoHedge},
+    layer::LoadBalanceLayer,
+    outlier::OutlierDetectionConfig,
+    retry::RetryPolicy,
+};
 use crate::{
     context::Endpoint,
     discovery::{Change, Discover},
@@ -41,6 +58,70 @@ where
         Self: 'future;
     /// `rebalance` is the callback method be used in service discovering subscription.
     fn rebalance(&self, changes: Change<D::Key>);
+
+    /// `on_call_start` is called by the load balance service right before the request is sent to
+    /// the picked address.
+    ///
+    /// Load balancers that need to track in-flight requests can override this, the default
+    /// implementation does nothing.
+    fn on_call_start(&self, _address: &Address) {}
+
+    /// `on_call_end` is called by the load balance service after the request to the picked address
+    /// returns, with the elapsed time and whether the call succeeded.
+    ///
+    /// Every `on_call_start` is paired with exactly one `on_call_end`, the default implementation
+    /// does nothing.
+    fn on_call_end(&self, _address: &Address, _elapsed: Duration, _success: bool) {}
+}
+
+/// [`CallGuard`] reports a call to the [`LoadBalance`] with `on_call_start` when it's created and
+/// `on_call_end` when it's dropped.
+///
+/// The call is reported as failed if the guard is dropped without calling [`CallGuard::finish`],
+/// for example when the request future is cancelled by a timeout.
+pub struct CallGuard<'a, D, LB>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    load_balance: &'a LB,
+    address: Address,
+    start: Instant,
+    success: bool,
+    _marker: PhantomData<fn(D)>,
+}
+
+impl<'a, D, LB> CallGuard<'a, D, LB>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    pub fn new(load_balance: &'a LB, address: Address) -> Self {
+        load_balance.on_call_start(&address);
+        Self {
+            load_balance,
+            address,
+            start: Instant::now(),
+            success: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Marks the call as finished with the given result.
+    pub fn finish(mut self, success: bool) {
+        self.success = success;
+    }
+}
+
+impl<D, LB> Drop for CallGuard<'_, D, LB>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    fn drop(&mut self) {
+        self.load_balance
+            .on_call_end(&self.address, self.start.elapsed(), self.success);
+    }
 }
 
 pub trait MkLbLayer {
@@ -49,10 +130,13 @@ pub trait MkLbLayer {
     fn make(self) -> Self::Layer;
 }
 
-pub struct LbConfig<L, DISC> {
+pub struct LbConfig<L, DISC, H = NoHedge> {
     load_balance: L,
     discover: DISC,
     retry_count: usize,
+    retry_policy: Option<RetryPolicy>,
+    outlier_detection: Option<OutlierDetectionConfig>,
+    hedge: H,
 }
 
 impl<L, DISC> LbConfig<L, DISC> {
@@ -61,22 +145,33 @@ impl<L, DISC> LbConfig<L, DISC> {
             load_balance,
             discover,
             retry_count: 0,
+            retry_policy: None,
+            outlier_detection: None,
+            hedge: NoHedge,
         }
     }
+}
 
-    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, DISC> {
+impl<L, DISC, H> LbConfig<L, DISC, H> {
+    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, DISC, H> {
         LbConfig {
             load_balance,
             discover: self.discover,
             retry_count: self.retry_count,
+            retry_policy: self.retry_policy,
+            outlier_detection: self.outlier_detection,
+            hedge: self.hedge,
         }
     }
 
-    pub fn discover<NDISC>(self, discover: NDISC) -> LbConfig<L, NDISC> {
+    pub fn discover<NDISC>(self, discover: NDISC) -> LbConfig<L, NDISC, H> {
         LbConfig {
             load_balance: self.load_balance,
             discover,
             retry_count: self.retry_count,
+            retry_policy: self.retry_policy,
+            outlier_detection: self.outlier_detection,
+            hedge: self.hedge,
         }
     }
 
@@ -85,15 +180,51 @@ impl<L, DISC> LbConfig<L, DISC> {
         self.retry_count = count;
         self
     }
+
+    /// Sets the retry policy of the client, which takes precedence over the retry count.
+    ///
+    /// Defaults to `None`, which means retrying `retry_count` times immediately.
+    pub fn retry_policy(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets the outlier detection config of the client.
+    ///
+    /// The addresses that keep failing will be ejected from the picked instances for a while.
+    /// Defaults to `None`, which means disabled.
+    pub fn outlier_detection(mut self, config: Option<OutlierDetectionConfig>) -> Self {
+        self.outlier_detection = config;
+        self
+    }
+
+    /// Sets the hedge policy of the client.
+    ///
+    /// If the first attempt of a request is slow, a backup request will be sent to another
+    /// instance and the first response is used. The context of the requests must implement
+    /// [`ForkContext`](crate::context::ForkContext).
+    pub fn hedge(self, policy: HedgePolicy) -> LbConfig<L, DISC, Hedger> {
+        LbConfig {
+            load_balance: self.load_balance,
+            discover: self.discover,
+            retry_count: self.retry_count,
+            retry_policy: self.retry_policy,
+            outlier_detection: self.outlier_detection,
+            hedge: Hedger::new(policy),
+        }
+    }
 }
 
 pub struct CustomLayer<L>(pub L);
 
-impl<LB, DISC> MkLbLayer for LbConfig<LB, DISC> {
-    type Layer = LoadBalanceLayer<DISC, LB>;
+impl<LB, DISC, H> MkLbLayer for LbConfig<LB, DISC, H> {
+    type Layer = LoadBalanceLayer<DISC, LB, H>;
 
     fn make(self) -> Self::Layer {
         LoadBalanceLayer::new(self.discover, self.load_balance, self.retry_count)
+            .retry_policy(self.retry_policy)
+            .outlier_detection(self.outlier_detection)
+            .with_hedge(self.hedge)
     }
 }
 
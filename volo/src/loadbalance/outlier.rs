@@ -0,0 +1,449 @@
+//! Outlier detection for the load balance service.
+//!
+//! The detector tracks the results of the calls to each [`Address`], and ejects the address from
+//! the picked instances for a while when it keeps failing. After the ejection time, the next call
+//! is allowed to go to the address as a probe: if the probe succeeds, the address is reinstated,
+//! otherwise it's ejected again for a longer time.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use super::error::Retryable;
+use crate::net::Address;
+
+const DEFAULT_CONSECUTIVE_FAILURES: usize = 5;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_BASE_EJECTION_TIME: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_EJECTION_TIME: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy)]
+pub struct OutlierDetectionConfig {
+    /// The number of consecutive failures that causes an ejection, `0` means disabled.
+    consecutive_failures: usize,
+    /// The failure rate in `[0, 1]` within an `interval` that causes an ejection.
+    failure_rate: Option<f64>,
+    /// The minimum number of calls within an `interval` to evaluate the `failure_rate`.
+    min_request_volume: usize,
+    /// The time window to calculate the failure rate.
+    interval: Duration,
+    /// An address is ejected for `base_ejection_time` multiplied by the number of times it has
+    /// been ejected in a row, and no longer than `max_ejection_time`.
+    base_ejection_time: Duration,
+    max_ejection_time: Duration,
+}
+
+impl OutlierDetectionConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of consecutive failures that causes an ejection.
+    ///
+    /// Defaults to 5, and `0` disables it.
+    pub fn consecutive_failures(mut self, n: usize) -> Self {
+        self.consecutive_failures = n;
+        self
+    }
+
+    /// Sets the failure rate that causes an ejection when there are at least `min_request_volume`
+    /// calls within an `interval`.
+    ///
+    /// Disabled by default.
+    pub fn failure_rate(mut self, rate: f64, min_request_volume: usize) -> Self {
+        self.failure_rate = Some(rate);
+        self.min_request_volume = min_request_volume;
+        self
+    }
+
+    /// Sets the time window to calculate the failure rate.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the base ejection time.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn base_ejection_time(mut self, time: Duration) -> Self {
+        self.base_ejection_time = time;
+        self
+    }
+
+    /// Sets the max ejection time.
+    ///
+    /// Defaults to 300 seconds.
+    pub fn max_ejection_time(mut self, time: Duration) -> Self {
+        self.max_ejection_time = time;
+        self
+    }
+}
+
+impl Default for OutlierDetectionConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: DEFAULT_CONSECUTIVE_FAILURES,
+            failure_rate: None,
+            min_request_volume: 0,
+            interval: DEFAULT_INTERVAL,
+            base_ejection_time: DEFAULT_BASE_EJECTION_TIME,
+            max_ejection_time: DEFAULT_MAX_EJECTION_TIME,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    consecutive_failures: usize,
+    requests: usize,
+    failures: usize,
+    window_start: Instant,
+    ejected_until: Option<Instant>,
+    ejection_times: u32,
+    probing: bool,
+}
+
+impl State {
+    fn new(now: Instant) -> Self {
+        Self {
+            consecutive_failures: 0,
+            requests: 0,
+            failures: 0,
+            window_start: now,
+            ejected_until: None,
+            ejection_times: 0,
+            probing: false,
+        }
+    }
+
+    fn reset_counters(&mut self, now: Instant) {
+        self.consecutive_failures = 0;
+        self.requests = 0;
+        self.failures = 0;
+        self.window_start = now;
+    }
+}
+
+/// [`OutlierDetector`] records the call results of each address and decides whether an address
+/// should be ejected.
+pub struct OutlierDetector {
+    config: OutlierDetectionConfig,
+    states: DashMap<Address, Arc<Mutex<State>>>,
+}
+
+impl OutlierDetector {
+    pub fn new(config: OutlierDetectionConfig) -> Self {
+        Self {
+            config,
+            states: DashMap::new(),
+        }
+    }
+
+    /// Returns whether the address can be called now.
+    ///
+    /// When the ejection time of an address expires, only the first caller gets `true` and is
+    /// responsible for probing, the others still see the address as ejected until the probe
+    /// reports.
+    pub fn try_acquire(&self, address: &Address) -> bool {
+        let state = match self.states.get(address) {
+            Some(state) => state.clone(),
+            None => return true,
+        };
+        let mut state = state.lock().unwrap();
+        match state.ejected_until {
+            None => true,
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                if state.probing {
+                    false
+                } else {
+                    state.probing = true;
+                    true
+                }
+            }
+        }
+    }
+
+    /// Records the result of a call to the address.
+    pub fn report(&self, address: &Address, success: bool) {
+        let now = Instant::now();
+        let state = self
+            .states
+            .entry(address.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(State::new(now))))
+            .clone();
+        let mut state = state.lock().unwrap();
+
+        if state.probing {
+            state.probing = false;
+            if success {
+                info!("[VOLO] outlier detection reinstates address: {}", address);
+                state.ejected_until = None;
+                state.ejection_times = 0;
+                state.reset_counters(now);
+            } else {
+                self.eject(address, &mut state, now);
+            }
+            return;
+        }
+        if state.ejected_until.is_some() {
+            // the call was sent before the address is ejected
+            return;
+        }
+
+        if now.duration_since(state.window_start) >= self.config.interval {
+            state.requests = 0;
+            state.failures = 0;
+            state.window_start = now;
+        }
+        state.requests += 1;
+        if success {
+            state.consecutive_failures = 0;
+            return;
+        }
+        state.failures += 1;
+        state.consecutive_failures += 1;
+
+        let too_many_consecutive_failures = self.config.consecutive_failures > 0
+            && state.consecutive_failures >= self.config.consecutive_failures;
+        let too_high_failure_rate = match self.config.failure_rate {
+            Some(rate) => {
+                state.requests >= self.config.min_request_volume
+                    && state.failures as f64 >= rate * state.requests as f64
+            }
+            None => false,
+        };
+        if too_many_consecutive_failures || too_high_failure_rate {
+            self.eject(address, &mut state, now);
+        }
+    }
+
+    /// Releases the probe of the address without reporting a result, it's used when the call is
+    /// cancelled for reasons other than the address itself.
+    pub fn release(&self, address: &Address) {
+        if let Some(state) = self.states.get(address) {
+            state.lock().unwrap().probing = false;
+        }
+    }
+
+    /// Removes the records of the address, it's used when the address is removed from the
+    /// discovery result.
+    pub fn remove(&self, address: &Address) {
+        self.states.remove(address);
+    }
+
+    fn eject(&self, address: &Address, state: &mut State, now: Instant) {
+        state.ejection_times = state.ejection_times.saturating_add(1);
+        let ejection_time = self
+            .config
+            .base_ejection_time
+            .saturating_mul(state.ejection_times)
+            .min(self.config.max_ejection_time);
+        warn!(
+            "[VOLO] outlier detection ejects address: {} for {:?}",
+            address, ejection_time
+        );
+        state.ejected_until = Some(now + ejection_time);
+        state.reset_counters(now);
+    }
+}
+
+impl fmt::Debug for OutlierDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutlierDetector")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// [`OutlierGuard`] reports the result of a call to the [`OutlierDetector`] when it's dropped.
+///
+/// The call is reported as failed if the guard is dropped without calling
+/// [`OutlierGuard::finish`], since a cancelled call is usually caused by a timeout.
+pub(super) struct OutlierGuard<'a> {
+    detector: &'a OutlierDetector,
+    address: Address,
+    success: bool,
+    cancelled: bool,
+}
+
+impl<'a> OutlierGuard<'a> {
+    pub(super) fn new(detector: &'a OutlierDetector, address: Address) -> Self {
+        Self {
+            detector,
+            address,
+            success: false,
+            cancelled: false,
+        }
+    }
+
+    pub(super) fn finish(mut self, success: bool) {
+        self.success = success;
+    }
+
+    /// Drops the guard without reporting, such as when a hedged request loses.
+    pub(super) fn cancel(mut self) {
+        self.cancelled = true;
+    }
+}
+
+impl Drop for OutlierGuard<'_> {
+    fn drop(&mut self) {
+        if self.cancelled {
+            self.detector.release(&self.address);
+        } else {
+            self.detector.report(&self.address, self.success);
+        }
+    }
+}
+
+/// Only the retryable errors, such as the transport errors, indicate that the instance may be
+/// unhealthy.
+pub(super) fn finish_outlier<T, E>(guard: Option<OutlierGuard<'_>>, result: &Result<T, E>)
+where
+    E: Retryable,
+{
+    if let Some(guard) = guard {
+        guard.finish(match result {
+            Ok(_) => true,
+            Err(err) => !err.retryable(),
+        });
+    }
+}
+
+/// [`OutlierFilter`] skips the ejected addresses of the inner picker.
+///
+/// If all the addresses are ejected, the ejected ones are yielded in order, so that the requests
+/// can still be sent rather than failing directly.
+pub(super) struct OutlierFilter<'a, I> {
+    picker: I,
+    detector: Option<&'a OutlierDetector>,
+    ejected: Vec<Address>,
+    yielded: bool,
+}
+
+impl<'a, I> OutlierFilter<'a, I> {
+    pub(super) fn new(picker: I, detector: Option<&'a OutlierDetector>) -> Self {
+        Self {
+            picker,
+            detector,
+            ejected: Vec::new(),
+            yielded: false,
+        }
+    }
+}
+
+impl<I> Iterator for OutlierFilter<'_, I>
+where
+    I: Iterator<Item = Address>,
+{
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let detector = match self.detector {
+            Some(detector) => detector,
+            None => return self.picker.next(),
+        };
+        for address in self.picker.by_ref() {
+            if detector.try_acquire(&address) {
+                self.yielded = true;
+                return Some(address);
+            }
+            self.ejected.push(address);
+        }
+        if self.yielded || self.ejected.is_empty() {
+            return None;
+        }
+        Some(self.ejected.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{OutlierDetectionConfig, OutlierDetector, OutlierFilter};
+    use crate::net::Address;
+
+    fn addr(s: &str) -> Address {
+        Address::Ip(s.parse().unwrap())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_consecutive_failures() {
+        let detector = OutlierDetector::new(
+            OutlierDetectionConfig::new()
+                .consecutive_failures(3)
+                .base_ejection_time(Duration::from_secs(10)),
+        );
+        let bad = addr("127.0.0.1:8000");
+
+        detector.report(&bad, false);
+        detector.report(&bad, false);
+        detector.report(&bad, true);
+        detector.report(&bad, false);
+        detector.report(&bad, false);
+        assert!(detector.try_acquire(&bad));
+        detector.report(&bad, false);
+        assert!(!detector.try_acquire(&bad));
+
+        // after the ejection time, only one probe is allowed
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(detector.try_acquire(&bad));
+        assert!(!detector.try_acquire(&bad));
+
+        // a failed probe doubles the ejection time
+        detector.report(&bad, false);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(!detector.try_acquire(&bad));
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(detector.try_acquire(&bad));
+
+        // a successful probe reinstates the address
+        detector.report(&bad, true);
+        assert!(detector.try_acquire(&bad));
+        assert!(detector.try_acquire(&bad));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failure_rate() {
+        let detector = OutlierDetector::new(
+            OutlierDetectionConfig::new()
+                .consecutive_failures(0)
+                .failure_rate(0.5, 4),
+        );
+        let bad = addr("127.0.0.1:8000");
+
+        detector.report(&bad, false);
+        detector.report(&bad, true);
+        detector.report(&bad, true);
+        assert!(detector.try_acquire(&bad));
+        detector.report(&bad, false);
+        assert!(!detector.try_acquire(&bad));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_filter() {
+        let detector = OutlierDetector::new(OutlierDetectionConfig::new().consecutive_failures(1));
+        let (a, b) = (addr("127.0.0.1:8000"), addr("127.0.0.2:8000"));
+        detector.report(&a, false);
+
+        let picked = OutlierFilter::new(vec![a.clone(), b.clone()].into_iter(), Some(&detector))
+            .collect::<Vec<_>>();
+        assert_eq!(picked, vec![b.clone()]);
+
+        // fall back to the ejected addresses if all of them are ejected
+        detector.report(&b, false);
+        let picked = OutlierFilter::new(vec![a.clone(), b.clone()].into_iter(), Some(&detector))
+            .collect::<Vec<_>>();
+        assert_eq!(picked, vec![a, b]);
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use rand::Rng;
+
+use super::{error::LoadBalanceError, LoadBalance};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+const DEFAULT_DECAY: f64 = 0.3;
+const DEFAULT_PENALTY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct P2COption {
+    /// The weight of the newest latency sample in the EWMA latency, should be in `(0, 1]`.
+    ///
+    /// The larger the value, the faster the balancer reacts to latency changes, and the more
+    /// sensitive it is to jitters.
+    decay: f64,
+    /// The latency recorded for a failed call if it fails faster, so that an instance failing
+    /// fast doesn't look like the least loaded one.
+    penalty: Duration,
+}
+
+impl P2COption {
+    pub fn new(decay: f64) -> Self {
+        assert!(
+            decay > 0.0 && decay <= 1.0,
+            "decay of P2COption must be in (0, 1]"
+        );
+        P2COption {
+            decay,
+            penalty: DEFAULT_PENALTY,
+        }
+    }
+
+    /// Sets the min latency recorded for a failed call.
+    ///
+    /// Defaults to 1s.
+    pub fn penalty(mut self, penalty: Duration) -> Self {
+        self.penalty = penalty;
+        self
+    }
+}
+
+impl Default for P2COption {
+    fn default() -> Self {
+        Self::new(DEFAULT_DECAY)
+    }
+}
+
+/// The load information of an address.
+#[derive(Debug, Default)]
+struct Load {
+    /// The number of outstanding requests.
+    inflight: AtomicUsize,
+    /// The EWMA latency in nanoseconds, stored as the bits of a `f64`.
+    ewma_latency: AtomicU64,
+}
+
+impl Load {
+    fn ewma_latency(&self) -> f64 {
+        f64::from_bits(self.ewma_latency.load(Ordering::Relaxed))
+    }
+
+    fn observe(&self, elapsed: Duration, decay: f64) {
+        let sample = elapsed.as_nanos() as f64;
+        let _ = self
+            .ewma_latency
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |prev| {
+                let prev = f64::from_bits(prev);
+                // the first sample is used as is
+                let next = if prev == 0.0 {
+                    sample
+                } else {
+                    prev * (1.0 - decay) + sample * decay
+                };
+                Some(next.to_bits())
+            });
+    }
+
+    /// The lower the score, the less loaded the address is.
+    ///
+    /// The score is the expected latency of a new request if the requests are served one by one,
+    /// and `default_latency` is used if there is no latency sample yet.
+    fn score(&self, default_latency: f64) -> f64 {
+        let mut latency = self.ewma_latency();
+        if latency == 0.0 {
+            latency = default_latency;
+        }
+        (latency + 1.0) * (self.inflight.load(Ordering::Relaxed) + 1) as f64
+    }
+}
+
+type Loads = DashMap<Address, Arc<Load>>;
+
+#[derive(Debug)]
+pub struct InstancePicker {
+    loads: Arc<Loads>,
+    /// The instances that have not been picked yet.
+    instances: Vec<Arc<Instance>>,
+    /// The latency of the instances without any sample, which is the mean EWMA latency of the
+    /// others, so that a new instance is neither flooded nor starved.
+    default_latency: f64,
+}
+
+impl InstancePicker {
+    fn new(loads: Arc<Loads>, instances: Vec<Arc<Instance>>) -> Self {
+        let (sum, count) = instances
+            .iter()
+            .filter_map(|instance| loads.get(&instance.address).map(|load| load.ewma_latency()))
+            .filter(|latency| *latency > 0.0)
+            .fold((0.0, 0), |(sum, count), latency| (sum + latency, count + 1));
+        let default_latency = if count == 0 { 0.0 } else { sum / count as f64 };
+        Self {
+            loads,
+            instances,
+            default_latency,
+        }
+    }
+
+    fn score(&self, instance: &Instance) -> f64 {
+        match self.loads.get(&instance.address) {
+            Some(load) => load.score(self.default_latency),
+            // no call has been made to the instance yet
+            None => self.default_latency + 1.0,
+        }
+    }
+}
+
+impl Iterator for InstancePicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = match self.instances.len() {
+            0 => return None,
+            1 => 0,
+            len => {
+                let mut rng = rand::thread_rng();
+                let a = rng.gen_range(0..len);
+                // pick another one that differs from `a`
+                let b = (a + rng.gen_range(1..len)) % len;
+                if self.score(&self.instances[a]) <= self.score(&self.instances[b]) {
+                    a
+                } else {
+                    b
+                }
+            }
+        };
+        Some(self.instances.swap_remove(offset).address.clone())
+    }
+}
+
+/// [`P2CBalance`] implements the "power of two choices" algorithm: it picks two instances randomly
+/// and chooses the less loaded one.
+///
+/// The load of an instance is measured by its outstanding requests and EWMA latency, which are
+/// reported by the load balance service through [`LoadBalance::on_call_start`] and
+/// [`LoadBalance::on_call_end`]. Instances with zero weight will never be picked, and the weight
+/// is ignored otherwise.
+#[derive(Debug, Clone)]
+pub struct P2CBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    option: P2COption,
+    router: DashMap<K, Arc<Vec<Arc<Instance>>>>,
+    loads: Arc<Loads>,
+}
+
+impl<K> P2CBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self::new(P2COption::default())
+    }
+
+    pub fn new(option: P2COption) -> Self {
+        Self {
+            option,
+            router: DashMap::new(),
+            loads: Arc::new(DashMap::new()),
+        }
+    }
+
+    fn build_instances(instances: Vec<Arc<Instance>>) -> Arc<Vec<Arc<Instance>>> {
+        Arc::new(instances.into_iter().filter(|i| i.weight != 0).collect())
+    }
+}
+
+impl<K> Default for P2CBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new(P2COption::default())
+    }
+}
+
+impl<D> LoadBalance<D> for P2CBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter = InstancePicker;
+
+    type GetFut<'future>
+        = impl Future<Output = Result<Self::InstanceIter, LoadBalanceError>> + Send + 'future
+    where
+        Self: 'future;
+
+    fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Self::GetFut<'future>
+    where
+        Self: 'future,
+    {
+        async {
+            let key = discover.key(endpoint);
+            let list = match self.router.entry(key) {
+                Entry::Occupied(e) => e.get().clone(),
+                Entry::Vacant(e) => {
+                    let instances = Self::build_instances(
+                        discover
+                            .discover(endpoint)
+                            .await
+                            .map_err(|err| err.into())?,
+                    );
+                    e.insert(instances).value().clone()
+                }
+            };
+            Ok(InstancePicker::new(self.loads.clone(), list.to_vec()))
+        }
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        for instance in changes.removed.iter() {
+            self.loads.remove(&instance.address);
+        }
+        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
+            entry.replace_entry(Self::build_instances(changes.all));
+        }
+    }
+
+    fn on_call_start(&self, address: &Address) {
+        self.loads
+            .entry(address.clone())
+            .or_default()
+            .inflight
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_call_end(&self, address: &Address, elapsed: Duration, success: bool) {
+        if let Some(load) = self.loads.get(address) {
+            // the load may be reset by `rebalance` during the call, so never underflow
+            let _ = load
+                .inflight
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+            let elapsed = if success {
+                elapsed
+            } else {
+                elapsed.max(self.option.penalty)
+            };
+            load.observe(elapsed, self.option.decay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::{LoadBalance, P2CBalance};
+    use crate::{context::Endpoint, discovery::StaticDiscover, net::Address};
+
+    #[tokio::test]
+    async fn test_p2c() {
+        let empty = Endpoint::new("".into());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:9000".parse().unwrap(),
+        ]);
+        let lb = P2CBalance::with_discover(&discover);
+        let picker = lb.get_picker(&empty, &discover).await.unwrap();
+        let all = picker.collect::<Vec<_>>();
+        assert_eq!(all.len(), 2);
+        assert_ne!(all[0], all[1]);
+    }
+
+    #[tokio::test]
+    async fn test_p2c_prefers_less_loaded() {
+        let empty = Endpoint::new("".into());
+        let slow: Address = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let fast: Address = Address::Ip("127.0.0.2:9000".parse().unwrap());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:9000".parse().unwrap(),
+        ]);
+        let lb = P2CBalance::with_discover(&discover);
+
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_start(&lb, &slow);
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_end(
+            &lb,
+            &slow,
+            Duration::from_millis(100),
+            true,
+        );
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_start(&lb, &fast);
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_end(
+            &lb,
+            &fast,
+            Duration::from_millis(1),
+            true,
+        );
+
+        let mut counts = HashMap::new();
+        for _ in 0..100 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            *counts.entry(picker.next().unwrap()).or_insert(0) += 1;
+        }
+        // with only two instances, both of them are always compared
+        assert_eq!(counts.get(&fast), Some(&100));
+    }
+
+    #[tokio::test]
+    async fn test_p2c_penalizes_failures() {
+        let empty = Endpoint::new("".into());
+        let failing: Address = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let healthy: Address = Address::Ip("127.0.0.2:9000".parse().unwrap());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:9000".parse().unwrap(),
+        ]);
+        let lb = P2CBalance::with_discover(&discover);
+
+        // the failing one fails much faster than the healthy one responds
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_start(&lb, &failing);
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_end(
+            &lb,
+            &failing,
+            Duration::from_millis(1),
+            false,
+        );
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_start(&lb, &healthy);
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_end(
+            &lb,
+            &healthy,
+            Duration::from_millis(100),
+            true,
+        );
+
+        for _ in 0..100 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            assert_eq!(picker.next(), Some(healthy.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_p2c_new_instance() {
+        let empty = Endpoint::new("".into());
+        let old: Address = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:9000".parse().unwrap(),
+        ]);
+        let lb = P2CBalance::with_discover(&discover);
+
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_start(&lb, &old);
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_end(
+            &lb,
+            &old,
+            Duration::from_millis(10),
+            true,
+        );
+        // the new instance is scored by the mean latency of the others, so it doesn't win every
+        // pick while the old one is idle, and takes the requests once the old one is busy
+        let mut counts = HashMap::new();
+        for _ in 0..100 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            *counts.entry(picker.next().unwrap()).or_insert(0) += 1;
+        }
+        assert!(counts.get(&old).is_some_and(|n| *n > 0 && *n < 100));
+        <P2CBalance<()> as LoadBalance<StaticDiscover>>::on_call_start(&lb, &old);
+        let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+        assert_ne!(picker.next(), Some(old.clone()));
+    }
+}
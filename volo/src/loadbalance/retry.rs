@@ -0,0 +1,289 @@
+//! Retry policy for the load balance service.
+//!
+//! A [`RetryPolicy`] decides how many times a request can be attempted, how long to wait between
+//! the attempts and which errors are worth retrying. Every retry goes to the next address yielded
+//! by the load balancer.
+//!
+//! To avoid retry storms during partial outages, the retries of a client can be limited by a
+//! budget: each request deposits `ratio` tokens and each retry withdraws one, so the retries are
+//! at most about `ratio` of the requests in the long run.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rand::Rng;
+
+use super::error::Retryable;
+use crate::FastStr;
+
+const DEFAULT_MAX_ATTEMPTS: usize = 3;
+const DEFAULT_BASE_BACKOFF: Duration = Duration::from_millis(10);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+/// The initial balance of a retry budget, so that the clients with little traffic can still
+/// retry.
+const MIN_RETRY_BUDGET: f64 = 10.0;
+/// The max balance of a retry budget, which limits the retries in a burst.
+const MAX_RETRY_BUDGET: f64 = 100.0;
+
+type RetryablePredicate = Arc<dyn Fn(&dyn Any) -> Option<bool> + Send + Sync>;
+
+/// [`RetryPolicy`] can be set to the client as the default policy, and be overridden for
+/// specified methods by [`RetryPolicy::method`] or for a single call by the `CallOpt`.
+///
+/// The default policy allows 3 attempts, with exponential backoff from 10ms to 1s with jitter and
+/// no budget.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    base_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    budget: Option<f64>,
+    retryable: Option<RetryablePredicate>,
+    methods: Arc<HashMap<FastStr, RetryPolicy>>,
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a policy that retries `retry_count` times immediately, which is the behavior of
+    /// `LbConfig::retry_count`.
+    pub fn with_retry_count(retry_count: usize) -> Self {
+        Self::new()
+            .max_attempts(retry_count + 1)
+            .backoff(Duration::ZERO, Duration::ZERO)
+    }
+
+    /// Sets the max number of attempts of a request, including the first one.
+    ///
+    /// Defaults to 3, and `1` disables retrying.
+    pub fn max_attempts(mut self, n: usize) -> Self {
+        self.max_attempts = n.max(1);
+        self
+    }
+
+    /// Sets the exponential backoff between the attempts.
+    ///
+    /// The n-th retry waits for `base * 2^(n-1)` and no longer than `max`. Defaults to 10ms and
+    /// 1s.
+    pub fn backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.base_backoff = base;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets whether to add a random jitter to the backoff, which waits for a random duration
+    /// between half of the backoff and the backoff.
+    ///
+    /// Defaults to `true`.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the retry budget ratio in `[0, 1]`, the retries will be at most about `ratio` of the
+    /// requests.
+    ///
+    /// The budget is shared by all the calls of a client, so only the budget of the client level
+    /// policy takes effect. Defaults to `None`, which means no limit.
+    pub fn budget(mut self, ratio: Option<f64>) -> Self {
+        self.budget = ratio;
+        self
+    }
+
+    /// Sets the predicate which decides whether an error should be retried.
+    ///
+    /// The predicate only applies to the errors of type `E`, the other errors are retried if
+    /// they're [`Retryable`]. Defaults to [`Retryable::retryable`].
+    pub fn retryable<E, F>(mut self, f: F) -> Self
+    where
+        E: 'static,
+        F: Fn(&E) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Some(Arc::new(move |err: &dyn Any| {
+            err.downcast_ref::<E>().map(&f)
+        }));
+        self
+    }
+
+    /// Overrides the policy of the specified method, for example, the methods that are not
+    /// idempotent should not be retried.
+    pub fn method(mut self, method: impl Into<FastStr>, policy: RetryPolicy) -> Self {
+        Arc::make_mut(&mut self.methods).insert(method.into(), policy);
+        self
+    }
+
+    /// Returns the policy of the method, which is `self` if it's not overridden.
+    pub fn for_method(&self, method: Option<&FastStr>) -> &RetryPolicy {
+        method
+            .and_then(|method| self.methods.get(method))
+            .unwrap_or(self)
+    }
+
+    /// Returns the max number of attempts of a request.
+    pub fn attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Returns whether the error should be retried.
+    pub fn is_retryable<E>(&self, err: &E) -> bool
+    where
+        E: Retryable + 'static,
+    {
+        self.retryable
+            .as_ref()
+            .and_then(|f| f(err))
+            .unwrap_or_else(|| err.retryable())
+    }
+
+    /// Returns the backoff before the `retry`-th retry, starting from 1.
+    pub fn backoff_of(&self, retry: usize) -> Duration {
+        if self.base_backoff.is_zero() || retry == 0 {
+            return Duration::ZERO;
+        }
+        let exp = (retry - 1).min(u32::MAX as usize) as u32;
+        let backoff = self
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(exp))
+            .min(self.max_backoff);
+        if self.jitter && !backoff.is_zero() {
+            rand::thread_rng().gen_range(backoff / 2..=backoff)
+        } else {
+            backoff
+        }
+    }
+
+    /// Creates the retry budget of the policy, which should be shared by all the calls of a
+    /// client.
+    pub fn make_budget(&self) -> Option<RetryBudget> {
+        self.budget.map(RetryBudget::new)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_backoff: DEFAULT_BASE_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            jitter: true,
+            budget: None,
+            retryable: None,
+            methods: Default::default(),
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_backoff", &self.base_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("jitter", &self.jitter)
+            .field("budget", &self.budget)
+            .field("methods", &self.methods)
+            .finish()
+    }
+}
+
+/// [`RetryBudget`] limits the ratio of the retries to the requests.
+#[derive(Debug)]
+pub struct RetryBudget {
+    ratio: f64,
+    balance: Mutex<f64>,
+}
+
+impl RetryBudget {
+    fn new(ratio: f64) -> Self {
+        Self {
+            ratio,
+            balance: Mutex::new(MIN_RETRY_BUDGET),
+        }
+    }
+
+    /// Deposits for a new request.
+    pub fn deposit(&self) {
+        let mut balance = self.balance.lock().unwrap();
+        *balance = (*balance + self.ratio).min(MAX_RETRY_BUDGET);
+    }
+
+    /// Returns whether a retry is allowed, and withdraws for it if so.
+    pub fn try_withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().unwrap();
+        if *balance >= 1.0 {
+            *balance -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RetryBudget, RetryPolicy};
+    use crate::loadbalance::error::Retryable;
+
+    struct TestError(bool);
+
+    impl Retryable for TestError {
+        fn retryable(&self) -> bool {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new()
+            .backoff(Duration::from_millis(10), Duration::from_millis(50))
+            .jitter(false);
+        assert_eq!(policy.backoff_of(1), Duration::from_millis(10));
+        assert_eq!(policy.backoff_of(2), Duration::from_millis(20));
+        assert_eq!(policy.backoff_of(3), Duration::from_millis(40));
+        assert_eq!(policy.backoff_of(4), Duration::from_millis(50));
+        assert_eq!(policy.backoff_of(100), Duration::from_millis(50));
+
+        let policy = policy.jitter(true);
+        for _ in 0..100 {
+            let backoff = policy.backoff_of(2);
+            assert!(backoff >= Duration::from_millis(10) && backoff <= Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_retryable_and_methods() {
+        let policy = RetryPolicy::new()
+            .retryable(|err: &TestError| !err.0)
+            .method("create", RetryPolicy::new().max_attempts(1));
+        assert!(policy.is_retryable(&TestError(false)));
+        assert!(!policy.is_retryable(&TestError(true)));
+        assert!(RetryPolicy::new().is_retryable(&TestError(true)));
+
+        assert_eq!(policy.for_method(Some(&"create".into())).attempts(), 1);
+        assert_eq!(policy.for_method(Some(&"get".into())).attempts(), 3);
+        assert_eq!(policy.for_method(None).attempts(), 3);
+    }
+
+    #[test]
+    fn test_budget() {
+        let budget = RetryBudget::new(0.5);
+        for _ in 0..10 {
+            assert!(budget.try_withdraw());
+        }
+        assert!(!budget.try_withdraw());
+        budget.deposit();
+        assert!(!budget.try_withdraw());
+        budget.deposit();
+        assert!(budget.try_withdraw());
+    }
+}
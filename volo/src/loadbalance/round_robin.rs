@@ -0,0 +1,371 @@
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use super::{error::LoadBalanceError, LoadBalance};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+/// [`InstancePicker`] yields the instances starting from the picked offset, in order, and wraps
+/// around until every instance has been yielded once.
+///
+/// The first item is the instance chosen by the load balancer, the following ones are only used
+/// when the client retries.
+#[derive(Debug)]
+pub struct InstancePicker {
+    shared_instances: Arc<[Arc<Instance>]>,
+    start: usize,
+    offset: usize,
+}
+
+impl Iterator for InstancePicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let shared_instances = &self.shared_instances;
+        while self.offset < shared_instances.len() {
+            let instance = &shared_instances[(self.start + self.offset) % shared_instances.len()];
+            self.offset += 1;
+            // instances with zero weight should never receive traffic
+            if instance.weight != 0 {
+                return Some(instance.address.clone());
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+struct RoundRobinInstances {
+    instances: Arc<[Arc<Instance>]>,
+    counter: AtomicUsize,
+}
+
+impl From<Vec<Arc<Instance>>> for RoundRobinInstances {
+    fn from(instances: Vec<Arc<Instance>>) -> Self {
+        Self {
+            instances: instances.into(),
+            // start from a random offset, so that the clients will not hit the same instance at
+            // the same time after restarting
+            counter: AtomicUsize::new(rand::random()),
+        }
+    }
+}
+
+/// [`RoundRobinBalance`] picks the instances one by one in order, ignoring the weight.
+///
+/// Instances with zero weight will never be picked.
+#[derive(Debug, Clone)]
+pub struct RoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    router: DashMap<K, Arc<RoundRobinInstances>>,
+}
+
+impl<K> RoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self {
+            router: DashMap::new(),
+        }
+    }
+
+    pub fn new() -> Self {
+        Self {
+            router: DashMap::new(),
+        }
+    }
+}
+
+impl<K> Default for RoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> LoadBalance<D> for RoundRobinBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter = InstancePicker;
+
+    type GetFut<'future> =
+        impl Future<Output = Result<Self::InstanceIter, LoadBalanceError>> + Send + 'future
+        where
+            Self: 'future;
+
+    fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Self::GetFut<'future>
+    where
+        Self: 'future,
+    {
+        async {
+            let key = discover.key(endpoint);
+            let list = match self.router.entry(key) {
+                Entry::Occupied(e) => e.get().clone(),
+                Entry::Vacant(e) => {
+                    let instances = Arc::new(RoundRobinInstances::from(
+                        discover
+                            .discover(endpoint)
+                            .await
+                            .map_err(|err| err.into())?,
+                    ));
+                    e.insert(instances).value().clone()
+                }
+            };
+            let start = if list.instances.is_empty() {
+                0
+            } else {
+                list.counter.fetch_add(1, Ordering::Relaxed) % list.instances.len()
+            };
+            Ok(InstancePicker {
+                shared_instances: list.instances.clone(),
+                start,
+                offset: 0,
+            })
+        }
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
+            entry.replace_entry(Arc::new(RoundRobinInstances::from(changes.all)));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct WeightedInstances {
+    instances: Arc<[Arc<Instance>]>,
+    sum_of_weights: isize,
+    /// The `current_weight` of each instance in the smooth weighted round-robin algorithm.
+    current_weights: Mutex<Vec<isize>>,
+}
+
+impl WeightedInstances {
+    /// Picks an instance with the smooth weighted round-robin algorithm used by nginx.
+    ///
+    /// Each time an instance is picked, every instance's current weight is increased by its own
+    /// weight, and the instance with the max current weight is chosen, then its current weight is
+    /// decreased by the sum of weights. This spreads the picks of heavy instances evenly instead
+    /// of picking them in a burst.
+    fn pick(&self) -> Option<usize> {
+        if self.sum_of_weights == 0 {
+            return None;
+        }
+        let mut current_weights = self.current_weights.lock().unwrap();
+        let mut best: Option<usize> = None;
+        for (offset, instance) in self.instances.iter().enumerate() {
+            current_weights[offset] += instance.weight as isize;
+            if instance.weight == 0 {
+                continue;
+            }
+            match best {
+                Some(b) if current_weights[b] >= current_weights[offset] => {}
+                _ => best = Some(offset),
+            }
+        }
+        let best = best?;
+        current_weights[best] -= self.sum_of_weights;
+        Some(best)
+    }
+}
+
+impl From<Vec<Arc<Instance>>> for WeightedInstances {
+    fn from(instances: Vec<Arc<Instance>>) -> Self {
+        let sum_of_weights = instances
+            .iter()
+            .fold(0, |lhs, rhs| lhs + rhs.weight as isize);
+        let current_weights = Mutex::new(vec![0; instances.len()]);
+        Self {
+            instances: instances.into(),
+            sum_of_weights,
+            current_weights,
+        }
+    }
+}
+
+/// [`WeightedRoundRobinBalance`] implements the smooth weighted round-robin algorithm of nginx.
+///
+/// For instances `a`, `b`, `c` with weights `5`, `1`, `1`, the picking sequence will be
+/// `a, a, b, a, c, a, a`, rather than `a, a, a, a, a, b, c`.
+#[derive(Debug, Clone)]
+pub struct WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    router: DashMap<K, Arc<WeightedInstances>>,
+}
+
+impl<K> WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self {
+            router: DashMap::new(),
+        }
+    }
+
+    pub fn new() -> Self {
+        Self {
+            router: DashMap::new(),
+        }
+    }
+}
+
+impl<K> Default for WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> LoadBalance<D> for WeightedRoundRobinBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter = InstancePicker;
+
+    type GetFut<'future> =
+        impl Future<Output = Result<Self::InstanceIter, LoadBalanceError>> + Send + 'future
+        where
+            Self: 'future;
+
+    fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Self::GetFut<'future>
+    where
+        Self: 'future,
+    {
+        async {
+            let key = discover.key(endpoint);
+            let weighted_list = match self.router.entry(key) {
+                Entry::Occupied(e) => e.get().clone(),
+                Entry::Vacant(e) => {
+                    let instances = Arc::new(WeightedInstances::from(
+                        discover
+                            .discover(endpoint)
+                            .await
+                            .map_err(|err| err.into())?,
+                    ));
+                    e.insert(instances).value().clone()
+                }
+            };
+            // if there's no instance with positive weight, `offset` is set to the end so that
+            // the picker yields nothing
+            let (start, offset) = match weighted_list.pick() {
+                Some(start) => (start, 0),
+                None => (0, weighted_list.instances.len()),
+            };
+            Ok(InstancePicker {
+                shared_instances: weighted_list.instances.clone(),
+                start,
+                offset,
+            })
+        }
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
+            entry.replace_entry(Arc::new(WeightedInstances::from(changes.all)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::{LoadBalance, RoundRobinBalance, WeightedRoundRobinBalance};
+    use crate::{
+        context::Endpoint,
+        discovery::{Instance, StaticDiscover},
+        net::Address,
+    };
+
+    fn new_instance(address: &str, weight: u32) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(address.parse().unwrap()),
+            weight,
+            tags: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let empty = Endpoint::new("".into());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:9000".parse().unwrap(),
+            "127.0.0.3:9000".parse().unwrap(),
+        ]);
+        let lb = RoundRobinBalance::with_discover(&discover);
+
+        let picker = lb.get_picker(&empty, &discover).await.unwrap();
+        let all = picker.collect::<Vec<_>>();
+        assert_eq!(all.len(), 3);
+
+        let mut counts = HashMap::new();
+        for _ in 0..300 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            *counts.entry(picker.next().unwrap()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 3);
+        assert!(counts.values().all(|c| *c == 100));
+    }
+
+    #[tokio::test]
+    async fn test_smooth_weighted_round_robin() {
+        let empty = Endpoint::new("".into());
+        let a = new_instance("127.0.0.1:8000", 5);
+        let b = new_instance("127.0.0.2:8000", 1);
+        let c = new_instance("127.0.0.3:8000", 1);
+        let zero = new_instance("127.0.0.4:8000", 0);
+        let discover = StaticDiscover::new(vec![a.clone(), b.clone(), c.clone(), zero]);
+        let lb = WeightedRoundRobinBalance::with_discover(&discover);
+
+        let mut picks = Vec::new();
+        for _ in 0..7 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            picks.push(picker.next().unwrap());
+        }
+        let expected = [&a, &a, &b, &a, &c, &a, &a]
+            .iter()
+            .map(|i| i.address.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(picks, expected);
+
+        // the retry picks skip the instance with zero weight
+        let picker = lb.get_picker(&empty, &discover).await.unwrap();
+        assert_eq!(picker.count(), 3);
+    }
+}
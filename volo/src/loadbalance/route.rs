@@ -0,0 +1,439 @@
+//! Tag based routing and traffic splitting.
+//!
+//! A [`Router`] consists of rules that match the callee [`Endpoint`] of a request, and each rule
+//! splits the requests by weight to the targets, which are subsets of the instances selected by
+//! their tags. It's useful for canary releases and blue/green deployments.
+//!
+//! The [`RouteLayer`] selects the target of each request before the load balancer, and the
+//! [`RouteDiscover`] returns the instances of the selected target to the load balancer, which
+//! caches the instances of each target separately. A request fails with no available instance if
+//! its target has no instances.
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use volo::{
+//!     discovery::StaticDiscover,
+//!     loadbalance::route::{RouteDiscover, RouteLayer, RouteTarget, Router},
+//! };
+//!
+//! struct XEnv;
+//!
+//! let router = Arc::new(
+//!     Router::new()
+//!         // the requests with `XEnv` set to canary go to the canary instances
+//!         .rule(
+//!             |callee| {
+//!                 callee
+//!                     .get_faststr::<XEnv>()
+//!                     .is_some_and(|env| env == "canary")
+//!             },
+//!             vec![RouteTarget::tag(1, "env", "canary")],
+//!         )
+//!         // 5% of the other requests go to the canary instances
+//!         .default_targets(vec![
+//!             RouteTarget::tag(5, "env", "canary"),
+//!             RouteTarget::new(95, |instance| {
+//!                 instance.tags.get("env").map(|env| env.as_ref()) != Some("canary")
+//!             }),
+//!         ]),
+//! );
+//! let layer = RouteLayer::new(router.clone());
+//! let discover = RouteDiscover::new(StaticDiscover::new(vec![]), router);
+//! ```
+
+use std::{borrow::Cow, collections::HashMap, fmt, future::Future, sync::Arc};
+
+use async_broadcast::{Receiver, RecvError, TrySendError};
+use motore::Service;
+use rand::Rng;
+
+use crate::{
+    context::{Context, Endpoint},
+    discovery::{combinator::previous, diff_instances, Change, Discover, Instance},
+    Layer,
+};
+
+const CHANNEL_CAPACITY: usize = 32;
+
+type EndpointPredicate = Arc<dyn Fn(&Endpoint) -> bool + Send + Sync>;
+type InstancePredicate = Arc<dyn Fn(&Instance) -> bool + Send + Sync>;
+
+/// [`RouteTarget`] is a subset of the instances and the weight of the requests routed to it.
+#[derive(Clone)]
+pub struct RouteTarget {
+    weight: u32,
+    predicate: InstancePredicate,
+}
+
+impl RouteTarget {
+    /// Creates a target of the instances that match the predicate.
+    pub fn new<F>(weight: u32, predicate: F) -> Self
+    where
+        F: Fn(&Instance) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            weight,
+            predicate: Arc::new(predicate),
+        }
+    }
+
+    /// Creates a target of the instances whose tag `key` equals `value`.
+    pub fn tag(
+        weight: u32,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        let (key, value) = (key.into(), value.into());
+        Self::new(weight, move |instance| {
+            instance.tags.get(&key) == Some(&value)
+        })
+    }
+}
+
+impl fmt::Debug for RouteTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RouteTarget")
+            .field("weight", &self.weight)
+            .finish()
+    }
+}
+
+struct RouteRule {
+    predicate: EndpointPredicate,
+    /// The indexes of the targets in the router.
+    targets: Vec<usize>,
+}
+
+/// [`Router`] selects the target of a request by the first matching rule, or the default targets
+/// if no rule matches.
+///
+/// The requests that don't match any rule are routed to all the instances if there's no default
+/// targets.
+#[derive(Default)]
+pub struct Router {
+    targets: Vec<RouteTarget>,
+    rules: Vec<RouteRule>,
+    default: Vec<usize>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule that routes the requests whose callee matches the predicate to the targets.
+    pub fn rule<F>(mut self, predicate: F, targets: Vec<RouteTarget>) -> Self
+    where
+        F: Fn(&Endpoint) -> bool + Send + Sync + 'static,
+    {
+        let targets = self.add_targets(targets);
+        self.rules.push(RouteRule {
+            predicate: Arc::new(predicate),
+            targets,
+        });
+        self
+    }
+
+    /// Sets the targets of the requests that don't match any rule.
+    pub fn default_targets(mut self, targets: Vec<RouteTarget>) -> Self {
+        self.default = self.add_targets(targets);
+        self
+    }
+
+    fn add_targets(&mut self, targets: Vec<RouteTarget>) -> Vec<usize> {
+        let start = self.targets.len();
+        self.targets.extend(targets);
+        (start..self.targets.len()).collect()
+    }
+
+    /// Selects the index of the target by weight, `None` means all the instances.
+    fn select(&self, callee: &Endpoint) -> Option<usize> {
+        let targets = self
+            .rules
+            .iter()
+            .find(|rule| (rule.predicate)(callee))
+            .map(|rule| &rule.targets)
+            .unwrap_or(&self.default);
+        let total = targets
+            .iter()
+            .map(|&i| self.targets[i].weight as u64)
+            .sum::<u64>();
+        if total == 0 {
+            return targets.first().copied();
+        }
+        let mut n = rand::thread_rng().gen_range(0..total);
+        for &i in targets {
+            let weight = self.targets[i].weight as u64;
+            if n < weight {
+                return Some(i);
+            }
+            n -= weight;
+        }
+        unreachable!()
+    }
+
+    fn filter(&self, target: Option<usize>, instances: &[Arc<Instance>]) -> Vec<Arc<Instance>> {
+        match target {
+            Some(i) => {
+                let predicate = &self.targets[i].predicate;
+                instances
+                    .iter()
+                    .filter(|instance| predicate(instance))
+                    .cloned()
+                    .collect()
+            }
+            None => instances.to_vec(),
+        }
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("targets", &self.targets)
+            .field("rules", &self.rules.len())
+            .field("default", &self.default)
+            .finish()
+    }
+}
+
+/// The target selected by the [`RouteLayer`], which is stored in the tags of the callee.
+#[derive(Debug, Clone, Copy)]
+struct SelectedTarget(usize);
+
+/// [`RouteService`] selects the target of the requests, see [`RouteLayer`].
+#[derive(Clone, Debug)]
+pub struct RouteService<S> {
+    inner: S,
+    router: Arc<Router>,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for RouteService<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx>
+        = S::Future<'cx>
+    where
+        Self: 'cx;
+
+    fn call<'cx, 's>(&'s self, cx: &'cx mut Cx, req: Req) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        if let Some(callee) = cx.rpc_info_mut().callee_mut() {
+            if let Some(target) = self.router.select(callee) {
+                callee.insert(SelectedTarget(target));
+            }
+        }
+        self.inner.call(cx, req)
+    }
+}
+
+/// [`RouteLayer`] selects the target of each request by the [`Router`], and should be added as
+/// an outer layer of the client, so that the target has been selected before the load balancer.
+#[derive(Clone, Debug)]
+pub struct RouteLayer {
+    router: Arc<Router>,
+}
+
+impl RouteLayer {
+    pub fn new(router: Arc<Router>) -> Self {
+        Self { router }
+    }
+}
+
+impl<S> Layer<S> for RouteLayer {
+    type Service = RouteService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RouteService {
+            inner,
+            router: self.router,
+        }
+    }
+}
+
+/// [`RouteDiscover`] only returns the instances of the target selected by the [`RouteLayer`].
+///
+/// The key contains the selected target, so that the load balancer caches the instances of each
+/// target separately.
+#[derive(Clone)]
+pub struct RouteDiscover<D> {
+    inner: D,
+    router: Arc<Router>,
+}
+
+impl<D> RouteDiscover<D> {
+    pub fn new(inner: D, router: Arc<Router>) -> Self {
+        Self { inner, router }
+    }
+}
+
+impl<D> Discover for RouteDiscover<D>
+where
+    D: Discover,
+{
+    type Key = (D::Key, Option<usize>);
+    type Error = D::Error;
+    type DiscFut<'a> = impl Future<Output = Result<Vec<Arc<Instance>>, Self::Error>> + Send + 'a;
+
+    fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Self::DiscFut<'s> {
+        async move {
+            let instances = self.inner.discover(endpoint).await?;
+            Ok(self.router.filter(selected(endpoint), &instances))
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        (self.inner.key(endpoint), selected(endpoint))
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        let keys = keys.map(|keys| {
+            let mut inner_keys = Vec::with_capacity(keys.len());
+            for (key, _) in keys {
+                if !inner_keys.contains(key) {
+                    inner_keys.push(key.clone());
+                }
+            }
+            inner_keys
+        });
+        let mut receiver = self.inner.watch(keys.as_deref())?;
+        let router = self.router.clone();
+        let (mut sender, routed) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+        sender.set_overflow(true);
+        // every change of the inner discover is mapped to the changes of all the targets
+        tokio::spawn(async move {
+            let mut states: HashMap<Self::Key, Vec<Arc<Instance>>> = HashMap::new();
+            let targets = std::iter::once(None)
+                .chain((0..router.targets.len()).map(Some))
+                .collect::<Vec<_>>();
+            loop {
+                let change = match receiver.recv().await {
+                    Ok(change) => change,
+                    Err(RecvError::Overflowed(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+                let prev = previous(&change);
+                for &target in &targets {
+                    let key = (change.key.clone(), target);
+                    let prev = match states.remove(&key) {
+                        Some(prev) => prev,
+                        None => router.filter(target, &prev),
+                    };
+                    let next = router.filter(target, &change.all);
+                    states.insert(key.clone(), next.clone());
+                    if let Some(change) = diff_instances(key, &prev, next) {
+                        if let Err(TrySendError::Closed(_)) = sender.try_broadcast(change) {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        Some(routed)
+    }
+}
+
+fn selected(endpoint: &Endpoint) -> Option<usize> {
+    endpoint.get::<SelectedTarget>().map(|target| target.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use motore::{service::service_fn, Service};
+
+    use super::{RouteDiscover, RouteLayer, RouteTarget, Router};
+    use crate::{
+        context::{Context, Endpoint, Role, RpcCx, RpcInfo},
+        discovery::{Discover, Instance, StaticDiscover},
+        net::Address,
+        Layer,
+    };
+
+    struct XEnv;
+
+    type TestContext = RpcCx<(), ()>;
+
+    async fn handle(_cx: &mut TestContext, _request: ()) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn instance(port: u16, env: &'static str) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(([127, 0, 0, 1], port).into()),
+            weight: 1,
+            tags: HashMap::from([("env".into(), env.into())]),
+        })
+    }
+
+    fn new_context(env: Option<&'static str>) -> TestContext {
+        let mut callee = Endpoint::new("test".into());
+        if let Some(env) = env {
+            callee.insert_faststr::<XEnv>(env.into());
+        }
+        RpcCx::new(
+            RpcInfo::new(
+                Role::Client,
+                "method".into(),
+                Endpoint::new("caller".into()),
+                callee,
+                (),
+            ),
+            (),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let router = Arc::new(
+            Router::new()
+                .rule(
+                    |callee| {
+                        callee
+                            .get_faststr::<XEnv>()
+                            .is_some_and(|env| env == "canary")
+                    },
+                    vec![RouteTarget::tag(1, "env", "canary")],
+                )
+                .default_targets(vec![
+                    RouteTarget::tag(0, "env", "canary"),
+                    RouteTarget::tag(1, "env", "prod"),
+                ]),
+        );
+        let discover = RouteDiscover::new(
+            StaticDiscover::new(vec![instance(8000, "prod"), instance(8001, "canary")]),
+            router.clone(),
+        );
+        let service = RouteLayer::new(router).layer(service_fn(handle));
+
+        for (env, port) in [(Some("canary"), 8001), (Some("prod"), 8000), (None, 8000)] {
+            let mut cx = new_context(env);
+            service.call(&mut cx, ()).await.unwrap();
+            let callee = cx.rpc_info().callee().unwrap();
+            let instances = discover.discover(callee).await.unwrap();
+            assert_eq!(
+                instances,
+                vec![instance(port, if port == 8000 { "prod" } else { "canary" })]
+            );
+        }
+
+        // the requests without the layer go to all the instances
+        let cx = new_context(Some("canary"));
+        let callee = cx.rpc_info().callee().unwrap();
+        assert_eq!(discover.key(callee), ((), None));
+        assert_eq!(discover.discover(callee).await.unwrap().len(), 2);
+    }
+}
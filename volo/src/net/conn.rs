@@ -5,12 +5,16 @@ use std::{
 };
 
 use pin_project::pin_project;
+#[cfg(feature = "tls")]
+use tokio::io::{ReadHalf, WriteHalf};
 #[cfg(target_family = "unix")]
 use tokio::net::{unix, UnixStream};
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
     net::{tcp, TcpStream},
 };
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsStream;
 
 use super::Address;
 
@@ -28,6 +32,8 @@ pub enum ConnStream {
     Tcp(#[pin] TcpStream),
     #[cfg(target_family = "unix")]
     Unix(#[pin] UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(#[pin] Box<TlsStream<TcpStream>>),
 }
 
 #[pin_project(project = OwnedWriteHalfProj)]
@@ -35,6 +41,8 @@ pub enum OwnedWriteHalf {
     Tcp(#[pin] tcp::OwnedWriteHalf),
     #[cfg(target_family = "unix")]
     Unix(#[pin] unix::OwnedWriteHalf),
+    #[cfg(feature = "tls")]
+    Tls(#[pin] WriteHalf<TlsStream<TcpStream>>),
 }
 
 impl AsyncWrite for OwnedWriteHalf {
@@ -48,6 +56,8 @@ impl AsyncWrite for OwnedWriteHalf {
             OwnedWriteHalfProj::Tcp(half) => half.poll_write(cx, buf),
             #[cfg(target_family = "unix")]
             OwnedWriteHalfProj::Unix(half) => half.poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            OwnedWriteHalfProj::Tls(half) => half.poll_write(cx, buf),
         }
     }
 
@@ -57,6 +67,8 @@ impl AsyncWrite for OwnedWriteHalf {
             OwnedWriteHalfProj::Tcp(half) => half.poll_flush(cx),
             #[cfg(target_family = "unix")]
             OwnedWriteHalfProj::Unix(half) => half.poll_flush(cx),
+            #[cfg(feature = "tls")]
+            OwnedWriteHalfProj::Tls(half) => half.poll_flush(cx),
         }
     }
 
@@ -66,6 +78,8 @@ impl AsyncWrite for OwnedWriteHalf {
             OwnedWriteHalfProj::Tcp(half) => half.poll_shutdown(cx),
             #[cfg(target_family = "unix")]
             OwnedWriteHalfProj::Unix(half) => half.poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            OwnedWriteHalfProj::Tls(half) => half.poll_shutdown(cx),
         }
     }
 
@@ -79,6 +93,8 @@ impl AsyncWrite for OwnedWriteHalf {
             OwnedWriteHalfProj::Tcp(half) => half.poll_write_vectored(cx, bufs),
             #[cfg(target_family = "unix")]
             OwnedWriteHalfProj::Unix(half) => half.poll_write_vectored(cx, bufs),
+            #[cfg(feature = "tls")]
+            OwnedWriteHalfProj::Tls(half) => half.poll_write_vectored(cx, bufs),
         }
     }
 
@@ -88,6 +104,8 @@ impl AsyncWrite for OwnedWriteHalf {
             Self::Tcp(half) => half.is_write_vectored(),
             #[cfg(target_family = "unix")]
             Self::Unix(half) => half.is_write_vectored(),
+            #[cfg(feature = "tls")]
+            Self::Tls(half) => half.is_write_vectored(),
         }
     }
 }
@@ -97,6 +115,8 @@ pub enum OwnedReadHalf {
     Tcp(#[pin] tcp::OwnedReadHalf),
     #[cfg(target_family = "unix")]
     Unix(#[pin] unix::OwnedReadHalf),
+    #[cfg(feature = "tls")]
+    Tls(#[pin] ReadHalf<TlsStream<TcpStream>>),
 }
 
 impl AsyncRead for OwnedReadHalf {
@@ -110,6 +130,8 @@ impl AsyncRead for OwnedReadHalf {
             OwnedReadHalfProj::Tcp(half) => half.poll_read(cx, buf),
             #[cfg(target_family = "unix")]
             OwnedReadHalfProj::Unix(half) => half.poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            OwnedReadHalfProj::Tls(half) => half.poll_read(cx, buf),
         }
     }
 }
@@ -127,6 +149,11 @@ impl ConnStream {
                 let (rh, wh) = stream.into_split();
                 (OwnedReadHalf::Unix(rh), OwnedWriteHalf::Unix(wh))
             }
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => {
+                let (rh, wh) = tokio::io::split(*stream);
+                (OwnedReadHalf::Tls(rh), OwnedWriteHalf::Tls(wh))
+            }
         }
     }
 }
@@ -147,6 +174,14 @@ impl From<UnixStream> for ConnStream {
     }
 }
 
+#[cfg(feature = "tls")]
+impl From<TlsStream<TcpStream>> for ConnStream {
+    #[inline]
+    fn from(s: TlsStream<TcpStream>) -> Self {
+        Self::Tls(Box::new(s))
+    }
+}
+
 impl AsyncRead for ConnStream {
     #[inline]
     fn poll_read(
@@ -158,6 +193,8 @@ impl AsyncRead for ConnStream {
             IoStreamProj::Tcp(s) => s.poll_read(cx, buf),
             #[cfg(target_family = "unix")]
             IoStreamProj::Unix(s) => s.poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            IoStreamProj::Tls(s) => s.poll_read(cx, buf),
         }
     }
 }
@@ -173,6 +210,8 @@ impl AsyncWrite for ConnStream {
             IoStreamProj::Tcp(s) => s.poll_write(cx, buf),
             #[cfg(target_family = "unix")]
             IoStreamProj::Unix(s) => s.poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            IoStreamProj::Tls(s) => s.poll_write(cx, buf),
         }
     }
 
@@ -182,6 +221,8 @@ impl AsyncWrite for ConnStream {
             IoStreamProj::Tcp(s) => s.poll_flush(cx),
             #[cfg(target_family = "unix")]
             IoStreamProj::Unix(s) => s.poll_flush(cx),
+            #[cfg(feature = "tls")]
+            IoStreamProj::Tls(s) => s.poll_flush(cx),
         }
     }
 
@@ -191,6 +232,8 @@ impl AsyncWrite for ConnStream {
             IoStreamProj::Tcp(s) => s.poll_shutdown(cx),
             #[cfg(target_family = "unix")]
             IoStreamProj::Unix(s) => s.poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            IoStreamProj::Tls(s) => s.poll_shutdown(cx),
         }
     }
 
@@ -204,6 +247,8 @@ impl AsyncWrite for ConnStream {
             IoStreamProj::Tcp(s) => s.poll_write_vectored(cx, bufs),
             #[cfg(target_family = "unix")]
             IoStreamProj::Unix(s) => s.poll_write_vectored(cx, bufs),
+            #[cfg(feature = "tls")]
+            IoStreamProj::Tls(s) => s.poll_write_vectored(cx, bufs),
         }
     }
 
@@ -213,6 +258,8 @@ impl AsyncWrite for ConnStream {
             Self::Tcp(s) => s.is_write_vectored(),
             #[cfg(target_family = "unix")]
             Self::Unix(s) => s.is_write_vectored(),
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.is_write_vectored(),
         }
     }
 }
@@ -224,18 +271,36 @@ impl ConnStream {
             Self::Tcp(s) => s.peer_addr().map(Address::from).ok(),
             #[cfg(target_family = "unix")]
             Self::Unix(s) => s.peer_addr().ok().and_then(|s| Address::try_from(s).ok()),
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.get_ref().0.peer_addr().map(Address::from).ok(),
         }
     }
 }
 pub struct Conn {
     pub stream: ConnStream,
     pub info: ConnInfo,
+    /// The guard should be kept until the connection is closed, which lets the wrappers of the
+    /// incoming track the live connections.
+    pub guard: Option<ConnGuard>,
 }
 
 impl Conn {
     #[inline]
     pub fn new(stream: ConnStream, info: ConnInfo) -> Self {
-        Conn { stream, info }
+        Conn {
+            stream,
+            info,
+            guard: None,
+        }
+    }
+}
+
+/// An opaque value to be dropped when the connection is closed.
+pub struct ConnGuard(#[allow(dead_code)] Box<dyn Send + Sync>);
+
+impl ConnGuard {
+    pub fn new<T: Send + Sync + 'static>(guard: T) -> Self {
+        Self(Box::new(guard))
     }
 }
 
@@ -1,3 +1,5 @@
+#[cfg(target_family = "unix")]
+use std::net::SocketAddr;
 use std::{
     fmt, io,
     task::{Context, Poll},
@@ -47,6 +49,11 @@ impl From<TcpListener> for DefaultIncoming {
 #[async_trait::async_trait]
 pub trait Incoming: fmt::Debug + Send + 'static {
     async fn accept(&mut self) -> io::Result<Option<Conn>>;
+
+    /// Returns the address the incoming is bound to, or `None` if it's unknown.
+    fn local_addr(&self) -> Option<Address> {
+        None
+    }
 }
 
 #[async_trait::async_trait]
@@ -59,6 +66,18 @@ impl Incoming for DefaultIncoming {
             Ok(None)
         }
     }
+
+    fn local_addr(&self) -> Option<Address> {
+        match self {
+            DefaultIncoming::Tcp(s) => s.as_ref().local_addr().ok().map(Address::from),
+            #[cfg(target_family = "unix")]
+            DefaultIncoming::Unix(s) => s
+                .as_ref()
+                .local_addr()
+                .ok()
+                .and_then(|addr| Address::try_from(addr).ok()),
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -101,6 +120,160 @@ impl MakeIncoming for Address {
     }
 }
 
+/// The capacity of the channel from the accept tasks to the [`ShardedIncoming`].
+#[cfg(target_family = "unix")]
+const SHARDED_BACKLOG: usize = 1024;
+
+/// [`ReusePort`] binds several `SO_REUSEPORT` listeners to the same address, and accepts on them
+/// concurrently, so that the kernel spreads the connections across the listeners instead of
+/// funneling them through a single accept loop.
+///
+/// The sharded listeners are not inherited by the hot restart.
+#[cfg(target_family = "unix")]
+#[derive(Debug, Clone)]
+pub struct ReusePort {
+    addr: SocketAddr,
+    shards: usize,
+    cpu_affinity: bool,
+}
+
+#[cfg(target_family = "unix")]
+impl ReusePort {
+    /// Creates `shards` listeners on the address, at least 1.
+    pub fn new(addr: SocketAddr, shards: usize) -> Self {
+        Self {
+            addr,
+            shards: shards.max(1),
+            cpu_affinity: false,
+        }
+    }
+
+    /// Sets whether each listener accepts on a dedicated thread pinned to a cpu, instead of a
+    /// task of the current runtime.
+    ///
+    /// Only supported on Linux, and default is `false`.
+    pub fn cpu_affinity(mut self, cpu_affinity: bool) -> Self {
+        self.cpu_affinity = cpu_affinity;
+        self
+    }
+
+    fn bind(&self) -> io::Result<Vec<std::net::TcpListener>> {
+        let mut addr = self.addr;
+        let mut listeners = Vec::with_capacity(self.shards);
+        for _ in 0..self.shards {
+            let listener: std::net::TcpListener =
+                unix_helper::create_reuse_port_tcp_socket(addr)?.into();
+            // the listeners must share the same port if it's assigned by the os
+            addr = listener.local_addr()?;
+            listeners.push(listener);
+        }
+        Ok(listeners)
+    }
+}
+
+#[cfg(target_family = "unix")]
+#[async_trait::async_trait]
+impl MakeIncoming for ReusePort {
+    type Incoming = ShardedIncoming;
+
+    async fn make_incoming(self) -> io::Result<Self::Incoming> {
+        let listeners = self.bind()?;
+        let local_addr = listeners[0].local_addr()?;
+        let (tx, rx) = tokio::sync::mpsc::channel(SHARDED_BACKLOG);
+
+        #[cfg(target_os = "linux")]
+        if self.cpu_affinity {
+            let cpus = unix_helper::allowed_cpus();
+            for (i, listener) in listeners.into_iter().enumerate() {
+                let cpu = (!cpus.is_empty()).then(|| cpus[i % cpus.len()]);
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_io()
+                    .build()?;
+                let tx = tx.clone();
+                std::thread::Builder::new()
+                    .name(format!("volo-accept-{i}"))
+                    .spawn(move || {
+                        if let Some(cpu) = cpu {
+                            if let Err(e) = unix_helper::set_cpu_affinity(cpu) {
+                                tracing::warn!(
+                                    "[VOLO] failed to pin accept thread to cpu {cpu}: {e}"
+                                );
+                            }
+                        }
+                        runtime.block_on(async move {
+                            match TcpListener::from_std(listener) {
+                                Ok(listener) => accept_shard(listener, tx).await,
+                                Err(e) => {
+                                    let _ = tx.send(Err(e)).await;
+                                }
+                            }
+                        })
+                    })?;
+            }
+            return Ok(ShardedIncoming { rx, local_addr });
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.cpu_affinity {
+            tracing::warn!("[VOLO] cpu affinity of the accept threads is only supported on linux");
+        }
+
+        for listener in listeners {
+            tokio::spawn(accept_shard(TcpListener::from_std(listener)?, tx.clone()));
+        }
+        Ok(ShardedIncoming { rx, local_addr })
+    }
+}
+
+/// Accepts on the listener until the [`ShardedIncoming`] is dropped.
+///
+/// The connections are sent as std streams, since they may be accepted by another runtime.
+#[cfg(target_family = "unix")]
+async fn accept_shard(
+    listener: TcpListener,
+    tx: tokio::sync::mpsc::Sender<io::Result<std::net::TcpStream>>,
+) {
+    loop {
+        let accepted = tokio::select! {
+            _ = tx.closed() => return,
+            accepted = listener.accept() => accepted,
+        };
+        if tx
+            .send(accepted.and_then(|(stream, _)| stream.into_std()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// [`ShardedIncoming`] merges the connections accepted by the listeners of [`ReusePort`].
+#[cfg(target_family = "unix")]
+#[derive(Debug)]
+pub struct ShardedIncoming {
+    rx: tokio::sync::mpsc::Receiver<io::Result<std::net::TcpStream>>,
+    local_addr: SocketAddr,
+}
+
+#[cfg(target_family = "unix")]
+#[async_trait::async_trait]
+impl Incoming for ShardedIncoming {
+    async fn accept(&mut self) -> io::Result<Option<Conn>> {
+        match self.rx.recv().await {
+            Some(stream) => {
+                let conn = Conn::from(tokio::net::TcpStream::from_std(stream?)?);
+                tracing::trace!("[VOLO] recv a connection from: {:?}", conn.info.peer_addr);
+                Ok(Some(conn))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn local_addr(&self) -> Option<Address> {
+        Some(Address::from(self.local_addr))
+    }
+}
+
 impl Stream for DefaultIncoming {
     type Item = io::Result<Conn>;
 
@@ -251,6 +424,14 @@ mod unix_helper {
             return Ok(socket.into());
         }
 
+        let socket = create_reuse_port_tcp_socket(addr)?;
+        DEFAULT_HOT_RESTART.register_listener_fd(addr.to_string(), socket.as_raw_fd());
+        Ok(socket.into())
+    }
+
+    /// Creates a listening socket with `SO_REUSEPORT`, so that several sockets can be bound to
+    /// the same address.
+    pub fn create_reuse_port_tcp_socket(addr: SocketAddr) -> std::io::Result<Socket> {
         let domain = if addr.is_ipv4() {
             Domain::IPV4
         } else {
@@ -270,9 +451,33 @@ mod unix_helper {
         #[cfg(not(target_os = "linux"))]
         let backlog = libc::SOMAXCONN as i32;
         socket.listen(backlog)?;
+        Ok(socket)
+    }
 
-        DEFAULT_HOT_RESTART.register_listener_fd(addr.to_string(), socket.as_raw_fd());
-        Ok(socket.into())
+    /// Returns the cpus the current thread is allowed to run on.
+    #[cfg(target_os = "linux")]
+    pub fn allowed_cpus() -> Vec<usize> {
+        let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+        if unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) }
+            != 0
+        {
+            return Vec::new();
+        }
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) })
+            .collect()
+    }
+
+    /// Pins the current thread to the cpu.
+    #[cfg(target_os = "linux")]
+    pub fn set_cpu_affinity(cpu: usize) -> std::io::Result<()> {
+        let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+        if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
     }
 
     pub async fn create_unix_listener_with_max_backlog<P: AsRef<Path>>(
@@ -311,3 +516,33 @@ mod unix_helper {
         Ok(unix_listener)
     }
 }
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::{Incoming, MakeIncoming, ReusePort};
+    use crate::net::Address;
+
+    #[tokio::test]
+    async fn test_reuse_port() {
+        for cpu_affinity in [false, true] {
+            let mut incoming = ReusePort::new("127.0.0.1:0".parse().unwrap(), 4)
+                .cpu_affinity(cpu_affinity)
+                .make_incoming()
+                .await
+                .unwrap();
+            let addr = match incoming.local_addr() {
+                Some(Address::Ip(addr)) => addr,
+                addr => panic!("unexpected local address: {addr:?}"),
+            };
+            let mut clients = Vec::new();
+            for _ in 0..16 {
+                clients.push(tokio::net::TcpStream::connect(addr).await.unwrap());
+            }
+            for client in &clients {
+                let conn = incoming.accept().await.unwrap().unwrap();
+                assert!(conn.info.peer_addr.is_some());
+                assert_eq!(client.local_addr().unwrap().ip(), addr.ip());
+            }
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! Connection level limits of the [`Incoming`].
+//!
+//! [`LimitedMakeIncoming`] wraps a [`MakeIncoming`], and closes the connections exceeding the
+//! maximum concurrent connections, the maximum connections per peer ip or the accept rate right
+//! after they're accepted.
+//!
+//! ```no_run
+//! use volo::net::{limit::LimitedMakeIncoming, Address};
+//!
+//! let addr: Address = "[::]:8080".parse::<std::net::SocketAddr>().unwrap().into();
+//! let incoming = LimitedMakeIncoming::new(addr)
+//!     .max_connections(10000)
+//!     .max_connections_per_ip(100)
+//!     .accept_rate(1000.0, 100);
+//! let stats = incoming.stats();
+//! // server.run(incoming).await
+//! ```
+
+use std::{
+    fmt, io,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use dashmap::DashMap;
+
+use super::{
+    conn::{Conn, ConnGuard},
+    incoming::{Incoming, MakeIncoming},
+    Address,
+};
+use crate::limit::rate::TokenBucket;
+
+/// The counters of a [`LimitedMakeIncoming`].
+#[derive(Debug, Default)]
+pub struct ConnStats {
+    active: AtomicUsize,
+    accepted: AtomicU64,
+    rejected_max_connections: AtomicU64,
+    rejected_per_ip: AtomicU64,
+    rejected_rate: AtomicU64,
+    per_ip: DashMap<IpAddr, usize>,
+}
+
+impl ConnStats {
+    /// Returns the number of the live connections.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the live connections from the ip.
+    pub fn active_of(&self, ip: &IpAddr) -> usize {
+        self.per_ip.get(ip).map_or(0, |count| *count)
+    }
+
+    /// Returns the number of the connections passing the limits.
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the connections closed for the maximum concurrent connections.
+    pub fn rejected_max_connections(&self) -> u64 {
+        self.rejected_max_connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the connections closed for the maximum connections per ip.
+    pub fn rejected_per_ip(&self) -> u64 {
+        self.rejected_per_ip.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the connections closed for the accept rate.
+    pub fn rejected_rate(&self) -> u64 {
+        self.rejected_rate.load(Ordering::Relaxed)
+    }
+}
+
+/// Released when the connection is closed.
+struct Permit {
+    stats: Arc<ConnStats>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.stats.active.fetch_sub(1, Ordering::Relaxed);
+        if let Some(ip) = self.ip {
+            self.stats.per_ip.remove_if_mut(&ip, |_, count| {
+                *count -= 1;
+                *count == 0
+            });
+        }
+    }
+}
+
+/// [`LimitedMakeIncoming`] enforces the connection limits on the incoming made by the inner
+/// [`MakeIncoming`].
+pub struct LimitedMakeIncoming<MI> {
+    make_incoming: MI,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    accept_rate: Option<(f64, u32)>,
+    stats: Arc<ConnStats>,
+}
+
+impl<MI> LimitedMakeIncoming<MI> {
+    pub fn new(make_incoming: MI) -> Self {
+        Self {
+            make_incoming,
+            max_connections: None,
+            max_connections_per_ip: None,
+            accept_rate: None,
+            stats: Default::default(),
+        }
+    }
+
+    /// Sets the maximum number of the concurrent connections.
+    ///
+    /// Default is unlimited.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of the concurrent connections from the same peer ip, the unix
+    /// socket connections are not limited.
+    ///
+    /// Default is unlimited.
+    pub fn max_connections_per_ip(mut self, max: usize) -> Self {
+        self.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Sets the number of the connections accepted per second, and the number of connections
+    /// allowed in a burst.
+    ///
+    /// Default is unlimited.
+    pub fn accept_rate(mut self, per_second: f64, burst: u32) -> Self {
+        self.accept_rate = Some((per_second, burst));
+        self
+    }
+
+    /// Returns the counters, which are updated after the server starts.
+    pub fn stats(&self) -> Arc<ConnStats> {
+        self.stats.clone()
+    }
+}
+
+impl<MI: fmt::Debug> fmt::Debug for LimitedMakeIncoming<MI> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LimitedMakeIncoming")
+            .field("make_incoming", &self.make_incoming)
+            .field("max_connections", &self.max_connections)
+            .field("max_connections_per_ip", &self.max_connections_per_ip)
+            .field("accept_rate", &self.accept_rate)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<MI> MakeIncoming for LimitedMakeIncoming<MI>
+where
+    MI: MakeIncoming + Send,
+{
+    type Incoming = LimitedIncoming<MI::Incoming>;
+
+    async fn make_incoming(self) -> io::Result<Self::Incoming> {
+        Ok(LimitedIncoming {
+            incoming: self.make_incoming.make_incoming().await?,
+            max_connections: self.max_connections,
+            max_connections_per_ip: self.max_connections_per_ip,
+            accept_rate: self
+                .accept_rate
+                .map(|(rate, burst)| TokenBucket::new(rate, burst)),
+            stats: self.stats,
+        })
+    }
+}
+
+/// [`LimitedIncoming`] closes the connections exceeding the limits, and attaches a guard to the
+/// others to track the live connections.
+#[derive(Debug)]
+pub struct LimitedIncoming<I> {
+    incoming: I,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    accept_rate: Option<TokenBucket>,
+    stats: Arc<ConnStats>,
+}
+
+impl<I> LimitedIncoming<I> {
+    pub fn stats(&self) -> &Arc<ConnStats> {
+        &self.stats
+    }
+
+    /// Returns the permit of the connection, or `None` if it exceeds the limits.
+    fn acquire(&mut self, conn: &Conn) -> Option<Permit> {
+        if let Some(bucket) = &mut self.accept_rate {
+            if !bucket.try_acquire() {
+                self.stats.rejected_rate.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        }
+        if let Some(max) = self.max_connections {
+            if self.stats.active() >= max {
+                self.stats
+                    .rejected_max_connections
+                    .fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        }
+        let ip = match &conn.info.peer_addr {
+            Some(Address::Ip(addr)) => Some(addr.ip()),
+            _ => None,
+        };
+        if let Some(ip) = ip {
+            let mut count = self.stats.per_ip.entry(ip).or_default();
+            if self.max_connections_per_ip.is_some_and(|max| *count >= max) {
+                drop(count);
+                // removes the entry inserted above
+                self.stats.per_ip.remove_if(&ip, |_, count| *count == 0);
+                self.stats.rejected_per_ip.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            *count += 1;
+        }
+        self.stats.active.fetch_add(1, Ordering::Relaxed);
+        self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+        Some(Permit {
+            stats: self.stats.clone(),
+            ip,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<I> Incoming for LimitedIncoming<I>
+where
+    I: Incoming,
+{
+    async fn accept(&mut self) -> io::Result<Option<Conn>> {
+        loop {
+            let mut conn = match self.incoming.accept().await? {
+                Some(conn) => conn,
+                None => return Ok(None),
+            };
+            match self.acquire(&conn) {
+                Some(permit) => {
+                    // the inner guard is released after the permit
+                    conn.guard = Some(match conn.guard.take() {
+                        Some(guard) => ConnGuard::new((permit, guard)),
+                        None => ConnGuard::new(permit),
+                    });
+                    return Ok(Some(conn));
+                }
+                None => {
+                    tracing::debug!(
+                        "[VOLO] close the connection from {:?} exceeding the limits",
+                        conn.info.peer_addr
+                    );
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.incoming.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{LimitedMakeIncoming, TokenBucket};
+    use crate::net::{incoming::Incoming, DefaultIncoming, MakeIncoming};
+
+    #[tokio::test]
+    async fn test_limits() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let make_incoming =
+            LimitedMakeIncoming::new(DefaultIncoming::from(listener)).max_connections_per_ip(2);
+        let stats = make_incoming.stats();
+        let mut incoming = make_incoming.make_incoming().await.unwrap();
+
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+        let first = incoming.accept().await.unwrap().unwrap();
+        let _second = incoming.accept().await.unwrap().unwrap();
+        // the third connection is closed
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), incoming.accept())
+                .await
+                .is_err()
+        );
+        assert_eq!(stats.active(), 2);
+        assert_eq!(stats.active_of(&addr.ip()), 2);
+        assert_eq!(stats.rejected_per_ip(), 1);
+
+        drop(first);
+        assert_eq!(stats.active(), 1);
+        clients.push(TcpStream::connect(addr).await.unwrap());
+        let _third = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(stats.accepted(), 3);
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(0.0, 2);
+        assert!(bucket.try_acquire());
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+    }
+}
@@ -1,10 +1,15 @@
 pub mod conn;
 pub mod dial;
 pub mod incoming;
+pub mod limit;
 mod probe;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 use std::{borrow::Cow, fmt, net::Ipv6Addr, path::Path};
 
+#[cfg(target_family = "unix")]
+pub use incoming::ReusePort;
 pub use incoming::{DefaultIncoming, MakeIncoming};
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
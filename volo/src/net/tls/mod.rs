@@ -0,0 +1,617 @@
+//! TLS transport based on [`rustls`], which is enabled by the `tls` feature.
+//!
+//! The client side uses [`TlsMakeTransport`] with a [`TlsConnector`], and the server side wraps
+//! any [`MakeIncoming`] with [`TlsMakeIncoming`]. The connections are [`ConnStream::Tls`], so
+//! they work with both volo-thrift and volo-grpc.
+//!
+//! ```no_run
+//! use volo::net::tls::{ClientTlsConfig, Identity, ServerTlsConfig, TlsAcceptor, TlsConnector};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let connector = TlsConnector::new(
+//!     ClientTlsConfig::new()
+//!         .ca_certificate(std::fs::read("ca.pem")?)
+//!         .server_name("example.com"),
+//! )?;
+//! let acceptor = TlsAcceptor::new(
+//!     ServerTlsConfig::new(Identity::from_pem_file("server.pem", "server.key")?)
+//!         // verifies the client certificates
+//!         .client_ca_certificate(std::fs::read("ca.pem")?),
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{fmt, io, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use futures::{future::BoxFuture, stream::FuturesUnordered, StreamExt};
+use tokio::net::TcpStream;
+pub use tokio_rustls::rustls;
+use tokio_rustls::{
+    rustls::{
+        server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+        Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig, ServerName,
+    },
+    TlsStream,
+};
+
+use super::{
+    conn::{Conn, ConnStream, OwnedReadHalf, OwnedWriteHalf},
+    dial::{DefaultMakeTransport, MakeTransport},
+    incoming::{Incoming, MakeIncoming},
+    Address,
+};
+
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parses all the certificates in the PEM.
+pub fn load_certs(pem: &[u8]) -> io::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut &*pem)?;
+    if certs.is_empty() {
+        return Err(invalid_data("no certificate found in the pem"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Parses the first PKCS#8, PKCS#1 or SEC1 private key in the PEM.
+pub fn load_private_key(pem: &[u8]) -> io::Result<PrivateKey> {
+    let mut reader = pem;
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(invalid_data("no private key found in the pem"))
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn root_store(pems: &[Vec<u8>]) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for pem in pems {
+        for cert in load_certs(pem)? {
+            roots.add(&cert).map_err(invalid_data)?;
+        }
+    }
+    Ok(roots)
+}
+
+/// A certificate chain with its private key in PEM.
+#[derive(Clone)]
+pub struct Identity {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl Identity {
+    pub fn from_pem(cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    pub fn from_pem_file(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from_pem(std::fs::read(cert)?, std::fs::read(key)?))
+    }
+
+    fn load(&self) -> io::Result<(Vec<Certificate>, PrivateKey)> {
+        Ok((load_certs(&self.cert)?, load_private_key(&self.key)?))
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity").finish_non_exhaustive()
+    }
+}
+
+/// The configuration of the client side TLS.
+#[derive(Debug, Clone, Default)]
+pub struct ClientTlsConfig {
+    ca_certs: Vec<Vec<u8>>,
+    identity: Option<Identity>,
+    server_name: Option<String>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl ClientTlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the PEM encoded CA certificates to verify the server.
+    pub fn ca_certificate(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca_certs.push(pem.into());
+        self
+    }
+
+    /// Sets the client certificate, which is sent to the servers requiring the client
+    /// authentication (mTLS).
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Sets the name to verify the server certificate against, which is also sent by SNI.
+    ///
+    /// Defaults to the ip address of the server.
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Sets the protocols to negotiate by ALPN, in the order of preference.
+    pub fn alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = protocols;
+        self
+    }
+}
+
+/// The configuration of the server side TLS.
+#[derive(Debug, Clone)]
+pub struct ServerTlsConfig {
+    identity: Identity,
+    client_ca_certs: Vec<Vec<u8>>,
+    client_auth_optional: bool,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl ServerTlsConfig {
+    pub fn new(identity: Identity) -> Self {
+        Self {
+            identity,
+            client_ca_certs: Vec::new(),
+            client_auth_optional: false,
+            alpn_protocols: Vec::new(),
+        }
+    }
+
+    /// Adds the PEM encoded CA certificates to verify the clients, which enables the client
+    /// authentication (mTLS).
+    pub fn client_ca_certificate(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.client_ca_certs.push(pem.into());
+        self
+    }
+
+    /// Sets whether the clients without a certificate are allowed when the client authentication
+    /// is enabled.
+    ///
+    /// Default is `false`.
+    pub fn client_auth_optional(mut self, optional: bool) -> Self {
+        self.client_auth_optional = optional;
+        self
+    }
+
+    /// Sets the protocols to negotiate by ALPN, in the order of preference.
+    pub fn alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = protocols;
+        self
+    }
+}
+
+/// [`TlsConnector`] performs the client side TLS handshakes.
+#[derive(Clone)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: Option<ServerName>,
+}
+
+impl TlsConnector {
+    pub fn new(config: ClientTlsConfig) -> io::Result<Self> {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store(&config.ca_certs)?);
+        let mut client_config = match &config.identity {
+            Some(identity) => {
+                let (certs, key) = identity.load()?;
+                builder
+                    .with_client_auth_cert(certs, key)
+                    .map_err(invalid_data)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        client_config.alpn_protocols = config.alpn_protocols;
+
+        let server_name = config
+            .server_name
+            .map(|name| ServerName::try_from(name.as_str()))
+            .transpose()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        Ok(Self {
+            config: Arc::new(client_config),
+            server_name,
+        })
+    }
+
+    /// Returns the protocols to negotiate by ALPN.
+    pub fn alpn_protocols(&self) -> &[Vec<u8>] {
+        &self.config.alpn_protocols
+    }
+
+    /// Overrides the protocols to negotiate by ALPN.
+    pub fn with_alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols = protocols;
+        self.config = Arc::new(config);
+        self
+    }
+
+    pub async fn connect(
+        &self,
+        addr: SocketAddr,
+        stream: TcpStream,
+    ) -> io::Result<TlsStream<TcpStream>> {
+        let server_name = self
+            .server_name
+            .clone()
+            .unwrap_or(ServerName::IpAddress(addr.ip()));
+        let stream = tokio_rustls::TlsConnector::from(self.config.clone())
+            .connect(server_name, stream)
+            .await?;
+        Ok(stream.into())
+    }
+}
+
+impl From<Arc<ClientConfig>> for TlsConnector {
+    fn from(config: Arc<ClientConfig>) -> Self {
+        Self {
+            config,
+            server_name: None,
+        }
+    }
+}
+
+impl fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConnector")
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// [`TlsAcceptor`] performs the server side TLS handshakes.
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    pub fn new(config: ServerTlsConfig) -> io::Result<Self> {
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = if config.client_ca_certs.is_empty() {
+            builder.with_no_client_auth()
+        } else {
+            let roots = root_store(&config.client_ca_certs)?;
+            if config.client_auth_optional {
+                builder.with_client_cert_verifier(
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+                )
+            } else {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+        };
+        let (certs, key) = config.identity.load()?;
+        let mut server_config = builder.with_single_cert(certs, key).map_err(invalid_data)?;
+        server_config.alpn_protocols = config.alpn_protocols;
+        Ok(Self {
+            config: Arc::new(server_config),
+        })
+    }
+
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        let stream = tokio_rustls::TlsAcceptor::from(self.config.clone())
+            .accept(stream)
+            .await?;
+        Ok(stream.into())
+    }
+}
+
+impl From<Arc<ServerConfig>> for TlsAcceptor {
+    fn from(config: Arc<ServerConfig>) -> Self {
+        Self { config }
+    }
+}
+
+impl fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsAcceptor").finish_non_exhaustive()
+    }
+}
+
+/// [`TlsMakeTransport`] connects to the server by TCP and then performs the TLS handshake.
+#[derive(Debug, Clone)]
+pub struct TlsMakeTransport {
+    make_transport: DefaultMakeTransport,
+    connector: TlsConnector,
+    handshake_timeout: Option<Duration>,
+}
+
+impl TlsMakeTransport {
+    pub fn new(connector: TlsConnector) -> Self {
+        Self {
+            make_transport: DefaultMakeTransport::new(),
+            connector,
+            handshake_timeout: None,
+        }
+    }
+
+    pub fn connector(&self) -> &TlsConnector {
+        &self.connector
+    }
+
+    pub async fn make_connection(&self, addr: Address) -> io::Result<Conn> {
+        let addr = match addr {
+            Address::Ip(addr) => addr,
+            #[cfg(target_family = "unix")]
+            Address::Unix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "tls over unix socket is not supported",
+                ))
+            }
+        };
+        let conn = self.make_transport.make_connection(addr.into()).await?;
+        let stream = match conn.stream {
+            ConnStream::Tcp(stream) => stream,
+            _ => unreachable!("tcp connection expected"),
+        };
+        let handshake = self.connector.connect(addr, stream);
+        let stream = match self.handshake_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, handshake).await??,
+            None => handshake.await?,
+        };
+        Ok(Conn::new(stream.into(), conn.info))
+    }
+}
+
+#[async_trait::async_trait]
+impl MakeTransport for TlsMakeTransport {
+    type ReadHalf = OwnedReadHalf;
+
+    type WriteHalf = OwnedWriteHalf;
+
+    async fn make_transport(&self, addr: Address) -> io::Result<(Self::ReadHalf, Self::WriteHalf)> {
+        let conn = self.make_connection(addr).await?;
+        Ok(conn.stream.into_split())
+    }
+
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        // the handshake is a part of the connecting
+        self.handshake_timeout = timeout;
+        self.make_transport.set_connect_timeout(timeout);
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.make_transport.set_read_timeout(timeout);
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.make_transport.set_write_timeout(timeout);
+    }
+}
+
+/// [`TlsMakeIncoming`] wraps a [`MakeIncoming`] to accept the TLS connections.
+#[derive(Debug)]
+pub struct TlsMakeIncoming<MI> {
+    make_incoming: MI,
+    acceptor: TlsAcceptor,
+    handshake_timeout: Duration,
+}
+
+impl<MI> TlsMakeIncoming<MI> {
+    pub fn new(make_incoming: MI, acceptor: TlsAcceptor) -> Self {
+        Self {
+            make_incoming,
+            acceptor,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+
+    /// Sets the timeout of the handshakes, after which the connections are dropped.
+    ///
+    /// Default is 10 seconds.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<MI> MakeIncoming for TlsMakeIncoming<MI>
+where
+    MI: MakeIncoming + Send,
+{
+    type Incoming = TlsIncoming<MI::Incoming>;
+
+    async fn make_incoming(self) -> io::Result<Self::Incoming> {
+        Ok(TlsIncoming {
+            incoming: Some(self.make_incoming.make_incoming().await?),
+            acceptor: self.acceptor,
+            handshake_timeout: self.handshake_timeout,
+            handshakes: FuturesUnordered::new(),
+        })
+    }
+}
+
+/// [`TlsIncoming`] performs the handshakes concurrently, so that a slow client doesn't block
+/// the others.
+pub struct TlsIncoming<I> {
+    /// `None` if the inner incoming is exhausted.
+    incoming: Option<I>,
+    acceptor: TlsAcceptor,
+    handshake_timeout: Duration,
+    handshakes: FuturesUnordered<BoxFuture<'static, io::Result<Conn>>>,
+}
+
+impl<I> TlsIncoming<I> {
+    fn handshake(&self, conn: Conn) -> BoxFuture<'static, io::Result<Conn>> {
+        let acceptor = self.acceptor.clone();
+        let handshake_timeout = self.handshake_timeout;
+        Box::pin(async move {
+            let stream = match conn.stream {
+                ConnStream::Tcp(stream) => stream,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "tls is only supported over tcp",
+                    ))
+                }
+            };
+            let stream = tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await??;
+            Ok(Conn {
+                stream: stream.into(),
+                info: conn.info,
+                guard: conn.guard,
+            })
+        })
+    }
+}
+
+impl<I: fmt::Debug> fmt::Debug for TlsIncoming<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsIncoming")
+            .field("incoming", &self.incoming)
+            .field("handshakes", &self.handshakes.len())
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<I> Incoming for TlsIncoming<I>
+where
+    I: Incoming,
+{
+    async fn accept(&mut self) -> io::Result<Option<Conn>> {
+        loop {
+            let accepted = match &mut self.incoming {
+                Some(incoming) => tokio::select! {
+                    conn = incoming.accept() => conn?,
+                    Some(conn) = self.handshakes.next() => {
+                        match conn {
+                            Ok(conn) => return Ok(Some(conn)),
+                            Err(err) => tracing::debug!("[VOLO] tls handshake error: {}", err),
+                        }
+                        continue;
+                    }
+                },
+                None => match self.handshakes.next().await {
+                    Some(Ok(conn)) => return Ok(Some(conn)),
+                    Some(Err(err)) => {
+                        tracing::debug!("[VOLO] tls handshake error: {}", err);
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+            };
+            match accepted {
+                Some(conn) => {
+                    let handshake = self.handshake(conn);
+                    self.handshakes.push(handshake);
+                }
+                None => self.incoming = None,
+            }
+        }
+    }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.incoming
+            .as_ref()
+            .and_then(|incoming| incoming.local_addr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::net::DefaultIncoming;
+
+    const CA: &[u8] = include_bytes!("testdata/ca.pem");
+
+    fn identity(name: &str) -> Identity {
+        match name {
+            "server" => Identity::from_pem(
+                &include_bytes!("testdata/server.pem")[..],
+                &include_bytes!("testdata/server.key")[..],
+            ),
+            _ => Identity::from_pem(
+                &include_bytes!("testdata/client.pem")[..],
+                &include_bytes!("testdata/client.key")[..],
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mtls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = TlsAcceptor::new(
+            ServerTlsConfig::new(identity("server"))
+                .client_ca_certificate(CA)
+                .alpn_protocols(vec![b"h2".to_vec()]),
+        )
+        .unwrap();
+        let mut incoming = TlsMakeIncoming::new(DefaultIncoming::from(listener), acceptor)
+            .make_incoming()
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            while let Ok(Some(mut conn)) = incoming.accept().await {
+                let mut buf = [0; 4];
+                conn.read_exact(&mut buf).await.unwrap();
+                conn.write_all(&buf).await.unwrap();
+            }
+        });
+
+        // the client certificate is required
+        let mt = TlsMakeTransport::new(
+            TlsConnector::new(ClientTlsConfig::new().ca_certificate(CA)).unwrap(),
+        );
+        let mut conn = mt.make_connection(addr.into()).await.unwrap();
+        let mut buf = [0; 4];
+        conn.write_all(b"ping").await.unwrap();
+        assert!(conn.read_exact(&mut buf).await.is_err());
+
+        let mt = TlsMakeTransport::new(
+            TlsConnector::new(
+                ClientTlsConfig::new()
+                    .ca_certificate(CA)
+                    .identity(identity("client"))
+                    .server_name("localhost")
+                    .alpn_protocols(vec![b"h2".to_vec()]),
+            )
+            .unwrap(),
+        );
+        let mut conn = mt.make_connection(addr.into()).await.unwrap();
+        match &conn.stream {
+            ConnStream::Tls(stream) => {
+                assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]))
+            }
+            _ => panic!("tls stream expected"),
+        }
+        conn.write_all(b"ping").await.unwrap();
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // the server name doesn't match the certificate
+        let mt = TlsMakeTransport::new(
+            TlsConnector::new(
+                ClientTlsConfig::new()
+                    .ca_certificate(CA)
+                    .server_name("example.com"),
+            )
+            .unwrap(),
+        );
+        assert!(mt.make_connection(addr.into()).await.is_err());
+    }
+}
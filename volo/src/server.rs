@@ -0,0 +1,227 @@
+//! Common parts of the servers.
+//!
+//! The servers spawned in background return a [`ServerHandle`], which tells when the server is
+//! ready to accept connections, and stops the server programmatically.
+
+use std::{
+    fmt, io,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use futures::{future::BoxFuture, Future, FutureExt};
+use motore::BoxError;
+use tokio::{
+    sync::{watch, Notify},
+    task::JoinHandle,
+};
+
+use crate::net::Address;
+
+/// The connection counters of a server.
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    active: AtomicUsize,
+    accepted: AtomicU64,
+    force_closed: AtomicU64,
+}
+
+impl ServerStats {
+    /// Returns the number of the live connections.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the accepted connections.
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the connections force-closed when the graceful shutdown times out.
+    pub fn force_closed(&self) -> u64 {
+        self.force_closed.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Status {
+    Starting,
+    Ready(Option<Address>),
+    Exited,
+}
+
+/// The state of a running server, which is reported by the server to its [`ServerHandle`].
+#[doc(hidden)]
+pub struct ServerState {
+    status: watch::Sender<Status>,
+    stats: ServerStats,
+}
+
+impl ServerState {
+    pub fn new() -> Self {
+        Self {
+            status: watch::channel(Status::Starting).0,
+            stats: ServerStats::default(),
+        }
+    }
+
+    /// Reports the server is listening on the address.
+    pub fn set_ready(&self, local_addr: Option<Address>) {
+        self.status.send_replace(Status::Ready(local_addr));
+    }
+
+    fn set_exited(&self) {
+        self.status.send_replace(Status::Exited);
+    }
+
+    pub fn on_accept(&self) {
+        self.stats.active.fetch_add(1, Ordering::Relaxed);
+        self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn on_close(&self) {
+        self.stats.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn on_force_close(&self, count: usize) {
+        self.stats
+            .force_closed
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ServerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerState")
+            .field("status", &*self.status.borrow())
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+/// [`ServerHandle`] controls a server running in background.
+///
+/// Dropping the handle doesn't stop the server.
+#[derive(Debug)]
+pub struct ServerHandle {
+    state: Arc<ServerState>,
+    shutdown: Arc<Notify>,
+    join: JoinHandle<Result<(), BoxError>>,
+}
+
+impl ServerHandle {
+    /// Spawns the server, which reports to the state and shuts down gracefully when the signal
+    /// resolves.
+    #[doc(hidden)]
+    pub fn spawn<F, Fut>(serve: F) -> Self
+    where
+        F: FnOnce(Arc<ServerState>, BoxFuture<'static, io::Result<()>>) -> Fut,
+        Fut: Future<Output = Result<(), BoxError>> + Send + 'static,
+    {
+        let state = Arc::new(ServerState::new());
+        let shutdown = Arc::new(Notify::new());
+        let signal = {
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown.notified().await;
+                Ok(())
+            }
+            .boxed()
+        };
+        let serve = serve(state.clone(), signal);
+        let join = {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let res = serve.await;
+                state.set_exited();
+                res
+            })
+        };
+        Self {
+            state,
+            shutdown,
+            join,
+        }
+    }
+
+    /// Waits until the server is ready to accept connections.
+    ///
+    /// Returns an error if the server exits before it's ready, and the error of the server is
+    /// returned by [`ServerHandle::wait`].
+    pub async fn ready(&self) -> io::Result<()> {
+        let mut status = self.state.status.subscribe();
+        loop {
+            match &*status.borrow_and_update() {
+                Status::Starting => {}
+                Status::Ready(_) => return Ok(()),
+                Status::Exited => break,
+            }
+            if status.changed().await.is_err() {
+                break;
+            }
+        }
+        Err(io::Error::other("the server exited before it's ready"))
+    }
+
+    /// Returns the address the server is listening on, or `None` if the server is not ready or
+    /// the address is unknown.
+    pub fn local_addr(&self) -> Option<Address> {
+        match &*self.state.status.borrow() {
+            Status::Ready(addr) => addr.clone(),
+            _ => None,
+        }
+    }
+
+    /// Starts the graceful shutdown of the server, use [`ServerHandle::wait`] to wait for it.
+    pub fn shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+
+    pub fn stats(&self) -> &ServerStats {
+        &self.state.stats
+    }
+
+    /// Waits until the server exits, and returns the result of the server.
+    pub async fn wait(self) -> Result<(), BoxError> {
+        match self.join.await {
+            Ok(res) => res,
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::ServerHandle;
+    use crate::net::{incoming::Incoming, Address, DefaultIncoming};
+
+    #[tokio::test]
+    async fn test_server_handle() {
+        let handle = ServerHandle::spawn(|state, signal| async move {
+            let incoming = DefaultIncoming::from(TcpListener::bind("127.0.0.1:0").await?);
+            state.set_ready(incoming.local_addr());
+            signal.await?;
+            Ok(())
+        });
+        handle.ready().await.unwrap();
+        assert!(matches!(handle.local_addr(), Some(Address::Ip(_))));
+        handle.shutdown();
+        handle.wait().await.unwrap();
+
+        // the server fails before it's ready
+        let handle = ServerHandle::spawn(|_, _| async { Err("failed to bind".into()) });
+        assert!(handle.ready().await.is_err());
+        assert!(handle.local_addr().is_none());
+        assert!(handle.wait().await.is_err());
+    }
+}